@@ -1,55 +1,109 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::error::Error;
-use std::fs;
 use std::path::Path;
 
+use super::output::{parse_map, read_text, write_json, write_map, write_meta, OutputFormat};
 use super::stubify;
+use crate::messages;
 
 /// Stub entry from stubs.json
 #[derive(Debug, Deserialize)]
-struct Stub {
+pub(crate) struct Stub {
     label: String,
+    /// Missing in stubs.json files from before stable IDs were derived
+    #[serde(rename = "stable-id")]
+    stable_id: Option<String>,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
     #[serde(rename = "code-name")]
     code_name: Option<String>,
     #[serde(rename = "spec-dependencies", default)]
     spec_dependencies: Vec<String>,
     #[serde(rename = "proof-dependencies")]
     proof_dependencies: Option<Vec<String>>,
+    /// Missing in stubs.json files from before readiness was derived
+    #[serde(rename = "can-state")]
+    can_state: Option<bool>,
+    #[serde(rename = "can-prove")]
+    can_prove: Option<bool>,
 }
 
 /// Atom entry for atoms.json
 #[derive(Debug, Serialize)]
-struct Atom {
+pub(crate) struct Atom {
+    /// `stable-id` of the stub, kept when its file is renamed or moved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     #[serde(rename = "display-name")]
     display_name: String,
     dependencies: Vec<String>,
+    /// Longest path from a root of the spec-only graph, with `--compute-depth`
+    #[serde(rename = "spec-dep-depth", skip_serializing_if = "Option::is_none")]
+    spec_dep_depth: Option<usize>,
+    /// Longest path from a root of the proof-only graph, with `--compute-depth`
+    #[serde(rename = "proof-dep-depth", skip_serializing_if = "Option::is_none")]
+    proof_dep_depth: Option<usize>,
+    /// `can-state` of the stub
+    #[serde(rename = "can-state", skip_serializing_if = "Option::is_none")]
+    can_state: Option<bool>,
+    /// `can-prove` of the stub
+    #[serde(rename = "can-prove", skip_serializing_if = "Option::is_none")]
+    can_prove: Option<bool>,
+    /// Blueprint file of the stub, for `--split-by-file`
+    #[serde(skip)]
+    stub_path: String,
 }
 
-/// Generate call graph atoms with line numbers
-pub fn run(project_path: &str, output: &str, regenerate_stubs: bool) -> Result<(), Box<dyn Error>> {
-    let project_path = Path::new(project_path);
-    let verilib_dir = project_path.join(".verilib");
-    let stubs_path = verilib_dir.join("stubs.json");
-
-    // Check if stubs.json exists, generate if needed
-    if regenerate_stubs || !stubs_path.exists() {
-        if regenerate_stubs {
-            eprintln!("Regenerating stubs.json...");
-        } else {
-            eprintln!("stubs.json not found, running stubify...");
-        }
+/// Dependency between atoms of different blueprint files, for boundary.json
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct BoundaryEdge {
+    /// The dependent atom
+    #[serde(rename = "from-atom")]
+    from_atom: String,
+    /// The atom it depends on
+    #[serde(rename = "to-atom")]
+    to_atom: String,
+    #[serde(rename = "from-file")]
+    from_file: String,
+    #[serde(rename = "to-file")]
+    to_file: String,
+}
 
-        stubify::run(
-            project_path.to_str().ok_or("Invalid project path")?,
-            stubs_path.to_str().ok_or("Invalid stubs path")?,
-        )?;
-    }
+/// Options for the atomize command
+#[derive(Debug, Default, Clone)]
+pub struct AtomizeOptions {
+    /// Also write atoms with only spec-dependency edges to this file
+    pub emit_spec_graph: Option<String>,
+    /// Also write atoms with only proof-dependency edges to this file
+    pub emit_proof_graph: Option<String>,
+    /// Add each atom's depth in the spec-only and proof-only graphs
+    pub compute_depth: bool,
+    /// Write one atoms file per blueprint file, plus boundary.json, to this
+    /// directory instead of the combined output
+    pub split_by_file: Option<String>,
+    /// With `split_by_file`, also write the combined output
+    pub also_full: bool,
+    /// Layout of the output files
+    pub format: OutputFormat,
+}
 
-    // Read stubs.json
-    let stubs_content = fs::read_to_string(&stubs_path)?;
-    let stubs: HashMap<String, Stub> = serde_json::from_str(&stubs_content)?;
+/// Dependency edges to include when building atoms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DependencyKind {
+    /// Both spec-dependencies and proof-dependencies
+    All,
+    /// Only spec-dependencies
+    Spec,
+    /// Only proof-dependencies
+    Proof,
+}
 
+/// Transform stubs into atoms (only stubs with code-name)
+pub(crate) fn build_atoms(
+    stubs: &HashMap<String, Stub>,
+    kind: DependencyKind,
+) -> HashMap<String, Atom> {
     // Build a mapping from stub-name to code-name
     let stub_name_to_code_name: HashMap<String, String> = stubs
         .iter()
@@ -60,73 +114,470 @@ pub fn run(project_path: &str, output: &str, regenerate_stubs: bool) -> Result<(
         })
         .collect();
 
-    // Transform stubs into atoms (only stubs with code-name)
+    let paths: Vec<&str> = stubs
+        .values()
+        .filter_map(|stub| stub.stub_path.as_deref())
+        .collect();
+
     let mut atoms: HashMap<String, Atom> = HashMap::new();
 
-    for stub in stubs.values() {
+    for (stub_name, stub) in stubs {
         // Skip stubs without code-name
         let code_name = match &stub.code_name {
             Some(cn) => cn,
             None => continue,
         };
 
+        // Split children have no stub-path; their name starts with the parent's
+        let stub_path = stub.stub_path.clone().unwrap_or_else(|| {
+            paths
+                .iter()
+                .filter(|path| {
+                    stub_name
+                        .strip_prefix(stubify::src_relative(path))
+                        .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .max_by_key(|path| path.len())
+                .map(|path| path.to_string())
+                .unwrap_or_default()
+        });
+
         // display-name is the label
         let display_name = stub.label.clone();
 
         // Map dependencies from stub-names to code-names
         let mut dependencies = Vec::new();
-        for dep_stub_name in &stub.spec_dependencies {
-            if let Some(dep_code_name) = stub_name_to_code_name.get(dep_stub_name) {
-                dependencies.push(dep_code_name.clone());
-            }
-        }
-        if let Some(proof_deps) = &stub.proof_dependencies {
-            for dep_stub_name in proof_deps {
+        if kind != DependencyKind::Proof {
+            for dep_stub_name in &stub.spec_dependencies {
                 if let Some(dep_code_name) = stub_name_to_code_name.get(dep_stub_name) {
                     dependencies.push(dep_code_name.clone());
                 }
             }
         }
+        if kind != DependencyKind::Spec {
+            if let Some(proof_deps) = &stub.proof_dependencies {
+                for dep_stub_name in proof_deps {
+                    if let Some(dep_code_name) = stub_name_to_code_name.get(dep_stub_name) {
+                        dependencies.push(dep_code_name.clone());
+                    }
+                }
+            }
+        }
 
         atoms.insert(
             code_name.clone(),
             Atom {
+                id: stub.stable_id.clone(),
                 display_name,
                 dependencies,
+                spec_dep_depth: None,
+                proof_dep_depth: None,
+                can_state: stub.can_state,
+                can_prove: stub.can_prove,
+                stub_path,
             },
         );
     }
 
-    // Write output
-    let output_path = Path::new(output);
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() && !parent.exists() {
-            fs::create_dir_all(parent)?;
+    atoms
+}
+
+/// The atoms of each blueprint file, and the dependencies between atoms of
+/// different files, sorted
+pub(crate) fn split_by_file(
+    atoms: &HashMap<String, Atom>,
+) -> (BTreeMap<&str, HashMap<String, &Atom>>, Vec<BoundaryEdge>) {
+    let mut files: BTreeMap<&str, HashMap<String, &Atom>> = BTreeMap::new();
+    let mut boundary: BTreeSet<BoundaryEdge> = BTreeSet::new();
+    for (code_name, atom) in atoms {
+        files
+            .entry(atom.stub_path.as_str())
+            .or_default()
+            .insert(code_name.clone(), atom);
+        for dependency in &atom.dependencies {
+            let Some(target) = atoms.get(dependency) else {
+                continue;
+            };
+            if target.stub_path != atom.stub_path {
+                boundary.insert(BoundaryEdge {
+                    from_atom: code_name.clone(),
+                    to_atom: dependency.clone(),
+                    from_file: atom.stub_path.clone(),
+                    to_file: target.stub_path.clone(),
+                });
+            }
         }
     }
+    (files, boundary.into_iter().collect())
+}
 
-    let json = serde_json::to_string_pretty(&atoms)?;
-    fs::write(output_path, json)?;
+/// Depth of every atom in the graph of its `dependencies`: 0 for atoms
+/// without dependencies, else one more than the deepest dependency
+///
+/// Atoms on or behind a dependency cycle have no depth and are left out.
+pub(crate) fn dependency_depths(atoms: &HashMap<String, Atom>) -> HashMap<&str, usize> {
+    // Longest paths in topological order (Kahn's algorithm), from the roots up
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (code_name, atom) in atoms {
+        let mut dependencies: Vec<&str> = atom.dependencies.iter().map(String::as_str).collect();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+        remaining.insert(code_name, dependencies.len());
+        for dependency in dependencies {
+            dependents.entry(dependency).or_default().push(code_name);
+        }
+    }
+
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut ready: VecDeque<&str> = remaining
+        .iter()
+        .filter(|(_, &count)| count == 0)
+        .map(|(&code_name, _)| code_name)
+        .collect();
+    for &code_name in &ready {
+        depths.insert(code_name, 0);
+    }
+    while let Some(code_name) = ready.pop_front() {
+        let depth = depths[code_name];
+        for &dependent in dependents.get(code_name).into_iter().flatten() {
+            let dependent_depth = depths.entry(dependent).or_insert(0);
+            *dependent_depth = (*dependent_depth).max(depth + 1);
+            let count = remaining.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push_back(dependent);
+            }
+        }
+    }
+
+    // Dependents of a cycle got a partial depth before getting stuck
+    depths.retain(|code_name, _| remaining[code_name] == 0);
+    depths
+}
+
+/// Set the spec and proof dependency depths of `atoms`
+fn add_depths(atoms: &mut HashMap<String, Atom>, stubs: &HashMap<String, Stub>) {
+    let spec_atoms = build_atoms(stubs, DependencyKind::Spec);
+    let proof_atoms = build_atoms(stubs, DependencyKind::Proof);
+    let spec_depths = dependency_depths(&spec_atoms);
+    let proof_depths = dependency_depths(&proof_atoms);
+
+    let unranked = atoms
+        .keys()
+        .filter(|code_name| {
+            !spec_depths.contains_key(code_name.as_str())
+                || !proof_depths.contains_key(code_name.as_str())
+        })
+        .count();
+    if unranked > 0 {
+        messages::warning(
+            None,
+            &format!(
+                "{} atoms are on or depend on a dependency cycle and have no depth",
+                unranked
+            ),
+        );
+    }
+
+    for (code_name, atom) in atoms.iter_mut() {
+        atom.spec_dep_depth = spec_depths.get(code_name.as_str()).copied();
+        atom.proof_dep_depth = proof_depths.get(code_name.as_str()).copied();
+    }
+}
+
+/// Write atoms to a JSON file (create parent directory if needed)
+fn write_atoms(
+    atoms: &HashMap<String, Atom>,
+    output: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    write_map(atoms, Path::new(output), format)?;
+    write_meta(&(), Path::new(output))?;
 
     eprintln!("Wrote {} atoms to {}", atoms.len(), output);
 
     Ok(())
 }
 
+/// Write the atoms of every blueprint file to `<dir>/<stub-path>.json`, and
+/// the dependencies crossing files to `<dir>/boundary.json`
+fn write_split_atoms(
+    atoms: &HashMap<String, Atom>,
+    dir: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let dir = Path::new(dir);
+    let (files, boundary) = split_by_file(atoms);
+    for (stub_path, file_atoms) in &files {
+        write_map(file_atoms, &dir.join(format!("{}.json", stub_path)), format)?;
+    }
+    write_json(&boundary, &dir.join("boundary.json"))?;
+
+    eprintln!(
+        "Wrote {} atoms of {} files and {} cross-file dependencies to {}",
+        atoms.len(),
+        files.len(),
+        boundary.len(),
+        dir.display()
+    );
+
+    Ok(())
+}
+
+/// Generate call graph atoms with line numbers
+///
+/// Besides the combined graph in `output`, `emit_spec_graph` and
+/// `emit_proof_graph` optionally write atoms restricted to spec-only or
+/// proof-only dependency edges. With `compute_depth`, every atom records its
+/// depth in the spec-only and proof-only graphs. With `split_by_file`, the
+/// combined graph is written as one file per blueprint file instead (or as
+/// well, with `also_full`).
+pub fn run(
+    project_path: &str,
+    output: &str,
+    regenerate_stubs: bool,
+    options: &AtomizeOptions,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    // Read stubs.json
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+
+    let atoms_of = |kind: DependencyKind| {
+        let mut atoms = build_atoms(&stubs, kind);
+        if options.compute_depth {
+            add_depths(&mut atoms, &stubs);
+        }
+        atoms
+    };
+
+    let atoms = atoms_of(DependencyKind::All);
+    if let Some(dir) = &options.split_by_file {
+        write_split_atoms(&atoms, dir, options.format)?;
+    }
+    if options.split_by_file.is_none() || options.also_full {
+        write_atoms(&atoms, output, options.format)?;
+    }
+
+    if let Some(spec_output) = &options.emit_spec_graph {
+        write_atoms(&atoms_of(DependencyKind::Spec), spec_output, options.format)?;
+    }
+    if let Some(proof_output) = &options.emit_proof_graph {
+        write_atoms(
+            &atoms_of(DependencyKind::Proof),
+            proof_output,
+            options.format,
+        )?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
     fn test_atom_serialization() {
         let atom = Atom {
+            id: None,
             display_name: "my_theorem".to_string(),
             dependencies: vec!["probe:Dep1".to_string(), "probe:Dep2".to_string()],
+            spec_dep_depth: None,
+            proof_dep_depth: None,
+            can_state: Some(true),
+            can_prove: None,
+            stub_path: String::new(),
         };
 
         let json = serde_json::to_string(&atom).unwrap();
         assert!(json.contains("\"display-name\":\"my_theorem\""));
         assert!(json.contains("\"dependencies\":[\"probe:Dep1\",\"probe:Dep2\"]"));
+        assert!(json.contains("\"can-state\":true"));
+        assert!(!json.contains("can-prove"));
+    }
+
+    #[test]
+    fn test_atom_id_survives_file_rename() {
+        let atoms = |path: &str, content: &str| {
+            let parsed =
+                crate::parse::parse_sources(&[(path.to_string(), content.to_string())]).unwrap();
+            let stubs: HashMap<String, Stub> =
+                serde_json::from_value(serde_json::to_value(&parsed.stubs).unwrap()).unwrap();
+            build_atoms(&stubs, DependencyKind::All)
+        };
+        let content = r"\begin{definition}\label{def:magma}\lean{Magma}A magma.\end{definition}
+\begin{theorem}\label{thm:assoc}\lean{assoc}\uses{def:magma}Every magma is associative.\end{theorem}";
+        let before = atoms("a.tex", content);
+        let after = atoms("chapter/magmas.tex", content);
+        let edited = atoms("a.tex", &content.replace("Every", "Each"));
+
+        let id = before["probe:assoc"].id.as_ref().unwrap();
+        assert_eq!(after["probe:assoc"].id.as_ref(), Some(id));
+        assert_ne!(edited["probe:assoc"].id.as_ref(), Some(id));
+        assert_ne!(before["probe:Magma"].id.as_ref(), Some(id));
+        assert_eq!(before["probe:Magma"].id, edited["probe:Magma"].id);
+    }
+
+    #[test]
+    fn test_split_by_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(src.join("chapter")).unwrap();
+        fs::write(
+            src.join("defs.tex"),
+            r"\begin{definition}\label{def}\lean{Def}\end{definition}
+\begin{lemma}\label{aux}\lean{Aux}\uses{def}\end{lemma}",
+        )
+        .unwrap();
+        fs::write(
+            src.join("chapter").join("main.tex"),
+            r"\begin{theorem}\label{thm}\lean{A, B}\uses{def}\end{theorem}
+\begin{proof}\uses{aux}\end{proof}",
+        )
+        .unwrap();
+        let project = dir.path().to_str().unwrap();
+        let output = dir.path().join("atoms.json");
+        let split = dir.path().join("split");
+
+        run(
+            project,
+            output.to_str().unwrap(),
+            false,
+            &AtomizeOptions {
+                split_by_file: Some(split.to_str().unwrap().to_string()),
+                ..AtomizeOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(!output.exists());
+        let read = |path: &Path| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let defs = read(&split.join("defs.tex.json"));
+        let main = read(&split.join("chapter").join("main.tex.json"));
+        assert_eq!(
+            defs.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["probe:Aux", "probe:Def"]
+        );
+        assert_eq!(
+            defs["probe:Aux"]["dependencies"],
+            serde_json::json!(["probe:Def"])
+        );
+        // Split children keep their full dependency lists
+        assert_eq!(
+            main["probe:A"]["dependencies"],
+            serde_json::json!(["probe:Def", "probe:Aux"])
+        );
+        assert_eq!(
+            main.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["probe:A", "probe:B"]
+        );
+
+        let edge = |from: &str, to: &str| {
+            serde_json::json!({
+                "from-atom": from, "to-atom": to,
+                "from-file": "chapter/main.tex", "to-file": "defs.tex"
+            })
+        };
+        assert_eq!(
+            read(&split.join("boundary.json")),
+            serde_json::json!([
+                edge("probe:A", "probe:Aux"),
+                edge("probe:A", "probe:Def"),
+                edge("probe:B", "probe:Aux"),
+                edge("probe:B", "probe:Def"),
+            ])
+        );
+
+        // The pieces compose to the combined output
+        run(
+            project,
+            output.to_str().unwrap(),
+            false,
+            &AtomizeOptions {
+                split_by_file: Some(split.to_str().unwrap().to_string()),
+                also_full: true,
+                ..AtomizeOptions::default()
+            },
+        )
+        .unwrap();
+        let mut composed = defs.as_object().unwrap().clone();
+        composed.extend(main.as_object().unwrap().clone());
+        assert_eq!(read(&output), serde_json::Value::Object(composed));
+    }
+
+    #[test]
+    fn test_atoms_same_from_json_and_ndjson_stubs() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{definition}\label{def}\lean{Def}\end{definition}
+\begin{theorem}\label{thm}\lean{A, B}\uses{def}\end{theorem}
+\begin{proof}\uses{def}\end{proof}"
+                .to_string(),
+        )];
+        let parsed = crate::parse::parse_sources(&files).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let atoms: Vec<serde_json::Value> = [OutputFormat::Json, OutputFormat::Ndjson]
+            .into_iter()
+            .map(|format| {
+                let stubs_path = dir.path().join("stubs");
+                write_map(&parsed.stubs, &stubs_path, format).unwrap();
+                let stubs: HashMap<String, Stub> =
+                    parse_map(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+                assert_eq!(stubs.len(), parsed.stubs.len());
+                serde_json::to_value(build_atoms(&stubs, DependencyKind::All)).unwrap()
+            })
+            .collect();
+
+        assert_eq!(atoms[0], atoms[1]);
+        assert_eq!(atoms[0]["probe:A"]["dependencies"][0], "probe:Def");
+    }
+
+    #[test]
+    fn test_atomize_reads_gzipped_stubs() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{definition}\label{def}\lean{Def}\end{definition}
+\begin{theorem}\label{thm}\lean{Thm}\uses{def}\end{theorem}",
+        )
+        .unwrap();
+        let project = dir.path().to_str().unwrap();
+        let stubs_path = dir.path().join(".verilib").join("stubs.json.gz");
+        stubify::run(
+            project,
+            stubs_path.to_str().unwrap(),
+            &stubify::StubifyOptions::default(),
+        )
+        .unwrap();
+        assert!(fs::read(&stubs_path).unwrap().starts_with(&[0x1f, 0x8b]));
+
+        let output = dir.path().join("atoms.json");
+        run(
+            project,
+            output.to_str().unwrap(),
+            false,
+            &AtomizeOptions::default(),
+        )
+        .unwrap();
+
+        let atoms: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(atoms["probe:Thm"]["dependencies"][0], "probe:Def");
+        // The gzipped stubs were up to date, so no plain stubs.json was generated
+        assert!(!dir.path().join(".verilib").join("stubs.json").exists());
     }
 
     #[test]
@@ -176,4 +627,98 @@ mod tests {
         assert!(stub.proof_dependencies.is_none());
         assert!(stub.spec_dependencies.is_empty());
     }
+
+    fn sample_stubs() -> HashMap<String, Stub> {
+        let json = r#"{
+            "a.tex/thm": {
+                "label": "thm",
+                "code-name": "probe:Thm",
+                "spec-dependencies": ["a.tex/def"],
+                "proof-dependencies": ["a.tex/lem", "a.tex/no_code"]
+            },
+            "a.tex/def": { "label": "def", "code-name": "probe:Def" },
+            "a.tex/lem": { "label": "lem", "code-name": "probe:Lem" },
+            "a.tex/no_code": { "label": "no_code" }
+        }"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_build_atoms_all_dependencies() {
+        let atoms = build_atoms(&sample_stubs(), DependencyKind::All);
+
+        assert_eq!(atoms.len(), 3);
+        assert_eq!(atoms["probe:Thm"].display_name, "thm");
+        assert_eq!(
+            atoms["probe:Thm"].dependencies,
+            vec!["probe:Def", "probe:Lem"]
+        );
+    }
+
+    #[test]
+    fn test_build_atoms_spec_only() {
+        let atoms = build_atoms(&sample_stubs(), DependencyKind::Spec);
+        assert_eq!(atoms["probe:Thm"].dependencies, vec!["probe:Def"]);
+    }
+
+    #[test]
+    fn test_build_atoms_proof_only() {
+        let atoms = build_atoms(&sample_stubs(), DependencyKind::Proof);
+        assert_eq!(atoms["probe:Thm"].dependencies, vec!["probe:Lem"]);
+        assert!(atoms["probe:Def"].dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_dependency_depths() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/def": { "label": "def", "code-name": "probe:Def" },
+                "a.tex/lem": {
+                    "label": "lem", "code-name": "probe:Lem",
+                    "proof-dependencies": ["a.tex/def"]
+                },
+                "a.tex/thm": {
+                    "label": "thm", "code-name": "probe:Thm",
+                    "spec-dependencies": ["a.tex/def"],
+                    "proof-dependencies": ["a.tex/lem", "a.tex/def"]
+                },
+                "a.tex/cyc1": {
+                    "label": "cyc1", "code-name": "probe:Cyc1",
+                    "proof-dependencies": ["a.tex/cyc2"]
+                },
+                "a.tex/cyc2": {
+                    "label": "cyc2", "code-name": "probe:Cyc2",
+                    "proof-dependencies": ["a.tex/cyc1"]
+                },
+                "a.tex/after": {
+                    "label": "after", "code-name": "probe:After",
+                    "proof-dependencies": ["a.tex/cyc1", "a.tex/def"]
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut atoms = build_atoms(&stubs, DependencyKind::All);
+        add_depths(&mut atoms, &stubs);
+
+        let depths = |code_name: &str| {
+            let atom = &atoms[code_name];
+            (atom.spec_dep_depth, atom.proof_dep_depth)
+        };
+        assert_eq!(depths("probe:Def"), (Some(0), Some(0)));
+        // The spec depth only follows \uses in statements
+        assert_eq!(depths("probe:Lem"), (Some(0), Some(1)));
+        // The longest path counts, not the shortest
+        assert_eq!(depths("probe:Thm"), (Some(1), Some(2)));
+        // Cycles (and what depends on them) have no proof depth
+        assert_eq!(depths("probe:Cyc1"), (Some(0), None));
+        assert_eq!(depths("probe:After"), (Some(0), None));
+
+        let json = serde_json::to_value(&atoms["probe:Lem"]).unwrap();
+        assert_eq!(json["spec-dep-depth"], 0);
+        assert_eq!(json["proof-dep-depth"], 1);
+        assert!(serde_json::to_value(&atoms["probe:After"])
+            .unwrap()
+            .get("proof-dep-depth")
+            .is_none());
+    }
 }
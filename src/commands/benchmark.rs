@@ -0,0 +1,173 @@
+//! Micro-benchmark of `stubify` on a synthetic blueprint
+//!
+//! The blueprint comes from the `gen-fixture` generator with a fixed seed,
+//! so runs with the same parameters are comparable across versions.
+
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use super::gen_fixture::{self, FixtureSpec, Structure, DAG_WINDOW};
+use super::output::{read_text, write_json};
+use super::stubify::{self, StubifyOptions};
+
+/// Benchmark parameters, recorded in the JSON results
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkSpec {
+    /// Number of .tex files
+    pub size: usize,
+    /// Theorems per file
+    pub env_count: usize,
+    /// Average number of `\uses` dependencies per theorem
+    pub dep_count: usize,
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+/// Wall-clock statistics over all iterations, in milliseconds
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Timings {
+    mean_ms: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+}
+
+/// Machine-readable benchmark results
+#[derive(Debug, Serialize)]
+struct BenchmarkResults<'a> {
+    version: &'static str,
+    parameters: &'a BenchmarkSpec,
+    stubs: usize,
+    #[serde(flatten)]
+    timings: Timings,
+    samples_ms: Vec<f64>,
+}
+
+/// Value at quantile `q` of sorted `samples` (nearest rank)
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Mean and percentiles of `samples` (which must not be empty)
+pub(crate) fn timings(samples: &[f64]) -> Timings {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    Timings {
+        mean_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        p50_ms: percentile(&sorted, 0.50),
+        p95_ms: percentile(&sorted, 0.95),
+        p99_ms: percentile(&sorted, 0.99),
+    }
+}
+
+/// Fixture with `dep_count` dependencies per theorem on average
+fn fixture_spec(spec: &BenchmarkSpec) -> FixtureSpec {
+    FixtureSpec {
+        files: spec.size,
+        envs_per_file: spec.env_count,
+        structure: Structure::Dag,
+        density: (spec.dep_count as f64 / DAG_WINDOW as f64).min(1.0),
+        seed: spec.seed,
+        ..FixtureSpec::default()
+    }
+}
+
+/// Generate the blueprint in `project_dir` and time `iterations` stubify runs
+fn measure(spec: &BenchmarkSpec, project_dir: &Path) -> Result<(usize, Vec<f64>), Box<dyn Error>> {
+    let project = project_dir.to_str().ok_or("Invalid UTF-8 in temp path")?;
+    gen_fixture::run(project, &fixture_spec(spec))?;
+
+    let output = project_dir.join(".verilib").join("stubs.json");
+    let output = output.to_str().ok_or("Invalid UTF-8 in temp path")?;
+    let mut samples = Vec::with_capacity(spec.iterations);
+    for _ in 0..spec.iterations {
+        let start = Instant::now();
+        stubify::run(project, output, &StubifyOptions::default())?;
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    let stubs: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&read_text(Path::new(output))?)?;
+    Ok((stubs.len(), samples))
+}
+
+/// Run the benchmark, print a summary and write the results to `output`
+pub fn run(spec: &BenchmarkSpec, output: &str) -> Result<(), Box<dyn Error>> {
+    if spec.iterations == 0 {
+        return Err("--iterations must be at least 1".into());
+    }
+
+    let project_dir =
+        std::env::temp_dir().join(format!("probe-blueprint-benchmark-{}", std::process::id()));
+    let measured = measure(spec, &project_dir);
+    let _ = fs::remove_dir_all(&project_dir);
+    let (stubs, samples) = measured?;
+
+    let results = BenchmarkResults {
+        version: env!("CARGO_PKG_VERSION"),
+        parameters: spec,
+        stubs,
+        timings: timings(&samples),
+        samples_ms: samples,
+    };
+    write_json(&results, Path::new(output))?;
+
+    let t = &results.timings;
+    println!(
+        "stubify on {} files x {} theorems ({} stubs), {} iterations",
+        spec.size, spec.env_count, stubs, spec.iterations
+    );
+    println!(
+        "  mean {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+        t.mean_ms, t.p50_ms, t.p95_ms, t.p99_ms
+    );
+    eprintln!("Wrote benchmark results to {}", output);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        assert_eq!(
+            timings(&samples),
+            Timings {
+                mean_ms: 50.5,
+                p50_ms: 50.0,
+                p95_ms: 95.0,
+                p99_ms: 99.0,
+            }
+        );
+        assert_eq!(timings(&[3.0]).p99_ms, 3.0);
+    }
+
+    #[test]
+    fn test_run_writes_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("bench.json");
+        let spec = BenchmarkSpec {
+            size: 2,
+            env_count: 5,
+            dep_count: 2,
+            iterations: 3,
+            seed: 0,
+        };
+
+        run(&spec, output.to_str().unwrap()).unwrap();
+
+        let results: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(results["parameters"]["env_count"], 5);
+        assert_eq!(results["stubs"], 10);
+        assert_eq!(results["samples_ms"].as_array().unwrap().len(), 3);
+        assert!(results["p95_ms"].as_f64().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,219 @@
+//! Heuristic cross-check of blueprint `\uses` against the Lean sources
+//!
+//! A declaration's source text is split into identifiers, which are matched
+//! against the code-names of other stubs (also by namespace suffix, since
+//! the text may refer to `Foo.bar` as `bar` inside `namespace Foo`). There
+//! is no elaboration, so both false positives (a shadowing local name) and
+//! false negatives (uses through notation or tactics) are possible.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::sync::LazyLock;
+
+use super::lean::scan_lean_source;
+
+static IDENTIFIER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z_][A-Za-z0-9_'!?]*(?:\.[A-Za-z_][A-Za-z0-9_'!?]*)*").unwrap()
+});
+pub(crate) static LINE_COMMENT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"--.*").unwrap());
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    #[serde(rename = "code-name")]
+    code_name: Option<String>,
+    #[serde(rename = "spec-dependencies", default)]
+    spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-dependencies")]
+    proof_dependencies: Option<Vec<String>>,
+}
+
+/// Disagreement between the `\uses` of a stub and its Lean declaration
+#[derive(Debug, PartialEq)]
+pub(crate) struct UsesMismatch {
+    pub(crate) stub: String,
+    /// Stubs referenced in the Lean declaration but not in `\uses`
+    pub(crate) missing: Vec<String>,
+    /// Stubs in `\uses` whose declarations the Lean code never references
+    pub(crate) unreferenced: Vec<String>,
+}
+
+/// Source text of every declaration in `lean_files`, keyed by full name
+pub(crate) fn declaration_texts(lean_files: &[(String, String)]) -> HashMap<String, String> {
+    let mut texts = HashMap::new();
+    for (_, content) in lean_files {
+        let lines: Vec<&str> = content.lines().collect();
+        for decl in scan_lean_source(content).declarations {
+            let text = lines[decl.lines.lines_start - 1..decl.lines.lines_end].join("\n");
+            texts.insert(decl.name, text);
+        }
+    }
+    texts
+}
+
+/// Compare each located stub's `\uses` with the stubs its Lean declaration references
+pub(crate) fn check_uses(
+    stubs: &HashMap<String, Stub>,
+    declarations: &HashMap<String, String>,
+) -> Vec<UsesMismatch> {
+    // Located code-names (without "probe:") to stub-names
+    let located: HashMap<&str, &str> = stubs
+        .iter()
+        .filter_map(|(stub_name, stub)| {
+            let code_name = stub.code_name.as_deref()?;
+            let decl = code_name.strip_prefix("probe:").unwrap_or(code_name);
+            declarations
+                .contains_key(decl)
+                .then_some((decl, stub_name.as_str()))
+        })
+        .collect();
+    let located_stubs: HashSet<&str> = located.values().copied().collect();
+
+    // Every dot-separated suffix of a code-name refers to it (`A.B.c`, `B.c`, `c`)
+    let mut by_suffix: HashMap<&str, Vec<&str>> = HashMap::new();
+    for &decl in located.keys() {
+        let mut suffix = decl;
+        loop {
+            by_suffix.entry(suffix).or_default().push(decl);
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => break,
+            }
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for (&decl, &stub_name) in &located {
+        let stub = &stubs[stub_name];
+        let text = LINE_COMMENT_RE.replace_all(&declarations[decl], "");
+
+        let lean_uses: BTreeSet<&str> = IDENTIFIER_RE
+            .find_iter(&text)
+            .filter_map(|ident| by_suffix.get(ident.as_str()))
+            .flatten()
+            .filter(|&&used| used != decl)
+            .map(|used| located[used])
+            .collect();
+        let blueprint_uses: BTreeSet<&str> = stub
+            .spec_dependencies
+            .iter()
+            .chain(stub.proof_dependencies.iter().flatten())
+            .map(String::as_str)
+            .filter(|dep| located_stubs.contains(dep))
+            .collect();
+
+        let missing: Vec<String> = lean_uses
+            .difference(&blueprint_uses)
+            .map(|s| s.to_string())
+            .collect();
+        let unreferenced: Vec<String> = blueprint_uses
+            .difference(&lean_uses)
+            .map(|s| s.to_string())
+            .collect();
+        if !missing.is_empty() || !unreferenced.is_empty() {
+            mismatches.push(UsesMismatch {
+                stub: stub_name.to_string(),
+                missing,
+                unreferenced,
+            });
+        }
+    }
+
+    mismatches.sort_by(|a, b| a.stub.cmp(&b.stub));
+    mismatches
+}
+
+/// Render mismatches as a report that is explicit about being heuristic
+pub(crate) fn format_report(mismatches: &[UsesMismatch]) -> String {
+    let mut out = String::from(
+        "\\uses check (heuristic: textual identifier matching against Lean declarations, \
+         review before editing the blueprint)\n",
+    );
+    if mismatches.is_empty() {
+        out.push_str("No mismatches found\n");
+    }
+    for mismatch in mismatches {
+        let _ = writeln!(out, "\n{}", mismatch.stub);
+        if !mismatch.missing.is_empty() {
+            let _ = writeln!(
+                out,
+                "  used in Lean but missing from \\uses: {}",
+                mismatch.missing.join(", ")
+            );
+        }
+        if !mismatch.unreferenced.is_empty() {
+            let _ = writeln!(
+                out,
+                "  in \\uses but not referenced in Lean: {}",
+                mismatch.unreferenced.join(", ")
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_uses_reports_both_directions() {
+        let lean = r#"namespace Foo
+
+theorem base : True := trivial
+
+theorem other : True := trivial
+
+-- not a use: base_helper, other
+theorem main : True := by
+  have := base
+  exact trivial
+
+end Foo
+"#;
+        let declarations = declaration_texts(&[("Foo.lean".to_string(), lean.to_string())]);
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/base": {"code-name": "probe:Foo.base"},
+                "a.tex/other": {"code-name": "probe:Foo.other"},
+                "a.tex/main": {
+                    "code-name": "probe:Foo.main",
+                    "proof-dependencies": ["a.tex/other", "a.tex/unlocated"]
+                },
+                "a.tex/unlocated": {"code-name": "probe:Elsewhere.lemma"}
+            }"#,
+        )
+        .unwrap();
+
+        let mismatches = check_uses(&stubs, &declarations);
+
+        assert_eq!(
+            mismatches,
+            vec![UsesMismatch {
+                stub: "a.tex/main".to_string(),
+                missing: vec!["a.tex/base".to_string()],
+                unreferenced: vec!["a.tex/other".to_string()],
+            }]
+        );
+        let report = format_report(&mismatches);
+        assert!(report.starts_with("\\uses check (heuristic"));
+        assert!(report.contains("used in Lean but missing from \\uses: a.tex/base"));
+    }
+
+    #[test]
+    fn test_check_uses_consistent() {
+        let lean = "theorem a : True := trivial\ntheorem b : True := a\n";
+        let declarations = declaration_texts(&[("A.lean".to_string(), lean.to_string())]);
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "x.tex/a": {"code-name": "probe:a"},
+                "x.tex/b": {"code-name": "probe:b", "spec-dependencies": ["x.tex/a"]}
+            }"#,
+        )
+        .unwrap();
+
+        assert!(check_uses(&stubs, &declarations).is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+//! Attribution of Lean build diagnostics to blueprint stubs
+//!
+//! Reads the messages `lean --json` prints (one JSON object per line) and
+//! finds the declaration enclosing each error or warning, and through its
+//! name the stubs that reference it. Diagnostics outside any declaration a
+//! stub references are grouped by Lean file.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use super::lean::{DeclarationIndex, LeanModule, Resolution};
+
+/// Severities reported; Lean also emits "information" messages
+const REPORTED_SEVERITIES: &[&str] = &["error", "warning"];
+
+/// Line range from stubs.json
+#[derive(Debug, Deserialize)]
+struct LineRange {
+    #[serde(rename = "lines-start")]
+    lines_start: usize,
+}
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    label: String,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
+    #[serde(rename = "stub-spec")]
+    stub_spec: Option<LineRange>,
+    #[serde(rename = "code-name")]
+    code_name: Option<String>,
+}
+
+/// Position in a Lean file (1-indexed line)
+#[derive(Debug, Deserialize)]
+struct Position {
+    line: usize,
+}
+
+/// One message of `lean --json` (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Diagnostic {
+    #[serde(rename = "fileName")]
+    file_name: String,
+    pos: Position,
+    severity: String,
+    data: String,
+}
+
+/// A diagnostic inside the declaration of a stub
+#[derive(Debug, PartialEq)]
+pub(crate) struct StubProblem {
+    pub(crate) stub: String,
+    pub(crate) label: String,
+    pub(crate) code_name: String,
+    /// Blueprint file and first line of the statement
+    pub(crate) blueprint_location: Option<(String, usize)>,
+    pub(crate) lean_file: String,
+    pub(crate) lean_line: usize,
+    pub(crate) severity: String,
+    pub(crate) message: String,
+}
+
+/// Diagnostics attributed to stubs, and the rest by Lean file
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DiagnosticsReport {
+    pub(crate) problems: Vec<StubProblem>,
+    /// `(line, severity, message)` by Lean file
+    pub(crate) unattributed: BTreeMap<String, Vec<(usize, String, String)>>,
+}
+
+/// Read a diagnostics log: one JSON message per line (other lines, such as
+/// build progress, are skipped), or a JSON array of messages
+pub(crate) fn load_diagnostics(path: &Path) -> Result<Vec<Diagnostic>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read diagnostics {}: {}", path.display(), e))?;
+    if content.trim_start().starts_with('[') {
+        return serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid diagnostics {}: {}", path.display(), e).into());
+    }
+    Ok(content
+        .lines()
+        .filter(|line| line.trim_start().starts_with('{'))
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The module whose path is the longest `/`-separated suffix of `file_name`
+fn find_module<'a>(file_name: &str, modules: &'a [LeanModule]) -> Option<&'a LeanModule> {
+    let file_name = file_name.replace('\\', "/");
+    modules
+        .iter()
+        .filter(|module| {
+            file_name == module.path || file_name.ends_with(&format!("/{}", module.path))
+        })
+        .max_by_key(|module| module.path.len())
+}
+
+/// Attribute each error and warning to the stubs whose declaration encloses it
+pub(crate) fn attribute_diagnostics(
+    stubs: &BTreeMap<String, Stub>,
+    modules: &[LeanModule],
+    diagnostics: &[Diagnostic],
+) -> DiagnosticsReport {
+    let index = DeclarationIndex::new(
+        modules
+            .iter()
+            .flat_map(|module| &module.declarations)
+            .map(|decl| decl.name.as_str()),
+    );
+    let mut by_declaration: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (stub_name, stub) in stubs {
+        let Some(code_name) = &stub.code_name else {
+            continue;
+        };
+        let decl = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        if let Resolution::Exact(name) | Resolution::Suffix(name) = index.resolve(decl) {
+            by_declaration.entry(name).or_default().push(stub_name);
+        }
+    }
+
+    let mut report = DiagnosticsReport::default();
+    for diagnostic in diagnostics {
+        if !REPORTED_SEVERITIES.contains(&diagnostic.severity.as_str()) {
+            continue;
+        }
+        let line = diagnostic.pos.line;
+        let module = find_module(&diagnostic.file_name, modules);
+        let lean_file = module.map_or(diagnostic.file_name.as_str(), |module| &module.path);
+
+        // Innermost declaration around the diagnostic that a stub references
+        let stub_names = module
+            .into_iter()
+            .flat_map(|module| &module.declarations)
+            .filter(|decl| decl.lines.lines_start <= line && line <= decl.lines.lines_end)
+            .filter_map(|decl| {
+                let stub_names = by_declaration.get(decl.name.as_str())?;
+                Some((decl.lines.lines_end - decl.lines.lines_start, stub_names))
+            })
+            .min_by_key(|(length, _)| *length)
+            .map(|(_, stub_names)| stub_names);
+
+        let Some(stub_names) = stub_names else {
+            report
+                .unattributed
+                .entry(lean_file.to_string())
+                .or_default()
+                .push((
+                    line,
+                    diagnostic.severity.clone(),
+                    diagnostic.data.trim().to_string(),
+                ));
+            continue;
+        };
+        for &stub_name in stub_names {
+            let stub = &stubs[stub_name];
+            report.problems.push(StubProblem {
+                stub: stub_name.to_string(),
+                label: stub.label.clone(),
+                code_name: stub.code_name.clone().unwrap_or_default(),
+                blueprint_location: stub
+                    .stub_path
+                    .clone()
+                    .zip(stub.stub_spec.as_ref().map(|spec| spec.lines_start)),
+                lean_file: lean_file.to_string(),
+                lean_line: line,
+                severity: diagnostic.severity.clone(),
+                message: diagnostic.data.trim().to_string(),
+            });
+        }
+    }
+
+    report.problems.sort_by(|a, b| {
+        (&a.stub, &a.lean_file, a.lean_line).cmp(&(&b.stub, &b.lean_file, b.lean_line))
+    });
+    for entries in report.unattributed.values_mut() {
+        entries.sort();
+    }
+    report
+}
+
+/// Render the report
+pub(crate) fn format_report(report: &DiagnosticsReport) -> String {
+    let mut out = format!(
+        "Blueprint items with build problems: {}\n",
+        report
+            .problems
+            .iter()
+            .map(|problem| &problem.stub)
+            .collect::<BTreeSet<_>>()
+            .len()
+    );
+    let mut current: Option<&str> = None;
+    for problem in &report.problems {
+        if current != Some(&problem.stub) {
+            current = Some(&problem.stub);
+            let _ = write!(out, "\n{} ({})\n", problem.label, problem.code_name);
+            if let Some((file, line)) = &problem.blueprint_location {
+                let _ = writeln!(out, "  blueprint: {}:{}", file, line);
+            }
+        }
+        let _ = writeln!(
+            out,
+            "  {} at {}:{}: {}",
+            problem.severity, problem.lean_file, problem.lean_line, problem.message
+        );
+    }
+    if !report.unattributed.is_empty() {
+        out.push_str("\nDiagnostics outside blueprint declarations:\n");
+        for (file, entries) in &report.unattributed {
+            let _ = write!(out, "\n{}\n", file);
+            for (line, severity, message) in entries {
+                let _ = writeln!(out, "  {} at line {}: {}", severity, line, message);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::lean::scan_lean_source;
+
+    #[test]
+    fn test_attribute_diagnostics() {
+        let mut module = scan_lean_source(
+            "namespace Foo\n\ntheorem main : True := by\n  exact bad\n\ntheorem helper : True := trivial\n\nend Foo\n",
+        );
+        module.path = "Foo/Basic.lean".to_string();
+        let stubs: BTreeMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/thm:main": {
+                    "label": "thm:main", "stub-path": "a.tex",
+                    "stub-spec": {"lines-start": 3, "lines-end": 5},
+                    "code-name": "probe:main"
+                }
+            }"#,
+        )
+        .unwrap();
+        let log = [
+            r#"{"severity":"error","pos":{"line":4,"column":8},"fileName":"/home/u/proj/Foo/Basic.lean","data":"unknown identifier 'bad'"}"#,
+            r#"{"severity":"warning","pos":{"line":6,"column":0},"fileName":"/home/u/proj/Foo/Basic.lean","data":"unused variable"}"#,
+            r#"{"severity":"information","pos":{"line":4,"column":0},"fileName":"Foo/Basic.lean","data":"hi"}"#,
+            r#"{"severity":"error","pos":{"line":1,"column":0},"fileName":"Other.lean","data":"parse error"}"#,
+        ];
+        let diagnostics: Vec<Diagnostic> = log
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let report = attribute_diagnostics(&stubs, &[module], &diagnostics);
+
+        assert_eq!(
+            report.problems,
+            vec![StubProblem {
+                stub: "a.tex/thm:main".to_string(),
+                label: "thm:main".to_string(),
+                code_name: "probe:main".to_string(),
+                blueprint_location: Some(("a.tex".to_string(), 3)),
+                lean_file: "Foo/Basic.lean".to_string(),
+                lean_line: 4,
+                severity: "error".to_string(),
+                message: "unknown identifier 'bad'".to_string(),
+            }]
+        );
+        assert_eq!(
+            report.unattributed.keys().collect::<Vec<_>>(),
+            vec!["Foo/Basic.lean", "Other.lean"]
+        );
+        let text = format_report(&report);
+        assert!(text.starts_with("Blueprint items with build problems: 1\n"));
+        assert!(text.contains(
+            "thm:main (probe:main)\n  blueprint: a.tex:3\n  error at Foo/Basic.lean:4: unknown identifier 'bad'\n"
+        ));
+        assert!(text.contains("\nOther.lean\n  error at line 1: parse error\n"));
+    }
+
+    #[test]
+    fn test_load_diagnostics_skips_other_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log");
+        fs::write(
+            &path,
+            "Build started\n{\"severity\":\"error\",\"pos\":{\"line\":1,\"column\":0},\"fileName\":\"A.lean\",\"data\":\"x\"}\n✖ [2/3] Building A\n",
+        )
+        .unwrap();
+
+        let diagnostics = load_diagnostics(&path).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file_name, "A.lean");
+    }
+}
@@ -0,0 +1,532 @@
+//! DOT rendering of how the dependency graph changed between two stubs.json files
+//!
+//! Stubs are matched by label, except that those with a generated label or a
+//! label that is gone are first matched by their `stable-id`, so that the
+//! shifted generated labels of unlabeled environments do not show up as
+//! changes. Nodes are green when added, dashed red when
+//! removed, yellow when their status (as on the status page) changed and gray
+//! otherwise; edges go from dependency to dependent, bold green when added
+//! and dashed red when removed. The same diff can be written as a Markdown
+//! changelog for PR comments.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::output::{parse_map, read_text, write_text};
+use super::status_page::{self, Status, StatusGraph};
+use super::stubify;
+
+/// Layout of the diff-graph output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    /// Graphviz DOT of the combined graph
+    #[default]
+    Dot,
+    /// Markdown changelog, e.g. for a PR comment
+    #[value(name = "md")]
+    Markdown,
+}
+
+/// What happened to a node or edge between the two versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    Added,
+    Removed,
+    /// Status before and after
+    Changed(Status, Status),
+    Unchanged,
+}
+
+/// Nodes by label and edges as `(dependency, dependent)` labels, with their change
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct GraphDiff {
+    nodes: BTreeMap<String, Change>,
+    edges: BTreeMap<(String, String), Change>,
+}
+
+/// Stub entry from stubs.json (only the fields the changelog and the
+/// matching need)
+#[derive(Debug, Deserialize)]
+struct Stub {
+    label: String,
+    #[serde(default)]
+    discussion: Vec<String>,
+    #[serde(rename = "generated-label", default)]
+    generated_label: bool,
+    #[serde(rename = "stable-id")]
+    stable_id: Option<String>,
+}
+
+/// Discussion issue numbers by label
+type Discussions = BTreeMap<String, Vec<String>>;
+
+/// Whether the label is generated, and the `stable-id` of the stub, by label
+type Ids = BTreeMap<String, (bool, Option<String>)>;
+
+/// One side of the diff: the graph of a stubs file, with what is needed to
+/// match its stubs and link their discussions
+#[derive(Debug, Default)]
+pub(crate) struct Version {
+    graph: StatusGraph,
+    discussions: Discussions,
+    ids: Ids,
+}
+
+impl Version {
+    /// The version of the stubs.json `content`
+    fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let stubs: HashMap<String, status_page::Stub> = parse_map(content)?;
+        let mut version = Version {
+            graph: status_page::status_graph(&stubs),
+            ..Version::default()
+        };
+        let stubs: HashMap<String, Stub> = parse_map(content)?;
+        for stub in stubs.into_values() {
+            if !stub.discussion.is_empty() {
+                version
+                    .discussions
+                    .insert(stub.label.clone(), stub.discussion);
+            }
+            version
+                .ids
+                .insert(stub.label, (stub.generated_label, stub.stable_id));
+        }
+        Ok(version)
+    }
+}
+
+/// The version of the stubs file at `path`
+fn load_version(path: &Path) -> Result<Version, Box<dyn Error>> {
+    let content =
+        read_text(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Version::parse(&content)
+}
+
+/// New labels of the stubs of `before` that are matched by ID: those with a
+/// generated label or a label that is gone from `after` get the label of the
+/// stub of `after` with the same `stable-id`, among those with a generated or
+/// new label. An ID shared by several such stubs on either side matches none
+/// of them, and a stub keeps its label rather than take one that an
+/// unmatched stub still has.
+fn relabeled(before: &Ids, after: &Ids) -> HashMap<String, String> {
+    // Labels on each side still to match, by ID
+    let mut candidates: BTreeMap<&str, (Vec<&str>, Vec<&str>)> = BTreeMap::new();
+    for (label, (generated, id)) in before {
+        if let Some(id) = id {
+            if *generated || !after.contains_key(label) {
+                candidates.entry(id).or_default().0.push(label);
+            }
+        }
+    }
+    for (label, (generated, id)) in after {
+        if let Some(id) = id {
+            if *generated || !before.contains_key(label) {
+                candidates.entry(id).or_default().1.push(label);
+            }
+        }
+    }
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for (old, new) in candidates.into_values() {
+        if let ([old], [new]) = (old.as_slice(), new.as_slice()) {
+            renames.insert(old.to_string(), new.to_string());
+        }
+    }
+
+    // Two stubs of `before` must not end up with the same label
+    loop {
+        let taken: Vec<String> = renames
+            .iter()
+            .filter(|(_, new)| before.contains_key(*new) && !renames.contains_key(*new))
+            .map(|(old, _)| old.clone())
+            .collect();
+        if taken.is_empty() {
+            break;
+        }
+        for old in taken {
+            renames.remove(&old);
+        }
+    }
+    renames
+}
+
+/// `graph` with the labels in `renames` replaced
+fn relabel(graph: &StatusGraph, renames: &HashMap<String, String>) -> StatusGraph {
+    let rename = |label: &String| renames.get(label).unwrap_or(label).clone();
+    let (nodes, edges) = graph;
+    (
+        nodes
+            .iter()
+            .map(|(label, status)| (rename(label), *status))
+            .collect(),
+        edges
+            .iter()
+            .map(|(from, to)| (rename(from), rename(to)))
+            .collect(),
+    )
+}
+
+/// Compare the graph before and after, with the stubs whose label changed
+/// matched by ID and shown under their new label
+pub(crate) fn diff_graphs(before: &Version, after: &Version) -> GraphDiff {
+    let renames = relabeled(&before.ids, &after.ids);
+    let (before_nodes, before_edges) = relabel(&before.graph, &renames);
+    let (after_nodes, after_edges) = &after.graph;
+
+    let mut diff = GraphDiff::default();
+    for (label, status) in &before_nodes {
+        let change = match after_nodes.get(label) {
+            None => Change::Removed,
+            Some(after) if after != status => Change::Changed(*status, *after),
+            Some(_) => Change::Unchanged,
+        };
+        diff.nodes.insert(label.clone(), change);
+    }
+    for label in after_nodes.keys() {
+        diff.nodes.entry(label.clone()).or_insert(Change::Added);
+    }
+
+    for edge in before_edges.difference(after_edges) {
+        diff.edges.insert(edge.clone(), Change::Removed);
+    }
+    for edge in after_edges {
+        let change = if before_edges.contains(edge) {
+            Change::Unchanged
+        } else {
+            Change::Added
+        };
+        diff.edges.insert(edge.clone(), change);
+    }
+    diff
+}
+
+/// Escape text for a DOT string literal
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A DOT string literal
+fn quote(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+/// Status name as in the status JSON (e.g. `can_prove`)
+fn status_name(status: Status) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// The diff as a DOT graph, nodes and edges in sorted order
+pub(crate) fn render_dot(diff: &GraphDiff) -> String {
+    let mut out = String::from("digraph blueprint_diff {\n");
+    out.push_str("  node [style=filled, fillcolor=lightgray, color=gray50];\n");
+    out.push_str("  edge [color=gray50];\n");
+
+    for (label, change) in &diff.nodes {
+        let attributes = match change {
+            Change::Added => "fillcolor=palegreen, color=green4".to_string(),
+            Change::Removed => "style=dashed, color=red, fontcolor=red".to_string(),
+            // `\n` is a line break in Graphviz labels
+            Change::Changed(before, after) => format!(
+                "fillcolor=yellow, color=goldenrod, label=\"{}\\n{} -> {}\"",
+                escape(label),
+                status_name(*before),
+                status_name(*after)
+            ),
+            Change::Unchanged => String::new(),
+        };
+        if attributes.is_empty() {
+            let _ = writeln!(out, "  {};", quote(label));
+        } else {
+            let _ = writeln!(out, "  {} [{}];", quote(label), attributes);
+        }
+    }
+
+    for ((from, to), change) in &diff.edges {
+        let attributes = match change {
+            Change::Added => " [color=green4, style=bold]",
+            Change::Removed => " [color=red, style=dashed]",
+            _ => "",
+        };
+        let _ = writeln!(out, "  {} -> {}{};", quote(from), quote(to), attributes);
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// `label` in Markdown inline code
+fn code(label: &str) -> String {
+    let fence = if label.contains('`') { "``" } else { "`" };
+    format!("{fence}{label}{fence}")
+}
+
+/// The discussion links of `label`, to the issues of the `github` repository
+/// when known and as bare issue numbers otherwise
+fn discussion_links(label: &str, discussions: &Discussions, github: Option<&str>) -> String {
+    let Some(issues) = discussions.get(label) else {
+        return String::new();
+    };
+    let links: Vec<String> = issues
+        .iter()
+        .map(|issue| match github {
+            Some(url) => format!(
+                "[#{}]({}/issues/{})",
+                issue,
+                url.trim_end_matches('/'),
+                issue
+            ),
+            None => format!("#{}", issue),
+        })
+        .collect();
+    format!(" ({})", links.join(", "))
+}
+
+/// The diff as a Markdown changelog: new and removed statements, status
+/// changes and dependency changes, each section only when it has entries.
+/// `discussions` are looked up in the later version, and in the earlier one
+/// for removed stubs.
+pub(crate) fn render_markdown(
+    diff: &GraphDiff,
+    discussions: (&Discussions, &Discussions),
+    github: Option<&str>,
+) -> String {
+    let (before, after) = discussions;
+    let mut sections: Vec<(&str, Vec<String>)> = vec![
+        ("New statements", Vec::new()),
+        ("Removed statements", Vec::new()),
+        ("Status changes", Vec::new()),
+        ("Dependency changes", Vec::new()),
+    ];
+    for (label, change) in &diff.nodes {
+        let links = |discussions| discussion_links(label, discussions, github);
+        match change {
+            Change::Added => sections[0]
+                .1
+                .push(format!("{}{}", code(label), links(after))),
+            Change::Removed => sections[1]
+                .1
+                .push(format!("{}{}", code(label), links(before))),
+            Change::Changed(from, to) => sections[2].1.push(format!(
+                "{}: {} \u{2192} {}{}",
+                code(label),
+                code(&status_name(*from)),
+                code(&status_name(*to)),
+                links(after)
+            )),
+            Change::Unchanged => {}
+        }
+    }
+    for ((from, to), change) in &diff.edges {
+        let verb = match change {
+            Change::Added => "now uses",
+            Change::Removed => "no longer uses",
+            _ => continue,
+        };
+        sections[3]
+            .1
+            .push(format!("{} {} {}", code(to), verb, code(from)));
+    }
+
+    let mut out = String::from("## Blueprint changes\n");
+    if sections.iter().all(|(_, entries)| entries.is_empty()) {
+        out.push_str("\nNo semantic changes.\n");
+        return out;
+    }
+    for (title, entries) in sections {
+        if entries.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n### {}\n", title);
+        for entry in entries {
+            let _ = writeln!(out, "- {}", entry);
+        }
+    }
+    out
+}
+
+/// Render how the dependency graph changed from `before` to `after` (both
+/// stubs.json files) to `output`, as DOT or as a Markdown changelog whose
+/// discussion links use the `github` URL in the `.verilib/config.json` of
+/// `project_path`
+pub fn run(
+    before: &str,
+    after: &str,
+    output: &str,
+    format: DiffFormat,
+    project_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let before = load_version(Path::new(before))?;
+    let after = load_version(Path::new(after))?;
+    let diff = diff_graphs(&before, &after);
+    let rendered = match format {
+        DiffFormat::Dot => render_dot(&diff),
+        DiffFormat::Markdown => {
+            let config = stubify::read_config(Path::new(project_path))?;
+            render_markdown(
+                &diff,
+                (&before.discussions, &after.discussions),
+                config.github.as_deref(),
+            )
+        }
+    };
+    write_text(Path::new(output), &rendered)?;
+
+    let nodes = |wanted: fn(&Change) -> bool| diff.nodes.values().filter(|c| wanted(c)).count();
+    let edges = |wanted: Change| diff.edges.values().filter(|c| **c == wanted).count();
+    eprintln!(
+        "{} stubs added, {} removed, {} changed; {} dependencies added, {} removed",
+        nodes(|c| *c == Change::Added),
+        nodes(|c| *c == Change::Removed),
+        nodes(|c| matches!(c, Change::Changed(..))),
+        edges(Change::Added),
+        edges(Change::Removed),
+    );
+    eprintln!("Wrote graph diff to {}", output);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(json: &str) -> Version {
+        Version::parse(json).unwrap()
+    }
+
+    /// The version of the stubs parsed from `files`
+    fn parsed_version(files: &[(&str, &str)]) -> Version {
+        let files: Vec<(String, String)> = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect();
+        let stubs = crate::parse::parse_sources(&files).unwrap().stubs;
+        version(&serde_json::to_string(&stubs).unwrap())
+    }
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/graph-diff")
+            .join(name)
+    }
+
+    #[test]
+    fn test_render_graph_diff() {
+        let before = version(
+            r#"{
+                "a.tex/def": {"label": "def", "spec-ok": true},
+                "a.tex/old": {"label": "old", "spec-ok": true},
+                "a.tex/thm": {
+                    "label": "thm", "spec-ok": true,
+                    "stub-proof": {"lines-start": 1, "lines-end": 2},
+                    "proof-dependencies": ["a.tex/old"]
+                }
+            }"#,
+        );
+        let after = version(
+            r#"{
+                "a.tex/def": {"label": "def", "spec-ok": true},
+                "b.tex/new \"x\"": {"label": "new \"x\"", "spec-ok": false},
+                "a.tex/thm": {
+                    "label": "thm", "spec-ok": true, "proof-ok": true,
+                    "stub-proof": {"lines-start": 1, "lines-end": 2},
+                    "spec-dependencies": ["a.tex/def"]
+                }
+            }"#,
+        );
+
+        let diff = diff_graphs(&before, &after);
+
+        assert_eq!(
+            render_dot(&diff),
+            r#"digraph blueprint_diff {
+  node [style=filled, fillcolor=lightgray, color=gray50];
+  edge [color=gray50];
+  "def";
+  "new \"x\"" [fillcolor=palegreen, color=green4];
+  "old" [style=dashed, color=red, fontcolor=red];
+  "thm" [fillcolor=yellow, color=goldenrod, label="thm\ncan_prove -> fully_proved"];
+  "def" -> "thm" [color=green4, style=bold];
+  "old" -> "thm" [color=red, style=dashed];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_markdown_matches_golden_file() {
+        let before = load_version(&fixture("before.json")).unwrap();
+        let after = load_version(&fixture("after.json")).unwrap();
+        let diff = diff_graphs(&before, &after);
+
+        let markdown = render_markdown(
+            &diff,
+            (&before.discussions, &after.discussions),
+            Some("https://github.com/example/blueprint/"),
+        );
+
+        let expected = std::fs::read_to_string(fixture("changelog.md")).unwrap();
+        assert_eq!(markdown, expected);
+    }
+
+    #[test]
+    fn test_render_markdown_without_semantic_changes() {
+        // Moved lines and files do not change labels, statuses or edges
+        let before =
+            version(r#"{"a.tex/def": {"label": "def", "spec-ok": true, "stub-lines": "3-5"}}"#);
+        let after =
+            version(r#"{"b.tex/def": {"label": "def", "spec-ok": true, "stub-lines": "10-12"}}"#);
+        let diff = diff_graphs(&before, &after);
+        let none = Discussions::new();
+
+        assert_eq!(
+            render_markdown(&diff, (&none, &none), None),
+            "## Blueprint changes\n\nNo semantic changes.\n"
+        );
+    }
+
+    #[test]
+    fn test_generated_labels_are_matched_by_id() {
+        let chapter = r"\begin{lemma}First.\end{lemma}
+\begin{lemma}Second.\end{lemma}
+\begin{theorem}\label{thm}\uses{a0000000001}Main.\end{theorem}
+";
+        let before = parsed_version(&[("a.tex", chapter)]);
+        // The generated labels of the lemmas shift by one, and so does the
+        // \uses that names one
+        let after = parsed_version(&[(
+            "a.tex",
+            &format!(
+                "\\begin{{lemma}}Inserted.\\end{{lemma}}\n{}",
+                chapter.replace("a0000000001", "a0000000002")
+            ),
+        )]);
+        let none = Discussions::new();
+
+        let diff = diff_graphs(&before, &after);
+
+        assert_eq!(
+            render_markdown(&diff, (&none, &none), None),
+            "## Blueprint changes\n\n### New statements\n\n- `a0000000000`\n"
+        );
+
+        // Reordering unlabeled statements swaps their generated labels
+        let reordered = parsed_version(&[(
+            "a.tex",
+            r"\begin{lemma}Second.\end{lemma}
+\begin{lemma}First.\end{lemma}
+\begin{theorem}\label{thm}\uses{a0000000000}Main.\end{theorem}
+",
+        )]);
+        let diff = diff_graphs(&before, &reordered);
+        assert_eq!(
+            render_markdown(&diff, (&none, &none), None),
+            "## Blueprint changes\n\nNo semantic changes.\n"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::lean::{scan_lean_modules, DeclarationIndex, LeanModule, Resolution};
+use super::output::{find_input, parse_map, read_text, write_text};
+use super::{atomize, lake};
+use crate::messages;
+
+/// Atom entry from atoms.json (only fields we need)
+#[derive(Debug, Deserialize)]
+struct Atom {
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// Compute the minimal set of modules to import so that every code-name is available
+///
+/// Each code-name is resolved to the module declaring it (by suffix when it is
+/// not fully qualified); modules already imported (transitively) by another
+/// selected module are dropped. Returns the sorted module list and the
+/// code-names that could not be resolved, with their candidates if ambiguous.
+fn minimal_imports(
+    code_names: &BTreeSet<String>,
+    modules: &HashMap<String, LeanModule>,
+) -> (Vec<String>, Vec<(String, Vec<String>)>) {
+    let declared_in: HashMap<&str, &str> = modules
+        .iter()
+        .flat_map(|(module, info)| {
+            info.declarations
+                .iter()
+                .map(move |decl| (decl.name.as_str(), module.as_str()))
+        })
+        .collect();
+
+    let index = DeclarationIndex::new(declared_in.keys().copied());
+
+    let mut needed: BTreeSet<&str> = BTreeSet::new();
+    let mut unresolved = Vec::new();
+    for code_name in code_names {
+        let decl = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        match index.resolve(decl) {
+            Resolution::Exact(name) | Resolution::Suffix(name) => {
+                needed.insert(declared_in[name]);
+            }
+            Resolution::Ambiguous(candidates) => unresolved.push((
+                code_name.clone(),
+                candidates.into_iter().map(str::to_string).collect(),
+            )),
+            Resolution::Missing => unresolved.push((code_name.clone(), Vec::new())),
+        }
+    }
+
+    // All modules reachable through imports of a given module
+    let reachable = |start: &str| -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<&str> = modules
+            .get(start)
+            .map(|m| m.imports.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        while let Some(module) = stack.pop() {
+            if seen.insert(module.to_string()) {
+                if let Some(info) = modules.get(module) {
+                    stack.extend(info.imports.iter().map(String::as_str));
+                }
+            }
+        }
+        seen
+    };
+
+    let covered: HashSet<String> = needed.iter().flat_map(|m| reachable(m)).collect();
+    let imports = needed
+        .into_iter()
+        .filter(|module| !covered.contains(*module))
+        .map(str::to_string)
+        .collect();
+
+    (imports, unresolved)
+}
+
+/// Generate a Lean file importing every module that declares a blueprint code-name
+///
+/// Without `lean_src_root`, the Lean source roots are read from the Lake
+/// configuration of the project.
+pub fn run(
+    project_path: &str,
+    lean_src_root: Option<&str>,
+    output: &str,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let atoms_path = find_input(&project_path.join(".verilib").join("atoms.json"));
+
+    // Check if atoms.json exists, generate if needed
+    if !atoms_path.exists() {
+        eprintln!("atoms.json not found, running atomize...");
+
+        atomize::run(
+            project_path.to_str().ok_or("Invalid project path")?,
+            atoms_path.to_str().ok_or("Invalid atoms path")?,
+            false,
+            &atomize::AtomizeOptions::default(),
+        )?;
+    }
+
+    let atoms_content = read_text(&atoms_path)?;
+    let atoms: HashMap<String, Atom> = parse_map(&atoms_content)?;
+
+    let code_names: BTreeSet<String> = atoms
+        .iter()
+        .flat_map(|(code_name, atom)| std::iter::once(code_name).chain(&atom.dependencies))
+        .cloned()
+        .collect();
+
+    let lean_src_roots = lake::resolve_lean_roots(project_path, lean_src_root);
+    let modules = scan_lean_modules(&lean_src_roots)?;
+    let (imports, unresolved) = minimal_imports(&code_names, &modules);
+
+    for (code_name, candidates) in &unresolved {
+        if candidates.is_empty() {
+            messages::warning(None, &format!("no declaration found for {code_name}"));
+        } else {
+            messages::warning(
+                None,
+                &format!(
+                    "ambiguous declaration {code_name}, candidates: {}",
+                    candidates.join(", ")
+                ),
+            );
+        }
+    }
+
+    let output_path = if output.ends_with(".lean") {
+        PathBuf::from(output)
+    } else {
+        PathBuf::from(format!("{output}.lean"))
+    };
+    if let Some(parent) = output_path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let mut content: String = imports.iter().map(|m| format!("import {m}\n")).collect();
+    if content.is_empty() {
+        content.push('\n');
+    }
+    write_text(&output_path, &content)?;
+
+    eprintln!(
+        "Wrote {} imports to {}",
+        imports.len(),
+        output_path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::lean::LeanDeclaration;
+    use crate::parse::LineRange;
+
+    fn module(declarations: &[&str], imports: &[&str]) -> LeanModule {
+        LeanModule {
+            path: String::new(),
+            declarations: declarations
+                .iter()
+                .map(|name| LeanDeclaration {
+                    name: name.to_string(),
+                    lines: LineRange {
+                        lines_start: 1,
+                        lines_end: 1,
+                    },
+                    doc: None,
+                    keyword: "theorem".to_string(),
+                    private: false,
+                })
+                .collect(),
+            imports: imports.iter().map(|s| s.to_string()).collect(),
+            annotations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_minimal_imports_drops_transitive() {
+        let mut modules = HashMap::new();
+        modules.insert("A".to_string(), module(&["a"], &[]));
+        modules.insert("B".to_string(), module(&["b"], &["A"]));
+        modules.insert("C".to_string(), module(&["Foo.c"], &[]));
+        modules.insert("D".to_string(), module(&["X.d", "Y.d"], &[]));
+
+        let code_names: BTreeSet<String> =
+            ["probe:a", "probe:b", "probe:c", "probe:d", "probe:missing"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+        let (imports, unresolved) = minimal_imports(&code_names, &modules);
+
+        assert_eq!(imports, vec!["B", "C"]);
+        assert_eq!(
+            unresolved,
+            vec![
+                (
+                    "probe:d".to_string(),
+                    vec!["X.d".to_string(), "Y.d".to_string()]
+                ),
+                ("probe:missing".to_string(), vec![]),
+            ]
+        );
+    }
+}
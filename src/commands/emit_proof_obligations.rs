@@ -0,0 +1,258 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use super::output::{parse_map, read_text, write_text};
+use super::stubify;
+
+/// Line range from stubs.json
+#[derive(Debug, Deserialize)]
+struct LineRange {
+    #[serde(rename = "lines-start")]
+    lines_start: usize,
+}
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    label: String,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
+    #[serde(rename = "stub-spec")]
+    stub_spec: Option<LineRange>,
+    #[serde(rename = "stub-proof")]
+    stub_proof: Option<LineRange>,
+    #[serde(rename = "env-options")]
+    env_options: Option<String>,
+    #[serde(rename = "spec-ok")]
+    spec_ok: Option<bool>,
+    #[serde(rename = "mathlib-ok")]
+    mathlib_ok: Option<bool>,
+    #[serde(rename = "spec-dependencies", default)]
+    spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-ok")]
+    proof_ok: Option<bool>,
+    #[serde(rename = "proof-dependencies")]
+    proof_dependencies: Option<Vec<String>>,
+}
+
+impl Stub {
+    /// A stated (`\leanok`) result with a proof that is not yet formalized
+    fn is_obligation(&self) -> bool {
+        self.spec_ok == Some(true) && self.stub_proof.is_some() && self.proof_ok != Some(true)
+    }
+
+    /// Nothing left to do: in Mathlib, proved, or a formalized statement
+    /// without a proof (e.g. a definition), as in `parse::derive_readiness`
+    fn is_verified(&self) -> bool {
+        self.mathlib_ok == Some(true)
+            || match self.stub_proof {
+                Some(_) => self.proof_ok == Some(true),
+                None => self.spec_ok == Some(true),
+            }
+    }
+
+    fn dependencies(&self) -> impl Iterator<Item = &String> {
+        self.spec_dependencies
+            .iter()
+            .chain(self.proof_dependencies.iter().flatten())
+    }
+}
+
+/// Stub names ordered so that every stub comes after its dependencies
+///
+/// Ties are broken by stub name so the order is deterministic; stubs on a
+/// dependency cycle are appended in name order.
+fn topological_order(stubs: &HashMap<String, Stub>) -> Vec<&str> {
+    let mut remaining: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, stub) in stubs {
+        let deps: BTreeSet<&str> = stub
+            .dependencies()
+            .map(String::as_str)
+            .filter(|dep| stubs.contains_key(*dep))
+            .collect();
+        remaining.insert(name, deps.len());
+        for dep in deps {
+            dependents.entry(dep).or_default().push(name);
+        }
+    }
+
+    let mut ready: BTreeSet<&str> = remaining
+        .iter()
+        .filter(|(_, &count)| count == 0)
+        .map(|(&name, _)| name)
+        .collect();
+    let mut order = Vec::with_capacity(stubs.len());
+    while let Some(name) = ready.pop_first() {
+        remaining.remove(name);
+        order.push(name);
+        for &dependent in dependents.get(name).into_iter().flatten() {
+            let count = remaining.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+
+    // Whatever is left is on (or behind) a cycle
+    order.extend(remaining.keys());
+    order
+}
+
+/// Render the proof obligations as a human-readable list
+pub(crate) fn format_obligations(stubs: &HashMap<String, Stub>) -> String {
+    let mut out = String::new();
+    let obligations: Vec<&str> = topological_order(stubs)
+        .into_iter()
+        .filter(|name| stubs[*name].is_obligation())
+        .collect();
+
+    let _ = writeln!(out, "{} proof obligations", obligations.len());
+    for (i, name) in obligations.iter().enumerate() {
+        let stub = &stubs[*name];
+        let _ = write!(out, "\n{}. {}", i + 1, stub.label);
+        if let Some(display_name) = &stub.env_options {
+            let _ = write!(out, " ({display_name})");
+        }
+        out.push('\n');
+
+        let path = stub.stub_path.as_deref().unwrap_or("?");
+        match &stub.stub_spec {
+            Some(spec) => {
+                let _ = writeln!(out, "   {}:{}", path, spec.lines_start);
+            }
+            None => {
+                let _ = writeln!(out, "   {path}");
+            }
+        }
+
+        let (verified, pending): (Vec<&String>, Vec<&String>) = stub
+            .proof_dependencies
+            .iter()
+            .flatten()
+            .partition(|dep| stubs.get(*dep).is_some_and(Stub::is_verified));
+        let labels = |deps: &[&String]| -> String {
+            if deps.is_empty() {
+                return "none".to_string();
+            }
+            deps.iter()
+                .map(|dep| stubs.get(*dep).map_or(dep.as_str(), |s| s.label.as_str()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let _ = writeln!(out, "   verified dependencies: {}", labels(&verified));
+        let _ = writeln!(out, "   pending dependencies: {}", labels(&pending));
+    }
+
+    out
+}
+
+/// List stubs whose statement is formalized but whose proof is not, in
+/// dependency order
+pub fn run(project_path: &str, output: &str, regenerate_stubs: bool) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+
+    let report = format_obligations(&stubs);
+
+    let output = Path::new(output);
+    write_text(output, &report)?;
+
+    eprintln!("Wrote proof obligations to {}", output.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stubs_from(json: &str) -> HashMap<String, Stub> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// The stubs parsed from a single `a.tex`
+    fn parsed_stubs(content: &str) -> HashMap<String, Stub> {
+        let parsed =
+            crate::parse::parse_sources(&[("a.tex".to_string(), content.to_string())]).unwrap();
+        serde_json::from_value(serde_json::to_value(&parsed.stubs).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_obligations_in_topological_order() {
+        let stubs = parsed_stubs(
+            r"\begin{definition}\label{def}\leanok
+A definition.
+\end{definition}
+
+\begin{lemma}\label{done}\leanok
+Already proved.
+\end{lemma}
+\begin{proof}\leanok
+Done.
+\end{proof}
+
+\begin{lemma}\label{lem}\leanok\uses{def}
+A lemma.
+\end{lemma}
+\begin{proof}
+Not formalized.
+\end{proof}
+
+\begin{theorem}[Main theorem]\label{main}\leanok
+The main result.
+\end{theorem}
+\begin{proof}\uses{lem, def, done}
+Not formalized either.
+\end{proof}
+
+\begin{lemma}\label{todo}
+Not stated.
+\end{lemma}
+\begin{proof}
+Nothing yet.
+\end{proof}
+",
+        );
+
+        let report = format_obligations(&stubs);
+        assert_eq!(
+            report,
+            "2 proof obligations
+
+1. lem
+   a.tex:12
+   verified dependencies: none
+   pending dependencies: none
+
+2. main (Main theorem)
+   a.tex:19
+   verified dependencies: def, done
+   pending dependencies: lem
+"
+        );
+    }
+
+    #[test]
+    fn test_topological_order_with_cycle() {
+        let stubs = stubs_from(
+            r#"{
+                "x": {"label": "x", "spec-dependencies": ["y"]},
+                "y": {"label": "y", "spec-dependencies": ["x"]},
+                "z": {"label": "z", "spec-dependencies": ["missing"]}
+            }"#,
+        );
+
+        assert_eq!(topological_order(&stubs), vec!["z", "x", "y"]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Export of the blueprint database to SQLite
+//!
+//! The stubs are parsed in memory with the options stubs.json was last
+//! generated with, and written to four tables: `stubs`, `labels` (every
+//! label, aliases included), `dependencies` and `proofs`. Exporting drops and
+//! recreates the tables, so the same file can be exported to repeatedly.
+
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use super::stubify;
+use super::verify;
+use crate::parse::{ParsedBlueprint, Stub};
+
+/// Tables and indexes of the database, recreated on every export
+const SCHEMA: &str = "
+DROP TABLE IF EXISTS proofs;
+DROP TABLE IF EXISTS dependencies;
+DROP TABLE IF EXISTS labels;
+DROP TABLE IF EXISTS stubs;
+
+CREATE TABLE stubs (
+    stub_name TEXT PRIMARY KEY,
+    label TEXT NOT NULL,
+    stable_id TEXT NOT NULL,
+    type TEXT,
+    path TEXT,
+    lines_start INTEGER,
+    lines_end INTEGER,
+    proof_lines_start INTEGER,
+    proof_lines_end INTEGER,
+    title TEXT,
+    theorem_number TEXT,
+    spec_ok INTEGER,
+    proof_ok INTEGER,
+    mathlib_ok INTEGER,
+    not_ready INTEGER,
+    code_name TEXT,
+    lean_path TEXT
+);
+
+CREATE TABLE labels (
+    alias TEXT PRIMARY KEY,
+    stub_name TEXT NOT NULL REFERENCES stubs(stub_name)
+);
+
+CREATE TABLE dependencies (
+    from_stub TEXT NOT NULL REFERENCES stubs(stub_name),
+    to_stub TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('spec', 'proof'))
+);
+CREATE INDEX dependencies_from ON dependencies(from_stub);
+CREATE INDEX dependencies_to ON dependencies(to_stub);
+
+CREATE TABLE proofs (
+    code_name TEXT PRIMARY KEY,
+    verified INTEGER NOT NULL,
+    status TEXT NOT NULL
+);
+";
+
+/// Proof entries of `stubs`, as `verify` writes them to proofs.json
+fn proofs(stubs: &HashMap<String, Stub>) -> Result<HashMap<String, verify::Proof>, Box<dyn Error>> {
+    let stubs: HashMap<String, verify::Stub> =
+        serde_json::from_value(serde_json::to_value(stubs)?)?;
+    Ok(verify::build_proofs(&stubs))
+}
+
+/// Write the blueprint to the database at `path`, replacing earlier exports
+pub(crate) fn write_database(
+    blueprint: &ParsedBlueprint,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+
+    let mut stubs: Vec<(&String, &Stub)> = blueprint.stubs.iter().collect();
+    stubs.sort_by_key(|(stub_name, _)| *stub_name);
+    {
+        let mut insert_stub = tx.prepare(
+            "INSERT INTO stubs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        )?;
+        let mut insert_dependency = tx.prepare("INSERT INTO dependencies VALUES (?1, ?2, ?3)")?;
+        for (stub_name, stub) in &stubs {
+            insert_stub.execute(params![
+                stub_name,
+                stub.label,
+                stub.stable_id,
+                stub.stub_type,
+                stub.stub_path,
+                stub.stub_spec.as_ref().map(|lines| lines.lines_start),
+                stub.stub_spec.as_ref().map(|lines| lines.lines_end),
+                stub.stub_proof.as_ref().map(|lines| lines.lines_start),
+                stub.stub_proof.as_ref().map(|lines| lines.lines_end),
+                stub.env_options,
+                stub.theorem_number,
+                stub.spec_ok,
+                stub.proof_ok,
+                stub.mathlib_ok,
+                stub.not_ready,
+                stub.code_name,
+                stub.lean_path,
+            ])?;
+            for dep in &stub.spec_dependencies {
+                insert_dependency.execute(params![stub_name, dep, "spec"])?;
+            }
+            for dep in stub.proof_dependencies.iter().flatten() {
+                insert_dependency.execute(params![stub_name, dep, "proof"])?;
+            }
+        }
+
+        let mut insert_label = tx.prepare("INSERT INTO labels VALUES (?1, ?2)")?;
+        for (alias, stub_name) in &blueprint.labels {
+            insert_label.execute(params![alias, stub_name])?;
+        }
+
+        let mut insert_proof = tx.prepare("INSERT INTO proofs VALUES (?1, ?2, ?3)")?;
+        for (code_name, proof) in proofs(&blueprint.stubs)? {
+            insert_proof.execute(params![code_name, proof.verified, proof.status])?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Export the blueprint of `project_path` to the SQLite database at `output`
+pub fn run_sqlite(project_path: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+    let blueprint = stubify::build_recorded(project_path, &stubs_path)?;
+
+    let output = Path::new(output);
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    write_database(&blueprint, output)?;
+
+    eprintln!(
+        "Exported {} stubs and {} labels to {}",
+        blueprint.stubs.len(),
+        blueprint.labels.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{definition}\label{def:magma-alias}\label{def:magma}\lean{Magma}\leanok
+A magma.
+\end{definition}
+\begin{theorem}\label{thm:main}\lean{Magma.main}\uses{def:magma}
+Main.
+\end{theorem}
+\begin{proof}\leanok\uses{def:magma}
+\end{proof}
+",
+        )
+        .unwrap();
+        let output = dir.path().join("out").join("blueprint.sqlite");
+        let project = dir.path().to_str().unwrap();
+
+        // Exporting twice replaces the tables instead of failing or duplicating rows
+        run_sqlite(project, output.to_str().unwrap()).unwrap();
+        run_sqlite(project, output.to_str().unwrap()).unwrap();
+
+        let conn = Connection::open(&output).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("stubs"), 2);
+        assert_eq!(count("labels"), 3);
+        assert_eq!(count("dependencies"), 2);
+
+        // Labels of the dependencies of the theorem, by kind
+        let mut statement = conn
+            .prepare(
+                "SELECT d.kind, s.label FROM stubs t
+                 JOIN dependencies d ON d.from_stub = t.stub_name
+                 JOIN stubs s ON s.stub_name = d.to_stub
+                 WHERE t.label = 'thm:main' ORDER BY d.kind",
+            )
+            .unwrap();
+        let deps: Vec<(String, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("proof".to_string(), "def:magma".to_string()),
+                ("spec".to_string(), "def:magma".to_string()),
+            ]
+        );
+
+        // Proof status of the stub an alias points to
+        let (code_name, status): (String, String) = conn
+            .query_row(
+                "SELECT p.code_name, p.status FROM labels l
+                 JOIN stubs s ON s.stub_name = l.stub_name
+                 JOIN proofs p ON p.code_name = s.code_name
+                 WHERE l.alias = 'def:magma-alias'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(code_name, "probe:Magma");
+        assert_eq!(status, "sorries");
+    }
+}
@@ -0,0 +1,239 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Environment types cycled through by the generator
+const ENV_TYPES: &[&str] = &["definition", "lemma", "theorem"];
+
+/// Number of preceding environments considered as dependencies in a random DAG
+pub(crate) const DAG_WINDOW: usize = 64;
+
+/// Shape of the dependency graph between generated environments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Structure {
+    /// Each environment depends on the previous one
+    Chain,
+    /// Environment `k` depends on environment `(k - 1) / 2`
+    Tree,
+    /// Each of the previous `DAG_WINDOW` environments is a dependency with
+    /// probability `density`
+    Dag,
+}
+
+impl std::str::FromStr for Structure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chain" => Ok(Structure::Chain),
+            "tree" => Ok(Structure::Tree),
+            "dag" => Ok(Structure::Dag),
+            _ => Err(format!(
+                "unknown structure '{s}' (expected chain, tree or dag)"
+            )),
+        }
+    }
+}
+
+/// Parameters of a synthetic blueprint; ratios are probabilities in `0.0..=1.0`
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    pub files: usize,
+    pub envs_per_file: usize,
+    /// Environments with an explicit `\label`
+    pub label_ratio: f64,
+    /// Environments that declare their dependencies with `\uses`
+    pub uses_ratio: f64,
+    /// Environments with a `\lean` code-name
+    pub lean_ratio: f64,
+    /// Statements (and proofs) marked `\leanok`
+    pub leanok_ratio: f64,
+    /// Environments followed by a proof
+    pub proof_ratio: f64,
+    pub structure: Structure,
+    /// Edge probability for `Structure::Dag`
+    pub density: f64,
+    pub seed: u64,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        FixtureSpec {
+            files: 10,
+            envs_per_file: 100,
+            label_ratio: 1.0,
+            uses_ratio: 1.0,
+            lean_ratio: 0.8,
+            leanok_ratio: 0.5,
+            proof_ratio: 0.7,
+            structure: Structure::Dag,
+            density: 0.05,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64: small, deterministic and good enough for fixtures
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// `true` with probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        unit < p
+    }
+}
+
+/// Generate the contents of `blueprint/src` as `(relative_path, content)` pairs
+pub fn generate_sources(spec: &FixtureSpec) -> Vec<(String, String)> {
+    let mut rng = Rng(spec.seed);
+    let total = spec.files * spec.envs_per_file;
+    let labeled: Vec<bool> = (0..total).map(|_| rng.chance(spec.label_ratio)).collect();
+
+    let mut files = Vec::with_capacity(spec.files);
+    for file in 0..spec.files {
+        let mut content = String::new();
+        for k in file * spec.envs_per_file..(file + 1) * spec.envs_per_file {
+            let env_type = ENV_TYPES[k % ENV_TYPES.len()];
+
+            // Only labeled environments can be referenced
+            let candidates: Vec<usize> = match spec.structure {
+                Structure::Chain => k.checked_sub(1).into_iter().collect(),
+                Structure::Tree => k.checked_sub(1).map(|p| p / 2).into_iter().collect(),
+                Structure::Dag => (k.saturating_sub(DAG_WINDOW)..k)
+                    .filter(|_| rng.chance(spec.density))
+                    .collect(),
+            };
+            let deps: Vec<String> = candidates
+                .into_iter()
+                .filter(|&j| labeled[j])
+                .map(|j| format!("l{j}"))
+                .collect();
+
+            let leanok = rng.chance(spec.leanok_ratio);
+            let _ = write!(content, "\\begin{{{env_type}}}");
+            if labeled[k] {
+                let _ = write!(content, "\\label{{l{k}}}");
+            }
+            if rng.chance(spec.lean_ratio) {
+                let _ = write!(content, "\\lean{{Fixture.decl{k}}}");
+            }
+            if leanok {
+                content.push_str("\\leanok");
+            }
+            content.push('\n');
+            if !deps.is_empty() && rng.chance(spec.uses_ratio) {
+                let _ = writeln!(content, "  \\uses{{{}}}", deps.join(", "));
+            }
+            let _ = writeln!(content, "  Statement {k}.\n\\end{{{env_type}}}");
+
+            if rng.chance(spec.proof_ratio) {
+                content.push_str("\\begin{proof}");
+                if leanok {
+                    content.push_str("\\leanok");
+                }
+                content.push_str("\n  Proof.\n\\end{proof}\n");
+            }
+        }
+        files.push((format!("chapter/ch{file}.tex"), content));
+    }
+
+    files
+}
+
+/// Write a synthetic blueprint project to `output_dir`
+pub fn run(output_dir: &str, spec: &FixtureSpec) -> Result<(), Box<dyn Error>> {
+    let blueprint_src = Path::new(output_dir).join("blueprint").join("src");
+
+    let files = generate_sources(spec);
+    for (relative_path, content) in &files {
+        let path = blueprint_src.join(relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+
+    eprintln!(
+        "Wrote {} environments in {} files to {}",
+        spec.files * spec.envs_per_file,
+        files.len(),
+        blueprint_src.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::stubify;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_generate_sources_is_deterministic() {
+        let spec = FixtureSpec {
+            files: 3,
+            envs_per_file: 20,
+            ..FixtureSpec::default()
+        };
+        assert_eq!(generate_sources(&spec), generate_sources(&spec));
+
+        let other_seed = FixtureSpec { seed: 1, ..spec };
+        assert_ne!(generate_sources(&other_seed), generate_sources(&spec));
+    }
+
+    #[test]
+    fn test_chain_dependencies() {
+        let spec = FixtureSpec {
+            files: 1,
+            envs_per_file: 3,
+            label_ratio: 1.0,
+            uses_ratio: 1.0,
+            structure: Structure::Chain,
+            ..FixtureSpec::default()
+        };
+        let files = generate_sources(&spec);
+        let parsed = crate::parse::parse_sources(&files).unwrap();
+
+        assert_eq!(parsed.stubs.len(), 3);
+        assert_eq!(
+            parsed.stubs["chapter/ch0.tex/l2"].spec_dependencies,
+            vec!["chapter/ch0.tex/l1"]
+        );
+    }
+
+    #[test]
+    fn test_stress_stubify_10k_environments() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = FixtureSpec {
+            files: 100,
+            envs_per_file: 100,
+            label_ratio: 0.9,
+            lean_ratio: 1.0,
+            ..FixtureSpec::default()
+        };
+        run(dir.path().to_str().unwrap(), &spec).unwrap();
+
+        let start = Instant::now();
+        let parsed = stubify::parse_project(dir.path()).unwrap();
+        let elapsed = start.elapsed();
+
+        // Every environment becomes exactly one stub: generated labels for
+        // unlabeled environments must not collide with real ones
+        assert_eq!(parsed.stubs.len(), 10_000);
+        assert!(
+            elapsed < Duration::from_secs(60),
+            "stubify took {elapsed:?} on 10k environments"
+        );
+    }
+}
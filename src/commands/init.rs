@@ -0,0 +1,373 @@
+//! Starter blueprint for a project that was formalized first
+//!
+//! Every public declaration of the Lean sources becomes a statement with its
+//! `\lean{...}` name, `\leanok`, a label derived from the name and its
+//! docstring as the text, in one chapter per Lean file or namespace.
+//! Theorems and lemmas keep their keyword as the environment type, axioms
+//! become assumptions and other declarations definitions; instances are left
+//! out. `content.tex` inputs the chapters.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use super::lean::{self, LeanDeclaration};
+use super::output::write_text;
+
+/// How declarations are grouped into chapters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// One chapter per Lean file
+    #[default]
+    File,
+    /// One chapter per namespace
+    Namespace,
+}
+
+/// Group of the declarations outside any namespace
+const ROOT_NAMESPACE: &str = "_root_";
+
+/// Generated blueprint files, relative to `blueprint/src`, and what went into them
+#[derive(Debug, Default)]
+pub(crate) struct Skeleton {
+    pub(crate) files: Vec<(String, String)>,
+    pub(crate) statements: usize,
+    /// Declarations left out for being private
+    pub(crate) private: usize,
+    /// Instances, which are left out
+    pub(crate) instances: usize,
+}
+
+/// Environment type and label prefix for a declaration keyword, `None` for
+/// declarations left out
+fn environment(keyword: &str) -> Option<(&'static str, &'static str)> {
+    match keyword {
+        "theorem" => Some(("theorem", "thm")),
+        "lemma" => Some(("lemma", "lem")),
+        "axiom" => Some(("assumption", "ax")),
+        "instance" => None,
+        _ => Some(("definition", "def")),
+    }
+}
+
+/// Escape text for LaTeX outside math mode
+fn escape_latex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '_' | '&' | '%' | '#' | '$' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// LaTeX for a Markdown docstring: `$...$` math is kept, code spans become
+/// `\texttt`, and the characters that would break the text are escaped
+fn docstring_latex(doc: &str) -> String {
+    let mut latex = String::with_capacity(doc.len());
+    let mut in_math = false;
+    let mut chars = doc.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                in_math = !in_math;
+                latex.push(c);
+            }
+            '`' if !in_math => {
+                let code: String = chars.by_ref().take_while(|&c| c != '`').collect();
+                let _ = write!(latex, "\\texttt{{{}}}", escape_latex(&code));
+            }
+            c if in_math => latex.push(c),
+            c => latex.push_str(&escape_latex(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    latex
+}
+
+/// Namespace of a fully qualified name (`Foo.Bar.baz` -> `Foo.Bar`)
+fn namespace(name: &str) -> &str {
+    name.rsplit_once('.')
+        .map_or(ROOT_NAMESPACE, |(prefix, _)| prefix)
+}
+
+/// Source of one chapter
+fn chapter(title: &str, source: &str, declarations: &[&LeanDeclaration]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "% Generated by probe-blueprint init from {}", source);
+    let _ = writeln!(out, "\\chapter{{{}}}", escape_latex(title));
+    for declaration in declarations {
+        let Some((env, prefix)) = environment(&declaration.keyword) else {
+            continue;
+        };
+        let _ = writeln!(
+            out,
+            "\n\\begin{{{env}}}\\label{{{prefix}:{name}}}\\lean{{{name}}}\\leanok",
+            name = declaration.name
+        );
+        match &declaration.doc {
+            Some(doc) => {
+                for line in docstring_latex(doc).lines() {
+                    let _ = writeln!(out, "  {}", line);
+                }
+            }
+            None => {
+                let _ = writeln!(out, "  % TODO: state {} informally", declaration.name);
+            }
+        }
+        let _ = writeln!(out, "\\end{{{}}}", env);
+    }
+    out
+}
+
+/// The blueprint files for the declarations of `modules` (keyed by module
+/// name), chapters in sorted order and statements in source order
+pub(crate) fn skeleton(
+    modules: &BTreeMap<String, lean::LeanModule>,
+    group_by: GroupBy,
+) -> Skeleton {
+    let mut skeleton = Skeleton::default();
+    let mut seen = HashSet::new();
+    // Title, source description and declarations of each chapter
+    let mut groups: BTreeMap<&str, (String, Vec<&LeanDeclaration>)> = BTreeMap::new();
+    for (module_name, module) in modules {
+        for declaration in &module.declarations {
+            if declaration.private {
+                skeleton.private += 1;
+                continue;
+            }
+            if environment(&declaration.keyword).is_none() {
+                skeleton.instances += 1;
+                continue;
+            }
+            if !seen.insert(declaration.name.as_str()) {
+                continue;
+            }
+            let (group, source) = match group_by {
+                GroupBy::File => (module_name.as_str(), module.path.clone()),
+                GroupBy::Namespace => {
+                    let namespace = namespace(&declaration.name);
+                    (namespace, format!("namespace {}", namespace))
+                }
+            };
+            groups
+                .entry(group)
+                .or_insert_with(|| (source, Vec::new()))
+                .1
+                .push(declaration);
+            skeleton.statements += 1;
+        }
+    }
+
+    let mut content = String::from("% Generated by probe-blueprint init\n");
+    for (group, (source, declarations)) in &groups {
+        // Lean names have no `-`, so the file names cannot collide
+        let file = format!("chapter/{}", group.replace('.', "-"));
+        let _ = writeln!(content, "\\input{{{}}}", file);
+        skeleton.files.push((
+            format!("{}.tex", file),
+            chapter(group, source, declarations),
+        ));
+    }
+    skeleton.files.push(("content.tex".to_string(), content));
+    skeleton
+}
+
+/// Generate a starter blueprint under `blueprint/src` of `project_path` from
+/// the Lean sources under `lean_src_root`
+pub fn run(
+    project_path: &str,
+    lean_src_root: &str,
+    group_by: GroupBy,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let modules: BTreeMap<String, lean::LeanModule> =
+        lean::scan_lean_modules(&[PathBuf::from(lean_src_root)])?
+            .into_iter()
+            .collect();
+    let skeleton = skeleton(&modules, group_by);
+    if skeleton.statements == 0 {
+        return Err(format!("No public declarations found under {}", lean_src_root).into());
+    }
+
+    let blueprint_src = Path::new(project_path).join("blueprint").join("src");
+    if !force {
+        let existing: Vec<String> = skeleton
+            .files
+            .iter()
+            .map(|(file, _)| blueprint_src.join(file))
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string())
+            .collect();
+        if !existing.is_empty() {
+            return Err(format!(
+                "Refusing to overwrite existing files (use --force): {}",
+                existing.join(", ")
+            )
+            .into());
+        }
+    }
+    for (file, content) in &skeleton.files {
+        write_text(&blueprint_src.join(file), content)?;
+    }
+
+    eprintln!(
+        "Wrote {} statements in {} chapters to {}",
+        skeleton.statements,
+        skeleton.files.len() - 1,
+        blueprint_src.display()
+    );
+    if skeleton.private + skeleton.instances > 0 {
+        eprintln!(
+            "Left out {} private declarations and {} instances",
+            skeleton.private, skeleton.instances
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::stubify;
+    use std::fs;
+
+    const BASIC: &str = r#"namespace Magma
+
+/-- A binary operation on `α`, written $a \cdot b$ (50% of the time). -/
+def op_of (α : Type) : Type := α → α → α
+
+theorem assoc_like : True := trivial
+
+private lemma helper : True := trivial
+
+instance instInhabited : Inhabited Nat := ⟨0⟩
+
+end Magma
+
+axiom choice_like : True
+"#;
+
+    #[test]
+    fn test_docstring_latex() {
+        assert_eq!(
+            docstring_latex("Uses `Nat.add_comm` on $a_1 + b$ & 50% #1"),
+            r"Uses \texttt{Nat.add\_comm} on $a_1 + b$ \& 50\% \#1"
+        );
+        assert_eq!(
+            docstring_latex(r"$x^{-1}$ is x^-1, ~x or \{x}"),
+            r"$x^{-1}$ is x\textasciicircum{}-1, \textasciitilde{}x or \textbackslash{}\{x\}"
+        );
+    }
+
+    #[test]
+    fn test_skeleton_by_namespace() {
+        let mut module = lean::scan_lean_source(BASIC);
+        module.path = "Magma/Basic.lean".to_string();
+        let modules = BTreeMap::from([("Magma.Basic".to_string(), module)]);
+
+        let skeleton = skeleton(&modules, GroupBy::Namespace);
+
+        assert_eq!(
+            (skeleton.statements, skeleton.private, skeleton.instances),
+            (3, 1, 1)
+        );
+        let files: Vec<&str> = skeleton.files.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(
+            files,
+            vec!["chapter/Magma.tex", "chapter/_root_.tex", "content.tex"]
+        );
+        assert_eq!(
+            skeleton.files[0].1,
+            r"% Generated by probe-blueprint init from namespace Magma
+\chapter{Magma}
+
+\begin{definition}\label{def:Magma.op_of}\lean{Magma.op_of}\leanok
+  A binary operation on \texttt{α}, written $a \cdot b$ (50\% of the time).
+\end{definition}
+
+\begin{theorem}\label{thm:Magma.assoc_like}\lean{Magma.assoc_like}\leanok
+  % TODO: state Magma.assoc_like informally
+\end{theorem}
+"
+        );
+        assert_eq!(
+            skeleton.files[2].1,
+            "% Generated by probe-blueprint init\n\\input{chapter/Magma}\n\\input{chapter/_root_}\n"
+        );
+    }
+
+    #[test]
+    fn test_init_round_trips_through_stubify() {
+        let dir = tempfile::tempdir().unwrap();
+        let lean_root = dir.path().join("Magma");
+        fs::create_dir_all(&lean_root).unwrap();
+        fs::write(lean_root.join("Basic.lean"), BASIC).unwrap();
+        fs::write(
+            lean_root.join("Extra.lean"),
+            "/-- Every magma is a magma. -/\ntheorem Magma.extra : True := trivial\n",
+        )
+        .unwrap();
+        let project = dir.path().to_str().unwrap();
+        let lean_src_root = lean_root.to_str().unwrap();
+
+        run(project, lean_src_root, GroupBy::File, false).unwrap();
+
+        let parsed = stubify::parse_project(dir.path()).unwrap();
+        let mut stubs: Vec<(&str, &str, Option<bool>, Option<&str>)> = parsed
+            .stubs
+            .values()
+            .map(|stub| {
+                (
+                    stub.label.as_str(),
+                    stub.stub_type.as_deref().unwrap_or_default(),
+                    stub.spec_ok,
+                    stub.code_name.as_deref(),
+                )
+            })
+            .collect();
+        stubs.sort();
+        assert_eq!(
+            stubs,
+            vec![
+                (
+                    "ax:choice_like",
+                    "assumption",
+                    Some(true),
+                    Some("probe:choice_like")
+                ),
+                (
+                    "def:Magma.op_of",
+                    "definition",
+                    Some(true),
+                    Some("probe:Magma.op_of")
+                ),
+                (
+                    "thm:Magma.assoc_like",
+                    "theorem",
+                    Some(true),
+                    Some("probe:Magma.assoc_like")
+                ),
+                (
+                    "thm:Magma.extra",
+                    "theorem",
+                    Some(true),
+                    Some("probe:Magma.extra")
+                ),
+            ]
+        );
+
+        // Nothing is overwritten without --force
+        let err = run(project, lean_src_root, GroupBy::File, false).unwrap_err();
+        assert!(err.to_string().contains("use --force"));
+        run(project, lean_src_root, GroupBy::File, true).unwrap();
+    }
+}
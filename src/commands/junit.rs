@@ -0,0 +1,154 @@
+//! JUnit XML reports for CI dashboards
+//!
+//! One `<testsuite>` per blueprint file and one `<testcase>` per checked
+//! item, failing with the problem found. Test case names are declaration
+//! names, not positions or counters, so a CI system can track their history
+//! across runs.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// One checked item of a report
+#[derive(Debug, PartialEq)]
+pub(crate) struct TestCase {
+    pub(crate) name: String,
+    /// Short reason and details of the failure, `None` when the item passed
+    pub(crate) failure: Option<(String, String)>,
+}
+
+/// Escape text for XML attributes and content, dropping the control
+/// characters XML 1.0 cannot represent at all
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// JUnit XML report named `name` with the test cases of each suite, suites
+/// and test cases in sorted order
+pub(crate) fn junit_xml(name: &str, suites: &BTreeMap<String, Vec<TestCase>>) -> String {
+    let count = |cases: &[TestCase]| {
+        let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+        (cases.len(), failures)
+    };
+    let (tests, failures) = suites
+        .values()
+        .map(|cases| count(cases))
+        .fold((0, 0), |(t, f), (tests, failures)| {
+            (t + tests, f + failures)
+        });
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\">",
+        escape(name),
+        tests,
+        failures
+    );
+    for (suite, cases) in suites {
+        let (tests, failures) = count(cases);
+        let _ = writeln!(
+            out,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">",
+            escape(suite),
+            tests,
+            failures
+        );
+        let mut cases: Vec<&TestCase> = cases.iter().collect();
+        cases.sort_by(|a, b| a.name.cmp(&b.name));
+        for case in cases {
+            let _ = write!(
+                out,
+                "    <testcase name=\"{}\" classname=\"{}\"",
+                escape(&case.name),
+                escape(suite)
+            );
+            match &case.failure {
+                None => out.push_str("/>\n"),
+                Some((message, details)) => {
+                    let _ = writeln!(
+                        out,
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                        escape(message),
+                        escape(details)
+                    );
+                }
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    #[test]
+    fn test_junit_xml_is_well_formed() {
+        let suites = BTreeMap::from([
+            (
+                "chapter/a&b.tex".to_string(),
+                vec![
+                    TestCase {
+                        name: "Foo.zeta".to_string(),
+                        failure: Some((
+                            "sorries".to_string(),
+                            "$a < b$ & \"c\" \\uses{x}\u{1}".to_string(),
+                        )),
+                    },
+                    TestCase {
+                        name: "Foo.alpha".to_string(),
+                        failure: None,
+                    },
+                ],
+            ),
+            ("b.tex".to_string(), vec![]),
+        ]);
+
+        let xml = junit_xml("probe-blueprint verify", &suites);
+
+        assert!(
+            xml.contains(r#"<testsuites name="probe-blueprint verify" tests="2" failures="1">"#)
+        );
+        assert!(xml.contains(r#"<testcase name="Foo.alpha" classname="chapter/a&amp;b.tex"/>"#));
+        assert!(xml.contains(
+            r#"<failure message="sorries">$a &lt; b$ &amp; &quot;c&quot; \uses{x}</failure>"#
+        ));
+        // Test cases are sorted by name
+        assert!(xml.find("Foo.alpha").unwrap() < xml.find("Foo.zeta").unwrap());
+
+        let mut reader = Reader::from_str(&xml);
+        reader.config_mut().check_end_names = true;
+        let mut depth = 0usize;
+        let mut failure_text = String::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                Event::Text(text) if depth == 4 => {
+                    failure_text.push_str(&text.unescape().unwrap());
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(depth, 0);
+        assert_eq!(failure_text, "$a < b$ & \"c\" \\uses{x}");
+    }
+}
@@ -0,0 +1,222 @@
+//! Discovery of Lean source roots from the Lake configuration
+//!
+//! `lakefile.toml` is parsed properly; `lakefile.lean` is Lean code, so it is
+//! scanned heuristically for `srcDir := "..."` settings and the `package` /
+//! `lean_lib` declarations they belong to.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::messages::{self, Location};
+
+static LEAN_PACKAGE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*package\b").unwrap());
+static LEAN_LIB_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*lean_lib\b").unwrap());
+static LEAN_TARGET_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:@\[[^\]]*\]\s*)?(?:lean_exe|target|extern_lib|require)\b").unwrap()
+});
+static LEAN_SRC_DIR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bsrcDir\s*:=\s*"([^"]*)""#).unwrap());
+
+/// `lakefile.toml` (only fields we need)
+#[derive(Debug, Deserialize)]
+struct LakeToml {
+    #[serde(rename = "srcDir", default)]
+    src_dir: Option<String>,
+    #[serde(default)]
+    lean_lib: Vec<LakeTomlLib>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LakeTomlLib {
+    #[serde(rename = "srcDir", default)]
+    src_dir: Option<String>,
+}
+
+/// Join a package `srcDir` and a library `srcDir` into one relative directory
+fn join_src_dirs(package: Option<&str>, library: Option<&str>) -> String {
+    let parts: Vec<&str> = [package, library]
+        .into_iter()
+        .flatten()
+        .flat_map(|dir| dir.split('/'))
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+/// Source directories of every library, deduplicated in declaration order
+fn library_dirs(package: Option<&str>, libraries: &[Option<String>]) -> Vec<String> {
+    let mut dirs: Vec<String> = Vec::new();
+    if libraries.is_empty() {
+        dirs.push(join_src_dirs(package, None));
+    }
+    for library in libraries {
+        let dir = join_src_dirs(package, library.as_deref());
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Source directories declared in a `lakefile.toml`
+fn roots_from_toml(content: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let config: LakeToml = toml::from_str(content)?;
+    let libraries: Vec<Option<String>> =
+        config.lean_lib.into_iter().map(|lib| lib.src_dir).collect();
+    Ok(library_dirs(config.src_dir.as_deref(), &libraries))
+}
+
+/// Source directories declared in a `lakefile.lean` (best-effort)
+fn roots_from_lean(content: &str) -> Vec<String> {
+    // Declaration that a `srcDir` setting belongs to
+    enum Block {
+        None,
+        Package,
+        Library(usize),
+        Other,
+    }
+
+    let mut package: Option<String> = None;
+    let mut libraries: Vec<Option<String>> = Vec::new();
+    let mut block = Block::None;
+    for line in content.lines() {
+        let line = line.split("--").next().unwrap_or_default();
+        if LEAN_PACKAGE_RE.is_match(line) {
+            block = Block::Package;
+        } else if LEAN_LIB_RE.is_match(line) {
+            libraries.push(None);
+            block = Block::Library(libraries.len() - 1);
+        } else if LEAN_TARGET_RE.is_match(line) {
+            block = Block::Other;
+        }
+
+        if let Some(caps) = LEAN_SRC_DIR_RE.captures(line) {
+            let dir = caps[1].to_string();
+            match block {
+                Block::Package | Block::None => package = Some(dir),
+                Block::Library(index) => libraries[index] = Some(dir),
+                Block::Other => {}
+            }
+        }
+    }
+
+    library_dirs(package.as_deref(), &libraries)
+}
+
+/// Lean source roots of the project at `project_path`
+///
+/// Read from `lakefile.toml` or `lakefile.lean`; falls back to the project
+/// root when there is no Lake configuration or it cannot be read. The chosen
+/// roots are logged to stderr.
+pub(crate) fn lean_source_roots(project_path: &Path) -> Vec<PathBuf> {
+    let toml_path = project_path.join("lakefile.toml");
+    let lean_path = project_path.join("lakefile.lean");
+
+    let (source, dirs) = if let Ok(content) = fs::read_to_string(&toml_path) {
+        match roots_from_toml(&content) {
+            Ok(dirs) => ("lakefile.toml", dirs),
+            Err(e) => {
+                messages::warning(
+                    Some(&Location::file("lakefile.toml")),
+                    &format!("could not parse {}: {}", toml_path.display(), e),
+                );
+                ("project root", vec![".".to_string()])
+            }
+        }
+    } else if let Ok(content) = fs::read_to_string(&lean_path) {
+        ("lakefile.lean", roots_from_lean(&content))
+    } else {
+        ("project root", vec![".".to_string()])
+    };
+
+    eprintln!("Lean source roots (from {}): {}", source, dirs.join(", "));
+    dirs.iter()
+        .map(|dir| {
+            if dir == "." {
+                project_path.to_path_buf()
+            } else {
+                project_path.join(dir)
+            }
+        })
+        .collect()
+}
+
+/// `explicit` if given, otherwise the roots from the Lake configuration
+pub(crate) fn resolve_lean_roots(project_path: &Path, explicit: Option<&str>) -> Vec<PathBuf> {
+    match explicit {
+        Some(root) => vec![PathBuf::from(root)],
+        None => lean_source_roots(project_path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roots_from_toml() {
+        let content = r#"
+name = "foo"
+srcDir = "src"
+defaultTargets = ["Foo"]
+
+[[lean_lib]]
+name = "Foo"
+
+[[lean_lib]]
+name = "Bar"
+srcDir = "./bar"
+
+[[lean_exe]]
+name = "main"
+srcDir = "exe"
+"#;
+        assert_eq!(roots_from_toml(content).unwrap(), vec!["src", "src/bar"]);
+        assert_eq!(roots_from_toml("name = \"foo\"").unwrap(), vec!["."]);
+    }
+
+    #[test]
+    fn test_roots_from_lean() {
+        let content = r#"import Lake
+open Lake DSL
+
+package «foo» where
+  srcDir := "src"
+
+require mathlib from git
+  "https://github.com/leanprover-community/mathlib4.git"
+
+@[default_target]
+lean_lib Foo where
+  -- srcDir := "ignored"
+
+lean_lib Bar where
+  srcDir := "bar"
+
+lean_exe main where
+  srcDir := "exe"
+"#;
+        assert_eq!(roots_from_lean(content), vec!["src", "src/bar"]);
+        assert_eq!(roots_from_lean("import Lake\npackage foo\n"), vec!["."]);
+    }
+
+    #[test]
+    fn test_lean_source_roots_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            lean_source_roots(dir.path()),
+            vec![dir.path().to_path_buf()]
+        );
+
+        fs::write(dir.path().join("lakefile.toml"), "srcDir = \"src\"\n").unwrap();
+        assert_eq!(lean_source_roots(dir.path()), vec![dir.path().join("src")]);
+    }
+}
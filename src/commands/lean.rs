@@ -0,0 +1,581 @@
+//! Line-based scanning of Lean 4 sources for declarations and imports
+//!
+//! This is a best-effort scan, not a Lean parser: declarations are recognized
+//! by their keyword at the start of a line, and a declaration's body is taken
+//! to run until the next declaration or scope command.
+
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use walkdir::WalkDir;
+
+use crate::parse::{normalize_path, LineRange};
+
+static NAMESPACE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*namespace\s+([^\s]+)").unwrap());
+static SECTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:noncomputable\s+)?section\b").unwrap());
+static END_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*end\b").unwrap());
+static DECLARATION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?:@\[[^\]]*\]\s*)?((?:(?:private|protected|noncomputable|partial|unsafe|nonrec)\s+)*)(theorem|lemma|def|abbrev|structure|class|inductive|instance|axiom|opaque)\s+([^\s:({\[]+)",
+    )
+    .unwrap()
+});
+static IMPORT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*import\s+(.+)$").unwrap());
+/// Unindented commands that end the body of the preceding declaration
+static COMMAND_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:open|variable|universe|set_option|example|instance|attribute|deriving|mutual|#[a-z_]+)\b",
+    )
+    .unwrap()
+});
+/// Default marker linking a Lean declaration to blueprint labels, e.g.
+/// `/-- blueprint: thm:main -/` or `-- blueprint: lem:a, lem:b` before it
+pub(crate) const DEFAULT_ANNOTATION_MARKER: &str =
+    r"(?:/--|--)\s*blueprint:\s*([^\s,]+(?:\s*,\s*[^\s,]+)*)";
+
+/// Lines that belong to the next declaration rather than the previous one
+static PREAMBLE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:@\[[^\]]*\]\s*$|/--|--)").unwrap());
+
+/// A declaration with its fully qualified name and source lines
+#[derive(Debug, Clone)]
+pub(crate) struct LeanDeclaration {
+    pub(crate) name: String,
+    /// From the declaration keyword to the last line of its body (1-indexed)
+    pub(crate) lines: LineRange,
+    /// Text of the `/-- ... -/` docstring before the declaration
+    pub(crate) doc: Option<String>,
+    /// Declaration keyword, e.g. `theorem` or `def`
+    pub(crate) keyword: String,
+    /// Declared `private`
+    pub(crate) private: bool,
+}
+
+/// Declarations and imports of a single Lean module
+#[derive(Debug, Default)]
+pub(crate) struct LeanModule {
+    /// Path of the file relative to the source root, with `/` separators
+    pub(crate) path: String,
+    pub(crate) declarations: Vec<LeanDeclaration>,
+    pub(crate) imports: Vec<String>,
+    /// `(label, declaration)` pairs from annotation markers
+    pub(crate) annotations: Vec<(String, String)>,
+}
+
+/// Text of a docstring from its source lines, without the `/--` and `-/`
+/// markers, the common indentation and surrounding blank lines
+fn docstring_text(lines: &[&str]) -> Option<String> {
+    let mut text = lines.join("\n");
+    if let Some(end) = text.rfind("-/") {
+        text.truncate(end);
+    }
+    let text = text.trim_start().strip_prefix("/--").unwrap_or(&text);
+
+    let mut doc_lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let first = doc_lines.first().map(|line| line.trim_start());
+    let indent = doc_lines
+        .iter()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    for line in doc_lines.iter_mut().skip(1) {
+        *line = line.get(indent..).unwrap_or_default();
+    }
+    if let (Some(line), Some(first)) = (doc_lines.first_mut(), first) {
+        *line = first;
+    }
+
+    let doc = doc_lines.join("\n").trim_matches('\n').to_string();
+    (!doc.is_empty()).then_some(doc)
+}
+
+/// Scan a Lean source file for its imports and fully qualified declarations
+pub(crate) fn scan_lean_source(content: &str) -> LeanModule {
+    scan_lean_source_with(content, None)
+}
+
+/// Like `scan_lean_source`, also collecting blueprint annotations
+///
+/// The first capture group of `marker` holds comma-separated labels; they are
+/// attached to the next declaration.
+pub(crate) fn scan_lean_source_with(content: &str, marker: Option<&Regex>) -> LeanModule {
+    let mut module = LeanModule::default();
+    // Open namespaces and sections (None for sections, which add no prefix)
+    let mut scopes: Vec<Option<String>> = Vec::new();
+    // Declaration whose body is being scanned, its last line not known yet
+    let mut open: Option<LeanDeclaration> = None;
+    // Last line that can belong to the open declaration's body
+    let mut last_body_line = 0;
+    // Lines of the docstring being read, and whether they are in a code fence
+    let mut doc_comment: Option<Vec<&str>> = None;
+    let mut in_code_fence = false;
+    // Docstring waiting for its declaration
+    let mut pending_doc: Option<String> = None;
+    // Labels from markers waiting for their declaration
+    let mut pending_labels: Vec<String> = Vec::new();
+
+    let close = |declarations: &mut Vec<LeanDeclaration>,
+                 open: &mut Option<LeanDeclaration>,
+                 last_body_line: usize| {
+        if let Some(mut declaration) = open.take() {
+            declaration.lines.lines_end = last_body_line.max(declaration.lines.lines_start);
+            declarations.push(declaration);
+        }
+    };
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+
+        if let Some(caps) = marker.and_then(|marker| marker.captures(line)) {
+            if let Some(labels) = caps.get(1) {
+                pending_labels.extend(
+                    labels
+                        .as_str()
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|label| !label.is_empty())
+                        .map(str::to_string),
+                );
+            }
+        }
+
+        // Doc comments precede the next declaration. A `-/` inside a code
+        // fence is part of the example, not the end of the comment
+        if let Some(doc_lines) = &mut doc_comment {
+            doc_lines.push(line);
+            if line.trim_start().starts_with("```") {
+                in_code_fence = !in_code_fence;
+            } else if !in_code_fence && line.contains("-/") {
+                pending_doc = docstring_text(doc_lines);
+                doc_comment = None;
+            }
+            continue;
+        }
+        if let Some(rest) = line.trim_start().strip_prefix("/--") {
+            if rest.contains("-/") {
+                pending_doc = docstring_text(&[line]);
+            } else {
+                doc_comment = Some(vec![line]);
+                in_code_fence = false;
+            }
+            continue;
+        }
+
+        if let Some(caps) = IMPORT_RE.captures(line) {
+            module
+                .imports
+                .extend(caps[1].split_whitespace().map(str::to_string));
+        } else if let Some(caps) = NAMESPACE_RE.captures(line) {
+            close(&mut module.declarations, &mut open, last_body_line);
+            scopes.push(Some(caps[1].to_string()));
+            pending_doc = None;
+        } else if SECTION_RE.is_match(line) {
+            close(&mut module.declarations, &mut open, last_body_line);
+            scopes.push(None);
+            pending_doc = None;
+        } else if END_RE.is_match(line) {
+            close(&mut module.declarations, &mut open, last_body_line);
+            scopes.pop();
+            pending_doc = None;
+        } else if let Some(caps) = DECLARATION_RE.captures(line) {
+            close(&mut module.declarations, &mut open, last_body_line);
+            let name = &caps[3];
+            let full_name = match name.strip_prefix("_root_.") {
+                Some(root_name) => root_name.to_string(),
+                None => scopes
+                    .iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .chain([name])
+                    .collect::<Vec<_>>()
+                    .join("."),
+            };
+            for label in pending_labels.drain(..) {
+                module.annotations.push((label, full_name.clone()));
+            }
+            open = Some(LeanDeclaration {
+                name: full_name,
+                lines: LineRange {
+                    lines_start: line_number,
+                    lines_end: line_number,
+                },
+                doc: pending_doc.take(),
+                keyword: caps[2].to_string(),
+                private: caps[1].split_whitespace().any(|word| word == "private"),
+            });
+            last_body_line = line_number;
+        } else if COMMAND_RE.is_match(line) {
+            close(&mut module.declarations, &mut open, last_body_line);
+            pending_doc = None;
+        } else if !line.trim().is_empty() && !PREAMBLE_RE.is_match(line) {
+            last_body_line = line_number;
+        }
+    }
+    close(&mut module.declarations, &mut open, last_body_line);
+
+    module
+}
+
+/// How a blueprint `\lean{...}` name resolved against a `DeclarationIndex`
+#[derive(Debug, PartialEq)]
+pub(crate) enum Resolution<'a> {
+    /// Declared under exactly this name
+    Exact(&'a str),
+    /// Declared under this single qualified name ending in `.<name>`
+    Suffix(&'a str),
+    /// Several qualified names end in `.<name>`, sorted
+    Ambiguous(Vec<&'a str>),
+    Missing,
+}
+
+/// Declaration names, indexed by full name and by every dot-separated suffix
+///
+/// Blueprints often name a declaration relative to the namespaces open around
+/// it (`\lean{bar}` for `Foo.bar`), so a name that is not declared as such is
+/// looked up among the qualified names ending in it.
+#[derive(Debug, Default)]
+pub(crate) struct DeclarationIndex<'a> {
+    names: HashSet<&'a str>,
+    by_suffix: HashMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> DeclarationIndex<'a> {
+    pub(crate) fn new(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut index = DeclarationIndex::default();
+        for name in names {
+            if !index.names.insert(name) {
+                continue;
+            }
+            let mut suffix = name;
+            while let Some((_, rest)) = suffix.split_once('.') {
+                index.by_suffix.entry(rest).or_default().push(name);
+                suffix = rest;
+            }
+        }
+        for candidates in index.by_suffix.values_mut() {
+            candidates.sort_unstable();
+        }
+        index
+    }
+
+    /// Resolve `name` exactly, or else by unambiguous suffix
+    pub(crate) fn resolve(&self, name: &str) -> Resolution<'a> {
+        if let Some(&exact) = self.names.get(name) {
+            return Resolution::Exact(exact);
+        }
+        match self.by_suffix.get(name).map(Vec::as_slice) {
+            Some(&[qualified]) => Resolution::Suffix(qualified),
+            Some(candidates) => Resolution::Ambiguous(candidates.to_vec()),
+            None => Resolution::Missing,
+        }
+    }
+}
+
+/// Module name of a Lean file relative to the source root, e.g. `Foo/Bar.lean` -> `Foo.Bar`
+pub(crate) fn module_name(relative_path: &Path) -> Option<String> {
+    let without_ext = relative_path.with_extension("");
+    let parts: Option<Vec<&str>> = without_ext.iter().map(|part| part.to_str()).collect();
+    Some(parts?.join("."))
+}
+
+/// Whether a directory is skipped when scanning: build output and hidden
+/// directories such as `.lake` (which holds the sources of dependencies)
+fn is_skipped_dir(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0
+        && entry.file_type().is_dir()
+        && entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with('.') || name == "lake-packages")
+}
+
+/// Read all .lean files under each of `lean_src_roots` as
+/// `(relative_path, content)` pairs, with paths relative to their own root
+pub(crate) fn read_lean_sources(
+    lean_src_roots: &[PathBuf],
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for lean_src_root in lean_src_roots {
+        for entry in WalkDir::new(lean_src_root)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !is_skipped_dir(entry))
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "lean") {
+                let content = fs::read_to_string(path)?;
+                let relative_path = path
+                    .strip_prefix(lean_src_root)?
+                    .to_str()
+                    .ok_or("Invalid UTF-8 in path")?;
+                files.push((normalize_path(relative_path), content));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Scan all .lean files under `lean_src_roots`, keyed by module name
+pub(crate) fn scan_lean_modules(
+    lean_src_roots: &[PathBuf],
+) -> Result<HashMap<String, LeanModule>, Box<dyn Error>> {
+    scan_lean_modules_with(lean_src_roots, None)
+}
+
+/// Like `scan_lean_modules`, also collecting blueprint annotations matching `marker`
+pub(crate) fn scan_lean_modules_with(
+    lean_src_roots: &[PathBuf],
+    marker: Option<&Regex>,
+) -> Result<HashMap<String, LeanModule>, Box<dyn Error>> {
+    let mut modules = HashMap::new();
+    for (relative_path, content) in read_lean_sources(lean_src_roots)? {
+        let name = module_name(Path::new(&relative_path)).ok_or("Invalid UTF-8 in path")?;
+        let mut module = scan_lean_source_with(&content, marker);
+        module.path = relative_path;
+        modules.insert(name, module);
+    }
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(module: &LeanModule) -> Vec<&str> {
+        module
+            .declarations
+            .iter()
+            .map(|decl| decl.name.as_str())
+            .collect()
+    }
+
+    fn lines(module: &LeanModule, name: &str) -> (usize, usize) {
+        let decl = module
+            .declarations
+            .iter()
+            .find(|decl| decl.name == name)
+            .unwrap();
+        (decl.lines.lines_start, decl.lines.lines_end)
+    }
+
+    #[test]
+    fn test_scan_lean_source_namespaces() {
+        let content = r#"import Mathlib.Algebra.Group
+import Foo.Basic Foo.Extra
+
+namespace Magma
+
+theorem assoc : True := trivial
+
+section
+@[simp] lemma helper : True := trivial
+end
+
+noncomputable def main : Nat := 0
+theorem _root_.global : True := trivial
+
+end Magma
+
+def top : Nat := 1
+"#;
+        let module = scan_lean_source(content);
+        assert_eq!(
+            module.imports,
+            vec!["Mathlib.Algebra.Group", "Foo.Basic", "Foo.Extra"]
+        );
+        assert_eq!(
+            names(&module),
+            vec!["Magma.assoc", "Magma.helper", "Magma.main", "global", "top"]
+        );
+    }
+
+    #[test]
+    fn test_scan_lean_source_line_ranges() {
+        let content = r#"namespace Foo
+
+/-- The answer,
+  documented -/
+protected def answer : Nat :=
+  helper + 1
+where
+  helper : Nat := 41
+
+@[simp]
+private theorem answer_eq : answer = 42 := by
+  unfold answer
+  rfl
+-- trailing comment
+
+open Nat
+
+theorem last : True := trivial
+end Foo
+"#;
+        let module = scan_lean_source(content);
+        assert_eq!(
+            names(&module),
+            vec!["Foo.answer", "Foo.answer_eq", "Foo.last"]
+        );
+        // The where-clause belongs to the body; doc comments and attributes do not
+        assert_eq!(lines(&module, "Foo.answer"), (5, 8));
+        assert_eq!(lines(&module, "Foo.answer_eq"), (11, 13));
+        assert_eq!(lines(&module, "Foo.last"), (18, 18));
+
+        let kinds: Vec<(&str, bool)> = module
+            .declarations
+            .iter()
+            .map(|decl| (decl.keyword.as_str(), decl.private))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![("def", false), ("theorem", true), ("theorem", false)]
+        );
+    }
+
+    fn doc<'a>(module: &'a LeanModule, name: &str) -> Option<&'a str> {
+        module
+            .declarations
+            .iter()
+            .find(|decl| decl.name == name)
+            .and_then(|decl| decl.doc.as_deref())
+    }
+
+    #[test]
+    fn test_scan_lean_source_docstrings() {
+        let content = r#"/-- One line. -/
+def one : Nat := 1
+
+/--
+  The answer.
+
+  Computed as:
+  ```lean
+  /- not the end -/ example : answer = 42 := rfl
+  ```
+  -/
+@[simp]
+def answer : Nat := 42
+
+/-- Orphaned by a command -/
+open Nat
+
+def plain : Nat := 0
+
+/-- Indented
+    continuation -/
+theorem t : True := trivial
+"#;
+        let module = scan_lean_source(content);
+        assert_eq!(names(&module), vec!["one", "answer", "plain", "t"]);
+        assert_eq!(doc(&module, "one"), Some("One line."));
+        assert_eq!(
+            doc(&module, "answer"),
+            Some(
+                "The answer.\n\nComputed as:\n```lean\n/- not the end -/ example : answer = 42 := rfl\n```"
+            )
+        );
+        assert_eq!(doc(&module, "plain"), None);
+        assert_eq!(doc(&module, "t"), Some("Indented\ncontinuation"));
+        assert_eq!(lines(&module, "answer"), (13, 13));
+    }
+
+    #[test]
+    fn test_scan_lean_source_annotations() {
+        let content = r#"namespace Foo
+
+/-- blueprint: thm:main -/
+theorem main : True := trivial
+
+-- blueprint: lem:a, lem:b
+@[simp]
+lemma helper : True := trivial
+
+-- a comment mentioning blueprint without a marker
+def plain : Nat := 0
+end Foo
+"#;
+        let marker = Regex::new(DEFAULT_ANNOTATION_MARKER).unwrap();
+        let module = scan_lean_source_with(content, Some(&marker));
+        assert_eq!(
+            module.annotations,
+            vec![
+                ("thm:main".to_string(), "Foo.main".to_string()),
+                ("lem:a".to_string(), "Foo.helper".to_string()),
+                ("lem:b".to_string(), "Foo.helper".to_string()),
+            ]
+        );
+        assert!(scan_lean_source(content).annotations.is_empty());
+    }
+
+    #[test]
+    fn test_declaration_index_resolves_suffixes() {
+        let content = r#"namespace Subgraph
+theorem Equation387_implies_Equation43 : True := trivial
+namespace Inner
+theorem deep : True := trivial
+theorem shared : True := trivial
+end Inner
+theorem shared : True := trivial
+end Subgraph
+def top : Nat := 0
+"#;
+        let module = scan_lean_source(content);
+        let index = DeclarationIndex::new(module.declarations.iter().map(|d| d.name.as_str()));
+
+        assert_eq!(index.resolve("top"), Resolution::Exact("top"));
+        assert_eq!(
+            index.resolve("Subgraph.Inner.deep"),
+            Resolution::Exact("Subgraph.Inner.deep")
+        );
+        assert_eq!(
+            index.resolve("Equation387_implies_Equation43"),
+            Resolution::Suffix("Subgraph.Equation387_implies_Equation43")
+        );
+        assert_eq!(
+            index.resolve("Inner.deep"),
+            Resolution::Suffix("Subgraph.Inner.deep")
+        );
+        assert_eq!(
+            index.resolve("deep"),
+            Resolution::Suffix("Subgraph.Inner.deep")
+        );
+        assert_eq!(
+            index.resolve("shared"),
+            Resolution::Ambiguous(vec!["Subgraph.Inner.shared", "Subgraph.shared"])
+        );
+        assert_eq!(index.resolve("nner.deep"), Resolution::Missing);
+    }
+
+    #[test]
+    fn test_module_name() {
+        assert_eq!(
+            module_name(Path::new("Foo/Bar/Baz.lean")),
+            Some("Foo.Bar.Baz".to_string())
+        );
+    }
+
+    #[test]
+    fn test_read_lean_sources_skips_lake_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Foo")).unwrap();
+        fs::create_dir_all(dir.path().join(".lake/packages/mathlib")).unwrap();
+        fs::write(dir.path().join("Foo/Basic.lean"), "def a := 1\n").unwrap();
+        fs::write(
+            dir.path().join(".lake/packages/mathlib/M.lean"),
+            "def m := 1\n",
+        )
+        .unwrap();
+
+        let files = read_lean_sources(&[dir.path().to_path_buf()]).unwrap();
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["Foo/Basic.lean"]);
+    }
+}
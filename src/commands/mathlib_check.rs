@@ -0,0 +1,266 @@
+//! Check `\mathlibok` claims against an index of Mathlib declaration names
+//!
+//! The index is a file listing declaration names (one per line, or a JSON
+//! array), or a Mathlib checkout whose `.lean` files are scanned. Names that
+//! are missing from the index are reported with the closest indexed names, to
+//! catch lemmas that Mathlib has renamed or moved.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::lean;
+
+/// Maximum edit distance between the last name components of a suggestion
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+/// Maximum number of suggestions per missing name
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    #[serde(rename = "code-name")]
+    code_name: Option<String>,
+    #[serde(rename = "code-names")]
+    lean_names: Option<Vec<String>>,
+    #[serde(rename = "mathlib-ok")]
+    mathlib_ok: Option<bool>,
+}
+
+/// A `\lean` name of a `\mathlibok` stub that is not in the index
+#[derive(Debug, PartialEq)]
+pub(crate) struct MissingDeclaration {
+    pub(crate) stub: String,
+    pub(crate) name: String,
+    /// Closest indexed names, best first
+    pub(crate) suggestions: Vec<String>,
+}
+
+/// Result of checking the `\mathlibok` stubs
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct MathlibReport {
+    pub(crate) missing: Vec<MissingDeclaration>,
+    /// `\mathlibok` stubs without a `\lean` name to check
+    pub(crate) unverifiable: Vec<String>,
+}
+
+/// Read a declaration index: a directory of `.lean` files, a JSON array of
+/// names, or one name per line
+pub(crate) fn load_index(path: &Path) -> Result<HashSet<String>, Box<dyn Error>> {
+    if path.is_dir() {
+        let lean_files = lean::read_lean_sources(&[PathBuf::from(path)])?;
+        return Ok(lean_files
+            .iter()
+            .flat_map(|(_, content)| lean::scan_lean_source(content).declarations)
+            .map(|decl| decl.name)
+            .collect());
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read Mathlib index {}: {}", path.display(), e))?;
+    if content.trim_start().starts_with('[') {
+        let names: Vec<String> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid Mathlib index {}: {}", path.display(), e))?;
+        return Ok(names.into_iter().collect());
+    }
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Levenshtein distance between two strings, by character
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Last dot-separated component of a declaration name
+fn last_component(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+/// Indexed names close to `name`: same or similar last component (a moved
+/// or renamed lemma), ranked by distance
+fn suggestions(name: &str, index: &HashSet<String>) -> Vec<String> {
+    let last = last_component(name);
+    let last_len = last.chars().count();
+    let mut candidates: Vec<(usize, usize, &String)> = index
+        .iter()
+        .filter_map(|candidate| {
+            let candidate_last = last_component(candidate);
+            if candidate_last.chars().count().abs_diff(last_len) > MAX_SUGGESTION_DISTANCE {
+                return None;
+            }
+            let distance = edit_distance(last, candidate_last);
+            (distance <= MAX_SUGGESTION_DISTANCE)
+                .then(|| (distance, edit_distance(name, candidate), candidate))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, candidate)| candidate.clone())
+        .collect()
+}
+
+/// Check that the `\lean` names of every `\mathlibok` stub are in `index`
+pub(crate) fn check_mathlib(
+    stubs: &BTreeMap<String, Stub>,
+    index: &HashSet<String>,
+) -> MathlibReport {
+    let mut report = MathlibReport::default();
+    for (stub_name, stub) in stubs {
+        if stub.mathlib_ok != Some(true) {
+            continue;
+        }
+        let names: Vec<&String> = match (&stub.lean_names, &stub.code_name) {
+            (Some(names), _) => names.iter().collect(),
+            (None, Some(code_name)) => vec![code_name],
+            (None, None) => {
+                report.unverifiable.push(stub_name.clone());
+                continue;
+            }
+        };
+        for name in names {
+            let name = name.strip_prefix("probe:").unwrap_or(name);
+            if !index.contains(name) {
+                report.missing.push(MissingDeclaration {
+                    stub: stub_name.clone(),
+                    name: name.to_string(),
+                    suggestions: suggestions(name, index),
+                });
+            }
+        }
+    }
+    report
+}
+
+/// Render the report
+pub(crate) fn format_report(report: &MathlibReport, index_size: usize) -> String {
+    let mut out = format!(
+        "mathlib-ok check against {} indexed declarations\n",
+        index_size
+    );
+    if report.missing.is_empty() {
+        out.push_str("All \\mathlibok declarations found\n");
+    }
+    for missing in &report.missing {
+        let _ = write!(
+            out,
+            "\n{}\n  {} not in the Mathlib index",
+            missing.stub, missing.name
+        );
+        if !missing.suggestions.is_empty() {
+            let _ = write!(out, " (did you mean {}?)", missing.suggestions.join(", "));
+        }
+        out.push('\n');
+    }
+    if !report.unverifiable.is_empty() {
+        let _ = writeln!(
+            out,
+            "\nUnverifiable claims (\\mathlibok without \\lean): {}",
+            report.unverifiable.len()
+        );
+        for stub in &report.unverifiable {
+            let _ = writeln!(out, "  {}", stub);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_mathlib_reports_misses_and_unverifiable() {
+        let index: HashSet<String> = [
+            "Nat.add_comm",
+            "Nat.succ_le_iff",
+            "Order.succ_le_iff",
+            "Finset.sum_comm",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+        let stubs: BTreeMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/ok": {"code-name": "probe:Nat.add_comm", "mathlib-ok": true},
+                "a.tex/renamed": {"code-name": "probe:Nat.lt_succ_iff_le", "mathlib-ok": true},
+                "a.tex/moved": {"code-name": "probe:succ_le_iff", "mathlib-ok": true},
+                "a.tex/claim": {"mathlib-ok": true},
+                "a.tex/own": {"code-name": "probe:Foo.bar"}
+            }"#,
+        )
+        .unwrap();
+
+        let report = check_mathlib(&stubs, &index);
+
+        assert_eq!(
+            report.missing,
+            vec![
+                MissingDeclaration {
+                    stub: "a.tex/moved".to_string(),
+                    name: "succ_le_iff".to_string(),
+                    suggestions: vec![
+                        "Nat.succ_le_iff".to_string(),
+                        "Order.succ_le_iff".to_string()
+                    ],
+                },
+                MissingDeclaration {
+                    stub: "a.tex/renamed".to_string(),
+                    name: "Nat.lt_succ_iff_le".to_string(),
+                    suggestions: vec![],
+                },
+            ]
+        );
+        assert_eq!(report.unverifiable, vec!["a.tex/claim"]);
+        let text = format_report(&report, index.len());
+        assert!(text.contains("succ_le_iff not in the Mathlib index (did you mean Nat.succ_le_iff"));
+        assert!(text.contains("Unverifiable claims (\\mathlibok without \\lean): 1\n  a.tex/claim"));
+    }
+
+    #[test]
+    fn test_load_index_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = dir.path().join("names.txt");
+        fs::write(&lines, "Nat.add_comm\n\n  Nat.mul_comm \n").unwrap();
+        let json = dir.path().join("names.json");
+        fs::write(&json, r#"["Nat.add_comm"]"#).unwrap();
+        let src = dir.path().join("Mathlib");
+        fs::create_dir(&src).unwrap();
+        fs::write(
+            src.join("Basic.lean"),
+            "namespace Nat\ntheorem add_comm : True := trivial\nend Nat\n",
+        )
+        .unwrap();
+
+        assert_eq!(load_index(&lines).unwrap().len(), 2);
+        assert!(load_index(&json).unwrap().contains("Nat.add_comm"));
+        assert!(load_index(&src).unwrap().contains("Nat.add_comm"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("succ_le", "succ_le"), 0);
+        assert_eq!(edit_distance("add_comm", "mul_comm"), 3);
+        assert_eq!(edit_distance("le_of_lt", "le_of_lt'"), 1);
+    }
+}
@@ -1,4 +1,20 @@
 pub mod atomize;
+pub mod benchmark;
+mod check_uses;
+mod diagnostics;
+pub mod diff_graph;
+pub mod dot_lean;
+pub mod emit_proof_obligations;
+pub mod export;
+pub mod gen_fixture;
+pub mod init;
+mod junit;
+mod lake;
+mod lean;
+mod mathlib_check;
+pub mod output;
+pub mod scaffold;
 pub mod specify;
+pub mod status_page;
 pub mod stubify;
 pub mod verify;
@@ -0,0 +1,941 @@
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use crate::messages;
+
+/// Layout of the maps written by stubify, atomize, specify and verify
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// One pretty-printed JSON object keyed by name
+    #[default]
+    Json,
+    /// One `{"key": "<name>", ...fields}` object per line, sorted by key
+    Ndjson,
+}
+
+/// Field holding the map key in NDJSON records
+const NDJSON_KEY: &str = "key";
+
+/// Whitespace of the JSON outputs (NDJSON records are always compact)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonStyle {
+    /// No whitespace at all
+    Compact,
+    /// Pretty-printed, indented by this many spaces per level
+    Indent(usize),
+}
+
+impl Default for JsonStyle {
+    fn default() -> Self {
+        JsonStyle::Indent(2)
+    }
+}
+
+/// Style of every JSON output of the process, set once from the command line
+static JSON_STYLE: RwLock<JsonStyle> = RwLock::new(JsonStyle::Indent(2));
+
+/// Set the style of the JSON outputs written from now on (`--compact`, `--indent`)
+pub fn set_json_style(style: JsonStyle) {
+    *JSON_STYLE.write().unwrap_or_else(|e| e.into_inner()) = style;
+}
+
+fn json_style() -> JsonStyle {
+    *JSON_STYLE.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Generation metadata settings of the process (`--meta`, `--reproducible`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaSettings {
+    /// Leave out the generation time, so reruns give identical bytes
+    pub reproducible: bool,
+    /// Hash of the command line options
+    pub options_hash: String,
+}
+
+/// Generation metadata of the process, `None` with `--no-meta` (the default)
+static META_SETTINGS: RwLock<Option<MetaSettings>> = RwLock::new(None);
+
+/// Record generation metadata next to the outputs written from now on
+pub fn set_meta_settings(settings: Option<MetaSettings>) {
+    *META_SETTINGS.write().unwrap_or_else(|e| e.into_inner()) = settings;
+}
+
+/// Hash of command line arguments, for `MetaSettings::options_hash`
+pub fn options_hash(args: impl IntoIterator<Item = impl AsRef<std::ffi::OsStr>>) -> String {
+    let mut hasher = Sha256::new();
+    for arg in args {
+        let arg = arg.as_ref().to_string_lossy();
+        hasher.update((arg.len() as u64).to_le_bytes());
+        hasher.update(arg.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// `secs` since the Unix epoch as an RFC 3339 UTC timestamp
+fn utc_timestamp(secs: u64) -> String {
+    let (days, time) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Commit checked out in the git repository containing `dir`, if any
+fn git_head(dir: &Path) -> Option<String> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "HEAD"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let head = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !head.trim().is_empty()).then(|| head.trim().to_string())
+}
+
+/// The `generator` block of a sidecar metadata file
+#[derive(Debug, Serialize)]
+struct Generator {
+    tool: &'static str,
+    version: &'static str,
+    #[serde(rename = "generated-at", skip_serializing_if = "Option::is_none")]
+    generated_at: Option<String>,
+    #[serde(rename = "git-head")]
+    git_head: Option<String>,
+    #[serde(rename = "options-hash")]
+    options_hash: String,
+}
+
+/// The `generator` block describing how `output` was produced
+fn generator(settings: &MetaSettings, output: &Path) -> Generator {
+    let generated_at = (!settings.reproducible).then(|| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        utc_timestamp(now)
+    });
+    Generator {
+        tool: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        generated_at,
+        git_head: git_head(output.parent().unwrap_or(Path::new("."))),
+        options_hash: settings.options_hash.clone(),
+    }
+}
+
+/// Placeholders of `--output` paths, for the error message
+const PLACEHOLDERS: &str = "{project}, {command} and {date}";
+
+static PLACEHOLDER_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([^{}]*)\}").unwrap());
+
+/// Expand the placeholders of an `--output` path: `{project}` (the final
+/// component of the project root), `{command}` and `{date}` (today, UTC)
+pub fn expand_output_template(
+    template: &str,
+    project_path: &str,
+    command: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut last = 0;
+    for caps in PLACEHOLDER_RE.captures_iter(template) {
+        let whole = caps.get(0).unwrap();
+        expanded.push_str(&template[last..whole.start()]);
+        match &caps[1] {
+            "project" => {
+                let root =
+                    fs::canonicalize(project_path).unwrap_or_else(|_| PathBuf::from(project_path));
+                let name = root
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| {
+                        format!("No project name in '{}' for {{project}}", project_path)
+                    })?;
+                expanded.push_str(name);
+            }
+            "command" => expanded.push_str(command),
+            "date" => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_secs())
+                    .unwrap_or_default();
+                expanded.push_str(&utc_timestamp(now)[..10]);
+            }
+            other => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in output path '{}' (supported: {})",
+                    other, template, PLACEHOLDERS
+                )
+                .into())
+            }
+        }
+        last = whole.end();
+    }
+    expanded.push_str(&template[last..]);
+    Ok(expanded)
+}
+
+/// Path of the sidecar metadata file of `output` (`stubs.json` -> `stubs.meta.json`)
+pub(crate) fn meta_path(output: &Path) -> PathBuf {
+    output.with_extension("meta.json")
+}
+
+/// Sidecar metadata file: the command's own fields, then the generator block
+#[derive(Serialize)]
+struct Sidecar<'a, T> {
+    #[serde(flatten)]
+    meta: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generator: Option<Generator>,
+}
+
+/// Write the sidecar metadata file of `output`: the fields of `meta` (`()`
+/// for none), plus a `generator` block with `--meta`
+///
+/// Nothing is written when there is neither. Keeping the metadata out of the
+/// output itself means readers iterating over its keys are unaffected.
+pub(crate) fn write_meta<T: Serialize>(meta: &T, output: &Path) -> Result<(), Box<dyn Error>> {
+    let settings = META_SETTINGS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let sidecar = Sidecar {
+        meta,
+        generator: settings.map(|settings| generator(&settings, output)),
+    };
+    if sidecar.generator.is_some() || !serde_json::to_value(meta)?.is_null() {
+        write_json(&sidecar, &meta_path(output))?;
+    }
+    Ok(())
+}
+
+/// Serialize `value` as JSON in `style` to `writer`
+fn to_writer_styled<T: Serialize + ?Sized>(
+    writer: &mut dyn Write,
+    value: &T,
+    style: JsonStyle,
+) -> serde_json::Result<()> {
+    match style {
+        JsonStyle::Compact => serde_json::to_writer(writer, value),
+        JsonStyle::Indent(width) => {
+            let indent = vec![b' '; width];
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(&indent);
+            value.serialize(&mut serde_json::Serializer::with_formatter(
+                writer, formatter,
+            ))
+        }
+    }
+}
+
+/// Compression of a file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    /// `.gz`
+    Gzip,
+    /// `.zst`
+    Zstd,
+}
+
+impl Compression {
+    /// Extensions of the compressed layouts, in lookup order
+    const EXTENSIONS: [&'static str; 2] = ["gz", "zst"];
+
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// `path` if it exists, else the first existing compressed variant
+/// (`stubs.json.gz`, `stubs.json.zst`), else `path`
+pub(crate) fn find_input(path: &Path) -> PathBuf {
+    if path.exists() {
+        return path.to_path_buf();
+    }
+    Compression::EXTENSIONS
+        .iter()
+        .map(|ext| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".");
+            name.push(ext);
+            PathBuf::from(name)
+        })
+        .find(|compressed| compressed.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Read a text file, decompressing `.gz` and `.zst` files and dropping a
+/// UTF-8 byte order mark
+pub(crate) fn read_text(path: &Path) -> Result<String, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut content = String::new();
+    match Compression::of(path) {
+        Compression::None => BufReader::new(file).read_to_string(&mut content)?,
+        Compression::Gzip => flate2::read::GzDecoder::new(file).read_to_string(&mut content)?,
+        Compression::Zstd => zstd::Decoder::new(file)?.read_to_string(&mut content)?,
+    };
+    if content.starts_with(BOM) {
+        content.drain(..BOM.len());
+    }
+    Ok(content)
+}
+
+/// Distinguishes the temporary files of concurrent writes within a process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary file next to `output`, on the same filesystem so it can be renamed
+fn temp_path(output: &Path) -> PathBuf {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Whether the files `a` and `b` have the same bytes, compared chunk by
+/// chunk; false if `b` does not exist
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let Ok(b_meta) = fs::metadata(b) else {
+        return Ok(false);
+    };
+    if fs::metadata(a)?.len() != b_meta.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(File::open(a)?);
+    let mut b = BufReader::new(File::open(b)?);
+    loop {
+        let length = {
+            let (a_buf, b_buf) = (a.fill_buf()?, b.fill_buf()?);
+            if a_buf.is_empty() || b_buf.is_empty() {
+                return Ok(a_buf.is_empty() && b_buf.is_empty());
+            }
+            let length = a_buf.len().min(b_buf.len());
+            if a_buf[..length] != b_buf[..length] {
+                return Ok(false);
+            }
+            length
+        };
+        a.consume(length);
+        b.consume(length);
+    }
+}
+
+/// Text encoding of the outputs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputEncoding {
+    /// Plain UTF-8
+    #[default]
+    #[value(name = "utf-8")]
+    Utf8,
+    /// UTF-8 starting with a byte order mark, for Windows tools that need one
+    #[value(name = "utf-8-bom")]
+    Utf8Bom,
+}
+
+/// UTF-8 byte order mark
+const BOM: &str = "\u{feff}";
+
+/// Whether JSON and NDJSON outputs start with a byte order mark
+/// (`--output-encoding utf-8-bom`)
+static WRITE_BOM: AtomicBool = AtomicBool::new(false);
+
+/// Set the encoding of the outputs written from now on
+pub fn set_output_encoding(encoding: OutputEncoding) {
+    WRITE_BOM.store(encoding == OutputEncoding::Utf8Bom, Ordering::Relaxed);
+}
+
+/// Whether written files are flushed to disk before they replace the outputs
+static FSYNC: AtomicBool = AtomicBool::new(false);
+
+/// Flush every output to disk before renaming it into place (`--fsync`)
+pub fn set_fsync(fsync: bool) {
+    FSYNC.store(fsync, Ordering::Relaxed);
+}
+
+/// Write the content of `output` to the file `temp` with `write`, after a
+/// byte order mark with `bom`, compressing it by the extension of `output`
+fn write_temp(
+    output: &Path,
+    temp: &Path,
+    bom: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let write = |writer: &mut dyn Write| -> Result<(), Box<dyn Error>> {
+        if bom {
+            writer.write_all(BOM.as_bytes())?;
+        }
+        write(writer)
+    };
+    let mut file = BufWriter::new(File::create(temp)?);
+    match Compression::of(output) {
+        Compression::None => write(&mut file)?,
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(&mut file, Default::default());
+            write(&mut encoder)?;
+            encoder.finish()?;
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(&mut file, 0)?;
+            write(&mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    file.flush()?;
+    if FSYNC.load(Ordering::Relaxed) {
+        file.get_ref().sync_all()?;
+    }
+    Ok(())
+}
+
+/// Write `output` with `write`, creating the parent directory if needed
+///
+/// Outputs ending in `.gz` or `.zst` are compressed. The content is streamed
+/// to a temporary file that then replaces `output` by a rename, so readers
+/// never see a partially written file, and a crash or error while writing
+/// leaves the previous `output` intact. If it equals the existing file, the
+/// temporary file is dropped instead and `output` (and its modification time)
+/// is left untouched. Returns whether `output` was written.
+pub(crate) fn write_output(
+    output: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    write_file(output, false, write)
+}
+
+/// Write the JSON or NDJSON `output` with `write` like `write_output`, after
+/// a byte order mark with `--output-encoding utf-8-bom`
+///
+/// Only these outputs get one: the other formats (BibTeX, Lean, Markdown,
+/// DOT, XML) are read by tools that do not expect it.
+fn write_json_output(
+    output: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    write_file(output, WRITE_BOM.load(Ordering::Relaxed), write)
+}
+
+/// `write_output`, after a byte order mark with `bom`
+fn write_file(
+    output: &Path,
+    bom: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    if let Some(parent) = output.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let temp = temp_path(output);
+    let result = (|| {
+        write_temp(output, &temp, bom, write)?;
+        if same_content(&temp, output)? {
+            fs::remove_file(&temp)?;
+            return Ok(false);
+        }
+        fs::rename(&temp, output)?;
+        if FSYNC.load(Ordering::Relaxed) {
+            sync_parent(output);
+        }
+        Ok(true)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    if let Ok(false) = result {
+        messages::notice(None, &format!("{} unchanged", output.display()));
+    }
+    result
+}
+
+/// Flush the directory entry of a renamed `output` to disk, where the
+/// platform supports it
+fn sync_parent(output: &Path) {
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Directories cannot be opened as files on Windows; the rename is durable
+    // enough there
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Write `content` to `output` like `write_output`
+pub(crate) fn write_text(output: &Path, content: &str) -> Result<bool, Box<dyn Error>> {
+    write_output(output, |writer| Ok(writer.write_all(content.as_bytes())?))
+}
+
+/// Write `value` as JSON to `output` like `write_output`, in the style set
+/// by `set_json_style` (pretty-printed with 2 spaces by default)
+///
+/// The JSON is serialized straight into a buffered file writer rather than
+/// built as one string first, so peak memory does not grow with output size.
+pub(crate) fn write_json<T: Serialize + ?Sized>(
+    value: &T,
+    output: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let style = json_style();
+    write_json_output(output, |writer| Ok(to_writer_styled(writer, value, style)?))
+}
+
+/// Write a map from names to objects to `output` in `format`, sorted by name,
+/// like `write_output`
+pub(crate) fn write_map<M, V>(
+    map: &M,
+    output: &Path,
+    format: OutputFormat,
+) -> Result<bool, Box<dyn Error>>
+where
+    for<'a> &'a M: IntoIterator<Item = (&'a String, &'a V)>,
+    V: Serialize,
+{
+    // Sorted keys, so that the same map is always written as the same bytes
+    let mut entries: Vec<(&String, &V)> = map.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    if format == OutputFormat::Json {
+        let sorted: BTreeMap<&String, &V> = entries.into_iter().collect();
+        return write_json(&sorted, output);
+    }
+
+    write_json_output(output, |writer| {
+        for (key, value) in entries {
+            // Splice the key in front of the fields of the serialized object
+            let fields = serde_json::to_string(value)?;
+            let fields = fields
+                .strip_prefix('{')
+                .ok_or_else(|| format!("NDJSON entry {} is not an object", key))?;
+            write!(
+                writer,
+                "{{\"{}\":{}",
+                NDJSON_KEY,
+                serde_json::to_string(key)?
+            )?;
+            if fields != "}" {
+                writer.write_all(b",")?;
+            }
+            writeln!(writer, "{}", fields)?;
+        }
+        Ok(())
+    })
+}
+
+/// Whether `content` is NDJSON, judged by its first line: a complete object
+/// with a string `key` (the first line of a pretty-printed map is just `{`,
+/// and a compact map's values are objects)
+fn is_ndjson(content: &str) -> bool {
+    let Some(first_line) = content.lines().map(str::trim).find(|line| !line.is_empty()) else {
+        return false;
+    };
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(first_line)
+        .is_ok_and(|record| record.get(NDJSON_KEY).is_some_and(|key| key.is_string()))
+}
+
+/// Parse a map written by `write_map` in either format
+pub(crate) fn parse_map<M, V>(content: &str) -> Result<M, Box<dyn Error>>
+where
+    M: DeserializeOwned + FromIterator<(String, V)>,
+    V: DeserializeOwned,
+{
+    if !is_ndjson(content) {
+        return Ok(serde_json::from_str(content)?);
+    }
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let invalid =
+                |e: &dyn std::fmt::Display| format!("Invalid NDJSON at line {}: {}", index + 1, e);
+            let mut record: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(line).map_err(|e| invalid(&e))?;
+            let key = match record.remove(NDJSON_KEY) {
+                Some(serde_json::Value::String(key)) => key,
+                _ => return Err(invalid(&"missing string \"key\"").into()),
+            };
+            let value = serde_json::from_value(serde_json::Value::Object(record))
+                .map_err(|e| invalid(&e))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        verified: bool,
+        #[serde(skip_serializing_if = "Vec::is_empty", default)]
+        deps: Vec<String>,
+    }
+
+    fn entries() -> HashMap<String, Entry> {
+        HashMap::from([
+            (
+                "b.tex/thm".to_string(),
+                Entry {
+                    verified: true,
+                    deps: vec!["a.tex/key".to_string()],
+                },
+            ),
+            (
+                "a.tex/key".to_string(),
+                Entry {
+                    verified: false,
+                    deps: vec![],
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_write_map_ndjson() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.ndjson");
+
+        write_map(&entries(), &output, OutputFormat::Ndjson).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            r#"{"key":"a.tex/key","verified":false}
+{"key":"b.tex/thm","verified":true,"deps":["a.tex/key"]}
+"#
+        );
+    }
+
+    #[test]
+    fn test_map_round_trips_across_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        for format in [OutputFormat::Json, OutputFormat::Ndjson] {
+            let output = dir.path().join("out");
+            write_map(&entries(), &output, format).unwrap();
+            let content = fs::read_to_string(&output).unwrap();
+
+            let read: HashMap<String, Entry> = parse_map(&content).unwrap();
+            assert_eq!(read, entries(), "{:?}", format);
+            let sorted: BTreeMap<String, Entry> = parse_map(&content).unwrap();
+            assert_eq!(sorted.len(), 2);
+        }
+
+        // A compact JSON map is not mistaken for NDJSON, even with a "key" entry
+        let compact = serde_json::to_string(&entries()).unwrap();
+        assert!(!is_ndjson(&compact));
+        let read: HashMap<String, Entry> = parse_map(r#"{"key": {"verified": true}}"#).unwrap();
+        assert!(read["key"].verified);
+    }
+
+    #[test]
+    fn test_parse_map_reports_bad_lines() {
+        let err = parse_map::<HashMap<String, Entry>, Entry>(
+            "{\"key\":\"a\",\"verified\":true}\n{\"verified\":true}\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_unchanged_output_is_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.json");
+
+        assert!(write_map(&entries(), &output, OutputFormat::Json).unwrap());
+        let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        File::options()
+            .write(true)
+            .open(&output)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+
+        assert!(!write_map(&entries(), &output, OutputFormat::Json).unwrap());
+        assert_eq!(fs::metadata(&output).unwrap().modified().unwrap(), past);
+
+        assert!(write_map(&entries(), &output, OutputFormat::Ndjson).unwrap());
+        assert_ne!(fs::metadata(&output).unwrap().modified().unwrap(), past);
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_byte_order_mark() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["stubs.json", "stubs.json.gz"] {
+            let output = dir.path().join(name);
+            write_temp(&output, &output, true, |writer| {
+                Ok(serde_json::to_writer(writer, &entries())?)
+            })
+            .unwrap();
+            if name == "stubs.json" {
+                assert!(fs::read(&output).unwrap().starts_with(b"\xEF\xBB\xBF{"));
+            }
+
+            // Readers skip the mark
+            let read: HashMap<String, Entry> = parse_map(&read_text(&output).unwrap()).unwrap();
+            assert_eq!(read, entries());
+        }
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("stubs.json");
+        write_map(&entries(), &output, OutputFormat::Json).unwrap();
+        let original = fs::read(&output).unwrap();
+
+        // A failing writer leaves neither a changed output nor a temporary file
+        let result = write_output(&output, |writer| {
+            writer.write_all(b"{\"truncated\": ")?;
+            Err("interrupted".into())
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&output).unwrap(), original);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // A crash between writing the temporary file and the rename
+        let temp = temp_path(&output);
+        write_temp(&output, &temp, false, |writer| {
+            Ok(writer.write_all(b"{\"trunc")?)
+        })
+        .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), original);
+        let read: HashMap<String, Entry> =
+            parse_map(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(read, entries());
+
+        // The leftover does not get in the way of the next write
+        assert!(write_text(&output, "{}").unwrap());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_readers_never_see_partial_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.json");
+        let value = |n: usize| -> BTreeMap<String, Vec<usize>> {
+            (0..2000)
+                .map(|i| (format!("key{}", i), vec![n; 8]))
+                .collect()
+        };
+        write_json(&value(0), &output).unwrap();
+
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for n in 1..40 {
+                    write_json(&value(n), &output).unwrap();
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+            while !done.load(Ordering::Relaxed) {
+                let content = fs::read_to_string(&output).unwrap();
+                let read: BTreeMap<String, Vec<usize>> = serde_json::from_str(&content)
+                    .unwrap_or_else(|e| panic!("partial output ({} bytes): {}", content.len(), e));
+                assert_eq!(read.len(), 2000);
+            }
+        });
+    }
+
+    #[test]
+    fn test_compressed_outputs_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, magic) in [
+            ("out.json.gz", &[0x1f, 0x8b][..]),
+            ("out.json.zst", &[0x28, 0xb5, 0x2f, 0xfd][..]),
+        ] {
+            let output = dir.path().join(name);
+            assert!(write_map(&entries(), &output, OutputFormat::Json).unwrap());
+            assert!(fs::read(&output).unwrap().starts_with(magic), "{}", name);
+
+            let read: HashMap<String, Entry> = parse_map(&read_text(&output).unwrap()).unwrap();
+            assert_eq!(read, entries());
+            // Compression is deterministic, so unchanged content is detected
+            assert!(!write_map(&entries(), &output, OutputFormat::Json).unwrap());
+        }
+
+        // Plain files take precedence over their compressed variants
+        let plain = dir.path().join("out.json");
+        assert_eq!(find_input(&plain), dir.path().join("out.json.gz"));
+        write_text(&plain, "{}").unwrap();
+        assert_eq!(find_input(&plain), plain);
+        assert_eq!(
+            find_input(&dir.path().join("missing.json")),
+            dir.path().join("missing.json")
+        );
+    }
+
+    #[test]
+    fn test_json_styles() {
+        let value: BTreeMap<&str, Vec<&str>> =
+            BTreeMap::from([("probe:A", vec!["probe:B"]), ("probe:B", vec![])]);
+        let styled = |style: JsonStyle| {
+            let mut out = Vec::new();
+            to_writer_styled(&mut out, &value, style).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            styled(JsonStyle::Compact),
+            r#"{"probe:A":["probe:B"],"probe:B":[]}"#
+        );
+        assert_eq!(
+            styled(JsonStyle::default()),
+            "{\n  \"probe:A\": [\n    \"probe:B\"\n  ],\n  \"probe:B\": []\n}"
+        );
+        assert_eq!(
+            styled(JsonStyle::Indent(4)),
+            "{\n    \"probe:A\": [\n        \"probe:B\"\n    ],\n    \"probe:B\": []\n}"
+        );
+
+        // Readers accept every style
+        for style in [JsonStyle::Compact, JsonStyle::Indent(4)] {
+            let read: HashMap<String, Entry> = parse_map(&{
+                let mut out = Vec::new();
+                to_writer_styled(&mut out, &entries(), style).unwrap();
+                String::from_utf8(out).unwrap()
+            })
+            .unwrap();
+            assert_eq!(read, entries());
+        }
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_792_174_268), "2026-10-16T18:11:08Z");
+    }
+
+    #[test]
+    fn test_expand_output_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("my-project");
+        fs::create_dir(&project).unwrap();
+        let project = project.to_str().unwrap();
+
+        assert_eq!(
+            expand_output_template("artifacts/{project}-{command}.json", project, "stubify")
+                .unwrap(),
+            "artifacts/my-project-stubify.json"
+        );
+        // The project name comes from the resolved root
+        let relative = format!("{}/../my-project/.", project);
+        assert_eq!(
+            expand_output_template("{project}.json", &relative, "atomize").unwrap(),
+            "my-project.json"
+        );
+        assert_eq!(
+            expand_output_template(".verilib/stubs.json", project, "stubify").unwrap(),
+            ".verilib/stubs.json"
+        );
+
+        let dated = expand_output_template("stubs-{date}.json", project, "stubify").unwrap();
+        assert_eq!(dated.len(), "stubs-2026-01-01.json".len());
+        assert_eq!(&dated[10..11], "-");
+
+        let err =
+            expand_output_template("{project}-{branch}.json", project, "stubify").unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown placeholder {branch}"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_sidecar_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("atoms.json");
+        let settings = MetaSettings {
+            reproducible: true,
+            options_hash: options_hash(["atomize", "."]),
+        };
+        let sidecar = Sidecar {
+            meta: &BTreeMap::from([("fingerprint", "abc")]),
+            generator: Some(generator(&settings, &output)),
+        };
+
+        let value = serde_json::to_value(&sidecar).unwrap();
+        assert_eq!(value["fingerprint"], "abc");
+        assert_eq!(value["generator"]["tool"], "probe-blueprint");
+        assert_eq!(value["generator"]["options-hash"], settings.options_hash);
+        // Reproducible metadata has no generation time
+        assert!(value["generator"].get("generated-at").is_none());
+        assert_ne!(options_hash(["atomize", "."]), options_hash(["atomize."]));
+
+        let sidecar = Sidecar {
+            meta: &(),
+            generator: Some(generator(
+                &MetaSettings {
+                    reproducible: false,
+                    ..settings
+                },
+                &output,
+            )),
+        };
+        let value = serde_json::to_value(&sidecar).unwrap();
+        assert!(value["generator"]["generated-at"]
+            .as_str()
+            .unwrap()
+            .ends_with('Z'));
+    }
+
+    #[test]
+    fn test_write_json_matches_to_string_pretty() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("nested").join("out.json");
+
+        let mut value = BTreeMap::new();
+        value.insert("probe:A", vec!["probe:B"]);
+        value.insert("probe:B", vec![]);
+
+        write_json(&value, &output).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Skeleton Lean files for the blueprint statements that are not formalized yet
+//!
+//! Every statement without a Lean declaration becomes a `sorry`ed theorem
+//! (or definition) with the LaTeX statement as its docstring, in a file
+//! mirroring the blueprint file it comes from.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use super::lean::{self, DeclarationIndex, Resolution};
+use super::output::{parse_map, read_text, write_text};
+use super::{lake, stubify};
+
+/// `\begin{...}[...]` opening a statement, with its optional argument
+static BEGIN_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\\begin\{[^}]*\}(?:\[[^\]]*\])?").unwrap());
+static END_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\end\{[^}]*\}\s*$").unwrap());
+/// Blueprint metadata commands, which say nothing about the statement itself
+static METADATA_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\(?:label|lean|uses|discussion)\{[^}]*\}|\\(?:leanok|mathlibok|notready)\b")
+        .unwrap()
+});
+
+/// Environment types scaffolded as `def` rather than `theorem`
+pub(crate) const DEFINITION_TYPES: &[&str] = &["definition", "dfn", "def"];
+
+/// Line range from stubs.json
+#[derive(Debug, Deserialize)]
+struct LineRange {
+    #[serde(rename = "lines-start")]
+    lines_start: usize,
+    #[serde(rename = "lines-end")]
+    lines_end: usize,
+}
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    label: String,
+    #[serde(rename = "stub-type")]
+    stub_type: Option<String>,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
+    #[serde(rename = "stub-spec")]
+    stub_spec: Option<LineRange>,
+    #[serde(rename = "env-options")]
+    env_options: Option<String>,
+    #[serde(rename = "code-name")]
+    code_name: Option<String>,
+    #[serde(rename = "code-names")]
+    lean_names: Option<Vec<String>>,
+    #[serde(rename = "lean-path")]
+    lean_path: Option<String>,
+    #[serde(rename = "spec-dependencies", default)]
+    spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-dependencies")]
+    proof_dependencies: Option<Vec<String>>,
+}
+
+/// Lean identifier derived from a label (`thm:main-result` -> `thm_main_result`)
+fn name_from_label(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Relative path of the Lean file for a blueprint file
+/// (`chapter/my-results.tex` -> `chapter/my_results.lean`)
+fn lean_file_path(stub_path: &str) -> PathBuf {
+    Path::new(stub_path)
+        .with_extension("lean")
+        .iter()
+        .map(|part| part.to_string_lossy().replace('-', "_"))
+        .collect()
+}
+
+/// The statement text of `lines` in `content`, without the environment
+/// markers and blueprint metadata
+fn statement_text(content: &str, lines: &LineRange) -> String {
+    let source: Vec<&str> = content
+        .lines()
+        .skip(lines.lines_start.saturating_sub(1))
+        .take(lines.lines_end + 1 - lines.lines_start)
+        .collect();
+    let source = source.join("\n");
+    let source = BEGIN_RE.replace(&source, "");
+    let source = END_RE.replace(&source, "");
+    let source = METADATA_RE.replace_all(&source, "");
+
+    let text_lines: Vec<&str> = source.lines().map(str::trim).collect();
+    text_lines.join("\n").trim_matches('\n').to_string()
+}
+
+/// Make text safe inside a `/-- ... -/` docstring, where comments nest
+fn escape_docstring(text: &str) -> String {
+    text.replace("/-", "/ -").replace("-/", "- /")
+}
+
+/// A statement to scaffold, with the Lean names to declare and the stub-names
+/// it depends on
+type Entry<'a> = (&'a Stub, Vec<String>, Vec<&'a str>);
+
+/// Split children of `stub_name` (`<stub-name>_<n>`, one per name of
+/// `\lean{a, b}`), in order
+fn split_children<'a>(stub_name: &str, stubs: &'a HashMap<String, Stub>) -> Vec<&'a Stub> {
+    (1..)
+        .map_while(|i| stubs.get(&format!("{}_{}", stub_name, i)))
+        .collect()
+}
+
+/// Spec- and proof-dependencies of `stubs`, without repetitions
+fn dependencies<'a>(stubs: &[&'a Stub]) -> Vec<&'a str> {
+    let mut dependencies: Vec<&str> = Vec::new();
+    for stub in stubs {
+        for dependency in stub
+            .spec_dependencies
+            .iter()
+            .chain(stub.proof_dependencies.iter().flatten())
+        {
+            if !dependencies.contains(&dependency.as_str()) {
+                dependencies.push(dependency);
+            }
+        }
+    }
+    dependencies
+}
+
+/// Lean source of the declarations for the stubs of one blueprint file
+fn scaffold_file(
+    stub_path: &str,
+    content: &str,
+    stubs: &[Entry],
+    labels: &HashMap<&str, &str>,
+) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "/-!\nScaffold for `{}`, generated by probe-blueprint.\n-/\n",
+        stub_path
+    );
+
+    for (stub, names, dependencies) in stubs {
+        let mut doc = String::new();
+        if let Some(title) = &stub.env_options {
+            let _ = write!(doc, "{}.\n\n", title);
+        }
+        if let Some(spec) = &stub.stub_spec {
+            doc.push_str(&statement_text(content, spec));
+        }
+        let uses: Vec<&str> = dependencies
+            .iter()
+            .map(|dep| labels.get(dep).copied().unwrap_or(dep))
+            .collect();
+        if !uses.is_empty() {
+            let _ = write!(doc, "\n\nUses: {}", uses.join(", "));
+        }
+        let _ = write!(doc, "\n\nBlueprint label: {}", stub.label);
+
+        let keyword = match stub.stub_type.as_deref() {
+            Some(t) if DEFINITION_TYPES.contains(&t) => "def",
+            Some("assumption") => "axiom",
+            _ => "theorem",
+        };
+        for name in names {
+            let _ = writeln!(out, "/-- {} -/", escape_docstring(doc.trim()));
+            if keyword == "axiom" {
+                let _ = writeln!(out, "axiom {} : sorry\n", name);
+            } else {
+                let _ = writeln!(out, "{} {} : sorry := sorry\n", keyword, name);
+            }
+        }
+    }
+    out
+}
+
+/// Generate Lean skeletons for every blueprint statement without a Lean declaration
+pub fn run(
+    project_path: &str,
+    output_dir: &str,
+    lean_src_root: Option<&str>,
+    regenerate_stubs: bool,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+    let labels: HashMap<&str, &str> = stubs
+        .iter()
+        .map(|(name, stub)| (name.as_str(), stub.label.as_str()))
+        .collect();
+
+    let lean_src_roots = lake::resolve_lean_roots(project_path, lean_src_root);
+    let modules = lean::scan_lean_modules(&lean_src_roots)?;
+    let index = DeclarationIndex::new(
+        modules
+            .values()
+            .flat_map(|module| &module.declarations)
+            .map(|decl| decl.name.as_str()),
+    );
+
+    // Statements (not split children) by blueprint file, with the names to declare
+    let mut by_file: BTreeMap<&str, Vec<Entry>> = BTreeMap::new();
+    for (stub_name, stub) in &stubs {
+        let (Some(stub_path), Some(_)) = (&stub.stub_path, &stub.stub_spec) else {
+            continue;
+        };
+        if stub.lean_path.is_some() {
+            continue;
+        }
+        // A statement with several Lean names is split into children that
+        // hold the names and dependencies, the parent only depends on them
+        let children = split_children(stub_name, &stubs);
+        let (names, dependencies): (Vec<String>, Vec<&str>) = if children.is_empty() {
+            let names = match (&stub.lean_names, &stub.code_name) {
+                (Some(names), _) => names.clone(),
+                (None, Some(code_name)) => vec![code_name.clone()],
+                (None, None) => vec![name_from_label(&stub.label)],
+            };
+            (names, dependencies(&[stub]))
+        } else {
+            let names = children
+                .iter()
+                .filter(|child| child.lean_path.is_none())
+                .filter_map(|child| child.code_name.clone())
+                .collect();
+            (names, dependencies(&children))
+        };
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| name.strip_prefix("probe:").unwrap_or(name).to_string())
+            .filter(|name| index.resolve(name) == Resolution::Missing)
+            .collect();
+        if !names.is_empty() {
+            by_file
+                .entry(stubify::src_relative(stub_path))
+                .or_default()
+                .push((stub, names, dependencies));
+        }
+    }
+
+    let output_dir = Path::new(output_dir);
+    let targets: Vec<(PathBuf, &str)> = by_file
+        .keys()
+        .map(|stub_path| (output_dir.join(lean_file_path(stub_path)), *stub_path))
+        .collect();
+    if !force {
+        let existing: Vec<String> = targets
+            .iter()
+            .filter(|(path, _)| path.exists())
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        if !existing.is_empty() {
+            return Err(format!(
+                "Refusing to overwrite existing files (use --force): {}",
+                existing.join(", ")
+            )
+            .into());
+        }
+    }
+
+    let blueprint_src = project_path.join("blueprint").join("src");
+    let mut declarations = 0;
+    for (target, stub_path) in targets {
+        let stubs = by_file.get_mut(stub_path).unwrap();
+        stubs.sort_by_key(|(stub, _, _)| stub.stub_spec.as_ref().map(|spec| spec.lines_start));
+        declarations += stubs.iter().map(|(_, names, _)| names.len()).sum::<usize>();
+
+        let content = fs::read_to_string(blueprint_src.join(stub_path))?;
+        write_text(&target, &scaffold_file(stub_path, &content, stubs, &labels))?;
+    }
+
+    eprintln!(
+        "Wrote {} declarations in {} files to {}",
+        declarations,
+        by_file.len(),
+        output_dir.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_from_label_and_path() {
+        assert_eq!(name_from_label("thm:main-result"), "thm_main_result");
+        assert_eq!(name_from_label("1.2"), "_1_2");
+        assert_eq!(
+            lean_file_path("chapter/my-results.tex"),
+            PathBuf::from("chapter/my_results.lean")
+        );
+    }
+
+    #[test]
+    fn test_scaffold_file() {
+        let content = r"\begin{definition}[Magma]\label{def:magma}
+  A magma is a set with a binary operation.
+\end{definition}
+
+\begin{theorem}\label{thm:main}\lean{Magma.main}
+  \uses{def:magma}
+  Every magma $M$ satisfies /-weird-/ things.
+\end{theorem}
+";
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/def:magma": {
+                    "label": "def:magma", "stub-type": "definition", "stub-path": "a.tex",
+                    "stub-spec": {"lines-start": 1, "lines-end": 3}, "env-options": "Magma"
+                },
+                "a.tex/thm:main": {
+                    "label": "thm:main", "stub-type": "theorem", "stub-path": "a.tex",
+                    "stub-spec": {"lines-start": 5, "lines-end": 8},
+                    "code-name": "probe:Magma.main",
+                    "spec-dependencies": ["a.tex/def:magma"]
+                }
+            }"#,
+        )
+        .unwrap();
+        let labels: HashMap<&str, &str> = stubs
+            .iter()
+            .map(|(name, stub)| (name.as_str(), stub.label.as_str()))
+            .collect();
+        let entries = vec![
+            (
+                &stubs["a.tex/def:magma"],
+                vec!["def_magma".to_string()],
+                vec![],
+            ),
+            (
+                &stubs["a.tex/thm:main"],
+                vec!["Magma.main".to_string()],
+                vec!["a.tex/def:magma"],
+            ),
+        ];
+
+        assert_eq!(
+            scaffold_file("a.tex", content, &entries, &labels),
+            r"/-!
+Scaffold for `a.tex`, generated by probe-blueprint.
+-/
+
+/-- Magma.
+
+A magma is a set with a binary operation.
+
+Blueprint label: def:magma -/
+def def_magma : sorry := sorry
+
+/-- Every magma $M$ satisfies / -weird- / things.
+
+Uses: def:magma
+
+Blueprint label: thm:main -/
+theorem Magma.main : sorry := sorry
+
+"
+        );
+    }
+
+    #[test]
+    fn test_run_declares_the_names_of_split_statements() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{definition}\label{def:x}\lean{Foo.x}
+  An x.
+\end{definition}
+
+\begin{theorem}\label{thm:main}\lean{Foo.a, Foo.b}\uses{def:x}
+  Two results.
+\end{theorem}
+",
+        )
+        .unwrap();
+        let lean = dir.path().join("Foo");
+        fs::create_dir_all(&lean).unwrap();
+        fs::write(lean.join("Basic.lean"), "def Foo.x : Nat := 0\n").unwrap();
+        let project = dir.path().to_str().unwrap();
+        let output = dir.path().join("scaffold");
+
+        run(
+            project,
+            output.to_str().unwrap(),
+            Some(lean.to_str().unwrap()),
+            true,
+            false,
+        )
+        .unwrap();
+
+        let scaffold = fs::read_to_string(output.join("a.lean")).unwrap();
+        assert!(scaffold.contains("theorem Foo.a : sorry := sorry"));
+        assert!(scaffold.contains("theorem Foo.b : sorry := sorry"));
+        assert!(scaffold.contains("Uses: def:x\n"));
+        assert!(!scaffold.contains("thm_main"));
+        assert!(!scaffold.contains("Foo.x"));
+
+        // Nothing is left to scaffold once both names exist
+        fs::write(
+            lean.join("Main.lean"),
+            "theorem Foo.a : True := trivial\ntheorem Foo.b : True := trivial\n",
+        )
+        .unwrap();
+        fs::remove_dir_all(&output).unwrap();
+        run(
+            project,
+            output.to_str().unwrap(),
+            Some(lean.to_str().unwrap()),
+            true,
+            false,
+        )
+        .unwrap();
+        assert!(!output.join("a.lean").exists());
+    }
+}
@@ -1,86 +1,234 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs;
 use std::path::Path;
 
+use super::output::{parse_map, read_text, write_map, write_meta, OutputFormat};
 use super::stubify;
+use crate::messages;
+
+/// Line range from stubs.json
+#[derive(Debug, Deserialize)]
+struct LineRange {
+    #[serde(rename = "lines-start")]
+    lines_start: usize,
+    #[serde(rename = "lines-end")]
+    lines_end: usize,
+}
 
 /// Stub entry from stubs.json (only fields we need)
 #[derive(Debug, Deserialize)]
 struct Stub {
+    #[serde(rename = "generated-label", default)]
+    generated_label: bool,
+    #[serde(rename = "stub-spec")]
+    stub_spec: Option<LineRange>,
     #[serde(rename = "code-name")]
     code_name: Option<String>,
     #[serde(rename = "spec-ok")]
     spec_ok: Option<bool>,
+    #[serde(rename = "mathlib-ok")]
+    mathlib_ok: Option<bool>,
+    #[serde(rename = "spec-dependencies", default)]
+    spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-dependencies")]
+    proof_dependencies: Option<Vec<String>>,
+}
+
+/// Weights of the quality criteria in the score, from the `spec-weights` of
+/// `.verilib/config.json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Weights {
+    #[serde(rename = "has-label")]
+    has_label: f64,
+    #[serde(rename = "has-explicit-dependencies")]
+    has_explicit_dependencies: f64,
+    #[serde(rename = "statement-nonempty")]
+    statement_nonempty: f64,
+    #[serde(rename = "in-mathlib")]
+    in_mathlib: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            has_label: 1.0,
+            has_explicit_dependencies: 1.0,
+            statement_nonempty: 2.0,
+            in_mathlib: 0.0,
+        }
+    }
+}
+
+/// The part of `.verilib/config.json` read by specify
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(rename = "spec-weights", default)]
+    spec_weights: Weights,
+}
+
+/// Quality criteria of a specification link
+#[derive(Debug, Serialize)]
+struct Quality {
+    /// The statement has a `\label` of its own, not a generated one
+    #[serde(rename = "has-label")]
+    has_label: bool,
+    /// The statement or its proof `\uses` other stubs
+    #[serde(rename = "has-explicit-dependencies")]
+    has_explicit_dependencies: bool,
+    /// The statement spans more lines than its `\begin` and `\end`
+    #[serde(rename = "statement-nonempty")]
+    statement_nonempty: bool,
+    /// The statement is marked `\mathlibok`
+    #[serde(rename = "in-mathlib")]
+    in_mathlib: bool,
+}
+
+impl Quality {
+    /// Weighted share of the criteria met, from 0 to 1 (rounded to two
+    /// decimals); 1 when all weights are zero
+    fn score(&self, weights: &Weights) -> f64 {
+        let criteria = [
+            (self.has_label, weights.has_label),
+            (
+                self.has_explicit_dependencies,
+                weights.has_explicit_dependencies,
+            ),
+            (self.statement_nonempty, weights.statement_nonempty),
+            (self.in_mathlib, weights.in_mathlib),
+        ];
+        let total: f64 = criteria.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return 1.0;
+        }
+        let met: f64 = criteria
+            .iter()
+            .filter(|(met, _)| *met)
+            .map(|(_, weight)| weight)
+            .sum();
+        (met / total * 100.0).round() / 100.0
+    }
 }
 
 /// Spec entry for specs.json
 #[derive(Debug, Serialize)]
 struct Spec {
     specified: bool,
+    quality: Quality,
+    score: f64,
 }
 
-/// Extract function specifications
-pub fn run(
-    project_path: &str,
-    output: &str,
-    regenerate_stubs: bool,
-    _with_atoms: Option<Option<String>>,
-) -> Result<(), Box<dyn Error>> {
-    let project_path = Path::new(project_path);
-    let verilib_dir = project_path.join(".verilib");
-    let stubs_path = verilib_dir.join("stubs.json");
-
-    // Check if stubs.json exists, generate if needed
-    if regenerate_stubs || !stubs_path.exists() {
-        if regenerate_stubs {
-            eprintln!("Regenerating stubs.json...");
-        } else {
-            eprintln!("stubs.json not found, running stubify...");
-        }
-
-        stubify::run(
-            project_path.to_str().ok_or("Invalid project path")?,
-            stubs_path.to_str().ok_or("Invalid stubs path")?,
-        )?;
+/// Read the score weights from `.verilib/config.json` (the defaults if the
+/// file is missing or has no `spec-weights`)
+fn read_weights(project_path: &Path) -> Result<Weights, Box<dyn Error>> {
+    let config_path = project_path.join(".verilib").join("config.json");
+    if !config_path.exists() {
+        return Ok(Weights::default());
     }
+    let config: Config = serde_json::from_str(&read_text(&config_path)?)
+        .map_err(|e| format!("Invalid {}: {}", config_path.display(), e))?;
+    Ok(config.spec_weights)
+}
 
-    // Read stubs.json
-    let stubs_content = fs::read_to_string(&stubs_path)?;
-    let stubs: HashMap<String, Stub> = serde_json::from_str(&stubs_content)?;
-
-    // Transform stubs into specs (only stubs with code-name)
+/// Transform stubs into specs (only stubs with code-name)
+///
+/// Split children have no `stub-spec`; they are judged by the statement of
+/// their parent, whose stub-name they extend with `_<n>`.
+fn build_specs(stubs: &HashMap<String, Stub>, weights: &Weights) -> HashMap<String, Spec> {
     let mut specs: HashMap<String, Spec> = HashMap::new();
 
-    for stub in stubs.values() {
+    for (stub_name, stub) in stubs {
         // Skip stubs without code-name
         let code_name = match &stub.code_name {
             Some(cn) => cn,
             None => continue,
         };
 
+        let statement = stub.stub_spec.as_ref().or_else(|| {
+            let (parent, _) = stub_name.rsplit_once('_')?;
+            stubs.get(parent)?.stub_spec.as_ref()
+        });
+        let quality = Quality {
+            has_label: !stub.generated_label,
+            has_explicit_dependencies: !stub.spec_dependencies.is_empty()
+                || stub
+                    .proof_dependencies
+                    .as_ref()
+                    .is_some_and(|deps| !deps.is_empty()),
+            statement_nonempty: statement
+                .is_some_and(|lines| lines.lines_end > lines.lines_start + 1),
+            in_mathlib: stub.mathlib_ok.unwrap_or(false),
+        };
+
         specs.insert(
             code_name.clone(),
             Spec {
                 specified: stub.spec_ok.unwrap_or(false),
+                score: quality.score(weights),
+                quality,
             },
         );
     }
 
-    // Write output
-    let output_path = Path::new(output);
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() && !parent.exists() {
-            fs::create_dir_all(parent)?;
-        }
+    specs
+}
+
+/// Parse the `--min-score` threshold, a number from 0 to 1
+pub fn parse_min_score(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(score) if (0.0..=1.0).contains(&score) => Ok(score),
+        _ => Err(format!("expected a number from 0 to 1, got '{}'", value)),
     }
+}
+
+/// Extract function specifications
+///
+/// With `min_score`, fails after writing the output if any spec scores below it.
+pub fn run(
+    project_path: &str,
+    output: &str,
+    regenerate_stubs: bool,
+    _with_atoms: Option<Option<String>>,
+    min_score: Option<f64>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
 
-    let json = serde_json::to_string_pretty(&specs)?;
-    fs::write(output_path, json)?;
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    // Read stubs.json
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+
+    let specs = build_specs(&stubs, &read_weights(project_path)?);
+
+    // Write output
+    write_map(&specs, Path::new(output), format)?;
+    write_meta(&(), Path::new(output))?;
 
     eprintln!("Wrote {} specs to {}", specs.len(), output);
 
+    if let Some(min_score) = min_score {
+        let mut below: Vec<(&String, f64)> = specs
+            .iter()
+            .filter(|(_, spec)| spec.score < min_score)
+            .map(|(code_name, spec)| (code_name, spec.score))
+            .collect();
+        below.sort_by(|a, b| a.0.cmp(b.0));
+        for (code_name, score) in &below {
+            messages::warning(
+                None,
+                &format!("{} scores {} (below {})", code_name, score, min_score),
+            );
+        }
+        if !below.is_empty() {
+            return Err(format!("{} specs score below {}", below.len(), min_score).into());
+        }
+    }
+
     Ok(())
 }
 
@@ -88,20 +236,113 @@ pub fn run(
 mod tests {
     use super::*;
 
+    fn quality(met: bool) -> Quality {
+        Quality {
+            has_label: met,
+            has_explicit_dependencies: met,
+            statement_nonempty: met,
+            in_mathlib: met,
+        }
+    }
+
     #[test]
     fn test_spec_serialization() {
-        let spec = Spec { specified: true };
+        let spec = Spec {
+            specified: true,
+            quality: quality(true),
+            score: 1.0,
+        };
 
         let json = serde_json::to_string(&spec).unwrap();
-        assert_eq!(json, r#"{"specified":true}"#);
+        assert_eq!(
+            json,
+            r#"{"specified":true,"quality":{"has-label":true,"has-explicit-dependencies":true,"statement-nonempty":true,"in-mathlib":true},"score":1.0}"#
+        );
     }
 
     #[test]
-    fn test_spec_serialization_false() {
-        let spec = Spec { specified: false };
+    fn test_build_specs_quality() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/good": {
+                    "code-name": "probe:Good", "spec-ok": true, "mathlib-ok": true,
+                    "stub-spec": {"lines-start": 1, "lines-end": 4},
+                    "spec-dependencies": ["a.tex/def"]
+                },
+                "a.tex/a0000000000": {
+                    "generated-label": true, "code-name": "probe:Unlabeled",
+                    "stub-spec": {"lines-start": 5, "lines-end": 8},
+                    "spec-dependencies": ["a.tex/def"]
+                },
+                "a.tex/isolated": {
+                    "code-name": "probe:Isolated",
+                    "stub-spec": {"lines-start": 9, "lines-end": 12}
+                },
+                "a.tex/proof_uses": {
+                    "code-name": "probe:ProofUses",
+                    "stub-spec": {"lines-start": 13, "lines-end": 16},
+                    "proof-dependencies": ["a.tex/def"]
+                },
+                "a.tex/empty": {
+                    "code-name": "probe:Empty",
+                    "stub-spec": {"lines-start": 17, "lines-end": 18},
+                    "spec-dependencies": ["a.tex/def"]
+                },
+                "a.tex/split": {
+                    "stub-spec": {"lines-start": 20, "lines-end": 20},
+                    "spec-dependencies": ["a.tex/split_1"]
+                },
+                "a.tex/split_1": {
+                    "code-name": "probe:Split", "spec-dependencies": ["a.tex/def"]
+                },
+                "a.tex/def": {"stub-spec": {"lines-start": 21, "lines-end": 23}}
+            }"#,
+        )
+        .unwrap();
 
-        let json = serde_json::to_string(&spec).unwrap();
-        assert_eq!(json, r#"{"specified":false}"#);
+        let specs = build_specs(&stubs, &Weights::default());
+
+        let score = |code_name: &str| specs[code_name].score;
+        assert_eq!(specs.len(), 6);
+        assert!(specs["probe:Good"].quality.in_mathlib);
+        assert_eq!(score("probe:Good"), 1.0);
+        assert!(!specs["probe:Unlabeled"].quality.has_label);
+        assert_eq!(score("probe:Unlabeled"), 0.75);
+        assert!(!specs["probe:Isolated"].quality.has_explicit_dependencies);
+        assert_eq!(score("probe:Isolated"), 0.75);
+        assert!(specs["probe:ProofUses"].quality.has_explicit_dependencies);
+        assert!(!specs["probe:Empty"].quality.statement_nonempty);
+        assert_eq!(score("probe:Empty"), 0.5);
+        // Judged by the one-line statement of the parent
+        assert!(!specs["probe:Split"].quality.statement_nonempty);
+    }
+
+    #[test]
+    fn test_parse_min_score() {
+        assert_eq!(parse_min_score("0.8"), Ok(0.8));
+        assert_eq!(parse_min_score("1"), Ok(1.0));
+        assert!(parse_min_score("1.5").is_err());
+        assert!(parse_min_score("-0.1").is_err());
+        assert!(parse_min_score("NaN").is_err());
+        assert!(parse_min_score("high").is_err());
+    }
+
+    #[test]
+    fn test_score_weights() {
+        let weights: Weights =
+            serde_json::from_str(r#"{"in-mathlib": 3, "statement-nonempty": 0}"#).unwrap();
+        let mut partial = quality(true);
+        partial.in_mathlib = false;
+        partial.statement_nonempty = false;
+
+        assert_eq!(partial.score(&weights), 0.4);
+        assert_eq!(quality(false).score(&weights), 0.0);
+        let zero: Weights = serde_json::from_str(
+            r#"{"has-label": 0, "has-explicit-dependencies": 0, "statement-nonempty": 0}"#,
+        )
+        .unwrap();
+        assert_eq!(quality(false).score(&zero), 1.0);
+        assert!(serde_json::from_str::<Weights>(r#"{"has-title": 1}"#).is_err());
     }
 
     #[test]
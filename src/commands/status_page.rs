@@ -0,0 +1,514 @@
+//! Status JSON for the LeanBlueprint dependency graph frontend
+//!
+//! Each stub becomes a node named by its label, with the status LeanBlueprint
+//! colors the graph by and its edges in both directions. With the
+//! `leanblueprint` format the same statuses are written as the nodes and
+//! edges LeanBlueprint's d3 graph page draws.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+use super::output::{parse_map, read_text, write_json};
+use super::scaffold::DEFINITION_TYPES;
+use super::stubify;
+
+/// Layout of the status-page output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatusFormat {
+    /// One status node per stub, with its edges in both directions
+    #[default]
+    Status,
+    /// Nodes and edges as LeanBlueprint's dependency graph page consumes them
+    Leanblueprint,
+}
+
+/// Stub entry from stubs.json (only fields we need)
+#[derive(Debug, Deserialize)]
+pub(crate) struct Stub {
+    label: String,
+    #[serde(rename = "stub-type")]
+    stub_type: Option<String>,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
+    #[serde(rename = "stub-proof")]
+    stub_proof: Option<serde_json::Value>,
+    #[serde(rename = "env-options")]
+    env_options: Option<String>,
+    #[serde(rename = "code-name")]
+    code_name: Option<String>,
+    #[serde(rename = "code-names")]
+    lean_names: Option<Vec<String>>,
+    #[serde(rename = "spec-ok")]
+    spec_ok: Option<bool>,
+    #[serde(rename = "mathlib-ok")]
+    mathlib_ok: Option<bool>,
+    #[serde(rename = "not-ready")]
+    not_ready: Option<bool>,
+    #[serde(rename = "spec-dependencies", default)]
+    spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-ok")]
+    proof_ok: Option<bool>,
+    #[serde(rename = "proof-dependencies")]
+    proof_dependencies: Option<Vec<String>>,
+}
+
+/// Node status, as named by LeanBlueprint's dependency graph legend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Status {
+    /// Marked `\notready`
+    NotReady,
+    /// Some statement dependency is not formalized yet
+    Blocked,
+    /// Not formalized, but every statement dependency is
+    CanState,
+    /// Statement formalized (`\leanok`)
+    Stated,
+    /// Statement formalized and every proof dependency proved
+    CanProve,
+    /// Proof formalized (or a formalized statement without a proof)
+    Proved,
+    /// Proved, and so is everything it depends on
+    FullyProved,
+    /// In Mathlib (`\mathlibok`)
+    Mathlib,
+}
+
+/// One node of the status JSON
+#[derive(Debug, Serialize)]
+pub(crate) struct Node {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    status: Status,
+    /// Lean declarations from `\lean{...}`
+    lean_decls: Vec<String>,
+    /// Labels this node depends on, statement and proof
+    uses: Vec<String>,
+    /// Labels whose statement depends on this node
+    used_by: Vec<String>,
+    /// Labels whose proof depends on this node
+    used_in: Vec<String>,
+}
+
+/// Node of the LeanBlueprint dependency graph
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct GraphNode {
+    id: String,
+    label: String,
+    /// `box` for definitions, `ellipse` for everything else
+    shape: &'static str,
+    /// Border class (statement) followed by fill class (proof), when set
+    classes: Vec<&'static str>,
+}
+
+/// Edge of the LeanBlueprint dependency graph, from dependency to dependent
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct GraphEdge {
+    source: String,
+    target: String,
+    /// `dashed` for statement dependencies, `solid` for proof dependencies
+    style: &'static str,
+}
+
+/// LeanBlueprint dependency graph data
+#[derive(Debug, Serialize)]
+pub(crate) struct Graph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+impl Stub {
+    fn proof_dependencies(&self) -> &[String] {
+        self.proof_dependencies.as_deref().unwrap_or_default()
+    }
+
+    /// Statement formalized, in Mathlib or not
+    fn is_stated(&self) -> bool {
+        self.spec_ok == Some(true) || self.mathlib_ok == Some(true)
+    }
+
+    /// Nothing left to prove for this stub itself; a stub without a proof is
+    /// proved once stated, as in `parse::derive_readiness`
+    fn is_proved(&self) -> bool {
+        self.mathlib_ok == Some(true)
+            || match self.stub_proof {
+                Some(_) => self.proof_ok == Some(true),
+                None => self.spec_ok == Some(true),
+            }
+    }
+}
+
+/// Whether `name` and everything it depends on are proved, memoized in
+/// `memo`; dependency cycles count as not fully proved
+fn fully_proved<'a>(
+    name: &'a str,
+    stubs: &'a HashMap<String, Stub>,
+    memo: &mut HashMap<&'a str, bool>,
+) -> bool {
+    if let Some(&done) = memo.get(name) {
+        return done;
+    }
+    let Some(stub) = stubs.get(name) else {
+        return true;
+    };
+    memo.insert(name, false);
+    let done = stub.is_proved()
+        && stub
+            .spec_dependencies
+            .iter()
+            .chain(stub.proof_dependencies())
+            .all(|dep| fully_proved(dep, stubs, memo));
+    memo.insert(name, done);
+    done
+}
+
+/// Status of one stub
+fn status<'a>(
+    name: &'a str,
+    stubs: &'a HashMap<String, Stub>,
+    memo: &mut HashMap<&'a str, bool>,
+) -> Status {
+    let stub = &stubs[name];
+    let all = |deps: &[String], check: fn(&Stub) -> bool| {
+        deps.iter().all(|dep| stubs.get(dep).is_none_or(check))
+    };
+
+    if stub.mathlib_ok == Some(true) {
+        Status::Mathlib
+    } else if stub.is_proved() {
+        if fully_proved(name, stubs, memo) {
+            Status::FullyProved
+        } else {
+            Status::Proved
+        }
+    } else if stub.spec_ok == Some(true) {
+        if all(stub.proof_dependencies(), Stub::is_proved) {
+            Status::CanProve
+        } else {
+            Status::Stated
+        }
+    } else if stub.not_ready == Some(true) {
+        Status::NotReady
+    } else if all(&stub.spec_dependencies, Stub::is_stated) {
+        Status::CanState
+    } else {
+        Status::Blocked
+    }
+}
+
+/// Build the status nodes, sorted by label
+pub(crate) fn build_nodes(stubs: &HashMap<String, Stub>) -> Vec<Node> {
+    let label = |name: &String| stubs.get(name).map_or(name.clone(), |s| s.label.clone());
+
+    let mut used_by: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut used_in: HashMap<&str, Vec<String>> = HashMap::new();
+    for (name, stub) in stubs {
+        for dep in &stub.spec_dependencies {
+            used_by.entry(dep).or_default().push(label(name));
+        }
+        for dep in stub.proof_dependencies() {
+            used_in.entry(dep).or_default().push(label(name));
+        }
+    }
+
+    let mut memo = HashMap::new();
+    let mut nodes: Vec<Node> = stubs
+        .iter()
+        .map(|(name, stub)| {
+            let mut uses: Vec<String> = stub
+                .spec_dependencies
+                .iter()
+                .chain(stub.proof_dependencies())
+                .map(label)
+                .collect();
+            uses.sort();
+            uses.dedup();
+            let sorted = |map: &mut HashMap<&str, Vec<String>>| {
+                let mut labels = map.remove(name.as_str()).unwrap_or_default();
+                labels.sort();
+                labels
+            };
+
+            Node {
+                name: stub.label.clone(),
+                kind: stub.stub_type.clone(),
+                title: stub.env_options.clone(),
+                file: stub.stub_path.clone(),
+                status: status(name, stubs, &mut memo),
+                lean_decls: stub
+                    .lean_names
+                    .clone()
+                    .or_else(|| stub.code_name.clone().map(|name| vec![name]))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|name| name.trim_start_matches("probe:").to_string())
+                    .collect(),
+                uses,
+                used_by: sorted(&mut used_by),
+                used_in: sorted(&mut used_in),
+            }
+        })
+        .collect();
+
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    nodes
+}
+
+/// Border and fill classes of a node, following LeanBlueprint's legend: the
+/// border tells whether the statement is formalized (or can be), the fill
+/// whether the proof is
+fn graph_classes(stub: &Stub, status: Status) -> Vec<&'static str> {
+    let border = match status {
+        Status::Mathlib => Some("mathlib"),
+        _ if stub.spec_ok == Some(true) => Some("stated"),
+        Status::NotReady => Some("not_ready"),
+        Status::CanState => Some("can_state"),
+        _ => None,
+    };
+    let fill = match status {
+        Status::Mathlib | Status::Proved => Some("proved"),
+        Status::FullyProved => Some("fully_proved"),
+        Status::CanProve => Some("can_prove"),
+        _ => None,
+    };
+    border.into_iter().chain(fill).collect()
+}
+
+/// Build the LeanBlueprint graph: nodes sorted by id, edges by target then source
+pub(crate) fn build_graph(stubs: &HashMap<String, Stub>) -> Graph {
+    let label = |name: &String| stubs.get(name).map_or(name.clone(), |s| s.label.clone());
+
+    let mut memo = HashMap::new();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for (name, stub) in stubs {
+        let status = status(name, stubs, &mut memo);
+        let is_definition = stub
+            .stub_type
+            .as_deref()
+            .is_some_and(|t| DEFINITION_TYPES.contains(&t));
+        nodes.push(GraphNode {
+            id: stub.label.clone(),
+            label: stub.label.clone(),
+            shape: if is_definition { "box" } else { "ellipse" },
+            classes: graph_classes(stub, status),
+        });
+        for (deps, style) in [
+            (stub.spec_dependencies.as_slice(), "dashed"),
+            (stub.proof_dependencies(), "solid"),
+        ] {
+            for dep in deps {
+                edges.push(GraphEdge {
+                    source: label(dep),
+                    target: stub.label.clone(),
+                    style,
+                });
+            }
+        }
+    }
+
+    nodes.sort_by(|a, b| a.id.cmp(&b.id));
+    edges.sort_by(|a, b| (&a.target, &a.source, a.style).cmp(&(&b.target, &b.source, b.style)));
+    edges.dedup();
+    Graph { nodes, edges }
+}
+
+/// Status of every stub by label, and the dependency edges (statement and
+/// proof alike) as `(dependency, dependent)` labels
+pub(crate) type StatusGraph = (BTreeMap<String, Status>, BTreeSet<(String, String)>);
+
+/// The `StatusGraph` of `stubs`
+pub(crate) fn status_graph(stubs: &HashMap<String, Stub>) -> StatusGraph {
+    let label = |name: &String| stubs.get(name).map_or(name.clone(), |s| s.label.clone());
+
+    let mut memo = HashMap::new();
+    let mut statuses = BTreeMap::new();
+    let mut edges = BTreeSet::new();
+    for (name, stub) in stubs {
+        statuses.insert(stub.label.clone(), status(name, stubs, &mut memo));
+        for dep in stub
+            .spec_dependencies
+            .iter()
+            .chain(stub.proof_dependencies())
+        {
+            edges.insert((label(dep), stub.label.clone()));
+        }
+    }
+    (statuses, edges)
+}
+
+/// Write the LeanBlueprint status JSON (or graph data) for a project
+pub fn run(
+    project_path: &str,
+    output: &str,
+    regenerate_stubs: bool,
+    format: StatusFormat,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+
+    if format == StatusFormat::Leanblueprint {
+        let graph = build_graph(&stubs);
+        write_json(&graph, Path::new(output))?;
+        eprintln!(
+            "Wrote {} nodes and {} edges to {}",
+            graph.nodes.len(),
+            graph.edges.len(),
+            output
+        );
+        return Ok(());
+    }
+
+    let nodes = build_nodes(&stubs);
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for node in &nodes {
+        let status = serde_json::to_value(node.status)?;
+        *counts
+            .entry(status.as_str().unwrap_or_default().to_string())
+            .or_default() += 1;
+    }
+
+    write_json(&nodes, Path::new(output))?;
+
+    eprintln!(
+        "Wrote {} nodes to {} ({})",
+        nodes.len(),
+        output,
+        counts
+            .iter()
+            .map(|(status, count)| format!("{} {}", count, status))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_build_nodes_statuses_and_edges() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/def": {"label": "def", "stub-type": "definition", "spec-ok": true},
+                "a.tex/lib": {"label": "lib", "code-name": "probe:Nat.add_comm", "mathlib-ok": true},
+                "a.tex/lem": {
+                    "label": "lem", "stub-type": "lemma", "stub-path": "a.tex",
+                    "code-name": "probe:Foo.lem",
+                    "spec-ok": true, "proof-ok": true,
+                    "stub-proof": {"lines-start": 1, "lines-end": 2},
+                    "spec-dependencies": ["a.tex/def"],
+                    "proof-dependencies": ["a.tex/lib"]
+                },
+                "a.tex/thm": {
+                    "label": "thm", "env-options": "Main", "spec-ok": true,
+                    "stub-proof": {"lines-start": 1, "lines-end": 2},
+                    "proof-dependencies": ["a.tex/lem", "a.tex/open"]
+                },
+                "a.tex/open": {"label": "open", "spec-ok": false, "spec-dependencies": ["a.tex/def"]},
+                "a.tex/far": {"label": "far", "spec-ok": false, "spec-dependencies": ["a.tex/open"]},
+                "a.tex/later": {"label": "later", "not-ready": true}
+            }"#,
+        )
+        .unwrap();
+
+        let nodes = build_nodes(&stubs);
+        let statuses: Vec<(&str, Status)> = nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("def", Status::FullyProved),
+                ("far", Status::Blocked),
+                ("later", Status::NotReady),
+                ("lem", Status::FullyProved),
+                ("lib", Status::Mathlib),
+                ("open", Status::CanState),
+                ("thm", Status::Stated),
+            ]
+        );
+
+        let def = &nodes[0];
+        assert_eq!(def.used_by, vec!["lem", "open"]);
+        assert!(def.used_in.is_empty());
+        let lem = &nodes[3];
+        assert_eq!(lem.lean_decls, vec!["Foo.lem"]);
+        assert_eq!(lem.uses, vec!["def", "lib"]);
+        assert_eq!(lem.used_in, vec!["thm"]);
+    }
+
+    #[test]
+    fn test_unproved_proof_of_parsed_stub() {
+        let content = r"\begin{lemma}\label{lem}\leanok
+A lemma.
+\end{lemma}
+\begin{proof}
+Not formalized.
+\end{proof}
+
+\begin{definition}\label{def}\leanok
+A definition.
+\end{definition}
+";
+        let parsed =
+            crate::parse::parse_sources(&[("a.tex".to_string(), content.to_string())]).unwrap();
+        let stubs: HashMap<String, Stub> =
+            serde_json::from_value(serde_json::to_value(&parsed.stubs).unwrap()).unwrap();
+
+        let statuses: Vec<(String, Status)> = build_nodes(&stubs)
+            .into_iter()
+            .map(|node| (node.name, node.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("def".to_string(), Status::FullyProved),
+                ("lem".to_string(), Status::CanProve),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_graph_matches_fixture() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/leanblueprint");
+        let stubs: HashMap<String, Stub> =
+            parse_map(&fs::read_to_string(dir.join("stubs.json")).unwrap()).unwrap();
+        let expected: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("graph.json")).unwrap()).unwrap();
+
+        assert_eq!(serde_json::to_value(build_graph(&stubs)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_node_serialization() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{"a.tex/x": {"label": "x", "stub-type": "lemma", "spec-ok": true, "stub-proof": {"lines-start": 1, "lines-end": 2}}}"#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_string(&build_nodes(&stubs)).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"name":"x","kind":"lemma","status":"can_prove","lean_decls":[],"uses":[],"used_by":[],"used_in":[]}]"#
+        );
+    }
+}
@@ -1,887 +1,1188 @@
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::fmt::Write as _;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
-/// Project-level configuration extracted from LaTeX files
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct Config {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub home: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub github: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub dochome: Option<String>,
+use super::output::{
+    find_input, meta_path, parse_map, read_text, write_json, write_map, write_meta, write_text,
+    OutputFormat,
+};
+use super::{lake, lean};
+use crate::error::ProbeError;
+use crate::messages::{self, Location};
+use crate::parse::{self, Config, ParseOptions, ParsedBlueprint, Stub};
+use crate::profile::Profile;
+
+/// Options for the stubify command
+#[derive(Debug, Default, Clone)]
+pub struct StubifyOptions {
+    /// Print a timing breakdown per processing stage to stderr
+    pub profile: bool,
+    /// Print how environments, proofs, macros and labels were matched to stderr
+    pub trace_parse: bool,
+    /// Number of threads used to parse files (default: one per CPU)
+    pub jobs: Option<usize>,
+    /// JSON file mapping labels to Lean declaration names
+    pub lean_names_file: Option<String>,
+    /// Keep `\lean{...}` names from the source over the Lean names file
+    pub prefer_source: bool,
+    /// Look up the `code-name` declarations in this Lean source root, or in
+    /// the roots from the Lake configuration for `Some(None)`
+    pub locate_lean: Option<Option<String>>,
+    /// Also write a map from every label to its stub-name to this file
+    pub emit_all_labels_map: Option<String>,
+    /// Collect blueprint annotations from this Lean source root, or from the
+    /// roots from the Lake configuration for `Some(None)`
+    pub lean_annotations: Option<Option<String>>,
+    /// Regex for annotation markers; the first group holds the labels
+    pub lean_annotation_marker: Option<String>,
+    /// Record the number LaTeX gives each environment
+    pub with_numbering: bool,
+    /// Rewrite code-names found by suffix to their fully qualified form
+    pub qualify_names: bool,
+    /// Also write a BibTeX entry per stub to this file
+    pub emit_bibtex: Option<String>,
+    /// Also write a Markdown review checklist to this file
+    pub emit_review_report: Option<String>,
+    /// Layout of the stubs file
+    pub format: OutputFormat,
+    /// Renames of `stub-type` values in the output (e.g. `dfn` -> `definition`)
+    pub env_type_map: BTreeMap<String, String>,
+    /// Warn about .tex files with more environments than this
+    pub warn_if_file_has_more_than: Option<usize>,
+    /// Make dependent commands read the stubs from this output (`Some(true)`,
+    /// for a templated `--output`) or from `.verilib/stubs.json` again
+    /// (`Some(false)`); `None` leaves the recorded location as it is
+    pub record_output: Option<bool>,
+    /// Warn instead of failing when two standalone proofs prove the same label
+    pub warn_duplicate_proofs: bool,
+    /// Fail instead of warning when a proof macro contradicts the statement's
+    pub strict: bool,
+    /// Take several standalone proofs of one label as parts of one proof
+    pub proof_parts: bool,
+    /// Environment types allowed between a statement and its proof
+    pub proof_after_envs: Vec<String>,
+    /// Bytes of whitespace allowed between a statement and its proof
+    /// (default `parse::DEFAULT_PROOF_LOOKAHEAD_BYTES`)
+    pub proof_lookahead: Option<usize>,
+    /// Record the lines of the status and dependency macros of each stub
+    pub macro_positions: bool,
+    /// Ignore `\uses` at sectioning level instead of applying it to the
+    /// stubs under the heading
+    pub no_section_uses: bool,
+    /// Macro that drops its environment (default `blueprintskip`)
+    pub skip_marker: Option<String>,
+    /// Reparse only these files (relative to `blueprint/src`) and patch their
+    /// stubs into the existing output
+    pub only: Vec<String>,
+    /// Directory the `stub-path`s are relative to
+    pub path_base: PathBase,
 }
 
-/// Default LaTeX environments to look for (from leanblueprint defaults)
-const DEFAULT_ENVS: &[&str] = &["definition", "lemma", "proposition", "theorem", "corollary"];
-
-/// Line range for source locations
-#[derive(Debug, Serialize, Clone, Copy)]
-pub struct LineRange {
-    #[serde(rename = "lines-start")]
-    pub lines_start: usize,
-    #[serde(rename = "lines-end")]
-    pub lines_end: usize,
-}
-
-/// Helper function for serde to skip empty Vec
-fn vec_is_empty(v: &[String]) -> bool {
-    v.is_empty()
-}
-
-#[derive(Debug, Serialize, Clone)]
-pub struct Stub {
-    pub label: String,
-    #[serde(rename = "stub-type", skip_serializing_if = "Option::is_none")]
-    pub stub_type: Option<String>,
-    #[serde(rename = "stub-path", skip_serializing_if = "Option::is_none")]
-    pub stub_path: Option<String>,
-    #[serde(rename = "stub-spec", skip_serializing_if = "Option::is_none")]
-    pub stub_spec: Option<LineRange>,
-    #[serde(rename = "stub-proof", skip_serializing_if = "Option::is_none")]
-    pub stub_proof: Option<LineRange>,
-    #[serde(rename = "code-name", skip_serializing_if = "Option::is_none")]
-    pub code_name: Option<String>,
-    #[serde(rename = "code-names", skip_serializing_if = "Option::is_none")]
-    pub lean_names: Option<Vec<String>>,
-    #[serde(rename = "spec-ok", skip_serializing_if = "Option::is_none")]
-    pub spec_ok: Option<bool>,
-    #[serde(rename = "mathlib-ok", skip_serializing_if = "Option::is_none")]
-    pub mathlib_ok: Option<bool>,
-    #[serde(rename = "not-ready", skip_serializing_if = "Option::is_none")]
-    pub not_ready: Option<bool>,
-    #[serde(rename = "discussion", skip_serializing_if = "Vec::is_empty")]
-    pub discussion: Vec<String>,
-    #[serde(rename = "spec-dependencies", skip_serializing_if = "vec_is_empty")]
-    pub spec_dependencies: Vec<String>,
-    #[serde(rename = "proof-ok", skip_serializing_if = "Option::is_none")]
-    pub proof_ok: Option<bool>,
-    #[serde(rename = "proof-mathlib-ok", skip_serializing_if = "Option::is_none")]
-    pub proof_mathlib_ok: Option<bool>,
-    #[serde(rename = "proof-not-ready", skip_serializing_if = "Option::is_none")]
-    pub proof_not_ready: Option<bool>,
-    #[serde(rename = "proof-discussion", skip_serializing_if = "Option::is_none")]
-    pub proof_discussion: Option<Vec<String>>,
-    #[serde(rename = "proof-dependencies", skip_serializing_if = "Option::is_none")]
-    pub proof_dependencies: Option<Vec<String>>,
-    #[serde(rename = "proof-lean-names", skip_serializing_if = "Option::is_none")]
-    pub proof_lean_names: Option<Vec<String>>,
+/// Blueprint sources, relative to the project root
+const SRC_DIR: &str = "blueprint/src";
+
+/// Directory the `stub-path`s of stubs.json are relative to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PathBase {
+    /// `blueprint/src`, as in stub-names
+    #[default]
+    Src,
+    /// The project root, for consumers that open files or build links from it
+    Project,
 }
 
-/// Extract environment types from the `thms` option in web.tex
-/// e.g., \usepackage[thms=dfn+lem+prop+thm+cor]{blueprint}
-fn parse_thms_option(web_tex_content: &str) -> Vec<String> {
-    // Look for \usepackage[...thms=...]{blueprint}
-    let re = Regex::new(r"\\usepackage\s*\[([^\]]*)\]\s*\{blueprint\}").unwrap();
-
-    if let Some(caps) = re.captures(web_tex_content) {
-        let options = &caps[1];
-        // Look for thms=xxx+yyy+zzz
-        let thms_re = Regex::new(r"thms\s*=\s*([a-zA-Z+_]+)").unwrap();
-        if let Some(thms_caps) = thms_re.captures(options) {
-            let thms_str = &thms_caps[1];
-            return thms_str.split('+').map(|s| s.trim().to_string()).collect();
+/// Make the `stub-path`s of `stubs` relative to `base`, whichever base they
+/// are relative to now
+fn rebase_paths(stubs: &mut HashMap<String, Stub>, base: PathBase) {
+    let rebase = |path: &mut String| {
+        let relative = src_relative(path);
+        *path = match base {
+            PathBase::Src => relative.to_string(),
+            PathBase::Project => format!("{}/{}", SRC_DIR, relative),
+        };
+    };
+    for stub in stubs.values_mut() {
+        if let Some(path) = &mut stub.stub_path {
+            rebase(path);
+        }
+        for part in &mut stub.stub_proof_parts {
+            rebase(&mut part.stub_path);
         }
     }
+}
 
-    // Return default environments if no thms option found
-    DEFAULT_ENVS.iter().map(|s| s.to_string()).collect()
+/// A `stub-path` relative to `blueprint/src`, whichever base it was written with
+pub(crate) fn src_relative(stub_path: &str) -> &str {
+    stub_path
+        .strip_prefix(SRC_DIR)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .unwrap_or(stub_path)
 }
 
-/// Strip LaTeX comments from content, preserving line structure
-/// Comments start with % and go to end of line, but \% is an escaped percent sign
-fn strip_latex_comments(content: &str) -> String {
-    let mut result = String::new();
-    let mut chars = content.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            // Escaped character - include both the backslash and next char
-            result.push(c);
-            if chars.peek().is_some() {
-                result.push(chars.next().unwrap());
+impl StubifyOptions {
+    /// Build the parse options, reading the Lean names file and Lean-side
+    /// annotations if set, with the exclusions of the project `config`
+    fn parse_options(
+        &self,
+        lean_roots: &LeanRoots,
+        config: &Config,
+    ) -> Result<ParseOptions, Box<dyn Error>> {
+        let lean_names: HashMap<String, String> = match &self.lean_names_file {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read Lean names file {}: {}", path, e))?;
+                serde_json::from_str(&content)
+                    .map_err(|e| format!("Invalid Lean names file {}: {}", path, e))?
             }
-        } else if c == '%' {
-            // Comment - skip until end of line, but preserve the newline
-            while let Some(&next) = chars.peek() {
-                if next == '\n' {
-                    break;
+            None => HashMap::new(),
+        };
+
+        let mut lean_annotations: HashMap<String, Vec<String>> = HashMap::new();
+        if let Some(lean_src_roots) = &lean_roots.annotations {
+            let marker = Regex::new(
+                self.lean_annotation_marker
+                    .as_deref()
+                    .unwrap_or(lean::DEFAULT_ANNOTATION_MARKER),
+            )?;
+            let mut modules: Vec<lean::LeanModule> =
+                lean::scan_lean_modules_with(lean_src_roots, Some(&marker))?
+                    .into_values()
+                    .collect();
+            modules.sort_by(|a, b| a.path.cmp(&b.path));
+            for module in modules {
+                for (label, declaration) in module.annotations {
+                    lean_annotations.entry(label).or_default().push(declaration);
                 }
-                chars.next();
             }
-        } else {
-            result.push(c);
         }
-    }
-
-    result
-}
 
-/// Strip nested environments from content (e.g., equation, align, etc. inside a proof)
-/// This ensures we only extract top-level labels, not labels from nested environments
-fn strip_nested_environments(content: &str) -> String {
-    let begin_re = Regex::new(r"\\begin\{([^}]+)\}").unwrap();
-
-    let mut result = String::new();
-    let mut pos = 0;
-
-    while pos < content.len() {
-        // Look for \begin{...} starting from current position
-        if let Some(caps) = begin_re.captures(&content[pos..]) {
-            let full_match = caps.get(0).unwrap();
-            let env_name = &caps[1];
-
-            // Add content before this \begin
-            let begin_start = pos + full_match.start();
-            result.push_str(&content[pos..begin_start]);
-
-            // Find the matching \end{env_name}
-            let end_pattern = format!(r"\\end\{{{}\}}", regex::escape(env_name));
-            let end_re = Regex::new(&end_pattern).unwrap();
-
-            let search_start = pos + full_match.end();
-            if let Some(end_match) = end_re.find(&content[search_start..]) {
-                // Skip past the entire nested environment
-                pos = search_start + end_match.end();
-            } else {
-                // No matching \end found, include the \begin and continue
-                result.push_str(full_match.as_str());
-                pos += full_match.end();
-            }
-        } else {
-            // No more \begin found, add remaining content
-            result.push_str(&content[pos..]);
-            break;
-        }
+        Ok(ParseOptions {
+            lean_names,
+            prefer_source: self.prefer_source,
+            lean_annotations,
+            with_numbering: self.with_numbering,
+            skip_files: config.skip_files.clone(),
+            macros: config.macros.clone(),
+            ignored_envs: config.ignored_envs.clone(),
+            warn_duplicate_proofs: self.warn_duplicate_proofs,
+            proof_parts: self.proof_parts,
+            proof_after_envs: self.proof_after_envs.clone(),
+            proof_lookahead: self.proof_lookahead,
+            macro_positions: self.macro_positions,
+            no_section_uses: self.no_section_uses,
+            skip_marker: self.skip_marker.clone(),
+            known_labels: HashMap::new(),
+            skip_readiness: false,
+            trace: self.trace_parse,
+        })
     }
-
-    result
 }
 
-/// Extract all top-level labels from \label{...} in order of appearance
-/// Labels inside nested environments (like equation, align) are ignored
-fn extract_all_labels(content: &str) -> Vec<String> {
-    // First strip nested environments to only get top-level labels
-    let top_level_content = strip_nested_environments(content);
-
-    let re = Regex::new(r"\\label\{([^}]+)\}").unwrap();
-    re.captures_iter(&top_level_content)
-        .map(|caps| caps[1].to_string())
-        .collect()
+/// Lean source roots of the options that scan Lean sources, resolved once per run
+#[derive(Debug, Default)]
+struct LeanRoots {
+    locate: Option<Vec<PathBuf>>,
+    annotations: Option<Vec<PathBuf>>,
 }
 
-/// Extract lean declarations from \lean{...}
-/// Returns a list of declaration names (comma-separated in the macro)
-fn extract_lean(content: &str) -> Vec<String> {
-    let re = Regex::new(r"\\lean\{([^}]+)\}").unwrap();
-    if let Some(caps) = re.captures(content) {
-        let lean_str = &caps[1];
-        return lean_str
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
+impl LeanRoots {
+    fn resolve(project_path: &Path, options: &StubifyOptions) -> Self {
+        let resolve = |root: &Option<Option<String>>| {
+            root.as_ref()
+                .map(|root| lake::resolve_lean_roots(project_path, root.as_deref()))
+        };
+        LeanRoots {
+            locate: resolve(&options.locate_lean),
+            annotations: resolve(&options.lean_annotations),
+        }
     }
-    Vec::new()
 }
 
-/// Check for \mathlibok macro
-fn extract_mathlibok(content: &str) -> bool {
-    content.contains(r"\mathlibok")
-}
-
-/// Check for \notready macro
-fn extract_notready(content: &str) -> bool {
-    content.contains(r"\notready")
-}
+/// Read all .tex files under `blueprint_src` as `(relative_path, content)` pairs
+fn read_sources(blueprint_src: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    for entry in WalkDir::new(blueprint_src)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "tex") {
+            let content = fs::read_to_string(path)?;
 
-/// Extract discussion issue numbers from \discussion{...}
-/// Can appear multiple times, so returns a list
-fn extract_discussion(content: &str) -> Vec<String> {
-    let re = Regex::new(r"\\discussion\{([^}]+)\}").unwrap();
-    re.captures_iter(content)
-        .map(|caps| caps[1].trim().to_string())
-        .collect()
-}
+            // Get path relative to blueprint/src
+            let relative_path = path
+                .strip_prefix(blueprint_src)?
+                .to_str()
+                .ok_or("Invalid UTF-8 in path")?;
 
-/// Extract labels from \proves{...}
-/// Returns a list of labels that this proof proves
-fn extract_proves(content: &str) -> Vec<String> {
-    let re = Regex::new(r"\\proves\{([^}]+)\}").unwrap();
-    if let Some(caps) = re.captures(content) {
-        let proves_str = &caps[1];
-        return proves_str
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
+            files.push((parse::normalize_path(relative_path), content));
+        }
     }
-    Vec::new()
+    Ok(files)
 }
 
-/// Extract dependencies from \uses{...}
-fn extract_uses(content: &str) -> Vec<String> {
-    let re = Regex::new(r"\\uses\{([^}]+)\}").unwrap();
-    if let Some(caps) = re.captures(content) {
-        let uses_str = &caps[1];
-        return uses_str
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
+/// Read the sources under `blueprint/src` of a project
+fn load_sources(
+    project_path: &Path,
+    profile: &mut Profile,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let blueprint_src = project_path.join("blueprint").join("src");
+
+    if !blueprint_src.exists() {
+        return Err(ProbeError::MissingBlueprintSrc {
+            path: blueprint_src.display().to_string(),
+        }
+        .into());
     }
-    Vec::new()
-}
 
-/// Extract \home{url} from content
-fn extract_home(content: &str) -> Option<String> {
-    let re = Regex::new(r"\\home\{([^}]+)\}").unwrap();
-    re.captures(content).map(|caps| caps[1].trim().to_string())
-}
+    let start = profile.start();
+    let files = read_sources(&blueprint_src)?;
+    profile.record("file discovery", start);
 
-/// Extract \github{url} from content
-fn extract_github(content: &str) -> Option<String> {
-    let re = Regex::new(r"\\github\{([^}]+)\}").unwrap();
-    re.captures(content).map(|caps| caps[1].trim().to_string())
+    Ok(files)
 }
 
-/// Extract \dochome{url} from content
-fn extract_dochome(content: &str) -> Option<String> {
-    let re = Regex::new(r"\\dochome\{([^}]+)\}").unwrap();
-    re.captures(content).map(|caps| caps[1].trim().to_string())
+/// File recording where the stubs of a project were last written to, when
+/// that is not `.verilib/stubs.json`
+fn outputs_record_path(project_path: &Path) -> PathBuf {
+    project_path.join(".verilib").join("outputs.json")
 }
 
-/// Extract project config from content
-fn extract_config(content: &str) -> Config {
-    Config {
-        home: extract_home(content),
-        github: extract_github(content),
-        dochome: extract_dochome(content),
+/// Path of the stubs that dependent commands read: the templated output
+/// recorded by the last `stubify` run, else `.verilib/stubs.json` (or a
+/// compressed variant)
+pub(crate) fn stubs_input(project_path: &Path) -> PathBuf {
+    let recorded: Option<String> = read_text(&outputs_record_path(project_path))
+        .ok()
+        .and_then(|content| serde_json::from_str::<HashMap<String, String>>(&content).ok())
+        .and_then(|mut outputs| outputs.remove("stubs"));
+    match recorded {
+        Some(path) => find_input(Path::new(&path)),
+        None => find_input(&project_path.join(".verilib").join("stubs.json")),
     }
 }
 
-/// Merge two configs, preferring values from `other` if present
-fn merge_config(base: Config, other: Config) -> Config {
-    Config {
-        home: other.home.or(base.home),
-        github: other.github.or(base.github),
-        dochome: other.dochome.or(base.dochome),
+/// Record `output` as the location of the stubs of `project_path`, or forget
+/// the recorded location for `None`
+fn record_stubs_output(project_path: &Path, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let record_path = outputs_record_path(project_path);
+    match output {
+        Some(output) => {
+            write_json(&BTreeMap::from([("stubs", output)]), &record_path)?;
+        }
+        None if record_path.exists() => fs::remove_file(&record_path)?,
+        None => {}
     }
+    Ok(())
 }
 
-/// Generate a fresh label in the form "a0000000000"
-fn generate_label(counter: u64) -> String {
-    format!("a{:010}", counter)
+/// The project's `.verilib/config.json` (the defaults if it is missing); a
+/// config that does not deserialize is an error rather than silently ignored
+pub(crate) fn read_config(project_path: &Path) -> Result<Config, Box<dyn Error>> {
+    let config_path = project_path.join(".verilib").join("config.json");
+    if !config_path.exists() {
+        return Ok(Config::default());
+    }
+    let config = serde_json::from_str(&read_text(&config_path)?)
+        .map_err(|e| format!("Invalid {}: {}", config_path.display(), e))?;
+    Ok(config)
 }
 
-/// Convert a byte position to a 1-indexed line number
-fn byte_pos_to_line(content: &str, pos: usize) -> usize {
-    content[..pos].chars().filter(|&c| c == '\n').count() + 1
+/// Parse the blueprint of a project with the default options without
+/// writing any output
+pub fn parse_project(project_path: &Path) -> Result<ParsedBlueprint, Box<dyn Error>> {
+    build_project(project_path, &StubifyOptions::default())
 }
 
-/// Parsed environment before label validation
-struct ParsedEnv {
-    env_type: String,
-    relative_path: String,
-    spec_lines: LineRange,
-    proof_lines: Option<LineRange>,
-    labels: Vec<String>,
-    code_name: Option<String>,
-    lean_names: Option<Vec<String>>,
-    spec_ok: bool,
-    mathlib_ok: bool,
-    not_ready: bool,
-    discussion: Vec<String>,
-    spec_dependencies: Vec<String>,
-    proof_ok: Option<bool>,
-    proof_mathlib_ok: Option<bool>,
-    proof_not_ready: Option<bool>,
-    proof_discussion: Option<Vec<String>>,
-    proof_dependencies: Option<Vec<String>>,
-    proof_lean_names: Option<Vec<String>>,
+/// Parse the blueprint of a project in memory as `run` does with `options`,
+/// honoring `.verilib/config.json`, without writing any output
+pub fn build_project(
+    project_path: &Path,
+    options: &StubifyOptions,
+) -> Result<ParsedBlueprint, Box<dyn Error>> {
+    let lean_roots = LeanRoots::resolve(project_path, options);
+    let mut profile = Profile::new(options.profile);
+    let files = load_sources(project_path, &mut profile)?;
+    let parse_options = options.parse_options(&lean_roots, &read_config(project_path)?)?;
+    let mut parsed = build(&files, options, &lean_roots, &parse_options, &mut profile)?;
+    rebase_paths(&mut parsed.stubs, options.path_base);
+    Ok(parsed)
 }
 
-/// A standalone proof that uses \proves to reference its statement
-struct StandaloneProof {
-    proves_labels: Vec<String>,
-    lines: LineRange,
-    proof_ok: bool,
-    mathlib_ok: bool,
-    not_ready: bool,
-    discussion: Vec<String>,
-    dependencies: Vec<String>,
-    lean_names: Vec<String>,
+/// Parse sources on a thread pool of `jobs` threads, or on the global pool
+fn parse_with_jobs(
+    files: &[(String, String)],
+    jobs: Option<usize>,
+    options: &ParseOptions,
+    profile: &mut Profile,
+) -> Result<ParsedBlueprint, Box<dyn Error>> {
+    eprintln!(
+        "Looking for environments: {}",
+        parse::env_types(files).join(", ")
+    );
+    // ProbeError is Send, so it comes out of the pool as it is and reports
+    // the same machine-readable error as a sequential run
+    let parsed = match jobs {
+        Some(jobs) => {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+            pool.install(|| parse::parse_sources_with(files, options, profile))?
+        }
+        None => parse::parse_sources_with(files, options, profile)?,
+    };
+    eprintln!("Found {} stubs", parsed.stubs.len());
+    Ok(parsed)
 }
 
-/// Proof match result with content and line range
-struct ProofMatch {
-    content: String,
-    lines: LineRange,
-    /// Labels from \proves{...} - if present, this is a standalone proof
-    proves_labels: Vec<String>,
+/// Fingerprint of a stubs.json, stored next to it as `<name>.meta.json`
+///
+/// Records the options stubs.json was generated with, so that dependent
+/// commands can check and regenerate it the same way.
+#[derive(Debug, Serialize, Deserialize)]
+struct StubsMeta {
+    fingerprint: String,
+    #[serde(rename = "lean-names-file")]
+    lean_names_file: Option<String>,
+    #[serde(rename = "prefer-source")]
+    prefer_source: bool,
+    /// Omitted when off, `null` for the roots from the Lake configuration
+    #[serde(
+        rename = "locate-lean",
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "double_option"
+    )]
+    locate_lean: Option<Option<String>>,
+    #[serde(
+        rename = "lean-annotations",
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "double_option"
+    )]
+    lean_annotations: Option<Option<String>>,
+    #[serde(rename = "lean-annotation-marker", default)]
+    lean_annotation_marker: Option<String>,
+    #[serde(rename = "with-numbering", default)]
+    with_numbering: bool,
+    #[serde(rename = "qualify-names", default)]
+    qualify_names: bool,
+    /// Not part of the fingerprint: both formats hold the same stubs
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(
+        rename = "env-type-map",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    env_type_map: BTreeMap<String, String>,
+    /// Not part of the fingerprint: it decides whether stubify fails, not
+    /// what the stubs contain
+    #[serde(rename = "warn-duplicate-proofs", default)]
+    warn_duplicate_proofs: bool,
+    /// Not part of the fingerprint, like `warn-duplicate-proofs`
+    #[serde(rename = "proof-parts", default)]
+    proof_parts: bool,
+    /// Not part of the fingerprint, like `warn-duplicate-proofs`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    strict: bool,
+    #[serde(
+        rename = "proof-after-envs",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    proof_after_envs: Vec<String>,
+    #[serde(
+        rename = "proof-lookahead",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    proof_lookahead: Option<usize>,
+    #[serde(rename = "macro-positions", default)]
+    macro_positions: bool,
+    #[serde(rename = "no-section-uses", default)]
+    no_section_uses: bool,
+    #[serde(
+        rename = "skip-marker",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    skip_marker: Option<String>,
+    /// Always written, so consumers can tell how to resolve `stub-path`s
+    #[serde(rename = "path-base", default)]
+    path_base: PathBase,
 }
 
-/// Find the proof environment that immediately follows a position in the content
-/// Returns the proof content and line range if found
-fn find_following_proof(content: &str, after_pos: usize) -> Option<ProofMatch> {
-    let remaining = &content[after_pos..];
-
-    // Look for \begin{proof} that appears next (allowing only whitespace before it)
-    let proof_re = Regex::new(r"(?s)^\s*(\\begin\{proof\})(.*?)\\end\{proof\}").unwrap();
-
-    proof_re.captures(remaining).map(|caps| {
-        // Get the position of \begin{proof} itself, not the leading whitespace
-        let begin_match = caps.get(1).unwrap();
-        let proof_start = after_pos + begin_match.start();
-        let full_match = caps.get(0).unwrap();
-        let proof_end = after_pos + full_match.end();
-        let proof_content = caps[2].to_string();
-
-        // Extract \proves{...} labels if present
-        let proves_labels = extract_proves(&proof_content);
-
-        ProofMatch {
-            content: proof_content,
-            lines: LineRange {
-                lines_start: byte_pos_to_line(content, proof_start),
-                lines_end: byte_pos_to_line(content, proof_end - 1), // -1 to get line of last char
-            },
-            proves_labels,
-        }
-    })
+/// Deserialize a present field (even `null`) as `Some`, so that a missing
+/// field and `null` can be told apart
+fn double_option<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(Some)
 }
 
-/// Find all standalone proofs (those with \proves) in a file
-fn find_standalone_proofs(content: &str, relative_path: &str) -> Vec<StandaloneProof> {
-    let mut proofs = Vec::new();
+/// Hash of the tool version, every source file and the options that affect
+/// the generated stubs
+fn fingerprint(
+    files: &[(String, String)],
+    options: &StubifyOptions,
+    lean_roots: &LeanRoots,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    let mut update = |bytes: &[u8]| {
+        // Length-prefix every field so that concatenations cannot collide
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
 
-    // Strip LaTeX comments before parsing
-    let content = strip_latex_comments(content);
+    update(env!("CARGO_PKG_VERSION").as_bytes());
 
-    // Find all \begin{proof}...\end{proof} environments
-    let proof_re = Regex::new(r"(?s)\\begin\{proof\}(.*?)\\end\{proof\}").unwrap();
+    let mut sorted: Vec<&(String, String)> = files.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    for (relative_path, content) in sorted {
+        update(relative_path.as_bytes());
+        update(content.as_bytes());
+    }
 
-    for caps in proof_re.captures_iter(&content) {
-        let full_match = caps.get(0).unwrap();
-        let proof_content = &caps[1];
+    match &options.lean_names_file {
+        Some(path) => update(&fs::read(path)?),
+        None => update(&[]),
+    }
+    update(&[options.prefer_source as u8]);
 
-        // Check if this proof has \proves
-        let proves_labels = extract_proves(proof_content);
-        if proves_labels.is_empty() {
-            continue; // Not a standalone proof
+    for lean_src_roots in [&lean_roots.locate, &lean_roots.annotations] {
+        match lean_src_roots {
+            Some(lean_src_roots) => {
+                for root in lean_src_roots {
+                    update(root.to_string_lossy().as_bytes());
+                }
+                for (relative_path, content) in lean::read_lean_sources(lean_src_roots)? {
+                    update(relative_path.as_bytes());
+                    update(content.as_bytes());
+                }
+            }
+            None => update(&[]),
         }
-
-        let lines = LineRange {
-            lines_start: byte_pos_to_line(&content, full_match.start()),
-            lines_end: byte_pos_to_line(&content, full_match.end() - 1),
-        };
-
-        proofs.push(StandaloneProof {
-            proves_labels,
-            lines,
-            proof_ok: proof_content.contains(r"\leanok"),
-            mathlib_ok: extract_mathlibok(proof_content),
-            not_ready: extract_notready(proof_content),
-            discussion: extract_discussion(proof_content),
-            dependencies: extract_uses(proof_content),
-            lean_names: extract_lean(proof_content),
-        });
     }
-
-    // Store relative_path for error messages (currently unused but available)
-    let _ = relative_path;
-
-    proofs
+    update(
+        options
+            .lean_annotation_marker
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    update(&[options.with_numbering as u8]);
+    update(&[options.qualify_names as u8]);
+    update(&(options.env_type_map.len() as u64).to_le_bytes());
+    for (from, to) in &options.env_type_map {
+        update(from.as_bytes());
+        update(to.as_bytes());
+    }
+    update(&(options.proof_after_envs.len() as u64).to_le_bytes());
+    for env_type in &options.proof_after_envs {
+        update(env_type.as_bytes());
+    }
+    update(
+        &(options
+            .proof_lookahead
+            .unwrap_or(parse::DEFAULT_PROOF_LOOKAHEAD_BYTES) as u64)
+            .to_le_bytes(),
+    );
+    update(&[options.macro_positions as u8]);
+    update(&[options.no_section_uses as u8]);
+    update(
+        options
+            .skip_marker
+            .as_deref()
+            .unwrap_or(parse::DEFAULT_SKIP_MARKER)
+            .as_bytes(),
+    );
+    update(&(config.skip_files.len() as u64).to_le_bytes());
+    for file_name in &config.skip_files {
+        update(file_name.as_bytes());
+    }
+    update(serde_json::to_string(&config.macros)?.as_bytes());
+    update(&[options.path_base as u8]);
+
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-/// Parse a single .tex file and extract environments
-fn parse_tex_file(content: &str, relative_path: &str, env_types: &[String]) -> Vec<ParsedEnv> {
-    let mut envs = Vec::new();
-
-    // Strip LaTeX comments before parsing (preserves line structure)
-    let content = strip_latex_comments(content);
+/// Fingerprint file of `stubs_path`, if there is a valid one
+fn read_meta(stubs_path: &Path) -> Option<StubsMeta> {
+    read_text(&meta_path(stubs_path))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
 
-    // Collect all environment matches with their positions
-    struct EnvMatch {
-        env_type: String,
-        start_pos: usize,
-        end_pos: usize,
-        env_content: String,
+/// Options recorded in a fingerprint file (the defaults without one)
+fn recorded_options(meta: Option<&StubsMeta>) -> StubifyOptions {
+    StubifyOptions {
+        lean_names_file: meta.and_then(|m| m.lean_names_file.clone()),
+        prefer_source: meta.is_some_and(|m| m.prefer_source),
+        locate_lean: meta.and_then(|m| m.locate_lean.clone()),
+        lean_annotations: meta.and_then(|m| m.lean_annotations.clone()),
+        lean_annotation_marker: meta.and_then(|m| m.lean_annotation_marker.clone()),
+        with_numbering: meta.is_some_and(|m| m.with_numbering),
+        qualify_names: meta.is_some_and(|m| m.qualify_names),
+        format: meta.map(|m| m.format).unwrap_or_default(),
+        env_type_map: meta.map(|m| m.env_type_map.clone()).unwrap_or_default(),
+        warn_duplicate_proofs: meta.is_some_and(|m| m.warn_duplicate_proofs),
+        strict: meta.is_some_and(|m| m.strict),
+        proof_parts: meta.is_some_and(|m| m.proof_parts),
+        proof_after_envs: meta.map(|m| m.proof_after_envs.clone()).unwrap_or_default(),
+        proof_lookahead: meta.and_then(|m| m.proof_lookahead),
+        macro_positions: meta.is_some_and(|m| m.macro_positions),
+        no_section_uses: meta.is_some_and(|m| m.no_section_uses),
+        skip_marker: meta.and_then(|m| m.skip_marker.clone()),
+        path_base: meta.map(|m| m.path_base).unwrap_or_default(),
+        ..StubifyOptions::default()
     }
+}
 
-    let mut all_matches: Vec<EnvMatch> = Vec::new();
-
-    // Build regex pattern for all environment types
-    // Match \begin{env}...\end{env} including nested content
-    for env_type in env_types {
-        let pattern = format!(
-            r"(?s)\\begin\{{{}\}}(.*?)\\end\{{{}\}}",
-            regex::escape(env_type),
-            regex::escape(env_type)
-        );
-        let env_re = Regex::new(&pattern).unwrap();
-
-        for caps in env_re.captures_iter(&content) {
-            let full_match = caps.get(0).unwrap();
-            all_matches.push(EnvMatch {
-                env_type: env_type.clone(),
-                start_pos: full_match.start(),
-                end_pos: full_match.end(),
-                env_content: caps[1].to_string(),
-            });
+/// Make sure `stubs_path` is up to date with the sources of `project_path`
+///
+/// stubs.json is regenerated when it is missing, when `force` is set, or when
+/// its fingerprint no longer matches the sources; it is regenerated with the
+/// options recorded in its fingerprint file. Without `blueprint/src` there is
+/// nothing to compare against and an existing stubs.json is used as is.
+pub(crate) fn ensure_stubs(
+    project_path: &Path,
+    stubs_path: &Path,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let meta = read_meta(stubs_path);
+    let options = recorded_options(meta.as_ref());
+
+    if force {
+        eprintln!("Regenerating stubs.json...");
+    } else if !stubs_path.exists() {
+        eprintln!("stubs.json not found, running stubify...");
+    } else if !project_path.join("blueprint").join("src").exists() {
+        return Ok(());
+    } else {
+        let files = load_sources(project_path, &mut Profile::default())?;
+        let lean_roots = LeanRoots::resolve(project_path, &options);
+        let current = fingerprint(&files, &options, &lean_roots, &read_config(project_path)?)?;
+        if meta.is_some_and(|m| m.fingerprint == current) {
+            return Ok(());
         }
+        eprintln!("stubs.json is out of date, running stubify...");
     }
 
-    // Sort by position to process in order
-    all_matches.sort_by_key(|m| m.start_pos);
-
-    for env_match in all_matches {
-        let env_content = &env_match.env_content;
+    run(
+        project_path.to_str().ok_or("Invalid project path")?,
+        stubs_path.to_str().ok_or("Invalid stubs path")?,
+        &options,
+    )
+}
 
-        // Calculate line numbers for the spec environment
-        let spec_lines = LineRange {
-            lines_start: byte_pos_to_line(&content, env_match.start_pos),
-            lines_end: byte_pos_to_line(&content, env_match.end_pos - 1),
-        };
+/// Record where the `code-name` of each stub is declared under `lean_src_roots`
+///
+/// Sets `lean-path`, `lean-lines` and `lean-doc`. Names that are not fully
+/// qualified are resolved by unambiguous suffix (`bar` for `Foo.bar`), and
+/// with `qualify_names` the code-names are rewritten to the qualified form.
+/// Code-names without a declaration, or with several, are reported as warnings.
+fn locate_lean_declarations(
+    stubs: &mut HashMap<String, Stub>,
+    lean_src_roots: &[PathBuf],
+    qualify_names: bool,
+) -> Result<(), Box<dyn Error>> {
+    let modules = lean::scan_lean_modules(lean_src_roots)?;
+    let declarations: HashMap<&str, (&str, &lean::LeanDeclaration)> = modules
+        .values()
+        .flat_map(|module| {
+            module
+                .declarations
+                .iter()
+                .map(move |decl| (decl.name.as_str(), (module.path.as_str(), decl)))
+        })
+        .collect();
+    let index = lean::DeclarationIndex::new(declarations.keys().copied());
+    let qualified = |code_name: &str| match index
+        .resolve(code_name.strip_prefix("probe:").unwrap_or(code_name))
+    {
+        lean::Resolution::Suffix(name) if qualify_names => Some(format!("probe:{}", name)),
+        _ => None,
+    };
 
-        // Extract all \label{...} in order from the statement
-        let mut labels = extract_all_labels(env_content);
+    let mut missing: Vec<String> = Vec::new();
+    let mut ambiguous: Vec<(String, Vec<&str>)> = Vec::new();
+    for stub in stubs.values_mut() {
+        for name in stub.lean_names.iter_mut().flatten() {
+            if let Some(full_name) = qualified(name) {
+                *name = full_name;
+            }
+        }
 
-        // Extract \lean{...} - returns list of declarations with "probe:" prefix
-        let lean_names_list = extract_lean(env_content);
-        let code_name = lean_names_list
-            .first()
-            .map(|name| format!("probe:{}", name));
-        let lean_names = if lean_names_list.len() > 1 {
-            Some(
-                lean_names_list
-                    .iter()
-                    .map(|name| format!("probe:{}", name))
-                    .collect(),
-            )
-        } else {
-            None
+        let Some(code_name) = &mut stub.code_name else {
+            continue;
         };
-
-        // Check for \leanok
-        let spec_ok = env_content.contains(r"\leanok");
-
-        // Check for \mathlibok
-        let mathlib_ok = extract_mathlibok(env_content);
-
-        // Check for \notready
-        let not_ready = extract_notready(env_content);
-
-        // Extract \discussion{...}
-        let discussion = extract_discussion(env_content);
-
-        // Extract \uses{...}
-        let spec_dependencies = extract_uses(env_content);
-
-        // Look for a following proof environment
-        let (
-            proof_lines,
-            proof_ok,
-            proof_mathlib_ok,
-            proof_not_ready,
-            proof_discussion,
-            proof_dependencies,
-            proof_lean_names,
-        ) = if let Some(proof_match) = find_following_proof(&content, env_match.end_pos) {
-            // Skip proofs that use \proves (they will be handled separately)
-            if !proof_match.proves_labels.is_empty() {
-                (None, None, None, None, None, None, None)
-            } else {
-                // Add proof labels to the labels list
-                let proof_labels = extract_all_labels(&proof_match.content);
-                labels.extend(proof_labels);
-
-                // Check for \leanok in proof
-                let p_ok = if proof_match.content.contains(r"\leanok") {
-                    Some(true)
-                } else {
-                    None
-                };
-
-                // Check for \mathlibok in proof
-                let p_mathlib = if extract_mathlibok(&proof_match.content) {
-                    Some(true)
-                } else {
-                    None
-                };
-
-                // Check for \notready in proof
-                let p_not_ready = if extract_notready(&proof_match.content) {
-                    Some(true)
-                } else {
-                    None
-                };
-
-                // Extract \discussion{...} from proof
-                let p_discussion = extract_discussion(&proof_match.content);
-                let p_discussion = if p_discussion.is_empty() {
-                    None
-                } else {
-                    Some(p_discussion)
-                };
-
-                // Extract \uses{...} from proof
-                let p_deps = extract_uses(&proof_match.content);
-                let p_deps = if p_deps.is_empty() {
-                    None
-                } else {
-                    Some(p_deps)
-                };
-
-                // Extract \lean{...} from proof
-                let p_lean = extract_lean(&proof_match.content);
-                let p_lean = if p_lean.is_empty() {
-                    None
-                } else {
-                    Some(p_lean)
-                };
-
-                (
-                    Some(proof_match.lines),
-                    p_ok,
-                    p_mathlib,
-                    p_not_ready,
-                    p_discussion,
-                    p_deps,
-                    p_lean,
-                )
+        let decl = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        match index.resolve(decl) {
+            lean::Resolution::Exact(name) | lean::Resolution::Suffix(name) => {
+                if let Some(full_name) = qualified(code_name) {
+                    *code_name = full_name;
+                }
+                let (path, declaration) = declarations[name];
+                stub.lean_path = Some(path.to_string());
+                stub.lean_lines = Some(declaration.lines);
+                stub.lean_doc = declaration.doc.clone();
             }
-        } else {
-            (None, None, None, None, None, None, None)
-        };
+            lean::Resolution::Ambiguous(candidates) => {
+                ambiguous.push((decl.to_string(), candidates))
+            }
+            lean::Resolution::Missing => missing.push(decl.to_string()),
+        }
+    }
 
-        envs.push(ParsedEnv {
-            env_type: env_match.env_type,
-            relative_path: relative_path.to_string(),
-            spec_lines,
-            proof_lines,
-            labels,
-            code_name,
-            lean_names,
-            spec_ok,
-            mathlib_ok,
-            not_ready,
-            discussion,
-            spec_dependencies,
-            proof_ok,
-            proof_mathlib_ok,
-            proof_not_ready,
-            proof_discussion,
-            proof_dependencies,
-            proof_lean_names,
-        });
+    missing.sort_unstable();
+    for decl in missing {
+        messages::warning(None, &format!("no Lean declaration found for {}", decl));
+    }
+    ambiguous.sort_unstable();
+    for (decl, candidates) in ambiguous {
+        messages::warning(
+            None,
+            &format!(
+                "ambiguous Lean name {}, candidates: {}",
+                decl,
+                candidates.join(", ")
+            ),
+        );
     }
 
-    envs
+    Ok(())
 }
 
-/// Run the stubify command
-pub fn run(project_path: &str, output: &str) -> Result<(), Box<dyn Error>> {
-    let project_path = Path::new(project_path);
-    let blueprint_src = project_path.join("blueprint").join("src");
+/// BibTeX entries citing each statement by its label, sorted by label
+///
+/// Split child stubs have no statement of their own and are left out.
+fn format_bibtex(stubs: &HashMap<String, Stub>, config: &Config) -> String {
+    let mut entries: Vec<&Stub> = stubs
+        .values()
+        .filter(|stub| stub.stub_spec.is_some())
+        .collect();
+    entries.sort_by(|a, b| a.label.cmp(&b.label));
+
+    let mut out = String::new();
+    for stub in entries {
+        let _ = writeln!(out, "@misc{{{},", stub.label);
+        let title = stub.env_options.as_deref().unwrap_or(&stub.label);
+        let _ = writeln!(out, "  title = {{{}}},", title);
+        if let Some(stub_type) = &stub.stub_type {
+            let _ = writeln!(out, "  type = {{{}}},", stub_type);
+        }
+        if let (Some(dochome), Some(path), Some(spec)) =
+            (&config.dochome, &stub.stub_path, &stub.stub_spec)
+        {
+            let _ = writeln!(
+                out,
+                "  howpublished = {{\\url{{{}/{}#L{}}}}},",
+                dochome.trim_end_matches('/'),
+                src_relative(path),
+                spec.lines_start
+            );
+        }
+        out.push_str("}\n\n");
+    }
+    out
+}
 
-    if !blueprint_src.exists() {
-        return Err(format!(
-            "blueprint/src directory not found at {}",
-            blueprint_src.display()
-        )
-        .into());
+/// Number of environments per .tex file, most first (ties by path)
+///
+/// Split child stubs are not counted; their parent is.
+fn env_count_per_file(stubs: &HashMap<String, Stub>) -> Vec<(&str, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for stub in stubs.values().filter(|stub| stub.stub_spec.is_some()) {
+        if let Some(path) = &stub.stub_path {
+            *counts.entry(path).or_default() += 1;
+        }
     }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+}
 
-    // Parse web.tex for environment types and config
-    let web_tex_path = blueprint_src.join("web.tex");
-    let (env_types, mut project_config) = if web_tex_path.exists() {
-        let web_tex_content = fs::read_to_string(&web_tex_path)?;
-        let envs = parse_thms_option(&web_tex_content);
-        let config = extract_config(&web_tex_content);
-        (envs, config)
+/// Whether `flag` is set on a statement, or on all its split children
+fn review_flag(
+    stub: &Stub,
+    stubs: &HashMap<String, Stub>,
+    flag: impl Fn(&Stub) -> Option<bool>,
+) -> bool {
+    let children: Vec<&Stub> = stub
+        .spec_dependencies
+        .iter()
+        .filter_map(|name| stubs.get(name))
+        .filter(|child| child.stub_spec.is_none())
+        .collect();
+    if children.is_empty() {
+        flag(stub).unwrap_or(false)
     } else {
-        (
-            DEFAULT_ENVS.iter().map(|s| s.to_string()).collect(),
-            Config::default(),
-        )
-    };
+        children.iter().all(|child| flag(child).unwrap_or(false))
+    }
+}
 
-    eprintln!("Looking for environments: {}", env_types.join(", "));
+/// Items ticked in an existing review report, by their text
+fn checked_review_items(report: &str) -> HashSet<&str> {
+    report
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("- [x] ")
+                .or_else(|| line.strip_prefix("- [X] "))
+        })
+        .collect()
+}
 
-    // Collect all parsed environments and standalone proofs
-    let mut all_envs: Vec<ParsedEnv> = Vec::new();
-    let mut all_standalone_proofs: Vec<(String, StandaloneProof)> = Vec::new(); // (relative_path, proof)
+/// Markdown review checklist, grouped by section in source order
+///
+/// Spec and proof items are ticked when the blueprint marks them formalized,
+/// and any item ticked in `previous` (an earlier report) stays ticked. Child
+/// stubs of a split are covered by their parent.
+fn format_review_report(stubs: &HashMap<String, Stub>, previous: &str) -> String {
+    let checked = checked_review_items(previous);
+    let mut statements: Vec<&Stub> = stubs
+        .values()
+        .filter(|stub| stub.stub_spec.is_some())
+        .collect();
+    statements.sort_by_key(|stub| {
+        (
+            stub.stub_path.as_deref(),
+            stub.stub_spec.map(|spec| spec.lines_start),
+        )
+    });
+
+    // Sections in order of their first statement
+    let mut sections: Vec<(&[String], Vec<&Stub>)> = Vec::new();
+    for stub in statements {
+        match sections
+            .iter_mut()
+            .find(|(path, _)| *path == stub.section_path.as_slice())
+        {
+            Some((_, section)) => section.push(stub),
+            None => sections.push((&stub.section_path, vec![stub])),
+        }
+    }
+    sections.sort_by_key(|(path, _)| !path.is_empty());
 
-    // Walk through all .tex files in blueprint/src
-    for entry in WalkDir::new(&blueprint_src)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.extension().is_some_and(|ext| ext == "tex") {
-            // Skip web.tex and print.tex (they're not content files)
-            let file_name = path.file_name().unwrap().to_str().unwrap();
-            if file_name == "web.tex" || file_name == "print.tex" {
-                continue;
+    let mut out = String::from("# Blueprint review checklist\n");
+    for (path, section) in sections {
+        if !path.is_empty() {
+            let _ = write!(out, "\n## {}\n", path.join(" > "));
+        }
+        out.push('\n');
+        for stub in section {
+            let mut items: Vec<(String, bool)> = Vec::new();
+            let kind = stub.stub_type.as_deref().map(|t| {
+                let mut chars = t.chars();
+                chars.next().map_or(String::new(), |first| {
+                    format!(" ({}{})", first.to_uppercase(), chars.as_str())
+                })
+            });
+            items.push((
+                format!("{}{}: Check spec", stub.label, kind.unwrap_or_default()),
+                review_flag(stub, stubs, |s| s.spec_ok),
+            ));
+            if stub.stub_proof.is_some() {
+                items.push((
+                    format!("{}: Check proof", stub.label),
+                    review_flag(stub, stubs, |s| s.proof_ok),
+                ));
             }
-
-            let content = fs::read_to_string(path)?;
-
-            // Extract config from content files as well (in case macros are there)
-            let file_config = extract_config(&content);
-            project_config = merge_config(project_config, file_config);
-
-            // Get path relative to blueprint/src
-            let relative_path = path
-                .strip_prefix(&blueprint_src)?
-                .to_str()
-                .ok_or("Invalid UTF-8 in path")?;
-
-            let envs = parse_tex_file(&content, relative_path, &env_types);
-            all_envs.extend(envs);
-
-            // Find standalone proofs with \proves
-            let standalone_proofs = find_standalone_proofs(&content, relative_path);
-            for proof in standalone_proofs {
-                all_standalone_proofs.push((relative_path.to_string(), proof));
+            items.push((
+                format!("{}: Verify Lean code matches blueprint", stub.label),
+                false,
+            ));
+            for (text, done) in items {
+                let mark = if done || checked.contains(text.as_str()) {
+                    'x'
+                } else {
+                    ' '
+                };
+                let _ = writeln!(out, "- [{}] {}", mark, text);
             }
         }
     }
+    out
+}
 
-    // Track all seen labels for duplicate detection
-    let mut seen_labels: HashSet<String> = HashSet::new();
-    let mut label_counter: u64 = 0;
-    let mut all_stubs: HashMap<String, Stub> = HashMap::new();
-    // Build a map from label to stub name for quick lookup
-    let mut label_to_stub_name: HashMap<String, String> = HashMap::new();
+/// Summary of the environments that were not parsed because their type is
+/// not configured, most frequent first, or `None` if there were none
+fn unconfigured_summary(unconfigured_envs: &BTreeMap<String, usize>) -> Option<String> {
+    let mut counts: Vec<(&String, &usize)> = unconfigured_envs.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let (first, rest) = counts.split_first()?;
+    let mut summary = format!(
+        "Skipped {} '{}' environment{}",
+        first.1,
+        first.0,
+        if *first.1 == 1 { "" } else { "s" }
+    );
+    for (env_type, count) in rest {
+        let _ = write!(summary, ", {} '{}'", count, env_type);
+    }
+    summary.push_str(
+        " of types that are not looked for; add them to thms= in web.tex, \
+         or to ignored-envs in .verilib/config.json if they hold no statements",
+    );
+    Some(summary)
+}
 
-    // Process each environment
-    for mut env in all_envs {
-        // Check existing labels for duplicates
-        for label in &env.labels {
-            if seen_labels.contains(label) {
-                return Err(format!("Duplicate label found: {}", label).into());
-            }
+/// Parse one `FROM:TO` entry of `--env-type-map`
+pub fn parse_env_type_mapping(entry: &str) -> Result<(String, String), String> {
+    match entry.split_once(':') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
         }
+        _ => Err(format!("expected FROM:TO, got '{}'", entry)),
+    }
+}
 
-        // If no labels, generate one
-        if env.labels.is_empty() {
-            loop {
-                let generated = generate_label(label_counter);
-                label_counter += 1;
-                if !seen_labels.contains(&generated) {
-                    env.labels.push(generated);
-                    break;
-                }
+/// Report the stubs whose proof macros contradict their statement's (see
+/// `parse::inconsistent_proof_macros`), as errors failing the run if `strict`
+fn check_proof_macros(stubs: &HashMap<String, Stub>, strict: bool) -> Result<(), Box<dyn Error>> {
+    let mut sorted: Vec<(&String, &Stub)> = stubs.iter().collect();
+    sorted.sort_unstable_by_key(|(stub_name, _)| *stub_name);
+    let mut count = 0;
+    for (stub_name, stub) in sorted {
+        let location = stub
+            .stub_path
+            .as_deref()
+            .map(|path| Location::blueprint(src_relative(path), stub.stub_proof.as_ref()));
+        for name in parse::inconsistent_proof_macros(
+            stub.spec_ok == Some(true),
+            stub.mathlib_ok == Some(true),
+            stub.proof_ok == Some(true),
+            stub.proof_mathlib_ok == Some(true),
+        ) {
+            let message = format!(
+                "the proof of stub '{}' is \\{} but its statement is not",
+                stub_name, name
+            );
+            if strict {
+                messages::error(location.as_ref(), &message);
+            } else {
+                messages::warning(location.as_ref(), &message);
             }
+            count += 1;
         }
+    }
+    if strict && count > 0 {
+        return Err(format!(
+            "{} proofs have a status macro their statement lacks (--strict)",
+            count
+        )
+        .into());
+    }
+    Ok(())
+}
 
-        // Add all labels to seen set
-        for label in &env.labels {
-            seen_labels.insert(label.clone());
+/// Rename the `stub-type` of every stub whose type is a key of `env_type_map`
+///
+/// Parsing is unaffected: environments are matched and numbered under their
+/// LaTeX names, only the recorded type changes.
+fn rename_env_types(stubs: &mut HashMap<String, Stub>, env_type_map: &BTreeMap<String, String>) {
+    if env_type_map.is_empty() {
+        return;
+    }
+    for stub in stubs.values_mut() {
+        if let Some(to) = stub.stub_type.as_ref().and_then(|t| env_type_map.get(t)) {
+            stub.stub_type = Some(to.clone());
         }
+    }
+}
 
-        // Use the last label for stub-name
-        let primary_label = env.labels.iter().next_back().unwrap().clone();
-        let stub_name = format!("{}/{}", env.relative_path, primary_label);
+/// Parse `files`, then locate the Lean declarations if `options` asks for it
+fn build(
+    files: &[(String, String)],
+    options: &StubifyOptions,
+    lean_roots: &LeanRoots,
+    parse_options: &ParseOptions,
+    profile: &mut Profile,
+) -> Result<ParsedBlueprint, Box<dyn Error>> {
+    let mut parsed = parse_with_jobs(files, options.jobs, parse_options, profile)?;
+
+    if let Some(lean_src_roots) = &lean_roots.locate {
+        let start = profile.start();
+        locate_lean_declarations(&mut parsed.stubs, lean_src_roots, options.qualify_names)?;
+        profile.record("lean lookup", start);
+    }
+    rename_env_types(&mut parsed.stubs, &options.env_type_map);
+    check_proof_macros(&parsed.stubs, options.strict)?;
+
+    Ok(parsed)
+}
 
-        // Map all labels (including non-canonical ones) to this stub name
-        for label in &env.labels {
-            label_to_stub_name.insert(label.clone(), stub_name.clone());
-        }
+/// Parse the blueprint of a project in memory, with the options `stubs_path`
+/// was last generated with
+pub(crate) fn build_recorded(
+    project_path: &Path,
+    stubs_path: &Path,
+) -> Result<ParsedBlueprint, Box<dyn Error>> {
+    build_project(
+        project_path,
+        &recorded_options(read_meta(stubs_path).as_ref()),
+    )
+}
 
-        all_stubs.insert(
-            stub_name,
-            Stub {
-                label: primary_label,
-                stub_type: Some(env.env_type),
-                stub_path: Some(env.relative_path),
-                stub_spec: Some(env.spec_lines),
-                stub_proof: env.proof_lines,
-                code_name: env.code_name,
-                lean_names: env.lean_names,
-                spec_ok: Some(env.spec_ok),
-                mathlib_ok: if env.mathlib_ok { Some(true) } else { None },
-                not_ready: if env.not_ready { Some(true) } else { None },
-                discussion: env.discussion,
-                spec_dependencies: env.spec_dependencies,
-                proof_ok: env.proof_ok,
-                proof_mathlib_ok: env.proof_mathlib_ok,
-                proof_not_ready: env.proof_not_ready,
-                proof_discussion: env.proof_discussion,
-                proof_dependencies: env.proof_dependencies,
-                proof_lean_names: env.proof_lean_names,
-            },
-        );
+/// Reparse the files of `options.only` and patch their stubs into the stubs
+/// file at `output`, keeping the stubs of all other files as they are
+///
+/// Files with standalone proofs (`\proves`) of labels in the reparsed files
+/// are reparsed along with them. The stubs of the reparsed files are
+/// replaced; their dependencies resolve against the labels of the other
+/// stubs, and dependencies of the other stubs on removed ones are reported. The fingerprint file is left alone,
+/// so dependent commands still regenerate the stubs from all sources.
+fn patch_stubs(
+    project_path: &Path,
+    output: &str,
+    options: &StubifyOptions,
+    lean_roots: &LeanRoots,
+    profile: &mut Profile,
+) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(output);
+    if !output_path.exists() {
+        return Err(format!(
+            "--only patches an existing stubs file, but {} does not exist; run stubify without --only first",
+            output
+        )
+        .into());
     }
+    let mut stubs: HashMap<String, Stub> = parse_map(&read_text(output_path)?)?;
+    // Statement IDs and locations are derived from paths relative to
+    // blueprint/src, as in a full run
+    rebase_paths(&mut stubs, PathBase::Src);
 
-    eprintln!("Found {} stubs", all_stubs.len());
-
-    // Merge standalone proofs (those with \proves) into their corresponding stubs
-    for (relative_path, proof) in all_standalone_proofs {
-        for proves_label in &proof.proves_labels {
-            if let Some(stub_name) = label_to_stub_name.get(proves_label) {
-                if let Some(stub) = all_stubs.get_mut(stub_name) {
-                    // Merge proof fields into the stub
-                    stub.stub_proof = Some(proof.lines);
-                    if proof.proof_ok {
-                        stub.proof_ok = Some(true);
-                    }
-                    if proof.mathlib_ok {
-                        stub.proof_mathlib_ok = Some(true);
-                    }
-                    if proof.not_ready {
-                        stub.proof_not_ready = Some(true);
-                    }
-                    if !proof.discussion.is_empty() {
-                        stub.proof_discussion = Some(proof.discussion.clone());
-                    }
-                    if !proof.dependencies.is_empty() {
-                        stub.proof_dependencies = Some(proof.dependencies.clone());
-                    }
-                    if !proof.lean_names.is_empty() {
-                        stub.proof_lean_names = Some(proof.lean_names.clone());
-                    }
-                }
-            } else {
-                eprintln!(
-                    "Warning: \\proves{{{}}} in {} references unknown label",
-                    proves_label, relative_path
-                );
-            }
+    let blueprint_src = project_path.join("blueprint").join("src");
+    if !blueprint_src.exists() {
+        return Err(ProbeError::MissingBlueprintSrc {
+            path: blueprint_src.display().to_string(),
         }
+        .into());
     }
-
-    // Resolve dependency labels to canonical stub-names
-    // Dependencies in .tex files are labels (possibly non-canonical), which we
-    // resolve to stub-names using the label_to_stub_name mapping
-    for (stub_name, stub) in all_stubs.iter_mut() {
-        // Resolve spec-dependencies labels to stub-names
-        let mut resolved_spec_deps = Vec::new();
-        for dep_label in &stub.spec_dependencies {
-            if let Some(dep_stub_name) = label_to_stub_name.get(dep_label) {
-                resolved_spec_deps.push(dep_stub_name.clone());
-            } else {
-                return Err(format!(
-                    "Unknown label '{}' in spec-dependencies of stub '{}'",
-                    dep_label, stub_name
-                )
-                .into());
-            }
+    let start = profile.start();
+    let mut files: Vec<(String, String)> = Vec::new();
+    for relative_path in &options.only {
+        let relative_path = parse::normalize_path(relative_path);
+        let relative_path = relative_path.trim_start_matches("./");
+        let content = fs::read_to_string(blueprint_src.join(relative_path)).map_err(|e| {
+            format!(
+                "Failed to read {} in {}: {}",
+                relative_path,
+                blueprint_src.display(),
+                e
+            )
+        })?;
+        files.push((relative_path.to_string(), content));
+    }
+    // web.tex selects the environment types
+    if !files.iter().any(|(path, _)| path == "web.tex") {
+        if let Ok(content) = fs::read_to_string(blueprint_src.join("web.tex")) {
+            files.push(("web.tex".to_string(), content));
         }
-        stub.spec_dependencies = resolved_spec_deps;
-
-        // Resolve proof-dependencies labels to stub-names
-        if let Some(proof_deps) = &stub.proof_dependencies {
-            let mut resolved_proof_deps = Vec::new();
-            for dep_label in proof_deps {
-                if let Some(dep_stub_name) = label_to_stub_name.get(dep_label) {
-                    resolved_proof_deps.push(dep_stub_name.clone());
-                } else {
-                    return Err(format!(
-                        "Unknown label '{}' in proof-dependencies of stub '{}'",
-                        dep_label, stub_name
-                    )
-                    .into());
-                }
-            }
-            stub.proof_dependencies = Some(resolved_proof_deps);
+    }
+    let mut parse_options = options.parse_options(lean_roots, &read_config(project_path)?)?;
+    // Standalone proofs in other files fill in the proofs of the reparsed
+    // stubs, so their files are reparsed too, until no further file proves
+    // a label of the reparsed ones
+    let mut others = read_sources(&blueprint_src)?;
+    let mut labels: HashSet<String> = files
+        .iter()
+        .flat_map(|(_, content)| parse::defined_labels(content))
+        .collect();
+    loop {
+        let (proving, rest): (Vec<_>, Vec<_>) = others.into_iter().partition(|(path, content)| {
+            !files.iter().any(|(parsed, _)| parsed == path)
+                && parse::proved_labels(content, &parse_options.macros)
+                    .iter()
+                    .any(|label| labels.contains(label))
+        });
+        others = rest;
+        if proving.is_empty() {
+            break;
         }
+        labels.extend(
+            proving
+                .iter()
+                .flat_map(|(_, content)| parse::defined_labels(content)),
+        );
+        files.extend(proving);
     }
+    profile.record("file discovery", start);
 
-    // Split stubs with multiple code-names into separate child stubs
-    // Each child stub gets one code-name, and the parent stub references the children
-    let stub_names_to_split: Vec<String> = all_stubs
+    // Stub-names start with the stub-path, also those of split children
+    let prefixes: Vec<String> = files.iter().map(|(path, _)| format!("{path}/")).collect();
+    let (removed, mut stubs): (HashMap<_, _>, HashMap<_, _>) = stubs
+        .into_iter()
+        .partition(|(stub_name, _)| prefixes.iter().any(|prefix| stub_name.starts_with(prefix)));
+
+    // Readiness depends on the stubs of every file, so it is derived once
+    // they are merged
+    parse_options.skip_readiness = true;
+    parse_options.known_labels = stubs
         .iter()
-        .filter(|(_, stub)| {
-            stub.lean_names
-                .as_ref()
-                .is_some_and(|names| names.len() > 1)
+        .flat_map(|(stub_name, stub)| {
+            std::iter::once(&stub.label)
+                .chain(&stub.label_aliases)
+                .map(move |label| (label.clone(), stub_name.clone()))
         })
-        .map(|(name, _)| name.clone())
         .collect();
+    let parsed = build(&files, options, lean_roots, &parse_options, profile)?;
+
+    let mut others: Vec<(&String, &Stub)> = stubs.iter().collect();
+    others.sort_unstable_by_key(|(stub_name, _)| *stub_name);
+    for (stub_name, stub) in others {
+        let dependencies = [
+            ("spec-dependencies", stub.spec_dependencies.as_slice()),
+            (
+                "proof-dependencies",
+                stub.proof_dependencies.as_deref().unwrap_or_default(),
+            ),
+        ];
+        for (field, dependencies) in dependencies {
+            for dependency in dependencies {
+                if removed.contains_key(dependency) && !parsed.stubs.contains_key(dependency) {
+                    let location = stub.stub_path.as_deref().map(|path| {
+                        Location::blueprint(src_relative(path), stub.stub_spec.as_ref())
+                    });
+                    messages::warning(
+                        location.as_ref(),
+                        &format!(
+                            "'{}' in {} of stub '{}' no longer exists",
+                            dependency, field, stub_name
+                        ),
+                    );
+                }
+            }
+        }
+    }
 
-    for stub_name in stub_names_to_split {
-        let stub = all_stubs.get(&stub_name).unwrap().clone();
-        let code_names = stub.lean_names.as_ref().unwrap();
-        let base_label = &stub.label;
-
-        // Create child stubs, one for each code-name
-        let mut child_stub_names = Vec::new();
-        for (i, code_name) in code_names.iter().enumerate() {
-            let child_label = format!("{}_{}", base_label, i + 1);
-            // Child stub-name uses parent's stub-path
-            let child_stub_name = format!(
-                "{}/{}",
-                stub.stub_path.as_ref().unwrap_or(&String::new()),
-                child_label
-            );
+    let start = profile.start();
+    let patched = parsed.stubs.len();
+    stubs.extend(parsed.stubs);
+    // Reparsed statements may repeat the text of others
+    parse::disambiguate_stable_ids(&mut stubs);
+    parse::derive_readiness(&mut stubs);
+    rebase_paths(&mut stubs, options.path_base);
+    let written = write_map(&stubs, output_path, options.format)?;
+    profile.record("json output", start);
+
+    if written {
+        eprintln!(
+            "Replaced {} stubs with {} in {output}",
+            removed.len(),
+            patched
+        );
+    } else {
+        eprintln!("Reparsed {} stubs; {output} is unchanged", patched);
+    }
+    if options.profile {
+        eprint!("{}", profile.report());
+    }
 
-            let child_stub = Stub {
-                label: child_label.clone(),
-                stub_type: None,
-                stub_path: None,
-                stub_spec: None,
-                stub_proof: None,
-                code_name: Some(code_name.clone()),
-                lean_names: None,
-                spec_ok: stub.spec_ok,
-                mathlib_ok: stub.mathlib_ok,
-                not_ready: stub.not_ready,
-                discussion: stub.discussion.clone(),
-                spec_dependencies: stub.spec_dependencies.clone(),
-                proof_ok: stub.proof_ok,
-                proof_mathlib_ok: stub.proof_mathlib_ok,
-                proof_not_ready: stub.proof_not_ready,
-                proof_discussion: stub.proof_discussion.clone(),
-                proof_dependencies: stub.proof_dependencies.clone(),
-                proof_lean_names: stub.proof_lean_names.clone(),
-            };
+    Ok(())
+}
 
-            child_stub_names.push(child_stub_name.clone());
+/// Run the stubify command
+pub fn run(
+    project_path: &str,
+    output: &str,
+    options: &StubifyOptions,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let mut profile = Profile::new(options.profile);
+    let lean_roots = LeanRoots::resolve(project_path, options);
+    if !options.only.is_empty() {
+        return patch_stubs(project_path, output, options, &lean_roots, &mut profile);
+    }
+    let files = load_sources(project_path, &mut profile)?;
+    let config = read_config(project_path)?;
+    let parse_options = options.parse_options(&lean_roots, &config)?;
+    let ParsedBlueprint {
+        stubs: mut all_stubs,
+        config: project_config,
+        labels,
+        unconfigured_envs,
+    } = build(&files, options, &lean_roots, &parse_options, &mut profile)?;
 
-            // Add child label to label_to_stub_name mapping
-            label_to_stub_name.insert(child_label, child_stub_name.clone());
+    // Write output (create parent directory if needed)
+    let start = profile.start();
+    rebase_paths(&mut all_stubs, options.path_base);
+    write_map(&all_stubs, Path::new(output), options.format)?;
+    profile.record("json output", start);
+
+    let meta = StubsMeta {
+        fingerprint: fingerprint(&files, options, &lean_roots, &config)?,
+        lean_names_file: options.lean_names_file.clone(),
+        prefer_source: options.prefer_source,
+        locate_lean: options.locate_lean.clone(),
+        lean_annotations: options.lean_annotations.clone(),
+        lean_annotation_marker: options.lean_annotation_marker.clone(),
+        with_numbering: options.with_numbering,
+        qualify_names: options.qualify_names,
+        format: options.format,
+        env_type_map: options.env_type_map.clone(),
+        warn_duplicate_proofs: options.warn_duplicate_proofs,
+        strict: options.strict,
+        proof_parts: options.proof_parts,
+        proof_after_envs: options.proof_after_envs.clone(),
+        proof_lookahead: options.proof_lookahead,
+        macro_positions: options.macro_positions,
+        no_section_uses: options.no_section_uses,
+        skip_marker: options.skip_marker.clone(),
+        path_base: options.path_base,
+    };
+    write_meta(&meta, Path::new(output))?;
 
-            all_stubs.insert(child_stub_name, child_stub);
-        }
+    eprintln!("Wrote stubs to {output}");
 
-        // Update the parent stub: remove code-related and verification fields,
-        // set spec-dependencies to point to child stubs
-        let parent_stub = all_stubs.get_mut(&stub_name).unwrap();
-        parent_stub.code_name = None;
-        parent_stub.lean_names = None;
-        parent_stub.spec_ok = None;
-        parent_stub.mathlib_ok = None;
-        parent_stub.not_ready = None;
-        parent_stub.discussion = Vec::new();
-        parent_stub.spec_dependencies = child_stub_names;
-        parent_stub.proof_ok = None;
-        parent_stub.proof_mathlib_ok = None;
-        parent_stub.proof_not_ready = None;
-        parent_stub.proof_discussion = None;
-        parent_stub.proof_dependencies = None;
-        parent_stub.proof_lean_names = None;
+    match options.record_output {
+        Some(true) => record_stubs_output(project_path, Some(output))?,
+        Some(false) => record_stubs_output(project_path, None)?,
+        None => {}
     }
 
-    // Write output (create parent directory if needed)
-    let output_path = Path::new(output);
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() && !parent.exists() {
-            fs::create_dir_all(parent)?;
+    if let Some(limit) = options.warn_if_file_has_more_than {
+        for (path, count) in env_count_per_file(&all_stubs) {
+            if count > limit {
+                messages::warning(
+                    Some(&Location::blueprint(src_relative(path), None)),
+                    &format!(
+                        "{path} has {count} environments (more than {limit}), consider splitting it"
+                    ),
+                );
+            }
         }
     }
 
-    let json = serde_json::to_string_pretty(&all_stubs)?;
-    fs::write(output_path, json)?;
+    if let Some(labels_output) = &options.emit_all_labels_map {
+        let labels: BTreeMap<&String, &String> = labels.iter().collect();
+        write_json(&labels, Path::new(labels_output))?;
+        eprintln!("Wrote {} labels to {labels_output}", labels.len());
+    }
 
-    eprintln!("Wrote stubs to {output}");
+    if let Some(report_output) = &options.emit_review_report {
+        let previous = read_text(Path::new(report_output)).unwrap_or_default();
+        write_text(
+            Path::new(report_output),
+            &format_review_report(&all_stubs, &previous),
+        )?;
+        eprintln!("Wrote review checklist to {report_output}");
+    }
+
+    if let Some(bibtex_output) = &options.emit_bibtex {
+        write_text(
+            Path::new(bibtex_output),
+            &format_bibtex(&all_stubs, &project_config),
+        )?;
+        eprintln!("Wrote BibTeX entries to {bibtex_output}");
+    }
 
     // Write config to .verilib/config.json if any config values were found
     if project_config.home.is_some()
@@ -895,10 +1196,12 @@ pub fn run(project_path: &str, output: &str) -> Result<(), Box<dyn Error>> {
 
         let config_path = verilib_dir.join("config.json");
 
-        // Read existing config as a generic JSON object to preserve unknown fields
+        // Read existing config as a generic JSON object to preserve unknown
+        // fields; an unreadable one is an error rather than silently replaced
         let mut config_obj: serde_json::Map<String, serde_json::Value> = if config_path.exists() {
-            let existing_content = fs::read_to_string(&config_path)?;
-            serde_json::from_str(&existing_content).unwrap_or_default()
+            let existing_content = read_text(&config_path)?;
+            serde_json::from_str(&existing_content)
+                .map_err(|e| format!("Invalid {}: {}", config_path.display(), e))?
         } else {
             serde_json::Map::new()
         };
@@ -914,12 +1217,19 @@ pub fn run(project_path: &str, output: &str) -> Result<(), Box<dyn Error>> {
             config_obj.insert("dochome".to_string(), serde_json::Value::String(dochome));
         }
 
-        let config_json = serde_json::to_string_pretty(&config_obj)?;
-        fs::write(&config_path, config_json)?;
+        write_json(&config_obj, &config_path)?;
 
         eprintln!("Wrote config to {}", config_path.display());
     }
 
+    if let Some(summary) = unconfigured_summary(&unconfigured_envs) {
+        messages::warning(None, &summary);
+    }
+
+    if options.profile {
+        eprint!("{}", profile.report());
+    }
+
     Ok(())
 }
 
@@ -928,905 +1238,760 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_parse_thms_option_default() {
-        let content = r"\usepackage[showmore, dep_graph]{blueprint}";
-        let envs = parse_thms_option(content);
-        assert_eq!(
-            envs,
-            DEFAULT_ENVS
-                .iter()
-                .map(|s| s.to_string())
-                .collect::<Vec<_>>()
-        );
-    }
-
-    #[test]
-    fn test_parse_thms_option_custom() {
-        let content = r"\usepackage[thms=dfn+lem+prop+thm+cor]{blueprint}";
-        let envs = parse_thms_option(content);
-        assert_eq!(envs, vec!["dfn", "lem", "prop", "thm", "cor"]);
-    }
-
-    #[test]
-    fn test_extract_all_labels_single() {
-        let labels = extract_all_labels(r"\label{foo}");
-        assert_eq!(labels, vec!["foo"]);
-    }
+    fn test_parallel_output_matches_sequential() {
+        let project = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/project");
+
+        let files = load_sources(&project, &mut Profile::default()).unwrap();
+
+        let outputs: Vec<serde_json::Value> = [Some(1), None]
+            .into_iter()
+            .map(|jobs| {
+                let parsed = parse_with_jobs(
+                    &files,
+                    jobs,
+                    &ParseOptions::default(),
+                    &mut Profile::default(),
+                )
+                .unwrap();
+                serde_json::to_value(&parsed.stubs).unwrap()
+            })
+            .collect();
 
-    #[test]
-    fn test_extract_all_labels_multiple() {
-        let labels = extract_all_labels(r"\label{first}\label{second}\label{third}");
-        assert_eq!(labels, vec!["first", "second", "third"]);
+        assert_eq!(outputs[0], outputs[1]);
     }
 
     #[test]
-    fn test_extract_all_labels_none() {
-        let labels = extract_all_labels(r"no labels here");
-        assert!(labels.is_empty());
-    }
+    fn test_parallel_error_matches_sequential() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{lemma}\label{t}\end{lemma}".to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{lemma}\label{t}\end{lemma}".to_string(),
+            ),
+        ];
 
-    #[test]
-    fn test_extract_lean() {
-        assert_eq!(
-            extract_lean(r"\lean{Subgraph.Equation387_implies_Equation43}"),
-            vec!["Subgraph.Equation387_implies_Equation43"]
-        );
-        assert_eq!(extract_lean(r"no lean here"), Vec::<String>::new());
-    }
+        let errors: Vec<serde_json::Value> = [Some(2), None]
+            .into_iter()
+            .map(|jobs| {
+                let err = parse_with_jobs(
+                    &files,
+                    jobs,
+                    &ParseOptions::default(),
+                    &mut Profile::default(),
+                )
+                .unwrap_err();
+                crate::error::to_machine_readable(err.as_ref())
+            })
+            .collect();
 
-    #[test]
-    fn test_extract_lean_multiple() {
-        assert_eq!(
-            extract_lean(r"\lean{Decl1, Decl2, Decl3}"),
-            vec!["Decl1", "Decl2", "Decl3"]
-        );
+        assert_eq!(errors[0], errors[1]);
+        assert_eq!(errors[0]["error_type"], "DuplicateLabel");
+        assert_eq!(errors[0]["context"], serde_json::json!({ "label": "t" }));
     }
 
-    #[test]
-    fn test_extract_mathlibok() {
-        assert!(extract_mathlibok(r"\mathlibok"));
-        assert!(extract_mathlibok(r"some text \mathlibok more text"));
-        assert!(!extract_mathlibok(r"no mathlib here"));
+    /// Project with a single lemma, plus the path of its stubs.json
+    fn temp_project() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.tex"), r"\begin{lemma}\label{lem1}\end{lemma}").unwrap();
+        let stubs_path = dir.path().join(".verilib").join("stubs.json");
+        (dir, stubs_path)
     }
 
     #[test]
-    fn test_extract_notready() {
-        assert!(extract_notready(r"\notready"));
-        assert!(extract_notready(r"some text \notready more text"));
-        assert!(!extract_notready(r"ready for formalization"));
-    }
+    fn test_ensure_stubs_skips_untouched_project() {
+        let (dir, stubs_path) = temp_project();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
+        assert!(meta_path(&stubs_path).exists());
 
-    #[test]
-    fn test_extract_discussion() {
-        assert_eq!(extract_discussion(r"\discussion{123}"), vec!["123"]);
-        assert_eq!(
-            extract_discussion(r"\discussion{123}\discussion{456}"),
-            vec!["123", "456"]
-        );
-        assert_eq!(extract_discussion(r"no discussion"), Vec::<String>::new());
-    }
+        // A regeneration would overwrite the marker
+        fs::write(&stubs_path, "{}").unwrap();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), "{}");
 
-    #[test]
-    fn test_extract_proves() {
-        assert_eq!(extract_proves(r"\proves{thm1}"), vec!["thm1"]);
-        assert_eq!(extract_proves(r"\proves{thm1, thm2}"), vec!["thm1", "thm2"]);
-        assert_eq!(extract_proves(r"no proves"), Vec::<String>::new());
+        ensure_stubs(dir.path(), &stubs_path, true).unwrap();
+        assert_ne!(fs::read_to_string(&stubs_path).unwrap(), "{}");
     }
 
     #[test]
-    fn test_extract_uses() {
-        assert_eq!(extract_uses(r"\uses{eq387,eq43}"), vec!["eq387", "eq43"]);
-        assert_eq!(extract_uses(r"\uses{r, s, t}"), vec!["r", "s", "t"]);
-        assert_eq!(extract_uses(r"no uses"), Vec::<String>::new());
-    }
+    fn test_read_meta_ignores_generator() {
+        let (dir, stubs_path) = temp_project();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
 
-    #[test]
-    fn test_generate_label() {
-        assert_eq!(generate_label(0), "a0000000000");
-        assert_eq!(generate_label(1), "a0000000001");
-        assert_eq!(generate_label(123), "a0000000123");
-        assert_eq!(generate_label(9999999999), "a9999999999");
-    }
+        let mut meta: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(meta_path(&stubs_path)).unwrap()).unwrap();
+        meta["generator"] = serde_json::json!({"tool": "probe-blueprint", "version": "0.0.0"});
+        fs::write(meta_path(&stubs_path), meta.to_string()).unwrap();
 
-    #[test]
-    fn test_byte_pos_to_line() {
-        let content = "line1\nline2\nline3";
-        assert_eq!(byte_pos_to_line(content, 0), 1); // Start of line1
-        assert_eq!(byte_pos_to_line(content, 5), 1); // End of line1 (before \n)
-        assert_eq!(byte_pos_to_line(content, 6), 2); // Start of line2
-        assert_eq!(byte_pos_to_line(content, 12), 3); // Start of line3
+        // Still a valid fingerprint, so the stubs are not regenerated
+        fs::write(&stubs_path, "{}").unwrap();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), "{}");
     }
 
     #[test]
-    fn test_parse_tex_file_theorem_with_labels() {
-        let content = r#"
-\begin{theorem}[387 implies 43]\label{387_implies_43}\uses{eq387,eq43}\lean{Subgraph.Equation387_implies_Equation43}\leanok
-  Some content here.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "chapter/implications.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].env_type, "theorem");
-        assert_eq!(envs[0].labels, vec!["387_implies_43"]);
-        assert_eq!(
-            envs[0].code_name,
-            Some("probe:Subgraph.Equation387_implies_Equation43".to_string())
-        );
-        assert!(envs[0].spec_ok);
-        assert_eq!(envs[0].spec_dependencies, vec!["eq387", "eq43"]);
-        assert_eq!(envs[0].proof_ok, None);
-        assert_eq!(envs[0].proof_dependencies, None);
-        // Line numbers: starts on line 2, ends on line 4
-        assert_eq!(envs[0].spec_lines.lines_start, 2);
-        assert_eq!(envs[0].spec_lines.lines_end, 4);
-    }
+    fn test_ensure_stubs_regenerates_after_edit() {
+        let (dir, stubs_path) = temp_project();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
 
-    #[test]
-    fn test_parse_tex_file_different_env_types() {
-        let content = r#"
-\begin{definition}\label{def1}
-  A definition.
-\end{definition}
-
-\begin{lemma}\label{lem1}
-  A lemma.
-\end{lemma}
-
-\begin{theorem}\label{thm1}
-  A theorem.
-\end{theorem}
-
-\begin{dfn}\label{dfn1}
-  A dfn (short form).
-\end{dfn}
-"#;
-        let env_types: Vec<String> = vec![
-            "definition".to_string(),
-            "lemma".to_string(),
-            "theorem".to_string(),
-            "dfn".to_string(),
-        ];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+        fs::write(
+            dir.path().join("blueprint").join("src").join("a.tex"),
+            r"\begin{lemma}\label{lem2}\end{lemma}",
+        )
+        .unwrap();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
 
-        assert_eq!(envs.len(), 4);
-        assert_eq!(envs[0].env_type, "definition");
-        assert_eq!(envs[0].labels, vec!["def1"]);
-        assert_eq!(envs[1].env_type, "lemma");
-        assert_eq!(envs[1].labels, vec!["lem1"]);
-        assert_eq!(envs[2].env_type, "theorem");
-        assert_eq!(envs[2].labels, vec!["thm1"]);
-        assert_eq!(envs[3].env_type, "dfn");
-        assert_eq!(envs[3].labels, vec!["dfn1"]);
+        let stubs: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+        assert!(stubs.get("a.tex/lem2").is_some());
+        assert!(stubs.get("a.tex/lem1").is_none());
     }
 
     #[test]
-    fn test_parse_tex_file_multiple_labels() {
-        let content = r#"
-\begin{theorem}\label{first_label}\label{second_label}\label{primary_label}
-  Content with multiple labels.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(
-            envs[0].labels,
-            vec!["first_label", "second_label", "primary_label"]
-        );
+    fn test_stubs_input_follows_recorded_output() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let templated = dir.path().join("artifacts").join("project-stubs.json");
+        assert_eq!(stubs_input(dir.path()), stubs_path);
+
+        let options = StubifyOptions {
+            record_output: Some(true),
+            ..StubifyOptions::default()
+        };
+        run(project, templated.to_str().unwrap(), &options).unwrap();
+        assert_eq!(stubs_input(dir.path()), templated);
+        // Dependent commands keep the recorded location when regenerating
+        ensure_stubs(dir.path(), &stubs_input(dir.path()), true).unwrap();
+        assert_eq!(stubs_input(dir.path()), templated);
+
+        let options = StubifyOptions {
+            record_output: Some(false),
+            ..StubifyOptions::default()
+        };
+        run(project, stubs_path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(stubs_input(dir.path()), stubs_path);
+        assert!(!outputs_record_path(dir.path()).exists());
     }
 
     #[test]
-    fn test_parse_tex_file_no_label() {
-        let content = r#"
-\begin{lemma}
-  A lemma without any label.
-\end{lemma}
-"#;
-        let env_types: Vec<String> = vec!["lemma".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert!(envs[0].labels.is_empty());
+    fn test_skip_files_from_config() {
+        let (dir, stubs_path) = temp_project();
+        let src = dir.path().join("blueprint").join("src");
+        fs::create_dir_all(src.join("generated")).unwrap();
+        fs::write(
+            src.join("generated").join("macros.tex"),
+            r"\begin{lemma}\label{generated}\end{lemma}",
+        )
+        .unwrap();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
+        let stubs: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+        assert!(stubs.get("generated/macros.tex/generated").is_some());
+
+        // Changing the exclusions makes the stubs out of date
+        fs::write(
+            dir.path().join(".verilib").join("config.json"),
+            r#"{"home": "https://example.com", "skip-files": ["macros.tex"]}"#,
+        )
+        .unwrap();
+        ensure_stubs(dir.path(), &stubs_path, false).unwrap();
+        let stubs: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+        assert!(stubs.get("generated/macros.tex/generated").is_none());
+        assert!(stubs.get("a.tex/lem1").is_some());
     }
 
     #[test]
-    fn test_stub_uses_last_label() {
-        // Simulate the processing logic
-        let mut seen_labels: HashSet<String> = HashSet::new();
-        let labels = vec![
-            "first".to_string(),
-            "second".to_string(),
-            "primary".to_string(),
-        ];
-
-        for label in &labels {
-            seen_labels.insert(label.clone());
-        }
+    fn test_invalid_config_is_an_error() {
+        let (dir, stubs_path) = temp_project();
+        fs::create_dir_all(dir.path().join(".verilib")).unwrap();
+        fs::write(
+            dir.path().join(".verilib").join("config.json"),
+            r#"{"skip-files": "gen.tex"}"#,
+        )
+        .unwrap();
 
-        let primary_label = labels.last().unwrap();
-        let stub_name = format!("{}/{}", "file.tex", primary_label);
+        let err = run(
+            dir.path().to_str().unwrap(),
+            stubs_path.to_str().unwrap(),
+            &StubifyOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
 
-        assert_eq!(stub_name, "file.tex/primary");
+        assert!(err.contains("config.json"), "{err}");
+        assert!(err.contains("invalid type: string \"gen.tex\""), "{err}");
+        assert!(!stubs_path.exists());
     }
 
     #[test]
-    fn test_strip_latex_comments_simple() {
-        let content = "hello % this is a comment\nworld";
-        let stripped = strip_latex_comments(content);
-        assert_eq!(stripped, "hello \nworld");
-    }
+    fn test_only_patches_stubs() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        let b_tex = src.join("chapter").join("b.tex");
+        fs::create_dir_all(b_tex.parent().unwrap()).unwrap();
+        fs::write(
+            &b_tex,
+            r"\begin{theorem}\label{thm1}\uses{lem1}\end{theorem}
+\begin{proof}\uses{lem1}\end{proof}",
+        )
+        .unwrap();
+        let only = StubifyOptions {
+            only: vec!["./chapter/b.tex".to_string()],
+            ..StubifyOptions::default()
+        };
 
-    #[test]
-    fn test_strip_latex_comments_escaped_percent() {
-        let content = r"50\% discount";
-        let stripped = strip_latex_comments(content);
-        assert_eq!(stripped, r"50\% discount");
-    }
+        // Without a stubs file there is nothing to patch
+        let error = run(project, output, &only).unwrap_err();
+        assert!(error.to_string().contains("run stubify without --only"));
 
-    #[test]
-    fn test_strip_latex_comments_full_line() {
-        let content = "% full line comment\nactual content";
-        let stripped = strip_latex_comments(content);
-        assert_eq!(stripped, "\nactual content");
-    }
+        run(project, output, &StubifyOptions::default()).unwrap();
+        let meta = fs::read_to_string(meta_path(&stubs_path)).unwrap();
 
-    #[test]
-    fn test_strip_latex_comments_no_comments() {
-        let content = r"\begin{theorem}\label{foo}\end{theorem}";
-        let stripped = strip_latex_comments(content);
-        assert_eq!(stripped, content);
+        // The patched stubs are the ones a full run writes; the dependency
+        // on a stub of another file still resolves
+        fs::write(
+            &b_tex,
+            r"\begin{theorem}\label{thm2}\uses{lem1}\end{theorem}
+\begin{lemma}\label{lem3}\end{lemma}",
+        )
+        .unwrap();
+        run(project, output, &only).unwrap();
+        let patched = fs::read_to_string(&stubs_path).unwrap();
+        let stubs: serde_json::Value = serde_json::from_str(&patched).unwrap();
+        assert!(stubs.get("chapter/b.tex/thm1").is_none());
+        assert_eq!(
+            stubs["chapter/b.tex/thm2"]["spec-dependencies"],
+            serde_json::json!(["a.tex/lem1"])
+        );
+        assert_eq!(fs::read_to_string(meta_path(&stubs_path)).unwrap(), meta);
+        run(project, output, &StubifyOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), patched);
+
+        // Labels of the other files cannot be defined again
+        fs::write(&b_tex, r"\begin{lemma}\label{lem1}\end{lemma}").unwrap();
+        let error = run(project, output, &only).unwrap_err();
+        assert!(error.to_string().contains("lem1"));
+
+        // Dependencies of other stubs on removed ones are left in place
+        fs::write(&b_tex, r"\begin{lemma}\label{lem3}\end{lemma}").unwrap();
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{lemma}\label{lem1}\uses{lem3}\end{lemma}",
+        )
+        .unwrap();
+        run(project, output, &StubifyOptions::default()).unwrap();
+        fs::write(&b_tex, "").unwrap();
+        run(project, output, &only).unwrap();
+        let stubs: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+        assert!(stubs.get("chapter/b.tex/lem3").is_none());
+        assert_eq!(
+            stubs["a.tex/lem1"]["spec-dependencies"],
+            serde_json::json!(["chapter/b.tex/lem3"])
+        );
     }
 
     #[test]
-    fn test_parse_tex_file_commented_out_env() {
-        let content = r#"
-% \begin{theorem}\label{commented_out}
-%   This theorem is commented out.
-% \end{theorem}
-
-\begin{theorem}\label{active_theorem}
-  This theorem is active.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        // Only the active theorem should be found
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].labels, vec!["active_theorem"]);
+    fn test_only_resolves_label_aliases() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{lemma}\label{lem:a}\label{lem:alias}\end{lemma}",
+        )
+        .unwrap();
+        fs::write(
+            src.join("b.tex"),
+            r"\begin{theorem}\label{thm:b}\uses{lem:a}\end{theorem}",
+        )
+        .unwrap();
+        run(project, output, &StubifyOptions::default()).unwrap();
+        let full = fs::read_to_string(&stubs_path).unwrap();
+
+        // b.tex refers to a.tex by its non-primary label
+        let only = StubifyOptions {
+            only: vec!["b.tex".to_string()],
+            ..StubifyOptions::default()
+        };
+        run(project, output, &only).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), full);
+        let stubs: serde_json::Value = serde_json::from_str(&full).unwrap();
+        assert_eq!(
+            stubs["b.tex/thm:b"]["spec-dependencies"],
+            serde_json::json!(["a.tex/lem:alias"])
+        );
+        assert_eq!(
+            stubs["a.tex/lem:alias"]["label-aliases"],
+            serde_json::json!(["lem:a"])
+        );
     }
 
     #[test]
-    fn test_parse_tex_file_partially_commented() {
-        let content = r#"
-\begin{theorem}\label{my_theorem}
-  % \label{commented_label}
-  Active content here.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+    fn test_only_reparses_files_proving_its_labels() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{theorem}\label{thm:a}\end{theorem}",
+        )
+        .unwrap();
+        fs::write(
+            src.join("b.tex"),
+            r"\begin{lemma}\label{lem:b}\end{lemma}
+\begin{proof}\proves{thm:a}\leanok\uses{lem:b}\end{proof}",
+        )
+        .unwrap();
+        run(project, output, &StubifyOptions::default()).unwrap();
+        let full = fs::read_to_string(&stubs_path).unwrap();
+        let stubs: serde_json::Value = serde_json::from_str(&full).unwrap();
+        assert_eq!(
+            stubs["a.tex/thm:a"]["proof-dependencies"],
+            serde_json::json!(["b.tex/lem:b"])
+        );
 
-        assert_eq!(envs.len(), 1);
-        // Only the non-commented label should be found
-        assert_eq!(envs[0].labels, vec!["my_theorem"]);
+        // The proof of thm:a is in b.tex, which is reparsed with a.tex
+        let only = StubifyOptions {
+            only: vec!["a.tex".to_string()],
+            ..StubifyOptions::default()
+        };
+        run(project, output, &only).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), full);
+    }
+
+    /// Stable IDs of the stubs written to `stubs_path` that were made
+    /// unique, sorted
+    fn disambiguated_ids(stubs_path: &Path) -> Vec<String> {
+        let stubs: HashMap<String, serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(stubs_path).unwrap()).unwrap();
+        let mut ids: Vec<String> = stubs
+            .values()
+            .filter_map(|stub| stub["stable-id"].as_str())
+            .filter(|id| id.contains('-'))
+            .map(str::to_string)
+            .collect();
+        ids.sort();
+        ids
     }
 
     #[test]
-    fn test_parse_tex_file_with_proof() {
-        let content = r#"
-\begin{theorem}\label{my_theorem}\lean{MyTheorem}\leanok
-  Statement of the theorem.
-\end{theorem}
-
-\begin{proof}\leanok\uses{lemma1,lemma2}
-  The proof goes here.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].labels, vec!["my_theorem"]);
-        assert!(envs[0].spec_ok);
-        assert_eq!(envs[0].proof_ok, Some(true));
+    fn test_only_keeps_stable_ids_of_a_full_run() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        // An unlabeled statement on the same source line in both files
+        fs::write(
+            src.join("a.tex"),
+            "\\begin{lemma}\\label{lem1}\\end{lemma}\n\\begin{theorem}\\end{theorem}",
+        )
+        .unwrap();
+        fs::write(src.join("b.tex"), r"\begin{theorem}\end{theorem}").unwrap();
+        run(project, output, &StubifyOptions::default()).unwrap();
+        let full = fs::read_to_string(&stubs_path).unwrap();
+        let ids = disambiguated_ids(&stubs_path);
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
         assert_eq!(
-            envs[0].proof_dependencies,
-            Some(vec!["lemma1".to_string(), "lemma2".to_string()])
+            ids[0].split_once('-').unwrap().0,
+            ids[1].split_once('-').unwrap().0
         );
-        // Check proof lines are captured
-        assert!(envs[0].proof_lines.is_some());
-        let proof_lines = envs[0].proof_lines.as_ref().unwrap();
-        assert_eq!(proof_lines.lines_start, 6);
-        assert_eq!(proof_lines.lines_end, 8);
-    }
-
-    #[test]
-    fn test_parse_tex_file_proof_with_label() {
-        let content = r#"
-\begin{theorem}\label{thm_label}\leanok
-  Statement.
-\end{theorem}
 
-\begin{proof}\label{proof_label}\leanok
-  Proof content.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+        let only = StubifyOptions {
+            only: vec!["b.tex".to_string()],
+            ..StubifyOptions::default()
+        };
+        run(project, output, &only).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), full);
 
-        assert_eq!(envs.len(), 1);
-        // Proof label should be added to the end
-        assert_eq!(envs[0].labels, vec!["thm_label", "proof_label"]);
-        // The stub name should use the last label (proof_label)
+        // Once the lines differ, the shared ID is no longer replaced
+        fs::write(src.join("b.tex"), r"\begin{theorem}Some text.\end{theorem}").unwrap();
+        run(project, output, &only).unwrap();
+        let patched = fs::read_to_string(&stubs_path).unwrap();
+        assert!(disambiguated_ids(&stubs_path).is_empty());
+        run(project, output, &StubifyOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), patched);
     }
 
     #[test]
-    fn test_parse_tex_file_proof_without_leanok() {
-        let content = r#"
-\begin{theorem}\label{my_theorem}\leanok
-  Statement.
-\end{theorem}
-
-\begin{proof}\uses{dep1}
-  Proof without leanok.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert!(envs[0].spec_ok);
-        // proof_ok should be None (not present) when \leanok is not in proof
-        assert_eq!(envs[0].proof_ok, None);
-        assert_eq!(envs[0].proof_dependencies, Some(vec!["dep1".to_string()]));
-    }
+    fn test_only_keeps_stable_ids_with_project_path_base() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        // An unlabeled statement on the same source line in both files
+        fs::write(
+            src.join("a.tex"),
+            "\\begin{lemma}\\label{lem1}\\end{lemma}\n\\begin{theorem}\\end{theorem}",
+        )
+        .unwrap();
+        fs::write(src.join("b.tex"), r"\begin{theorem}\end{theorem}").unwrap();
+        let options = StubifyOptions {
+            path_base: PathBase::Project,
+            ..StubifyOptions::default()
+        };
+        run(project, output, &options).unwrap();
+        let full = fs::read_to_string(&stubs_path).unwrap();
+        assert_eq!(disambiguated_ids(&stubs_path).len(), 2);
 
-    #[test]
-    fn test_parse_tex_file_no_proof() {
-        let content = r#"
-\begin{definition}\label{my_def}\lean{MyDef}\leanok
-  A definition without proof.
-\end{definition}
-"#;
-        let env_types: Vec<String> = vec!["definition".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].labels, vec!["my_def"]);
-        assert_eq!(envs[0].proof_ok, None);
-        assert_eq!(envs[0].proof_dependencies, None);
-        assert!(envs[0].proof_lines.is_none());
+        let only = StubifyOptions {
+            only: vec!["b.tex".to_string()],
+            ..options
+        };
+        run(project, output, &only).unwrap();
+        assert_eq!(fs::read_to_string(&stubs_path).unwrap(), full);
+        let stubs: serde_json::Value = serde_json::from_str(&full).unwrap();
+        assert_eq!(stubs["a.tex/lem1"]["stub-path"], "blueprint/src/a.tex");
     }
 
     #[test]
-    fn test_parse_tex_file_theorem_then_other_content_then_proof() {
-        // Proof should only be found if it immediately follows (with whitespace only)
-        let content = r#"
-\begin{theorem}\label{thm1}\leanok
-  First theorem.
-\end{theorem}
-
-Some intervening text here.
+    fn test_path_base() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let chapter = dir.path().join("blueprint").join("src").join("chapter");
+        fs::create_dir_all(&chapter).unwrap();
+        fs::write(
+            chapter.join("b.tex"),
+            r"\begin{theorem}\label{thm1}\lean{Foo.a, Foo.b}\end{theorem}",
+        )
+        .unwrap();
 
-\begin{proof}\leanok
-  This proof should NOT be associated with thm1.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+        let read = |path: &Path| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        };
+        for (path_base, name, prefix) in [
+            (PathBase::Src, "src", ""),
+            (PathBase::Project, "project", "blueprint/src/"),
+        ] {
+            let options = StubifyOptions {
+                path_base,
+                ..StubifyOptions::default()
+            };
+            run(project, output, &options).unwrap();
 
-        assert_eq!(envs.len(), 1);
-        // The proof should not be associated because there's intervening text
-        assert_eq!(envs[0].proof_ok, None);
-        assert!(envs[0].proof_lines.is_none());
-    }
+            // Stub-names stay relative to blueprint/src
+            let stubs = read(&stubs_path);
+            assert_eq!(
+                stubs["a.tex/lem1"]["stub-path"],
+                format!("{prefix}a.tex").as_str()
+            );
+            assert_eq!(
+                stubs["chapter/b.tex/thm1"]["stub-path"],
+                format!("{prefix}chapter/b.tex").as_str()
+            );
+            assert!(stubs["chapter/b.tex/thm1_1"].get("stub-path").is_none());
+            assert_eq!(read(&meta_path(&stubs_path))["path-base"], name);
+            assert_eq!(
+                read_meta(&stubs_path).map(|meta| meta.path_base),
+                Some(path_base)
+            );
+        }
 
-    #[test]
-    fn test_strip_nested_environments() {
-        let content =
-            r#"Top level content \begin{equation}\label{nested}\end{equation} more content"#;
-        let stripped = strip_nested_environments(content);
-        assert_eq!(stripped, "Top level content  more content");
+        assert_eq!(src_relative("blueprint/src/chapter/b.tex"), "chapter/b.tex");
+        assert_eq!(src_relative("chapter/b.tex"), "chapter/b.tex");
     }
 
     #[test]
-    fn test_strip_nested_environments_multiple() {
-        let content =
-            r#"\begin{align}\label{a1}\end{align} text \begin{equation}\label{a2}\end{equation}"#;
-        let stripped = strip_nested_environments(content);
-        assert_eq!(stripped, " text ");
-    }
+    fn test_strict_proof_macros() {
+        let (dir, stubs_path) = temp_project();
+        let project = dir.path().to_str().unwrap();
+        let output = stubs_path.to_str().unwrap();
+        let src = dir.path().join("blueprint").join("src");
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{lemma}\label{lem1}\end{lemma}
+\begin{proof}\leanok\end{proof}
+\begin{lemma}\label{lem2}\leanok\end{lemma}
+\begin{proof}\mathlibok\end{proof}",
+        )
+        .unwrap();
 
-    #[test]
-    fn test_extract_labels_ignores_nested() {
-        // Labels inside nested environments should be ignored
-        let content = r#"\label{top_level}
-Some text here.
-\begin{equation}\label{nested_eq}
-  x = y
-\end{equation}
-More text.
-\begin{align}\label{nested_align}
-  a = b
-\end{align}"#;
-        let labels = extract_all_labels(content);
-        assert_eq!(labels, vec!["top_level"]);
-    }
+        // Only a warning by default
+        run(project, output, &StubifyOptions::default()).unwrap();
+        let stubs: HashMap<String, Stub> =
+            serde_json::from_str(&fs::read_to_string(&stubs_path).unwrap()).unwrap();
+        assert!(check_proof_macros(&stubs, false).is_ok());
 
-    #[test]
-    fn test_parse_tex_file_proof_with_nested_equation() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\leanok
-  Statement of theorem.
-\end{theorem}
-
-\begin{proof}\label{my_proof}\leanok
-  We have
-  \begin{equation}\label{eq1}
-    x = y
-  \end{equation}
-  and therefore the result follows.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        // Only top-level labels: my_thm from theorem, my_proof from proof
-        // eq1 from nested equation should be ignored
-        assert_eq!(envs[0].labels, vec!["my_thm", "my_proof"]);
-        assert_eq!(envs[0].proof_ok, Some(true));
-    }
-
-    #[test]
-    fn test_parse_tex_file_theorem_with_nested_env() {
-        let content = r#"
-\begin{theorem}\label{main_thm}
-  For all $x$, we have
-  \begin{equation}\label{internal_eq}
-    f(x) = g(x)
-  \end{equation}
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        // Only main_thm should be captured, not internal_eq
-        assert_eq!(envs[0].labels, vec!["main_thm"]);
+        let strict = StubifyOptions {
+            strict: true,
+            ..StubifyOptions::default()
+        };
+        let error = run(project, output, &strict).unwrap_err();
+        assert!(error.to_string().starts_with("2 proofs"));
+
+        fs::write(
+            src.join("a.tex"),
+            r"\begin{lemma}\label{lem1}\leanok\end{lemma}
+\begin{proof}\leanok\end{proof}
+\begin{lemma}\label{lem2}\mathlibok\end{lemma}
+\begin{proof}\leanok\mathlibok\end{proof}",
+        )
+        .unwrap();
+        run(project, output, &strict).unwrap();
     }
 
     #[test]
-    fn test_parse_tex_file_line_numbers() {
-        let content = r#"\begin{theorem}\label{thm1}
-Line 2 content.
-Line 3 content.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].spec_lines.lines_start, 1);
-        assert_eq!(envs[0].spec_lines.lines_end, 4);
-    }
+    fn test_unconfigured_envs_summary() {
+        let files = vec![
+            (
+                "web.tex".to_string(),
+                r"\usepackage[thms=theorem+lemma]{blueprint}".to_string(),
+            ),
+            (
+                "a.tex".to_string(),
+                r"\begin{conjecture}\label{conj1}\end{conjecture}
+\begin{theorem}\label{thm1}\begin{align}x\end{align}\end{theorem}
+\begin{proof}\begin{itemize}\item y\end{itemize}\end{proof}
+\begin{remark}\end{remark}
+\begin{conjecture}\label{conj2}\end{conjecture}
+\begin{sidenote}\end{sidenote}
+% \begin{conjecture}\end{conjecture}"
+                    .to_string(),
+            ),
+        ];
 
-    #[test]
-    fn test_parse_tex_file_with_mathlibok() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\lean{MyThm}\mathlibok
-  A theorem in mathlib.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+        let parsed = parse::parse_sources(&files).unwrap();
+        assert_eq!(
+            unconfigured_summary(&parsed.unconfigured_envs).as_deref(),
+            Some(
+                "Skipped 2 'conjecture' environments, 1 'remark', 1 'sidenote' of types that \
+                 are not looked for; add them to thms= in web.tex, or to ignored-envs in \
+                 .verilib/config.json if they hold no statements"
+            )
+        );
 
-        assert_eq!(envs.len(), 1);
-        assert!(envs[0].mathlib_ok);
-        // mathlibok should imply leanok for the spec
-        assert!(!envs[0].spec_ok); // leanok was not explicitly present
+        // Types the project lists as holding no statements are left out
+        let config: Config =
+            serde_json::from_str(r#"{"ignored-envs": ["sidenote", "remark"]}"#).unwrap();
+        let options = ParseOptions {
+            ignored_envs: config.ignored_envs,
+            ..ParseOptions::default()
+        };
+        let parsed = parse::parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        assert_eq!(
+            parsed.unconfigured_envs,
+            BTreeMap::from([("conjecture".to_string(), 2)])
+        );
+        assert_eq!(unconfigured_summary(&BTreeMap::new()), None);
     }
 
     #[test]
-    fn test_parse_tex_file_with_notready() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\notready
-  A theorem not ready for formalization.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert!(envs[0].not_ready);
-        assert!(!envs[0].spec_ok);
-    }
+    fn test_locate_lean_declarations() {
+        let dir = tempfile::tempdir().unwrap();
+        let lean_dir = dir.path().join("Foo");
+        fs::create_dir_all(&lean_dir).unwrap();
+        fs::write(
+            lean_dir.join("Basic.lean"),
+            "namespace Foo\n\n/-- Bar holds. -/\ntheorem bar : True := by\n  trivial\n\nend Foo\n",
+        )
+        .unwrap();
 
-    #[test]
-    fn test_parse_tex_file_with_discussion() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\discussion{123}\discussion{456}
-  A theorem with discussions.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\lean{Foo.bar}\end{lemma}
+\begin{lemma}\label{lem2}\lean{Foo.missing}\end{lemma}"
+                .to_string(),
+        )];
+        let mut stubs = parse::parse_sources(&files).unwrap().stubs;
+        locate_lean_declarations(&mut stubs, &[dir.path().to_path_buf()], false).unwrap();
 
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].discussion, vec!["123", "456"]);
+        let found = &stubs["a.tex/lem1"];
+        assert_eq!(found.lean_path.as_deref(), Some("Foo/Basic.lean"));
+        let lines = found.lean_lines.unwrap();
+        assert_eq!((lines.lines_start, lines.lines_end), (4, 5));
+        assert_eq!(found.lean_doc.as_deref(), Some("Bar holds."));
+        assert!(stubs["a.tex/lem2"].lean_path.is_none());
     }
 
     #[test]
-    fn test_parse_tex_file_with_multiple_lean_names() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\lean{Thm1, Thm2, Thm3}\leanok
-  A theorem with multiple lean names.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        assert_eq!(envs[0].code_name, Some("probe:Thm1".to_string()));
+    fn test_env_type_map() {
+        assert_eq!(
+            parse_env_type_mapping("dfn:definition"),
+            Ok(("dfn".to_string(), "definition".to_string()))
+        );
+        assert!(parse_env_type_mapping("dfn").is_err());
+        assert!(parse_env_type_mapping(":definition").is_err());
+
+        let files = vec![
+            (
+                "web.tex".to_string(),
+                r"\usepackage[thms=dfn+lem+theorem]{blueprint}".to_string(),
+            ),
+            (
+                "a.tex".to_string(),
+                r"\begin{dfn}\label{d}\end{dfn}
+\begin{lem}\label{l}\end{lem}
+\begin{theorem}\label{t}\end{theorem}"
+                    .to_string(),
+            ),
+        ];
+        let mut parsed = parse::parse_sources(&files).unwrap();
+        let env_type_map = BTreeMap::from([
+            ("dfn".to_string(), "definition".to_string()),
+            ("lem".to_string(), "lemma".to_string()),
+        ]);
+        rename_env_types(&mut parsed.stubs, &env_type_map);
+
+        let types: BTreeMap<&str, Option<&str>> = parsed
+            .stubs
+            .values()
+            .map(|stub| (stub.label.as_str(), stub.stub_type.as_deref()))
+            .collect();
         assert_eq!(
-            envs[0].lean_names,
-            Some(vec![
-                "probe:Thm1".to_string(),
-                "probe:Thm2".to_string(),
-                "probe:Thm3".to_string()
+            types,
+            BTreeMap::from([
+                ("d", Some("definition")),
+                ("l", Some("lemma")),
+                ("t", Some("theorem")),
             ])
         );
     }
 
     #[test]
-    fn test_parse_tex_file_proof_with_proves_not_associated() {
-        // When a proof has \proves, it should NOT be associated with the preceding theorem
-        let content = r#"
-\begin{theorem}\label{thm1}\leanok
-  First theorem.
-\end{theorem}
-
-\begin{proof}\proves{some_other_thm}\leanok
-  This proof is for a different theorem.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        // The proof should NOT be associated since it has \proves
-        assert!(envs[0].proof_lines.is_none());
-        assert_eq!(envs[0].proof_ok, None);
-    }
-
-    #[test]
-    fn test_find_standalone_proofs() {
-        let content = r#"
-\begin{theorem}\label{thm1}
-  A theorem.
-\end{theorem}
-
-\begin{proof}\proves{thm1}\leanok\uses{lemma1}\lean{TheoremProof}
-  The proof.
-\end{proof}
-"#;
-        let proofs = find_standalone_proofs(content, "file.tex");
-
-        assert_eq!(proofs.len(), 1);
-        assert_eq!(proofs[0].proves_labels, vec!["thm1"]);
-        assert!(proofs[0].proof_ok);
-        assert_eq!(proofs[0].dependencies, vec!["lemma1"]);
-        assert_eq!(proofs[0].lean_names, vec!["TheoremProof"]);
-    }
-
-    #[test]
-    fn test_find_standalone_proofs_with_mathlibok() {
-        let content = r#"
-\begin{proof}\proves{thm1}\mathlibok
-  A mathlib proof.
-\end{proof}
-"#;
-        let proofs = find_standalone_proofs(content, "file.tex");
+    fn test_env_count_per_file() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{lemma}\label{a1}\end{lemma}".to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{lemma}\label{b1}\lean{X, Y}\end{lemma}
+\begin{lemma}\label{b2}\end{lemma}"
+                    .to_string(),
+            ),
+            (
+                "c.tex".to_string(),
+                r"\begin{lemma}\label{c1}\end{lemma}".to_string(),
+            ),
+        ];
+        let stubs = parse::parse_sources(&files).unwrap().stubs;
 
-        assert_eq!(proofs.len(), 1);
-        assert!(proofs[0].mathlib_ok);
+        assert_eq!(
+            env_count_per_file(&stubs),
+            vec![("b.tex", 2), ("a.tex", 1), ("c.tex", 1)]
+        );
     }
 
     #[test]
-    fn test_find_standalone_proofs_with_notready() {
-        let content = r#"
-\begin{proof}\proves{thm1}\notready
-  A proof not ready.
-\end{proof}
-"#;
-        let proofs = find_standalone_proofs(content, "file.tex");
+    fn test_format_review_report() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem:free}\leanok\end{lemma}
+\section{Magmas}
+\begin{definition}\label{def:magma}\lean{Magma}\leanok\end{definition}
+\subsection{Laws}
+\begin{theorem}\label{thm:main}\lean{A, B}\leanok\end{theorem}
+\begin{proof}\leanok\end{proof}
+\begin{lemma}\label{lem:aux}\end{lemma}
+\begin{proof}\end{proof}"
+                .to_string(),
+        )];
+        let stubs = parse::parse_sources(&files).unwrap().stubs;
+
+        let report = format_review_report(&stubs, "- [x] lem:aux: Check proof\n");
+        assert_eq!(
+            report,
+            "# Blueprint review checklist
 
-        assert_eq!(proofs.len(), 1);
-        assert!(proofs[0].not_ready);
-    }
+- [x] lem:free (Lemma): Check spec
+- [ ] lem:free: Verify Lean code matches blueprint
 
-    #[test]
-    fn test_find_standalone_proofs_with_discussion() {
-        let content = r#"
-\begin{proof}\proves{thm1}\discussion{789}
-  A proof with discussion.
-\end{proof}
-"#;
-        let proofs = find_standalone_proofs(content, "file.tex");
+## Magmas
 
-        assert_eq!(proofs.len(), 1);
-        assert_eq!(proofs[0].discussion, vec!["789"]);
-    }
+- [x] def:magma (Definition): Check spec
+- [ ] def:magma: Verify Lean code matches blueprint
 
-    #[test]
-    fn test_proof_with_all_macros() {
-        let content = r#"
-\begin{theorem}\label{my_thm}\lean{MyTheorem}\leanok\mathlibok\discussion{100}
-  Statement.
-\end{theorem}
-
-\begin{proof}\leanok\mathlibok\uses{dep1}\lean{MyProof}\discussion{200}
-  Proof.
-\end{proof}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "file.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
-        // Spec fields
-        assert!(envs[0].spec_ok);
-        assert!(envs[0].mathlib_ok);
-        assert_eq!(envs[0].discussion, vec!["100"]);
-        assert_eq!(envs[0].code_name, Some("probe:MyTheorem".to_string()));
-
-        // Proof fields
-        assert_eq!(envs[0].proof_ok, Some(true));
-        assert_eq!(envs[0].proof_mathlib_ok, Some(true));
-        assert_eq!(envs[0].proof_discussion, Some(vec!["200".to_string()]));
-        assert_eq!(envs[0].proof_dependencies, Some(vec!["dep1".to_string()]));
-        assert_eq!(envs[0].proof_lean_names, Some(vec!["MyProof".to_string()]));
-    }
+## Magmas > Laws
 
-    #[test]
-    fn test_extract_home() {
-        assert_eq!(
-            extract_home(r"\home{https://example.com/project}"),
-            Some("https://example.com/project".to_string())
+- [x] thm:main (Theorem): Check spec
+- [x] thm:main: Check proof
+- [ ] thm:main: Verify Lean code matches blueprint
+- [ ] lem:aux (Lemma): Check spec
+- [x] lem:aux: Check proof
+- [ ] lem:aux: Verify Lean code matches blueprint
+"
         );
-        assert_eq!(extract_home(r"no home here"), None);
     }
 
     #[test]
-    fn test_extract_github() {
-        assert_eq!(
-            extract_github(r"\github{https://github.com/user/repo}"),
-            Some("https://github.com/user/repo".to_string())
-        );
-        assert_eq!(extract_github(r"no github here"), None);
-    }
+    fn test_format_bibtex() {
+        let files = vec![
+            (
+                "web.tex".to_string(),
+                r"\dochome{https://example.org/docs/}".to_string(),
+            ),
+            (
+                "ch/a.tex".to_string(),
+                r"\begin{theorem}[Main result]\label{thm:main}\lean{A.b, A.c}\end{theorem}
+
+\begin{lemma}\label{lem:aux}\end{lemma}"
+                    .to_string(),
+            ),
+        ];
+        let parsed = parse::parse_sources(&files).unwrap();
 
-    #[test]
-    fn test_extract_dochome() {
         assert_eq!(
-            extract_dochome(r"\dochome{https://docs.example.com/}"),
-            Some("https://docs.example.com/".to_string())
-        );
-        assert_eq!(extract_dochome(r"no dochome here"), None);
-    }
+            format_bibtex(&parsed.stubs, &parsed.config),
+            r"@misc{lem:aux,
+  title = {lem:aux},
+  type = {lemma},
+  howpublished = {\url{https://example.org/docs/ch/a.tex#L3}},
+}
 
-    #[test]
-    fn test_extract_config() {
-        let content = r#"
-\home{https://example.com}
-\github{https://github.com/user/repo}
-\dochome{https://docs.example.com}
-"#;
-        let config = extract_config(content);
-        assert_eq!(config.home, Some("https://example.com".to_string()));
-        assert_eq!(
-            config.github,
-            Some("https://github.com/user/repo".to_string())
-        );
-        assert_eq!(config.dochome, Some("https://docs.example.com".to_string()));
-    }
+@misc{thm:main,
+  title = {Main result},
+  type = {theorem},
+  howpublished = {\url{https://example.org/docs/ch/a.tex#L1}},
+}
 
-    #[test]
-    fn test_extract_config_partial() {
-        let content = r#"\github{https://github.com/user/repo}"#;
-        let config = extract_config(content);
-        assert_eq!(config.home, None);
-        assert_eq!(
-            config.github,
-            Some("https://github.com/user/repo".to_string())
+"
         );
-        assert_eq!(config.dochome, None);
     }
 
     #[test]
-    fn test_merge_config() {
-        let base = Config {
-            home: Some("base_home".to_string()),
-            github: Some("base_github".to_string()),
-            dochome: None,
-        };
-        let other = Config {
-            home: None,
-            github: Some("other_github".to_string()),
-            dochome: Some("other_dochome".to_string()),
-        };
-        let merged = merge_config(base, other);
-        assert_eq!(merged.home, Some("base_home".to_string())); // kept from base
-        assert_eq!(merged.github, Some("other_github".to_string())); // overridden by other
-        assert_eq!(merged.dochome, Some("other_dochome".to_string())); // added from other
-    }
-
-    #[test]
-    fn test_stub_splitting_multiple_code_names() {
-        // Test that stubs with multiple code-names get split into child stubs
-        let content = r#"
-\begin{theorem}\label{multi_thm}\lean{Thm1, Thm2, Thm3}\leanok
-  A theorem with multiple lean names.
-\end{theorem}
-"#;
-        let env_types: Vec<String> = vec!["theorem".to_string()];
-        let envs = parse_tex_file(content, "chapter/test.tex", &env_types);
-
-        assert_eq!(envs.len(), 1);
+    fn test_locate_lean_declarations_by_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("Graph.lean"),
+            "namespace Subgraph\ntheorem eq387 : True := trivial\nnamespace A\ntheorem shared : True := trivial\nend A\nnamespace B\ntheorem shared : True := trivial\nend B\nend Subgraph\n",
+        )
+        .unwrap();
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\lean{eq387}\end{lemma}
+\begin{lemma}\label{lem2}\lean{shared}\end{lemma}"
+                .to_string(),
+        )];
+        let roots = [dir.path().to_path_buf()];
+
+        let mut stubs = parse::parse_sources(&files).unwrap().stubs;
+        locate_lean_declarations(&mut stubs, &roots, false).unwrap();
+        let found = &stubs["a.tex/lem1"];
+        assert_eq!(found.lean_path.as_deref(), Some("Graph.lean"));
+        assert_eq!(found.code_name.as_deref(), Some("probe:eq387"));
+        assert!(stubs["a.tex/lem2"].lean_path.is_none());
+
+        let mut stubs = parse::parse_sources(&files).unwrap().stubs;
+        locate_lean_declarations(&mut stubs, &roots, true).unwrap();
         assert_eq!(
-            envs[0].lean_names,
-            Some(vec![
-                "probe:Thm1".to_string(),
-                "probe:Thm2".to_string(),
-                "probe:Thm3".to_string()
-            ])
+            stubs["a.tex/lem1"].code_name.as_deref(),
+            Some("probe:Subgraph.eq387")
         );
-
-        // Build stubs from envs (simulating what run() does)
-        let mut all_stubs: HashMap<String, Stub> = HashMap::new();
-        let mut label_to_stub_name: HashMap<String, String> = HashMap::new();
-
-        for env in envs {
-            let label = env.labels.iter().next_back().unwrap().clone();
-            let stub_name = format!("{}/{}", env.relative_path, label);
-
-            label_to_stub_name.insert(label.clone(), stub_name.clone());
-            for lbl in &env.labels {
-                label_to_stub_name.insert(lbl.clone(), stub_name.clone());
-            }
-
-            all_stubs.insert(
-                stub_name,
-                Stub {
-                    label,
-                    stub_type: Some(env.env_type.clone()),
-                    stub_path: Some(env.relative_path.clone()),
-                    stub_spec: Some(env.spec_lines),
-                    stub_proof: None,
-                    code_name: env.code_name.clone(),
-                    lean_names: env.lean_names.clone(),
-                    spec_ok: Some(env.spec_ok),
-                    mathlib_ok: Some(env.mathlib_ok),
-                    not_ready: Some(env.not_ready),
-                    discussion: env.discussion.clone(),
-                    spec_dependencies: vec![],
-                    proof_ok: None,
-                    proof_mathlib_ok: None,
-                    proof_not_ready: None,
-                    proof_discussion: None,
-                    proof_dependencies: None,
-                    proof_lean_names: None,
-                },
-            );
-        }
-
-        // Simulate stub splitting
-        let stub_names_to_split: Vec<String> = all_stubs
-            .iter()
-            .filter(|(_, stub)| {
-                stub.lean_names
-                    .as_ref()
-                    .is_some_and(|names| names.len() > 1)
-            })
-            .map(|(name, _)| name.clone())
-            .collect();
-
-        for stub_name in stub_names_to_split {
-            let stub = all_stubs.get(&stub_name).unwrap().clone();
-            let code_names = stub.lean_names.as_ref().unwrap();
-            let base_label = &stub.label;
-
-            let mut child_stub_names = Vec::new();
-            for (i, code_name) in code_names.iter().enumerate() {
-                let child_label = format!("{}_{}", base_label, i + 1);
-                let child_stub_name = format!(
-                    "{}/{}",
-                    stub.stub_path.as_ref().unwrap_or(&String::new()),
-                    child_label
-                );
-
-                let child_stub = Stub {
-                    label: child_label.clone(),
-                    stub_type: None,
-                    stub_path: None,
-                    stub_spec: None,
-                    stub_proof: None,
-                    code_name: Some(code_name.clone()),
-                    lean_names: None,
-                    spec_ok: stub.spec_ok,
-                    mathlib_ok: stub.mathlib_ok,
-                    not_ready: stub.not_ready,
-                    discussion: stub.discussion.clone(),
-                    spec_dependencies: stub.spec_dependencies.clone(),
-                    proof_ok: stub.proof_ok,
-                    proof_mathlib_ok: stub.proof_mathlib_ok,
-                    proof_not_ready: stub.proof_not_ready,
-                    proof_discussion: stub.proof_discussion.clone(),
-                    proof_dependencies: stub.proof_dependencies.clone(),
-                    proof_lean_names: stub.proof_lean_names.clone(),
-                };
-
-                child_stub_names.push(child_stub_name.clone());
-                label_to_stub_name.insert(child_label, child_stub_name.clone());
-                all_stubs.insert(child_stub_name, child_stub);
-            }
-
-            let parent_stub = all_stubs.get_mut(&stub_name).unwrap();
-            parent_stub.code_name = None;
-            parent_stub.lean_names = None;
-            parent_stub.spec_ok = None;
-            parent_stub.mathlib_ok = None;
-            parent_stub.not_ready = None;
-            parent_stub.discussion = Vec::new();
-            parent_stub.spec_dependencies = child_stub_names;
-            parent_stub.proof_ok = None;
-            parent_stub.proof_mathlib_ok = None;
-            parent_stub.proof_not_ready = None;
-            parent_stub.proof_discussion = None;
-            parent_stub.proof_dependencies = None;
-            parent_stub.proof_lean_names = None;
-        }
-
-        // Verify: should have 4 stubs now (1 parent + 3 children)
-        assert_eq!(all_stubs.len(), 4);
-
-        // Check parent stub
-        let parent = all_stubs.get("chapter/test.tex/multi_thm").unwrap();
-        assert_eq!(parent.label, "multi_thm");
-        assert!(parent.stub_type.is_some()); // Parent keeps stub-type
-        assert!(parent.stub_path.is_some()); // Parent keeps stub-path
-        assert!(parent.stub_spec.is_some()); // Parent keeps stub-spec
-        assert!(parent.code_name.is_none()); // Parent loses code-name
-        assert!(parent.lean_names.is_none()); // Parent loses lean-names
-        assert!(parent.spec_ok.is_none()); // Parent loses spec-ok
         assert_eq!(
-            parent.spec_dependencies,
-            vec![
-                "chapter/test.tex/multi_thm_1",
-                "chapter/test.tex/multi_thm_2",
-                "chapter/test.tex/multi_thm_3"
-            ]
+            stubs["a.tex/lem2"].code_name.as_deref(),
+            Some("probe:shared")
         );
-
-        // Check child stubs
-        let child1 = all_stubs.get("chapter/test.tex/multi_thm_1").unwrap();
-        assert_eq!(child1.label, "multi_thm_1");
-        assert!(child1.stub_type.is_none()); // Child has no stub-type
-        assert!(child1.stub_path.is_none()); // Child has no stub-path
-        assert!(child1.stub_spec.is_none()); // Child has no stub-spec
-        assert_eq!(child1.code_name, Some("probe:Thm1".to_string()));
-        assert!(child1.lean_names.is_none()); // Child has no lean-names
-        assert_eq!(child1.spec_ok, Some(true)); // Child inherits spec-ok
-
-        let child2 = all_stubs.get("chapter/test.tex/multi_thm_2").unwrap();
-        assert_eq!(child2.label, "multi_thm_2");
-        assert_eq!(child2.code_name, Some("probe:Thm2".to_string()));
-
-        let child3 = all_stubs.get("chapter/test.tex/multi_thm_3").unwrap();
-        assert_eq!(child3.label, "multi_thm_3");
-        assert_eq!(child3.code_name, Some("probe:Thm3".to_string()));
     }
 }
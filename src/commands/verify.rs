@@ -1,57 +1,102 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::path::Path;
+use std::sync::LazyLock;
 
-use super::stubify;
+use super::junit::{self, TestCase};
+use super::lean::{DeclarationIndex, Resolution};
+use super::output::{parse_map, read_text, write_map, write_meta, write_text, OutputFormat};
+use super::{check_uses, diagnostics, lake, lean, mathlib_check, stubify};
+use crate::messages;
+use crate::parse;
+
+/// Axioms a proof may depend on and still count as verified
+pub(crate) const STANDARD_AXIOMS: &[&str] = &["propext", "Classical.choice", "Quot.sound"];
+
+/// The axiom `sorry` elaborates to; already reported by the "sorries" status
+const SORRY_AXIOM: &str = "sorryAx";
+
+/// `sorry` as a term or tactic (not part of a longer identifier)
+static SORRY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\bsorry\b").unwrap());
+
+/// Options for the verify command
+#[derive(Debug, Default, Clone)]
+pub struct VerifyOptions {
+    /// Enrich results with atoms.json (reserved for future use)
+    pub with_atoms: Option<Option<String>>,
+    /// Cross-check `\uses` against this Lean source root, or against the
+    /// roots from the Lake configuration for `Some(None)`
+    pub check_uses: Option<Option<String>>,
+    /// Check `\mathlibok` names against this declaration index
+    pub mathlib_index: Option<String>,
+    /// JSON map from declaration name to its `#print axioms` list
+    pub axioms: Option<String>,
+    /// Axioms allowed in addition to `STANDARD_AXIOMS`
+    pub allow_axioms: Vec<String>,
+    /// `lean --json` diagnostics log to attribute to stubs
+    pub diagnostics: Option<String>,
+    /// Mark proofs whose declaration contains `sorry` in this Lean source
+    /// root, or in the roots from the Lake configuration for `Some(None)`
+    pub lean_sorry_grep: Option<Option<String>>,
+    /// Newline-separated list of declarations an external checker verified
+    pub verified_decls: Option<String>,
+    /// Layout of the output file
+    pub format: OutputFormat,
+    /// Also write a JUnit XML report with one test case per declaration
+    pub junit: Option<String>,
+}
 
 /// Stub entry from stubs.json (only fields we need)
 #[derive(Debug, Deserialize)]
-struct Stub {
+pub(crate) struct Stub {
+    label: Option<String>,
+    #[serde(rename = "stub-path")]
+    stub_path: Option<String>,
     #[serde(rename = "code-name")]
     code_name: Option<String>,
     #[serde(rename = "proof-ok")]
     proof_ok: Option<bool>,
+    #[serde(rename = "spec-ok")]
+    spec_ok: Option<bool>,
+    #[serde(rename = "mathlib-ok")]
+    mathlib_ok: Option<bool>,
+    #[serde(rename = "proof-mathlib-ok")]
+    proof_mathlib_ok: Option<bool>,
 }
 
 /// Proof entry for proofs.json
 #[derive(Debug, Serialize)]
-struct Proof {
-    verified: bool,
-    status: String,
+pub(crate) struct Proof {
+    pub(crate) verified: bool,
+    pub(crate) status: String,
+    /// Axioms outside the allowlist, with status "nonstandard-axioms"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    axioms: Option<Vec<String>>,
+    /// Set by an entry of `.verilib/overrides.json`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    overridden: bool,
+    /// Why the status was overridden
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// A proof macro of the stub contradicts its statement's, see
+    /// `parse::inconsistent_proof_macros`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    inconsistent: bool,
 }
 
-/// Extract proof verification status
-pub fn run(
-    project_path: &str,
-    output: &str,
-    regenerate_stubs: bool,
-    _with_atoms: Option<Option<String>>,
-) -> Result<(), Box<dyn Error>> {
-    let project_path = Path::new(project_path);
-    let verilib_dir = project_path.join(".verilib");
-    let stubs_path = verilib_dir.join("stubs.json");
-
-    // Check if stubs.json exists, generate if needed
-    if regenerate_stubs || !stubs_path.exists() {
-        if regenerate_stubs {
-            eprintln!("Regenerating stubs.json...");
-        } else {
-            eprintln!("stubs.json not found, running stubify...");
-        }
-
-        stubify::run(
-            project_path.to_str().ok_or("Invalid project path")?,
-            stubs_path.to_str().ok_or("Invalid stubs path")?,
-        )?;
-    }
-
-    // Read stubs.json
-    let stubs_content = fs::read_to_string(&stubs_path)?;
-    let stubs: HashMap<String, Stub> = serde_json::from_str(&stubs_content)?;
+/// Entry of `.verilib/overrides.json`
+#[derive(Debug, Deserialize)]
+pub(crate) struct Override {
+    verified: Option<bool>,
+    status: Option<String>,
+    reason: Option<String>,
+}
 
-    // Transform stubs into proofs (only stubs with code-name)
+/// Transform stubs into proofs (only stubs with code-name)
+pub(crate) fn build_proofs(stubs: &HashMap<String, Stub>) -> HashMap<String, Proof> {
     let mut proofs: HashMap<String, Proof> = HashMap::new();
 
     for stub in stubs.values() {
@@ -62,6 +107,13 @@ pub fn run(
         };
 
         let proof_ok = stub.proof_ok.unwrap_or(false);
+        let inconsistent = !parse::inconsistent_proof_macros(
+            stub.spec_ok == Some(true),
+            stub.mathlib_ok == Some(true),
+            proof_ok,
+            stub.proof_mathlib_ok == Some(true),
+        )
+        .is_empty();
 
         proofs.insert(
             code_name.clone(),
@@ -72,23 +124,372 @@ pub fn run(
                 } else {
                     "sorries".to_string()
                 },
+                axioms: None,
+                overridden: false,
+                reason: None,
+                inconsistent,
             },
         );
     }
 
-    // Write output
-    let output_path = Path::new(output);
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() && !parent.exists() {
-            fs::create_dir_all(parent)?;
+    proofs
+}
+
+/// Read `#print axioms` results: a JSON map from declaration name to the
+/// axioms it depends on
+fn load_axioms(path: &Path) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read axioms file {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid axioms file {}: {}", path.display(), e).into())
+}
+
+/// Read a list of verified declarations, one name per line (with or
+/// without the "probe:" prefix)
+fn load_verified_decls(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Failed to read verified declarations {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.strip_prefix("probe:").unwrap_or(line).to_string())
+        .collect())
+}
+
+/// Replace the `\leanok` status of the proofs by an external list of
+/// verified declarations
+///
+/// Proofs whose code-name resolves (exactly or by unambiguous namespace
+/// suffix) to a listed declaration become verified with status "checked";
+/// unlisted proofs claimed by `\leanok` become unverified with status
+/// "claimed-unchecked". Proofs whose code-name is ambiguous keep their
+/// status. Must run on the proofs of `build_proofs`; returns the number of
+/// proofs upgraded and downgraded.
+pub(crate) fn apply_verified_decls(
+    proofs: &mut HashMap<String, Proof>,
+    verified: &[String],
+) -> (usize, usize) {
+    let index = DeclarationIndex::new(verified.iter().map(String::as_str));
+    let (mut upgraded, mut downgraded) = (0, 0);
+    for (code_name, proof) in proofs.iter_mut() {
+        let name = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        match index.resolve(name) {
+            Resolution::Exact(_) | Resolution::Suffix(_) => {
+                if !proof.verified {
+                    upgraded += 1;
+                }
+                proof.verified = true;
+                proof.status = "checked".to_string();
+            }
+            Resolution::Missing if proof.verified => {
+                downgraded += 1;
+                proof.verified = false;
+                proof.status = "claimed-unchecked".to_string();
+            }
+            _ => {}
         }
     }
+    (upgraded, downgraded)
+}
 
-    let json = serde_json::to_string_pretty(&proofs)?;
-    fs::write(output_path, json)?;
+/// Mark the proofs whose declaration depends on axioms outside `allowed`
+///
+/// Declarations are looked up with and without the "probe:" prefix;
+/// proofs missing from `axioms` keep their status. Returns the number of
+/// proofs marked.
+pub(crate) fn apply_axioms(
+    proofs: &mut HashMap<String, Proof>,
+    axioms: &HashMap<String, Vec<String>>,
+    allowed: &HashSet<&str>,
+) -> usize {
+    let mut marked = 0;
+    for (code_name, proof) in proofs.iter_mut() {
+        let name = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        let Some(used) = axioms.get(name).or_else(|| axioms.get(code_name)) else {
+            continue;
+        };
+        let mut offending: Vec<String> = used
+            .iter()
+            .filter(|axiom| !allowed.contains(axiom.as_str()) && *axiom != SORRY_AXIOM)
+            .cloned()
+            .collect();
+        if offending.is_empty() {
+            continue;
+        }
+        offending.sort();
+        offending.dedup();
+        proof.verified = false;
+        proof.status = "nonstandard-axioms".to_string();
+        proof.axioms = Some(offending);
+        marked += 1;
+    }
+    marked
+}
+
+/// Read `.verilib/overrides.json`: a JSON map from code-name or label to
+/// the status to report instead
+fn load_overrides(path: &Path) -> Result<BTreeMap<String, Override>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read overrides file {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid overrides file {}: {}", path.display(), e).into())
+}
+
+/// Replace the computed status of the proofs named in `overrides`
+///
+/// Keys are code-names (with or without the "probe:" prefix) or stub
+/// labels. A missing `status` follows `verified` ("success" or "sorries"),
+/// a missing `verified` follows `status`. Returns the number of proofs
+/// overridden and the keys that name no proof.
+pub(crate) fn apply_overrides(
+    proofs: &mut HashMap<String, Proof>,
+    stubs: &HashMap<String, Stub>,
+    overrides: &BTreeMap<String, Override>,
+) -> (usize, Vec<String>) {
+    let by_label: HashMap<&str, &str> = stubs
+        .values()
+        .filter_map(|stub| Some((stub.label.as_deref()?, stub.code_name.as_deref()?)))
+        .collect();
+
+    let mut applied = 0;
+    let mut unknown = Vec::new();
+    for (key, entry) in overrides {
+        let prefixed = format!("probe:{}", key);
+        let code_name = [key.as_str(), prefixed.as_str()]
+            .into_iter()
+            .chain(by_label.get(key.as_str()).copied())
+            .find(|name| proofs.contains_key(*name))
+            .map(str::to_string);
+        let Some(proof) = code_name.and_then(|name| proofs.get_mut(&name)) else {
+            unknown.push(key.clone());
+            continue;
+        };
+        let verified = entry.verified.unwrap_or_else(|| {
+            entry
+                .status
+                .as_deref()
+                .map_or(proof.verified, |s| s == "success")
+        });
+        proof.status = entry
+            .status
+            .clone()
+            .unwrap_or_else(|| if verified { "success" } else { "sorries" }.to_string());
+        proof.verified = verified;
+        if proof.status != "nonstandard-axioms" {
+            proof.axioms = None;
+        }
+        proof.overridden = true;
+        proof.reason = entry.reason.clone();
+        applied += 1;
+    }
+    (applied, unknown)
+}
+
+/// Mark the proofs whose Lean declaration contains `sorry`
+///
+/// `declarations` maps declaration names to their source text. Code-names
+/// are resolved exactly or by unambiguous namespace suffix, and line comments
+/// are ignored. Returns the number of proofs marked.
+pub(crate) fn apply_sorry_grep(
+    proofs: &mut HashMap<String, Proof>,
+    declarations: &HashMap<String, String>,
+) -> usize {
+    let index = DeclarationIndex::new(declarations.keys().map(String::as_str));
+    let mut marked = 0;
+    for (code_name, proof) in proofs.iter_mut() {
+        let name = code_name.strip_prefix("probe:").unwrap_or(code_name);
+        let (Resolution::Exact(decl) | Resolution::Suffix(decl)) = index.resolve(name) else {
+            continue;
+        };
+        let code = check_uses::LINE_COMMENT_RE.replace_all(&declarations[decl], "");
+        if SORRY_RE.is_match(&code) {
+            proof.verified = false;
+            proof.status = "sorry_in_source".to_string();
+            marked += 1;
+        }
+    }
+    marked
+}
+
+/// Test suites of the JUnit report: one test case per declaration, in the
+/// suite of the first stub (by name) referencing it
+///
+/// Split children have no `stub-path`; they go to the suite of the file their
+/// stub-name starts with.
+fn junit_suites(
+    stubs: &HashMap<String, Stub>,
+    proofs: &HashMap<String, Proof>,
+) -> BTreeMap<String, Vec<TestCase>> {
+    let mut sorted: Vec<(&String, &Stub)> = stubs.iter().collect();
+    sorted.sort_by_key(|(stub_name, _)| *stub_name);
+
+    let paths: HashSet<&str> = stubs
+        .values()
+        .filter_map(|stub| stub.stub_path.as_deref())
+        .collect();
+    let file_of = |stub_name: &str, stub: &Stub| -> String {
+        if let Some(path) = &stub.stub_path {
+            return path.clone();
+        }
+        paths
+            .iter()
+            .filter(|path| {
+                stub_name
+                    .strip_prefix(stubify::src_relative(path))
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|path| path.len())
+            .map(|path| path.to_string())
+            .unwrap_or_default()
+    };
+
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut suites: BTreeMap<String, Vec<TestCase>> = BTreeMap::new();
+    for (stub_name, stub) in sorted {
+        let Some(code_name) = &stub.code_name else {
+            continue;
+        };
+        let Some(proof) = proofs.get(code_name) else {
+            continue;
+        };
+        if !seen.insert(code_name) {
+            continue;
+        }
+        let failure = (!proof.verified).then(|| {
+            let message = match (proof.status.as_str(), &proof.axioms) {
+                ("nonstandard-axioms", Some(axioms)) => {
+                    format!("nonstandard-axioms: {}", axioms.join(", "))
+                }
+                (status, _) => status.to_string(),
+            };
+            (message, format!("Stub {}", stub_name))
+        });
+        suites
+            .entry(file_of(stub_name, stub))
+            .or_default()
+            .push(TestCase {
+                name: code_name
+                    .strip_prefix("probe:")
+                    .unwrap_or(code_name)
+                    .to_string(),
+                failure,
+            });
+    }
+    suites
+}
+
+/// Extract proof verification status
+pub fn run(
+    project_path: &str,
+    output: &str,
+    regenerate_stubs: bool,
+    options: &VerifyOptions,
+) -> Result<(), Box<dyn Error>> {
+    let project_path = Path::new(project_path);
+    let stubs_path = stubify::stubs_input(project_path);
+
+    // Generate stubs.json if it is missing or out of date
+    stubify::ensure_stubs(project_path, &stubs_path, regenerate_stubs)?;
+
+    // Read stubs.json
+    let stubs_content = read_text(&stubs_path)?;
+    let stubs: HashMap<String, Stub> = parse_map(&stubs_content)?;
+
+    let mut proofs = build_proofs(&stubs);
+    let inconsistent = proofs.values().filter(|proof| proof.inconsistent).count();
+    if inconsistent > 0 {
+        eprintln!(
+            "{} proofs have a status macro their statement lacks (marked inconsistent)",
+            inconsistent
+        );
+    }
+
+    if let Some(verified_decls) = &options.verified_decls {
+        let verified = load_verified_decls(Path::new(verified_decls))?;
+        let (upgraded, downgraded) = apply_verified_decls(&mut proofs, &verified);
+        eprintln!(
+            "{} proofs upgraded and {} downgraded by the verified declarations",
+            upgraded, downgraded
+        );
+    }
+
+    if let Some(axioms) = &options.axioms {
+        let axioms = load_axioms(Path::new(axioms))?;
+        let allowed: HashSet<&str> = STANDARD_AXIOMS
+            .iter()
+            .copied()
+            .chain(options.allow_axioms.iter().map(String::as_str))
+            .collect();
+        let marked = apply_axioms(&mut proofs, &axioms, &allowed);
+        eprintln!("{} proofs depend on nonstandard axioms", marked);
+    }
+
+    if let Some(lean_src_root) = &options.lean_sorry_grep {
+        let lean_src_roots = lake::resolve_lean_roots(project_path, lean_src_root.as_deref());
+        let lean_files = lean::read_lean_sources(&lean_src_roots)?;
+        let marked = apply_sorry_grep(&mut proofs, &check_uses::declaration_texts(&lean_files));
+        eprintln!("{} proofs contain sorry in the Lean sources", marked);
+    }
+
+    let overrides_path = project_path.join(".verilib").join("overrides.json");
+    if overrides_path.exists() {
+        let overrides = load_overrides(&overrides_path)?;
+        let (applied, unknown) = apply_overrides(&mut proofs, &stubs, &overrides);
+        let location = messages::Location::file(".verilib/overrides.json");
+        for key in unknown {
+            messages::warning(
+                Some(&location),
+                &format!("override '{}' matches no code-name or label", key),
+            );
+        }
+        eprintln!("{} proofs overridden", applied);
+    }
+
+    // Write output
+    write_map(&proofs, Path::new(output), options.format)?;
+    write_meta(&(), Path::new(output))?;
 
     eprintln!("Wrote {} proofs to {}", proofs.len(), output);
 
+    if let Some(junit_output) = &options.junit {
+        let xml = junit::junit_xml("probe-blueprint verify", &junit_suites(&stubs, &proofs));
+        write_text(Path::new(junit_output), &xml)?;
+        eprintln!("Wrote JUnit report to {}", junit_output);
+    }
+
+    if let Some(lean_src_root) = &options.check_uses {
+        let stubs: HashMap<String, check_uses::Stub> = parse_map(&stubs_content)?;
+        let lean_src_roots = lake::resolve_lean_roots(project_path, lean_src_root.as_deref());
+        let lean_files = lean::read_lean_sources(&lean_src_roots)?;
+        let mismatches =
+            check_uses::check_uses(&stubs, &check_uses::declaration_texts(&lean_files));
+        print!("{}", check_uses::format_report(&mismatches));
+    }
+
+    if let Some(mathlib_index) = &options.mathlib_index {
+        let stubs: BTreeMap<String, mathlib_check::Stub> = parse_map(&stubs_content)?;
+        let index = mathlib_check::load_index(Path::new(mathlib_index))?;
+        let report = mathlib_check::check_mathlib(&stubs, &index);
+        print!("{}", mathlib_check::format_report(&report, index.len()));
+    }
+
+    if let Some(log) = &options.diagnostics {
+        let stubs: BTreeMap<String, diagnostics::Stub> = parse_map(&stubs_content)?;
+        let lean_src_roots = lake::lean_source_roots(project_path);
+        let modules: Vec<lean::LeanModule> = lean::scan_lean_modules(&lean_src_roots)?
+            .into_values()
+            .collect();
+        let log = diagnostics::load_diagnostics(Path::new(log))?;
+        let report = diagnostics::attribute_diagnostics(&stubs, &modules, &log);
+        print!("{}", diagnostics::format_report(&report));
+    }
+
     Ok(())
 }
 
@@ -101,6 +502,10 @@ mod tests {
         let proof = Proof {
             verified: true,
             status: "success".to_string(),
+            axioms: None,
+            overridden: false,
+            reason: None,
+            inconsistent: false,
         };
 
         let json = serde_json::to_string(&proof).unwrap();
@@ -112,12 +517,247 @@ mod tests {
         let proof = Proof {
             verified: false,
             status: "sorries".to_string(),
+            axioms: None,
+            overridden: false,
+            reason: None,
+            inconsistent: false,
         };
 
         let json = serde_json::to_string(&proof).unwrap();
         assert_eq!(json, r#"{"verified":false,"status":"sorries"}"#);
     }
 
+    #[test]
+    fn test_apply_axioms() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/std": {"code-name": "probe:Foo.std", "spec-ok": true, "proof-ok": true},
+                "a.tex/ext": {"code-name": "probe:Foo.ext", "spec-ok": true, "proof-ok": true},
+                "a.tex/sorry": {"code-name": "probe:Foo.sorry", "proof-ok": false},
+                "a.tex/unknown": {"code-name": "probe:Foo.unknown", "spec-ok": true, "proof-ok": true}
+            }"#,
+        )
+        .unwrap();
+        let axioms: HashMap<String, Vec<String>> = serde_json::from_str(
+            r#"{
+                "Foo.std": ["propext", "Classical.choice", "Quot.sound"],
+                "Foo.ext": ["propext", "Foo.magic", "funext_ax", "Foo.magic"],
+                "Foo.sorry": ["propext", "sorryAx"]
+            }"#,
+        )
+        .unwrap();
+        let mut proofs = build_proofs(&stubs);
+        let allowed: HashSet<&str> = STANDARD_AXIOMS
+            .iter()
+            .copied()
+            .chain(["funext_ax"])
+            .collect();
+
+        assert_eq!(apply_axioms(&mut proofs, &axioms, &allowed), 1);
+        assert_eq!(proofs["probe:Foo.std"].status, "success");
+        assert_eq!(proofs["probe:Foo.sorry"].status, "sorries");
+        assert_eq!(proofs["probe:Foo.unknown"].status, "success");
+        assert_eq!(
+            serde_json::to_string(&proofs["probe:Foo.ext"]).unwrap(),
+            r#"{"verified":false,"status":"nonstandard-axioms","axioms":["Foo.magic"]}"#
+        );
+    }
+
+    #[test]
+    fn test_apply_verified_decls() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/listed": {"code-name": "probe:Foo.listed", "proof-ok": true},
+                "a.tex/upgraded": {"code-name": "probe:upgraded"},
+                "a.tex/claimed": {"code-name": "probe:Foo.claimed", "proof-ok": true},
+                "a.tex/open": {"code-name": "probe:Foo.open"},
+                "a.tex/ambiguous": {"code-name": "probe:twice", "proof-ok": true}
+            }"#,
+        )
+        .unwrap();
+        let verified: Vec<String> = ["Foo.listed", "Bar.upgraded", "A.twice", "B.twice"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut proofs = build_proofs(&stubs);
+
+        assert_eq!(apply_verified_decls(&mut proofs, &verified), (1, 1));
+        let status = |code_name: &str| {
+            let proof = &proofs[code_name];
+            (proof.verified, proof.status.as_str())
+        };
+        assert_eq!(status("probe:Foo.listed"), (true, "checked"));
+        assert_eq!(status("probe:upgraded"), (true, "checked"));
+        assert_eq!(status("probe:Foo.claimed"), (false, "claimed-unchecked"));
+        assert_eq!(status("probe:Foo.open"), (false, "sorries"));
+        assert_eq!(status("probe:twice"), (true, "success"));
+    }
+
+    #[test]
+    fn test_load_verified_decls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("verified.txt");
+        fs::write(&path, "Foo.bar\n\n  probe:Foo.baz \r\n").unwrap();
+
+        assert_eq!(load_verified_decls(&path).unwrap(), ["Foo.bar", "Foo.baz"]);
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/done": {"label": "done", "code-name": "probe:Foo.done", "spec-ok": true, "proof-ok": true},
+                "a.tex/todo": {"label": "todo", "code-name": "probe:Foo.todo", "proof-ok": false},
+                "a.tex/kept": {"label": "kept", "code-name": "probe:Foo.kept", "spec-ok": true, "proof-ok": true}
+            }"#,
+        )
+        .unwrap();
+        let overrides: BTreeMap<String, Override> = serde_json::from_str(
+            r#"{
+                "Foo.done": {"verified": false, "reason": "proof relies on an unchecked lemma"},
+                "todo": {"status": "success", "reason": "checked by hand"},
+                "Foo.gone": {"verified": true}
+            }"#,
+        )
+        .unwrap();
+        let mut proofs = build_proofs(&stubs);
+
+        let (applied, unknown) = apply_overrides(&mut proofs, &stubs, &overrides);
+
+        assert_eq!(applied, 2);
+        assert_eq!(unknown, vec!["Foo.gone".to_string()]);
+        assert_eq!(
+            serde_json::to_string(&proofs["probe:Foo.done"]).unwrap(),
+            r#"{"verified":false,"status":"sorries","overridden":true,"reason":"proof relies on an unchecked lemma"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&proofs["probe:Foo.todo"]).unwrap(),
+            r#"{"verified":true,"status":"success","overridden":true,"reason":"checked by hand"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&proofs["probe:Foo.kept"]).unwrap(),
+            r#"{"verified":true,"status":"success"}"#
+        );
+    }
+
+    #[test]
+    fn test_junit_suites() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/done": {"stub-path": "a.tex", "code-name": "probe:Foo.done", "proof-ok": true},
+                "a.tex/todo": {"stub-path": "a.tex", "code-name": "probe:Foo.todo"},
+                "b.tex/again": {"stub-path": "b.tex", "code-name": "probe:Foo.done", "proof-ok": true},
+                "b.tex/def": {"stub-path": "b.tex"},
+                "b.tex/thm_1": {"code-name": "probe:Foo.split", "proof-ok": true}
+            }"#,
+        )
+        .unwrap();
+        let proofs = build_proofs(&stubs);
+
+        let suites = junit_suites(&stubs, &proofs);
+
+        // Each declaration once, in the suite of its first stub
+        assert_eq!(suites.len(), 2);
+        assert_eq!(
+            suites["b.tex"],
+            vec![TestCase {
+                name: "Foo.split".to_string(),
+                failure: None,
+            }]
+        );
+        assert_eq!(
+            suites["a.tex"],
+            vec![
+                TestCase {
+                    name: "Foo.done".to_string(),
+                    failure: None,
+                },
+                TestCase {
+                    name: "Foo.todo".to_string(),
+                    failure: Some(("sorries".to_string(), "Stub a.tex/todo".to_string())),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_sorry_grep() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/done": {"code-name": "probe:Foo.done", "spec-ok": true, "proof-ok": true},
+                "a.tex/hole": {"code-name": "probe:hole", "spec-ok": true, "proof-ok": true},
+                "a.tex/note": {"code-name": "probe:Foo.note", "spec-ok": true, "proof-ok": true},
+                "a.tex/pending": {"code-name": "probe:Foo.pending", "proof-ok": false},
+                "a.tex/gone": {"code-name": "probe:Foo.gone", "spec-ok": true, "proof-ok": true}
+            }"#,
+        )
+        .unwrap();
+        let lean_files = vec![(
+            "Foo.lean".to_string(),
+            "namespace Foo
+
+theorem done : True := trivial
+
+theorem hole : True := by
+  sorry
+
+/-- Proved without sorry. -/
+theorem note : True := by
+  trivial -- no sorry here
+
+theorem sorry_free : True := trivial
+
+theorem pending : True := sorry
+
+end Foo
+"
+            .to_string(),
+        )];
+        let mut proofs = build_proofs(&stubs);
+
+        let marked = apply_sorry_grep(&mut proofs, &check_uses::declaration_texts(&lean_files));
+
+        assert_eq!(marked, 2);
+        assert_eq!(
+            serde_json::to_string(&proofs["probe:hole"]).unwrap(),
+            r#"{"verified":false,"status":"sorry_in_source"}"#
+        );
+        assert_eq!(proofs["probe:Foo.pending"].status, "sorry_in_source");
+        assert_eq!(proofs["probe:Foo.done"].status, "success");
+        assert_eq!(proofs["probe:Foo.note"].status, "success");
+        assert_eq!(proofs["probe:Foo.gone"].status, "success");
+    }
+
+    #[test]
+    fn test_inconsistent_proofs() {
+        let stubs: HashMap<String, Stub> = serde_json::from_str(
+            r#"{
+                "a.tex/ok": {"code-name": "probe:ok", "spec-ok": true, "proof-ok": true},
+                "a.tex/unstated": {"code-name": "probe:unstated", "spec-ok": false, "proof-ok": true},
+                "a.tex/mathlib": {"code-name": "probe:mathlib", "mathlib-ok": true, "proof-ok": true},
+                "a.tex/proof_mathlib": {
+                    "code-name": "probe:proof_mathlib", "spec-ok": true, "proof-mathlib-ok": true
+                },
+                "a.tex/open": {"code-name": "probe:open", "spec-ok": false}
+            }"#,
+        )
+        .unwrap();
+
+        let proofs = build_proofs(&stubs);
+
+        let mut inconsistent: Vec<&str> = proofs
+            .iter()
+            .filter(|(_, proof)| proof.inconsistent)
+            .map(|(code_name, _)| code_name.as_str())
+            .collect();
+        inconsistent.sort();
+        assert_eq!(inconsistent, vec!["probe:proof_mathlib", "probe:unstated"]);
+        assert_eq!(
+            serde_json::to_value(&proofs["probe:unstated"]).unwrap(),
+            serde_json::json!({"verified": true, "status": "success", "inconsistent": true})
+        );
+    }
+
     #[test]
     fn test_stub_deserialization_proof_ok() {
         let json = r#"{
@@ -0,0 +1,151 @@
+//! Structured errors for conditions callers may want to tell apart
+//!
+//! Functions keep returning `Box<dyn Error>`; these variants are boxed into it
+//! and recovered with `downcast_ref` where the distinction matters (e.g. for
+//! `--emit-machine-readable-errors`).
+
+use crate::messages::Location;
+use crate::parse::LineRange;
+use serde_json::{json, Value};
+use std::error::Error;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProbeError {
+    #[error("blueprint/src directory not found at {path}")]
+    MissingBlueprintSrc { path: String },
+
+    #[error("Duplicate label found: {label}")]
+    DuplicateLabel { label: String },
+
+    #[error("Unknown label '{label}' in {field} of stub '{stub}' at {path}:{line}")]
+    UnknownLabel {
+        label: String,
+        field: &'static str,
+        stub: String,
+        /// File of the `\uses` entry, relative to `blueprint/src`, and its line
+        path: String,
+        line: usize,
+    },
+
+    #[error("Label '{label}' is proved by two standalone proofs, at {first} and {second}")]
+    DuplicateProof {
+        label: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl ProbeError {
+    /// Variant name, used as `error_type` in machine-readable output
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            ProbeError::MissingBlueprintSrc { .. } => "MissingBlueprintSrc",
+            ProbeError::DuplicateLabel { .. } => "DuplicateLabel",
+            ProbeError::UnknownLabel { .. } => "UnknownLabel",
+            ProbeError::DuplicateProof { .. } => "DuplicateProof",
+        }
+    }
+
+    /// Variant fields as a JSON object
+    pub fn context(&self) -> Value {
+        match self {
+            ProbeError::MissingBlueprintSrc { path } => json!({ "path": path }),
+            ProbeError::DuplicateLabel { label } => json!({ "label": label }),
+            ProbeError::UnknownLabel {
+                label,
+                field,
+                stub,
+                path,
+                line,
+            } => {
+                json!({ "label": label, "field": field, "stub": stub, "path": path, "line": line })
+            }
+            ProbeError::DuplicateProof {
+                label,
+                first,
+                second,
+            } => json!({ "label": label, "first": first, "second": second }),
+        }
+    }
+
+    /// Source location the error points at, if any
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            ProbeError::UnknownLabel { path, line, .. } => Some(Location::blueprint(
+                path,
+                Some(&LineRange {
+                    lines_start: *line,
+                    lines_end: *line,
+                }),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Render any error as `{"error_type", "message", "context"}`
+///
+/// `ProbeError`s report their variant name and fields; I/O and JSON errors
+/// are reported as `Io` and `Json`, everything else as `Other`.
+pub fn to_machine_readable(err: &(dyn Error + 'static)) -> Value {
+    let (error_type, context) = if let Some(probe_err) = err.downcast_ref::<ProbeError>() {
+        (probe_err.error_type(), probe_err.context())
+    } else if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+        ("Io", json!({ "kind": io_err.kind().to_string() }))
+    } else if let Some(json_err) = err.downcast_ref::<serde_json::Error>() {
+        if let Some(kind) = json_err.io_error_kind() {
+            // I/O failure while reading or writing JSON
+            ("Io", json!({ "kind": kind.to_string() }))
+        } else {
+            (
+                "Json",
+                json!({ "line": json_err.line(), "column": json_err.column() }),
+            )
+        }
+    } else {
+        ("Other", json!({}))
+    };
+
+    json!({
+        "error_type": error_type,
+        "message": err.to_string(),
+        "context": context,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_error_machine_readable() {
+        let err: Box<dyn Error> = ProbeError::UnknownLabel {
+            label: "missing".to_string(),
+            field: "spec-dependencies",
+            stub: "a.tex/thm1".to_string(),
+            path: "a.tex".to_string(),
+            line: 3,
+        }
+        .into();
+
+        let value = to_machine_readable(err.as_ref());
+        assert_eq!(value["error_type"], "UnknownLabel");
+        assert_eq!(
+            value["message"],
+            "Unknown label 'missing' in spec-dependencies of stub 'a.tex/thm1' at a.tex:3"
+        );
+        assert_eq!(value["context"]["stub"], "a.tex/thm1");
+        assert_eq!(value["context"]["line"], 3);
+    }
+
+    #[test]
+    fn test_other_error_machine_readable() {
+        let err: Box<dyn Error> = "Invalid project path".into();
+
+        let value = to_machine_readable(err.as_ref());
+        assert_eq!(value["error_type"], "Other");
+        assert_eq!(value["message"], "Invalid project path");
+        assert_eq!(value["context"], json!({}));
+    }
+}
@@ -1,3 +1,14 @@
 //! probe-blueprint: Generate call graph atoms and analyze Blueprint verification results for Lean 4
 
+#[cfg(feature = "cli")]
 pub mod commands;
+pub mod error;
+pub mod messages;
+pub mod parse;
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+mod tokenize;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,6 +1,13 @@
 use clap::{Parser, Subcommand};
-
-mod commands;
+use probe_blueprint::commands::diff_graph::DiffFormat;
+use probe_blueprint::commands::init::GroupBy;
+use probe_blueprint::commands::output::{
+    self, JsonStyle, MetaSettings, OutputEncoding, OutputFormat,
+};
+use probe_blueprint::commands::status_page::StatusFormat;
+use probe_blueprint::messages::{self, MessageFormat};
+use probe_blueprint::{commands, error};
+use std::error::Error;
 
 #[derive(Parser)]
 #[command(name = "probe-blueprint")]
@@ -11,6 +18,46 @@ mod commands;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Print errors to stderr as JSON objects with error_type, message and context
+    #[arg(long, global = true)]
+    emit_machine_readable_errors: bool,
+
+    /// Print warnings and errors as plain lines or as GitHub Actions workflow
+    /// commands [default: github when GITHUB_ACTIONS is set, else human]
+    #[arg(long, global = true, value_enum)]
+    message_format: Option<MessageFormat>,
+
+    /// Also print notices, e.g. about environments left out by a skip marker
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Write JSON outputs without any whitespace
+    #[arg(long, global = true, conflicts_with = "indent")]
+    compact: bool,
+
+    /// Indent JSON outputs by N spaces per level [default: 2]
+    #[arg(long, global = true, value_name = "N")]
+    indent: Option<usize>,
+
+    /// Write generation metadata (tool version, time, git HEAD, options hash)
+    /// next to each output as <name>.meta.json
+    #[arg(long, global = true, overrides_with = "no_meta")]
+    meta: bool,
+
+    /// Do not write generation metadata (the default)
+    #[arg(long, global = true, overrides_with = "meta")]
+    no_meta: bool,
+
+    /// Leave the generation time out of the metadata, so reruns give identical files
+    #[arg(long, global = true)]
+    reproducible: bool,
+    /// Flush every output to disk before it replaces the previous file
+    #[arg(long, global = true)]
+    fsync: bool,
+    /// Text encoding of the JSON and NDJSON outputs
+    #[arg(long, global = true, value_enum, default_value_t = OutputEncoding::Utf8)]
+    output_encoding: OutputEncoding,
 }
 
 #[derive(Subcommand)]
@@ -20,9 +67,126 @@ enum Commands {
         /// Path to the project root (must contain blueprint/src)
         project_path: String,
 
-        /// Output file path
+        /// Output file path; {project}, {command} and {date} are expanded
         #[arg(short, long, default_value = ".verilib/stubs.json")]
         output: String,
+
+        /// Print a timing breakdown per processing stage to stderr
+        #[arg(long)]
+        profile: bool,
+
+        /// Print every environment match, proof association, macro extraction and label
+        /// resolution to stderr
+        #[arg(long)]
+        trace_parse: bool,
+
+        /// Number of threads used to parse files (default: one per CPU)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// JSON file mapping labels to Lean declaration names, applied as code-names
+        #[arg(long, value_name = "JSON")]
+        lean_names_file: Option<String>,
+
+        /// Keep \lean{...} names from the source over --lean-names-file
+        #[arg(long, requires = "lean_names_file")]
+        prefer_source: bool,
+
+        /// Locate each code-name in the Lean sources (adds lean-path, lean-lines and lean-doc);
+        /// without SRC_DIR, the source roots are read from the Lake configuration
+        #[arg(long, value_name = "SRC_DIR")]
+        locate_lean: Option<Option<String>>,
+
+        /// Also write a JSON map from every label (including aliases) to its stub-name
+        #[arg(long, value_name = "OUTPUT")]
+        emit_all_labels_map: Option<String>,
+
+        /// Read blueprint annotations (e.g. `-- blueprint: thm:main`) from the Lean sources;
+        /// without SRC_DIR, the source roots are read from the Lake configuration
+        #[arg(long, value_name = "SRC_DIR")]
+        lean_annotations: Option<Option<String>>,
+
+        /// Regex matching an annotation marker; its first group holds the labels
+        #[arg(long, value_name = "REGEX", requires = "lean_annotations")]
+        lean_annotation_marker: Option<String>,
+
+        /// Record the number LaTeX gives each environment (e.g. 3.2) as theorem-number
+        #[arg(long)]
+        with_numbering: bool,
+
+        /// Rewrite code-names that --locate-lean resolves by suffix to their fully qualified form
+        #[arg(long, requires = "locate_lean")]
+        qualify_names: bool,
+
+        /// Also write a BibTeX file with one entry per stub, keyed by label
+        #[arg(long, value_name = "OUTPUT")]
+        emit_bibtex: Option<String>,
+
+        /// Also write a Markdown review checklist per stub, grouped by section
+        #[arg(long, value_name = "OUTPUT")]
+        emit_review_report: Option<String>,
+
+        /// Output layout: one JSON object, or one line per stub
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// Rename environment types in the output only (e.g. dfn:definition,lem:lemma)
+        #[arg(
+            long,
+            value_name = "FROM:TO,...",
+            value_delimiter = ',',
+            value_parser = commands::stubify::parse_env_type_mapping
+        )]
+        env_type_map: Vec<(String, String)>,
+
+        /// Warn about .tex files with more than N environments, which may need splitting
+        #[arg(long, value_name = "N")]
+        warn_if_file_has_more_than: Option<usize>,
+
+        /// Warn instead of failing when two standalone proofs \proves the same label
+        #[arg(long)]
+        warn_duplicate_proofs: bool,
+
+        /// Fail instead of warning when a proof is \leanok or \mathlibok but its statement is not
+        #[arg(long)]
+        strict: bool,
+
+        /// Take several standalone proofs \proves-ing one label as parts of a single proof
+        #[arg(long)]
+        proof_parts: bool,
+
+        /// Environment types that may come between a statement and its proof (e.g. remark)
+        #[arg(long, value_name = "ENV,...", value_delimiter = ',')]
+        proof_after_envs: Vec<String>,
+
+        /// Bytes of whitespace allowed between a statement and its proof (comments do not count) [default: 4096]
+        #[arg(long, value_name = "BYTES")]
+        proof_lookahead: Option<usize>,
+
+        /// Record the line of each \leanok, \lean, \mathlibok, \notready and \uses as macro-positions
+        #[arg(long)]
+        macro_positions: bool,
+
+        /// Ignore \uses after a heading instead of adding it to every statement under the heading
+        #[arg(long)]
+        no_section_uses: bool,
+
+        /// Macro that leaves its environment out of the stubs [default: blueprintskip]
+        #[arg(long, value_name = "NAME")]
+        skip_marker: Option<String>,
+
+        /// Directory the stub-paths are relative to
+        #[arg(long, value_enum, default_value_t = commands::stubify::PathBase::Src)]
+        path_base: commands::stubify::PathBase,
+
+        /// Reparse only this file (relative to blueprint/src, repeatable) and patch its
+        /// stubs into the existing output
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["emit_all_labels_map", "emit_bibtex", "emit_review_report", "warn_if_file_has_more_than"]
+        )]
+        only: Vec<String>,
     },
 
     /// Generate call graph atoms with line numbers
@@ -30,13 +194,39 @@ enum Commands {
         /// Path to the project root (must contain blueprint/src)
         project_path: String,
 
-        /// Output file path
+        /// Output file path; {project}, {command} and {date} are expanded
         #[arg(short, long, default_value = ".verilib/atoms.json")]
         output: String,
 
-        /// Regenerate stubs.json even if it exists
+        /// Regenerate stubs.json even if it is up to date
         #[arg(long)]
         regenerate_stubs: bool,
+
+        /// Also write atoms with only spec-dependency edges to this file
+        #[arg(long, value_name = "OUTPUT")]
+        emit_spec_graph: Option<String>,
+
+        /// Also write atoms with only proof-dependency edges to this file
+        #[arg(long, value_name = "OUTPUT")]
+        emit_proof_graph: Option<String>,
+
+        /// Add spec-dep-depth and proof-dep-depth, the longest dependency path from a root
+        /// in the spec-only and proof-only graphs
+        #[arg(long)]
+        compute_depth: bool,
+
+        /// Write one atoms file per blueprint file, plus boundary.json with the
+        /// cross-file dependencies, to this directory instead of the output file
+        #[arg(long, value_name = "DIR")]
+        split_by_file: Option<String>,
+
+        /// With --split-by-file, also write the combined atoms to the output file
+        #[arg(long, requires = "split_by_file")]
+        also_full: bool,
+
+        /// Output layout: one JSON object, or one line per atom
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
     },
 
     /// Extract function specifications
@@ -44,17 +234,25 @@ enum Commands {
         /// Path to the project root (must contain blueprint/src)
         project_path: String,
 
-        /// Output file path
+        /// Output file path; {project}, {command} and {date} are expanded
         #[arg(short, long, default_value = ".verilib/specs.json")]
         output: String,
 
-        /// Regenerate stubs.json even if it exists
+        /// Regenerate stubs.json even if it is up to date
         #[arg(long)]
         regenerate_stubs: bool,
 
         /// Enrich results with atoms.json (reserved for future use)
         #[arg(short = 'a', long = "with-atoms")]
         with_atoms: Option<Option<String>>,
+
+        /// Fail if any spec's quality score (0 to 1) is below this
+        #[arg(long, value_name = "SCORE", value_parser = commands::specify::parse_min_score)]
+        min_score: Option<f64>,
+
+        /// Output layout: one JSON object, or one line per spec
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
     },
 
     /// Extract proof verification status
@@ -62,49 +260,550 @@ enum Commands {
         /// Path to the project root (must contain blueprint/src)
         project_path: String,
 
-        /// Output file path
+        /// Output file path; {project}, {command} and {date} are expanded
         #[arg(short, long, default_value = ".verilib/proofs.json")]
         output: String,
 
-        /// Regenerate stubs.json even if it exists
+        /// Regenerate stubs.json even if it is up to date
         #[arg(long)]
         regenerate_stubs: bool,
 
         /// Enrich results with atoms.json (reserved for future use)
         #[arg(short = 'a', long = "with-atoms")]
         with_atoms: Option<Option<String>>,
+
+        /// Heuristically cross-check \uses against identifiers in the Lean sources;
+        /// without SRC_DIR, the source roots are read from the Lake configuration
+        #[arg(long, value_name = "SRC_DIR")]
+        check_uses: Option<Option<String>>,
+
+        /// Check \mathlibok names against a Mathlib declaration index (a file with one
+        /// name per line or a JSON list, or a Mathlib source directory)
+        #[arg(long, value_name = "FILE")]
+        mathlib_index: Option<String>,
+
+        /// Mark proofs depending on nonstandard axioms, from a JSON map of
+        /// declaration name to its `#print axioms` list
+        #[arg(long, value_name = "FILE")]
+        axioms: Option<String>,
+
+        /// Additional axiom allowed with --axioms (repeatable; propext,
+        /// Classical.choice and Quot.sound are always allowed)
+        #[arg(long = "allow-axiom", value_name = "NAME", requires = "axioms")]
+        allow_axioms: Vec<String>,
+
+        /// Attribute the errors and warnings of a `lean --json` log to the stubs
+        /// whose declarations contain them
+        #[arg(long, value_name = "FILE")]
+        diagnostics: Option<String>,
+
+        /// Mark proofs whose Lean declaration contains `sorry` as unverified;
+        /// without SRC_DIR, the source roots are read from the Lake configuration
+        #[arg(long, value_name = "SRC_DIR")]
+        lean_sorry_grep: Option<Option<String>>,
+
+        /// Newline-separated list of fully checked declarations (e.g. from lean4checker):
+        /// listed ones become "checked", unlisted \leanok proofs "claimed-unchecked"
+        #[arg(long, value_name = "FILE")]
+        verified_decls: Option<String>,
+
+        /// Output layout: one JSON object, or one line per proof
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// Also write a JUnit XML report, one test case per declaration and one
+        /// suite per blueprint file
+        #[arg(long, value_name = "OUTPUT")]
+        junit: Option<String>,
+    },
+
+    /// Generate Lean 4 imports for all declarations referenced by the blueprint
+    DotLean {
+        /// Path to the project root (must contain blueprint/src)
+        project_path: String,
+
+        /// Root directory of the Lean sources to search for declarations
+        /// (default: the source roots from the Lake configuration)
+        lean_src_root: Option<String>,
+
+        /// Output file path (.lean is appended if missing); {project}, {command} and {date}
+        /// are expanded
+        #[arg(short, long, default_value = ".verilib/Blueprint.lean")]
+        output: String,
+    },
+
+    /// List formalized statements whose proofs are not yet formalized, in dependency order
+    EmitProofObligations {
+        /// Path to the project root (must contain blueprint/src)
+        project_path: String,
+
+        /// Output file path; {project}, {command} and {date} are expanded
+        #[arg(short, long, default_value = ".verilib/obligations.txt")]
+        output: String,
+
+        /// Regenerate stubs.json even if it is up to date
+        #[arg(long)]
+        regenerate_stubs: bool,
+    },
+
+    /// Generate skeleton Lean files for blueprint statements without a Lean declaration
+    Scaffold {
+        /// Path to the project root (must contain blueprint/src)
+        project_path: String,
+
+        /// Directory to write the Lean files to; {project}, {command} and {date} are expanded
+        #[arg(short, long, default_value = "Scaffold")]
+        output_dir: String,
+
+        /// Lean source root to look for existing declarations in
+        /// (default: the source roots from the Lake configuration)
+        #[arg(long, value_name = "SRC_DIR")]
+        lean_src_root: Option<String>,
+
+        /// Regenerate stubs.json even if it is up to date
+        #[arg(long)]
+        regenerate_stubs: bool,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Generate a starter blueprint from the declarations of existing Lean code
+    Init {
+        /// Path to the project root (blueprint/src is created inside)
+        #[arg(default_value = ".")]
+        project_path: String,
+
+        /// Lean source root to generate the blueprint from
+        #[arg(long, value_name = "SRC_DIR")]
+        from_lean: String,
+
+        /// One chapter per Lean file or per namespace
+        #[arg(long, value_enum, default_value_t = GroupBy::File)]
+        group_by: GroupBy,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Export the blueprint database to another format
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+
+    /// Generate a LeanBlueprint-compatible status JSON for the dependency graph
+    StatusPage {
+        /// Path to the project root (must contain blueprint/src)
+        project_path: String,
+
+        /// Output file path; {project}, {command} and {date} are expanded
+        #[arg(short, long, default_value = ".verilib/decls.json")]
+        output: String,
+
+        /// Regenerate stubs.json even if it is up to date
+        #[arg(long)]
+        regenerate_stubs: bool,
+
+        /// Output layout: status nodes, or LeanBlueprint's graph nodes and edges
+        #[arg(long, value_enum, default_value_t = StatusFormat::Status)]
+        format: StatusFormat,
+    },
+
+    /// Render how the dependency graph changed between two stubs.json files as DOT
+    DiffGraph {
+        /// stubs.json of the earlier version
+        before: String,
+
+        /// stubs.json of the later version
+        after: String,
+
+        /// File to write; {project} (from --project-path), {command} and {date} are expanded
+        #[arg(short, long, default_value = "graph-diff.dot")]
+        output: String,
+
+        /// Output layout: a DOT graph, or a Markdown changelog
+        #[arg(long, value_enum, default_value_t = DiffFormat::Dot)]
+        format: DiffFormat,
+
+        /// Project whose .verilib/config.json gives the GitHub URL for the
+        /// discussion links of the Markdown changelog
+        #[arg(long, default_value = ".")]
+        project_path: String,
+    },
+
+    /// Generate a synthetic blueprint project for benchmarks and stress tests
+    #[command(hide = true)]
+    GenFixture {
+        /// Directory to write the project to (blueprint/src is created inside)
+        output_dir: String,
+
+        /// Number of .tex files
+        #[arg(long, default_value_t = 10)]
+        files: usize,
+
+        /// Number of environments per file
+        #[arg(long, default_value_t = 100)]
+        envs: usize,
+
+        /// Fraction of environments with a \label
+        #[arg(long, default_value_t = 1.0)]
+        label_ratio: f64,
+
+        /// Fraction of environments declaring their dependencies with \uses
+        #[arg(long, default_value_t = 1.0)]
+        uses_ratio: f64,
+
+        /// Fraction of environments with a \lean code-name
+        #[arg(long, default_value_t = 0.8)]
+        lean_ratio: f64,
+
+        /// Fraction of environments marked \leanok
+        #[arg(long, default_value_t = 0.5)]
+        leanok_ratio: f64,
+
+        /// Fraction of environments followed by a proof
+        #[arg(long, default_value_t = 0.7)]
+        proof_ratio: f64,
+
+        /// Dependency structure: chain, tree or dag
+        #[arg(long, default_value = "dag")]
+        structure: commands::gen_fixture::Structure,
+
+        /// Edge probability for the dag structure
+        #[arg(long, default_value_t = 0.05)]
+        density: f64,
+
+        /// Random seed
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+
+    /// Time stubify on a synthetic blueprint
+    #[command(hide = true)]
+    Benchmark {
+        /// Number of .tex files
+        #[arg(long, default_value_t = 10)]
+        size: usize,
+
+        /// Number of theorems per file
+        #[arg(long, default_value_t = 100)]
+        env_count: usize,
+
+        /// Average number of \uses dependencies per theorem
+        #[arg(long, default_value_t = 3)]
+        dep_count: usize,
+
+        /// Number of timed stubify runs
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// Random seed of the generated blueprint
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Output file for the parameters and results as JSON; {project} (the current
+        /// directory), {command} and {date} are expanded
+        #[arg(short, long, default_value = "benchmark.json")]
+        output: String,
     },
 }
 
+#[derive(Subcommand)]
+enum ExportFormat {
+    /// Write stubs, labels, dependencies and proofs to an SQLite database
+    Sqlite {
+        /// Path to the project root (must contain blueprint/src)
+        project_path: String,
+
+        /// Database file to write (existing tables are replaced)
+        path: String,
+    },
+}
+
+/// Expand the placeholders of the `--output` of `command`
+fn expand(output: &str, project_path: &str, command: &str) -> Result<String, Box<dyn Error>> {
+    output::expand_output_template(output, project_path, command)
+}
+
 fn main() {
     let cli = Cli::parse();
+    messages::set_verbose(cli.verbose);
+    messages::set_message_format(cli.message_format.unwrap_or_else(|| {
+        if std::env::var_os("GITHUB_ACTIONS").is_some_and(|value| !value.is_empty()) {
+            MessageFormat::Github
+        } else {
+            MessageFormat::Human
+        }
+    }));
+    output::set_json_style(match (cli.compact, cli.indent) {
+        (true, _) => JsonStyle::Compact,
+        (false, Some(width)) => JsonStyle::Indent(width),
+        (false, None) => JsonStyle::default(),
+    });
+    output::set_fsync(cli.fsync);
+    output::set_output_encoding(cli.output_encoding);
+    output::set_meta_settings(cli.meta.then(|| MetaSettings {
+        reproducible: cli.reproducible,
+        options_hash: output::options_hash(std::env::args_os().skip(1)),
+    }));
 
     let result = match cli.command {
         Commands::Stubify {
             project_path,
             output,
-        } => commands::stubify::run(&project_path, &output),
+            profile,
+            trace_parse,
+            jobs,
+            lean_names_file,
+            prefer_source,
+            locate_lean,
+            emit_all_labels_map,
+            lean_annotations,
+            lean_annotation_marker,
+            with_numbering,
+            qualify_names,
+            emit_bibtex,
+            emit_review_report,
+            format,
+            env_type_map,
+            warn_if_file_has_more_than,
+            warn_duplicate_proofs,
+            strict,
+            proof_parts,
+            proof_after_envs,
+            proof_lookahead,
+            macro_positions,
+            no_section_uses,
+            skip_marker,
+            path_base,
+            only,
+        } => expand(&output, &project_path, "stubify").and_then(|expanded| {
+            commands::stubify::run(
+                &project_path,
+                &expanded,
+                &commands::stubify::StubifyOptions {
+                    profile,
+                    trace_parse,
+                    jobs,
+                    lean_names_file,
+                    prefer_source,
+                    locate_lean,
+                    emit_all_labels_map,
+                    lean_annotations,
+                    lean_annotation_marker,
+                    with_numbering,
+                    qualify_names,
+                    emit_bibtex,
+                    emit_review_report,
+                    format,
+                    env_type_map: env_type_map.into_iter().collect(),
+                    warn_if_file_has_more_than,
+                    record_output: Some(expanded != output),
+                    warn_duplicate_proofs,
+                    strict,
+                    proof_parts,
+                    proof_after_envs,
+                    proof_lookahead,
+                    macro_positions,
+                    no_section_uses,
+                    skip_marker,
+                    path_base,
+                    only,
+                },
+            )
+        }),
         Commands::Atomize {
             project_path,
             output,
             regenerate_stubs,
-        } => commands::atomize::run(&project_path, &output, regenerate_stubs),
+            emit_spec_graph,
+            emit_proof_graph,
+            compute_depth,
+            split_by_file,
+            also_full,
+            format,
+        } => expand(&output, &project_path, "atomize").and_then(|output| {
+            commands::atomize::run(
+                &project_path,
+                &output,
+                regenerate_stubs,
+                &commands::atomize::AtomizeOptions {
+                    emit_spec_graph,
+                    emit_proof_graph,
+                    compute_depth,
+                    split_by_file,
+                    also_full,
+                    format,
+                },
+            )
+        }),
         Commands::Specify {
             project_path,
             output,
             regenerate_stubs,
             with_atoms,
-        } => commands::specify::run(&project_path, &output, regenerate_stubs, with_atoms),
+            min_score,
+            format,
+        } => expand(&output, &project_path, "specify").and_then(|output| {
+            commands::specify::run(
+                &project_path,
+                &output,
+                regenerate_stubs,
+                with_atoms,
+                min_score,
+                format,
+            )
+        }),
         Commands::Verify {
             project_path,
             output,
             regenerate_stubs,
             with_atoms,
-        } => commands::verify::run(&project_path, &output, regenerate_stubs, with_atoms),
+            check_uses,
+            mathlib_index,
+            axioms,
+            allow_axioms,
+            diagnostics,
+            lean_sorry_grep,
+            verified_decls,
+            format,
+            junit,
+        } => expand(&output, &project_path, "verify").and_then(|output| {
+            commands::verify::run(
+                &project_path,
+                &output,
+                regenerate_stubs,
+                &commands::verify::VerifyOptions {
+                    with_atoms,
+                    check_uses,
+                    mathlib_index,
+                    axioms,
+                    allow_axioms,
+                    diagnostics,
+                    lean_sorry_grep,
+                    verified_decls,
+                    format,
+                    junit,
+                },
+            )
+        }),
+        Commands::EmitProofObligations {
+            project_path,
+            output,
+            regenerate_stubs,
+        } => expand(&output, &project_path, "emit-proof-obligations").and_then(|output| {
+            commands::emit_proof_obligations::run(&project_path, &output, regenerate_stubs)
+        }),
+        Commands::Scaffold {
+            project_path,
+            output_dir,
+            lean_src_root,
+            regenerate_stubs,
+            force,
+        } => expand(&output_dir, &project_path, "scaffold").and_then(|output_dir| {
+            commands::scaffold::run(
+                &project_path,
+                &output_dir,
+                lean_src_root.as_deref(),
+                regenerate_stubs,
+                force,
+            )
+        }),
+        Commands::Init {
+            project_path,
+            from_lean,
+            group_by,
+            force,
+        } => commands::init::run(&project_path, &from_lean, group_by, force),
+        Commands::Export { format } => match format {
+            ExportFormat::Sqlite { project_path, path } => {
+                commands::export::run_sqlite(&project_path, &path)
+            }
+        },
+        Commands::StatusPage {
+            project_path,
+            output,
+            regenerate_stubs,
+            format,
+        } => expand(&output, &project_path, "status-page").and_then(|output| {
+            commands::status_page::run(&project_path, &output, regenerate_stubs, format)
+        }),
+        Commands::DiffGraph {
+            before,
+            after,
+            output,
+            format,
+            project_path,
+        } => expand(&output, &project_path, "diff-graph").and_then(|output| {
+            commands::diff_graph::run(&before, &after, &output, format, &project_path)
+        }),
+        Commands::DotLean {
+            project_path,
+            lean_src_root,
+            output,
+        } => expand(&output, &project_path, "dot-lean").and_then(|output| {
+            commands::dot_lean::run(&project_path, lean_src_root.as_deref(), &output)
+        }),
+        Commands::GenFixture {
+            output_dir,
+            files,
+            envs,
+            label_ratio,
+            uses_ratio,
+            lean_ratio,
+            leanok_ratio,
+            proof_ratio,
+            structure,
+            density,
+            seed,
+        } => commands::gen_fixture::run(
+            &output_dir,
+            &commands::gen_fixture::FixtureSpec {
+                files,
+                envs_per_file: envs,
+                label_ratio,
+                uses_ratio,
+                lean_ratio,
+                leanok_ratio,
+                proof_ratio,
+                structure,
+                density,
+                seed,
+            },
+        ),
+        Commands::Benchmark {
+            size,
+            env_count,
+            dep_count,
+            iterations,
+            seed,
+            output,
+        } => expand(&output, ".", "benchmark").and_then(|output| {
+            commands::benchmark::run(
+                &commands::benchmark::BenchmarkSpec {
+                    size,
+                    env_count,
+                    dep_count,
+                    iterations,
+                    seed,
+                },
+                &output,
+            )
+        }),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {e}");
+        if cli.emit_machine_readable_errors {
+            eprintln!("{}", error::to_machine_readable(e.as_ref()));
+        } else {
+            let location = e
+                .downcast_ref::<error::ProbeError>()
+                .and_then(error::ProbeError::location);
+            messages::error(location.as_ref(), &e.to_string());
+        }
         std::process::exit(1);
     }
 }
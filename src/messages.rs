@@ -0,0 +1,220 @@
+//! Warnings and errors for the user, and notices with `--verbose`
+//!
+//! Diagnostics go to stderr either as plain `Warning: ...` lines or, under
+//! `--message-format github`, as GitHub Actions workflow commands
+//! (`::warning file=...,line=...,endLine=...::message`), which the Actions UI
+//! turns into annotations on the pull request diff. Annotation paths are
+//! relative to the repository root, so blueprint files are prefixed with
+//! `blueprint/src/`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::parse::{normalize_path, LineRange};
+
+/// How warnings and errors are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MessageFormat {
+    /// `Warning: ...` and `Error: ...` lines
+    #[default]
+    Human,
+    /// GitHub Actions workflow commands
+    Github,
+}
+
+/// Whether diagnostics are printed as GitHub workflow commands, set once by the CLI
+static GITHUB: AtomicBool = AtomicBool::new(false);
+
+/// Set the format of all diagnostics printed by this process
+pub fn set_message_format(format: MessageFormat) {
+    GITHUB.store(format == MessageFormat::Github, Ordering::Relaxed);
+}
+
+/// Whether notices are printed, set once by the CLI
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Print notices (see `notice`) too
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+fn message_format() -> MessageFormat {
+    if GITHUB.load(Ordering::Relaxed) {
+        MessageFormat::Github
+    } else {
+        MessageFormat::Human
+    }
+}
+
+/// Where a diagnostic points, relative to the repository root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub lines: Option<(usize, usize)>,
+}
+
+impl Location {
+    /// A file relative to the project root
+    pub fn file(path: &str) -> Self {
+        Location {
+            file: normalize_path(path),
+            lines: None,
+        }
+    }
+
+    /// A blueprint file, given relative to `blueprint/src` as in stubs.json
+    pub fn blueprint(relative_path: &str, lines: Option<&LineRange>) -> Self {
+        Location {
+            file: format!(
+                "blueprint/src/{}",
+                normalize_path(relative_path).trim_start_matches("./")
+            ),
+            lines: lines.map(|lines| (lines.lines_start, lines.lines_end)),
+        }
+    }
+}
+
+/// Escape workflow command data (`%`, CR and LF)
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a workflow command property value, which also ends at `:` and `,`
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// The line printed for a diagnostic of `level` (`notice`, `warning` or `error`)
+fn format_diagnostic(
+    format: MessageFormat,
+    level: &str,
+    location: Option<&Location>,
+    message: &str,
+) -> String {
+    match format {
+        MessageFormat::Human => {
+            let mut prefix = level.to_string();
+            prefix[..1].make_ascii_uppercase();
+            format!("{}: {}", prefix, message)
+        }
+        MessageFormat::Github => {
+            let mut properties = Vec::new();
+            if let Some(location) = location {
+                properties.push(format!("file={}", escape_property(&location.file)));
+                if let Some((start, end)) = location.lines {
+                    properties.push(format!("line={}", start));
+                    properties.push(format!("endLine={}", end));
+                }
+            }
+            let properties = if properties.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", properties.join(","))
+            };
+            format!("::{}{}::{}", level, properties, escape_data(message))
+        }
+    }
+}
+
+/// Print a warning, annotated with `location` under `--message-format github`
+pub fn warning(location: Option<&Location>, message: &str) {
+    eprintln!(
+        "{}",
+        format_diagnostic(message_format(), "warning", location, message)
+    );
+}
+
+/// Print a notice, only with `--verbose`: details that need no action
+pub fn notice(location: Option<&Location>, message: &str) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprintln!(
+            "{}",
+            format_diagnostic(message_format(), "notice", location, message)
+        );
+    }
+}
+
+/// Print an error that ends the run
+pub fn error(location: Option<&Location>, message: &str) {
+    eprintln!(
+        "{}",
+        format_diagnostic(message_format(), "error", location, message)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation_paths() {
+        let lines = LineRange {
+            lines_start: 12,
+            lines_end: 15,
+        };
+        assert_eq!(
+            Location::blueprint("chapter/foo.tex", Some(&lines)),
+            Location {
+                file: "blueprint/src/chapter/foo.tex".to_string(),
+                lines: Some((12, 15)),
+            }
+        );
+        assert_eq!(
+            Location::blueprint("chapter\\foo.tex", None).file,
+            "blueprint/src/chapter/foo.tex"
+        );
+        assert_eq!(
+            Location::blueprint("./foo.tex", None).file,
+            "blueprint/src/foo.tex"
+        );
+        assert_eq!(Location::file("lakefile.toml").file, "lakefile.toml");
+    }
+
+    #[test]
+    fn test_format_diagnostic() {
+        let location = Location::blueprint(
+            "chapter/foo.tex",
+            Some(&LineRange {
+                lines_start: 12,
+                lines_end: 15,
+            }),
+        );
+        assert_eq!(
+            format_diagnostic(
+                MessageFormat::Github,
+                "warning",
+                Some(&location),
+                "stub has multiple proofs"
+            ),
+            "::warning file=blueprint/src/chapter/foo.tex,line=12,endLine=15::stub has multiple proofs"
+        );
+        assert_eq!(
+            format_diagnostic(
+                MessageFormat::Human,
+                "warning",
+                Some(&location),
+                "stub has multiple proofs"
+            ),
+            "Warning: stub has multiple proofs"
+        );
+        assert_eq!(
+            format_diagnostic(
+                MessageFormat::Github,
+                "warning",
+                Some(&Location::file("a,b:c.tex")),
+                "50% done\nnext"
+            ),
+            "::warning file=a%2Cb%3Ac.tex::50%25 done%0Anext"
+        );
+        assert_eq!(
+            format_diagnostic(MessageFormat::Github, "error", None, "No stubs"),
+            "::error::No stubs"
+        );
+        assert_eq!(
+            format_diagnostic(MessageFormat::Human, "error", None, "No stubs"),
+            "Error: No stubs"
+        );
+    }
+}
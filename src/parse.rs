@@ -0,0 +1,5764 @@
+//! Pure parsing of Blueprint LaTeX sources into stubs
+//!
+//! Nothing in this module touches the filesystem, so it also builds for
+//! `wasm32-unknown-unknown` without the `cli` feature.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::error::ProbeError;
+use crate::messages::{self, Location};
+use crate::profile::Profile;
+use crate::tokenize::{braced_group_end, environments, tokenize, Environment, Token, TokenKind};
+use crate::trace::Trace;
+
+/// Project-level configuration extracted from LaTeX files
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub home: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dochome: Option<String>,
+    /// File names (not paths) never scanned for environments, in addition to
+    /// `web.tex` and `print.tex`; only read from `.verilib/config.json`
+    #[serde(rename = "skip-files", default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_files: Vec<String>,
+    /// Names of the blueprint macros; only read from `.verilib/config.json`
+    #[serde(default, skip_serializing_if = "MacroNames::is_default")]
+    pub macros: MacroNames,
+    /// Environment types that are neither stubs nor reported as unconfigured,
+    /// in addition to `NON_STATEMENT_ENVS`; only read from `.verilib/config.json`
+    #[serde(
+        rename = "ignored-envs",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub ignored_envs: Vec<String>,
+}
+
+/// Names (without backslash) of the macros of each role, for blueprints that
+/// use their own, e.g. `\dependson` for `\uses`
+///
+/// In `.verilib/config.json`, each role takes a name or a list of names; all
+/// of them are recognized, so old and new names can be mixed while migrating.
+/// Roles left out keep the leanblueprint name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MacroNames {
+    #[serde(deserialize_with = "one_or_many")]
+    pub uses: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub proves: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub lean: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub leanok: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub mathlibok: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub notready: Vec<String>,
+}
+
+impl Default for MacroNames {
+    fn default() -> Self {
+        let name = |name: &str| vec![name.to_string()];
+        MacroNames {
+            uses: name("uses"),
+            proves: name("proves"),
+            lean: name("lean"),
+            leanok: name("leanok"),
+            mathlibok: name("mathlibok"),
+            notready: name("notready"),
+        }
+    }
+}
+
+impl MacroNames {
+    fn is_default(&self) -> bool {
+        *self == MacroNames::default()
+    }
+
+    /// Each role with its names
+    fn roles(&self) -> [(&'static str, &[String]); 6] {
+        [
+            ("uses", &self.uses),
+            ("proves", &self.proves),
+            ("lean", &self.lean),
+            ("leanok", &self.leanok),
+            ("mathlibok", &self.mathlibok),
+            ("notready", &self.notready),
+        ]
+    }
+}
+
+/// Deserialize a string or a list of strings as a list
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+/// The patterns of `MacroNames`, built once per parse
+struct Macros {
+    uses: Regex,
+    proves: Regex,
+    lean: Regex,
+    leanok: Regex,
+    mathlibok: Regex,
+    notready: Regex,
+    /// Role of every macro name, see `macro_lines`
+    roles: HashMap<String, &'static str>,
+}
+
+impl Macros {
+    fn new(names: &MacroNames) -> Self {
+        let alternatives = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| regex::escape(name))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        // Escaped names always make a valid pattern
+        let pattern = |names: &[String], suffix: &str| {
+            Regex::new(&format!(r"\\(?:{}){}", alternatives(names), suffix)).unwrap()
+        };
+        let roles = names
+            .roles()
+            .into_iter()
+            .flat_map(|(role, names)| names.iter().map(move |name| (name.clone(), role)))
+            .collect();
+        Macros {
+            uses: pattern(&names.uses, r"\s*\{"),
+            proves: pattern(&names.proves, r"\s*\{"),
+            lean: pattern(&names.lean, r"\{([^}]+)\}"),
+            leanok: pattern(&names.leanok, ""),
+            mathlibok: pattern(&names.mathlibok, ""),
+            notready: pattern(&names.notready, ""),
+            roles,
+        }
+    }
+}
+
+/// The leanblueprint macros
+#[cfg(test)]
+static DEFAULT_MACROS: LazyLock<Macros> = LazyLock::new(|| Macros::new(&MacroNames::default()));
+
+/// Default LaTeX environments to look for (from leanblueprint defaults)
+const DEFAULT_ENVS: &[&str] = &[
+    "definition",
+    "lemma",
+    "proposition",
+    "theorem",
+    "corollary",
+    "assumption",
+];
+
+/// File names that are never scanned for environments
+const SKIPPED_FILES: &[&str] = &["web.tex", "print.tex"];
+
+/// Environments that never hold a statement, so not being configured is no
+/// oversight (more can be listed in `ignored-envs` of `.verilib/config.json`)
+const NON_STATEMENT_ENVS: &[&str] = &[
+    "document",
+    "abstract",
+    "proof",
+    "itemize",
+    "enumerate",
+    "description",
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "alignat",
+    "alignat*",
+    "flalign",
+    "flalign*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "eqnarray",
+    "eqnarray*",
+    "subequations",
+    "split",
+    "aligned",
+    "gathered",
+    "cases",
+    "array",
+    "matrix",
+    "pmatrix",
+    "bmatrix",
+    "Bmatrix",
+    "vmatrix",
+    "Vmatrix",
+    "smallmatrix",
+    "displaymath",
+    "math",
+    "figure",
+    "figure*",
+    "table",
+    "table*",
+    "tabular",
+    "tabular*",
+    "tabularx",
+    "center",
+    "flushleft",
+    "flushright",
+    "minipage",
+    "quote",
+    "quotation",
+    "verse",
+    "verbatim",
+    "verbatim*",
+    "Verbatim",
+    "lstlisting",
+    "minted",
+    "tikzpicture",
+    "tikzcd",
+    "thebibliography",
+    "comment",
+];
+
+/// Environment type for assumptions, which are taken as axioms rather than proved
+const ASSUMPTION_ENV: &str = "assumption";
+
+/// Line range for source locations
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LineRange {
+    #[serde(rename = "lines-start")]
+    pub lines_start: usize,
+    #[serde(rename = "lines-end")]
+    pub lines_end: usize,
+}
+
+/// One of several proofs of a statement, possibly in another file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProofPart {
+    #[serde(rename = "stub-path")]
+    pub stub_path: String,
+    #[serde(flatten)]
+    pub lines: LineRange,
+}
+
+/// Helper function for serde to skip empty Vec
+fn vec_is_empty(v: &[String]) -> bool {
+    v.is_empty()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Stub {
+    pub label: String,
+    /// The environment has no `\label`, so `label` was generated
+    #[serde(
+        rename = "generated-label",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub generated_label: bool,
+    /// The environment's other labels, which refer to this stub as well
+    #[serde(
+        rename = "label-aliases",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub label_aliases: Vec<String>,
+    /// Content-based ID that survives file renames, see `stable_id` and
+    /// `disambiguate_stable_ids`
+    #[serde(rename = "stable-id")]
+    pub stable_id: String,
+    #[serde(rename = "stub-type", skip_serializing_if = "Option::is_none")]
+    pub stub_type: Option<String>,
+    #[serde(rename = "stub-path", skip_serializing_if = "Option::is_none")]
+    pub stub_path: Option<String>,
+    #[serde(rename = "stub-spec", skip_serializing_if = "Option::is_none")]
+    pub stub_spec: Option<LineRange>,
+    #[serde(rename = "stub-proof", skip_serializing_if = "Option::is_none")]
+    pub stub_proof: Option<LineRange>,
+    /// Number of proofs of the statement, following it or with `\proves`;
+    /// `stub-proof` is the last one. Only serialized when more than one
+    #[serde(rename = "proof-count", default, skip_serializing_if = "at_most_one")]
+    pub proof_count: usize,
+    /// All proofs in source order, when there is more than one
+    #[serde(
+        rename = "stub-proof-parts",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub stub_proof_parts: Vec<ProofPart>,
+    #[serde(rename = "env-options", skip_serializing_if = "Option::is_none")]
+    pub env_options: Option<String>,
+    #[serde(rename = "env-args", skip_serializing_if = "Option::is_none")]
+    pub env_args: Option<String>,
+    /// Number LaTeX gives the environment (e.g. `3.2`), with `stubify --with-numbering`
+    #[serde(rename = "theorem-number", skip_serializing_if = "Option::is_none")]
+    pub theorem_number: Option<String>,
+    /// Titles of the enclosing `\chapter`, `\section`, `\subsection` and
+    /// `\subsubsection` headings in the stub's file, outermost first
+    #[serde(
+        rename = "section-path",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub section_path: Vec<String>,
+    /// Stub-name of the statement environment this one is nested in (e.g. a
+    /// definition inside a theorem)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(rename = "code-name", skip_serializing_if = "Option::is_none")]
+    pub code_name: Option<String>,
+    #[serde(rename = "code-names", skip_serializing_if = "Option::is_none")]
+    pub lean_names: Option<Vec<String>>,
+    /// File declaring `code-name`, relative to the `stubify --locate-lean` root
+    #[serde(rename = "lean-path", skip_serializing_if = "Option::is_none")]
+    pub lean_path: Option<String>,
+    /// Lines of the `code-name` declaration, from its keyword to the end of its body
+    #[serde(rename = "lean-lines", skip_serializing_if = "Option::is_none")]
+    pub lean_lines: Option<LineRange>,
+    /// Docstring of the `code-name` declaration, without comment markers
+    #[serde(rename = "lean-doc", skip_serializing_if = "Option::is_none")]
+    pub lean_doc: Option<String>,
+    #[serde(rename = "spec-ok", skip_serializing_if = "Option::is_none")]
+    pub spec_ok: Option<bool>,
+    #[serde(rename = "mathlib-ok", skip_serializing_if = "Option::is_none")]
+    pub mathlib_ok: Option<bool>,
+    #[serde(rename = "not-ready", skip_serializing_if = "Option::is_none")]
+    pub not_ready: Option<bool>,
+    /// Set on `assumption` stubs: the statement is taken as an axiom
+    #[serde(rename = "is-axiom", skip_serializing_if = "Option::is_none")]
+    pub is_axiom: Option<bool>,
+    #[serde(rename = "discussion", default, skip_serializing_if = "Vec::is_empty")]
+    pub discussion: Vec<String>,
+    #[serde(
+        rename = "spec-dependencies",
+        default,
+        skip_serializing_if = "vec_is_empty"
+    )]
+    pub spec_dependencies: Vec<String>,
+    #[serde(rename = "proof-ok", skip_serializing_if = "Option::is_none")]
+    pub proof_ok: Option<bool>,
+    #[serde(rename = "proof-mathlib-ok", skip_serializing_if = "Option::is_none")]
+    pub proof_mathlib_ok: Option<bool>,
+    #[serde(rename = "proof-not-ready", skip_serializing_if = "Option::is_none")]
+    pub proof_not_ready: Option<bool>,
+    #[serde(rename = "proof-discussion", skip_serializing_if = "Option::is_none")]
+    pub proof_discussion: Option<Vec<String>>,
+    #[serde(rename = "proof-dependencies", skip_serializing_if = "Option::is_none")]
+    pub proof_dependencies: Option<Vec<String>>,
+    #[serde(rename = "proof-lean-names", skip_serializing_if = "Option::is_none")]
+    pub proof_lean_names: Option<Vec<String>>,
+    #[serde(rename = "macro-positions", skip_serializing_if = "Option::is_none")]
+    pub macro_positions: Option<MacroPositions>,
+    /// Every spec-dependency is formalized, see `derive_readiness`
+    #[serde(rename = "can-state", default)]
+    pub can_state: bool,
+    /// Formalized, and every proof dependency is proved, see `derive_readiness`
+    #[serde(rename = "can-prove", default)]
+    pub can_prove: bool,
+}
+
+/// Lines of the status and dependency macros of a stub, with
+/// `stubify --macro-positions`
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MacroPositions {
+    /// 1-based file line of the first occurrence of each macro in the
+    /// statement, by macro name (`leanok`, `lean`, `mathlibok`, `notready`, `uses`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub statement: BTreeMap<String, usize>,
+    /// The same within the proof
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub proof: BTreeMap<String, usize>,
+}
+
+/// `skip_serializing_if` for `Stub::proof_count`
+fn at_most_one(count: &usize) -> bool {
+    *count <= 1
+}
+
+static BLUEPRINT_PACKAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\usepackage\s*\[([^\]]*)\]\s*\{blueprint\}").unwrap());
+static THMS_OPTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"thms\s*=\s*([a-zA-Z_]+(?:\s*\+\s*[a-zA-Z_]+)*)").unwrap());
+
+/// Extract environment types from the `thms` option in web.tex
+/// e.g., \usepackage[thms=dfn+lem+prop+thm+cor]{blueprint}, spaces around
+/// `=` and `+` allowed
+fn parse_thms_option(web_tex_content: &str) -> Vec<String> {
+    // Look for \usepackage[...thms=...]{blueprint}
+    if let Some(caps) = BLUEPRINT_PACKAGE_RE.captures(web_tex_content) {
+        let options = &caps[1];
+        // Look for thms=xxx+yyy+zzz
+        if let Some(thms_caps) = THMS_OPTION_RE.captures(options) {
+            let thms_str = &thms_caps[1];
+            return thms_str.split('+').map(|s| s.trim().to_string()).collect();
+        }
+    }
+
+    // Return default environments if no thms option found
+    DEFAULT_ENVS.iter().map(|s| s.to_string()).collect()
+}
+
+/// `text` without its `%` comments (keeping the line breaks that end them),
+/// for command arguments, which the tokenizer keeps whole
+fn strip_argument_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                result.push(c);
+                result.extend(chars.next());
+            }
+            '%' => {
+                if chars.by_ref().any(|c| c == '\n') {
+                    result.push('\n');
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Strip LaTeX comments from content, preserving line structure
+/// Comments start with % and go to end of line, but \% is an escaped percent sign.
+/// `\iffalse ... \fi` blocks count as comments and verbatim content is blanked
+/// (keeping its newlines) so that commands inside it are never picked up.
+/// Comments inside command arguments (e.g. a `\uses{...}` list wrapped over
+/// several lines) are stripped as well.
+fn strip_latex_comments(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    // Start of the pending span of kept text, copied in one go
+    let mut kept_from = 0;
+
+    for token in tokenize(content) {
+        if let TokenKind::Command { args, .. } = &token.kind {
+            let text = &content[token.start..token.end];
+            if !args.is_empty() && text.contains('%') {
+                result.push_str(&content[kept_from..token.start]);
+                result.push_str(&strip_argument_comments(text));
+                kept_from = token.end;
+            }
+            continue;
+        }
+        if !matches!(token.kind, TokenKind::Comment | TokenKind::Verbatim) {
+            continue;
+        }
+        result.push_str(&content[kept_from..token.start]);
+        kept_from = token.end;
+
+        for line in content[token.start..token.end].split_inclusive('\n') {
+            if token.kind == TokenKind::Verbatim {
+                let blank = line.strip_suffix('\n').unwrap_or(line).chars().count();
+                result.extend(std::iter::repeat_n(' ', blank));
+            }
+            if line.ends_with('\n') {
+                result.push('\n');
+            }
+        }
+    }
+    result.push_str(&content[kept_from..]);
+
+    result
+}
+
+/// Strip nested environments from content (e.g., equation, align, etc. inside a proof)
+/// This ensures we only extract top-level labels, not labels from nested environments
+///
+/// Display math written without an environment (`\[...\]`, `$$...$$`) and
+/// inline `$...$` are stripped as well, since packages that tag equations
+/// allow a `\label` there too. Removed spans keep their line breaks, so lines
+/// of the result match lines of `content`.
+fn strip_nested_environments(content: &str) -> String {
+    let tokens = tokenize(content);
+
+    // Environments come ordered by start, so anything starting before the end
+    // of the last one kept is nested in it
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    for env in environments(&tokens) {
+        if removed
+            .last()
+            .is_some_and(|last| env.outer.start < last.end)
+        {
+            continue;
+        }
+        removed.push(env.outer);
+    }
+    removed.extend(math_spans(content, &tokens, &removed));
+    remove_spans(content, removed)
+}
+
+/// `content` without the byte ranges `removed` (which may overlap), keeping
+/// their line breaks so that lines of the result match lines of `content`
+fn remove_spans(content: &str, mut removed: Vec<Range<usize>>) -> String {
+    removed.sort_by_key(|span| span.start);
+
+    let mut result = String::with_capacity(content.len());
+    // End of the last removed span; kept text is copied in spans
+    let mut pos = 0;
+    for span in removed {
+        if span.start < pos {
+            pos = pos.max(span.end);
+            continue;
+        }
+        result.push_str(&content[pos..span.start]);
+        result.extend(content[span.clone()].matches('\n'));
+        pos = span.end;
+    }
+    result.push_str(&content[pos..]);
+
+    result
+}
+
+/// Math mode opened by a delimiter rather than an environment
+#[derive(Clone, Copy, PartialEq)]
+enum MathDelimiter {
+    /// `\[`
+    Bracket,
+    /// `$$`
+    DoubleDollar,
+    /// `$`
+    Dollar,
+}
+
+/// Byte ranges of `\[...\]`, `$$...$$` and `$...$` spans outside the
+/// `skipped` ranges, delimiters included
+///
+/// Escaped dollars, comments and verbatim text are separate tokens, so only
+/// math delimiters are seen. A span that is never closed is dropped, as is a
+/// `$` span reaching a blank line, which TeX would reject: stripping to the
+/// end of the content would also drop the labels that should be kept.
+fn math_spans(content: &str, tokens: &[Token], skipped: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut open: Option<(MathDelimiter, usize)> = None;
+    let mut skipped = skipped.iter().peekable();
+
+    for token in tokens {
+        while skipped.next_if(|range| range.end <= token.start).is_some() {}
+        if skipped
+            .peek()
+            .is_some_and(|range| range.start <= token.start)
+        {
+            continue;
+        }
+
+        match &token.kind {
+            TokenKind::Command { name: "[", .. } if open.is_none() => {
+                open = Some((MathDelimiter::Bracket, token.start));
+            }
+            TokenKind::Command { name: "]", .. } => {
+                if let Some((MathDelimiter::Bracket, start)) = open {
+                    spans.push(start..token.end);
+                    open = None;
+                }
+            }
+            TokenKind::Text => {
+                let text = &content[token.start..token.end];
+                let mut i = 0;
+                while i < text.len() {
+                    let at = token.start + i;
+                    let double = text.as_bytes()[i..].starts_with(b"$$");
+                    match (open, text.as_bytes()[i]) {
+                        (Some((MathDelimiter::Dollar, _)), b'\n')
+                            if starts_blank_line(&text[i..]) =>
+                        {
+                            open = None;
+                        }
+                        (None, b'$') if double => {
+                            open = Some((MathDelimiter::DoubleDollar, at));
+                            i += 1;
+                        }
+                        (None, b'$') => open = Some((MathDelimiter::Dollar, at)),
+                        (Some((MathDelimiter::Dollar, start)), b'$') => {
+                            spans.push(start..at + 1);
+                            open = None;
+                        }
+                        (Some((MathDelimiter::DoubleDollar, start)), b'$') if double => {
+                            spans.push(start..at + 2);
+                            open = None;
+                            i += 1;
+                        }
+                        _ => {}
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    spans
+}
+
+/// Whether `text`, starting at a line break, continues with a blank line
+fn starts_blank_line(text: &str) -> bool {
+    text[1..]
+        .trim_start_matches([' ', '\t', '\r'])
+        .starts_with('\n')
+}
+
+static LABEL_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\label\{([^}]+)\}").unwrap());
+
+/// Extract all top-level labels from \label{...} in order of appearance
+/// Labels inside nested environments (like equation, align) are ignored
+#[cfg(test)]
+fn extract_all_labels(content: &str) -> Vec<String> {
+    extract_all_labels_lines(content)
+        .into_iter()
+        .map(|(label, _)| label)
+        .collect()
+}
+
+/// Top-level labels in order of appearance, like `extract_all_labels`, with
+/// the line of each label counted from 0 at the start of `content`
+fn extract_all_labels_lines(content: &str) -> Vec<(String, usize)> {
+    // First strip nested environments to only get top-level labels
+    let top_level_content = strip_nested_environments(content);
+
+    let mut line = 0;
+    let mut counted_to = 0;
+    LABEL_RE
+        .captures_iter(&top_level_content)
+        .map(|caps| {
+            let start = caps.get(0).unwrap().start();
+            line += top_level_content[counted_to..start].matches('\n').count();
+            counted_to = start;
+            (caps[1].to_string(), line)
+        })
+        .collect()
+}
+
+/// The labels of one environment (statement and proof) with their file
+/// lines, each kept once: a repetition, usually a copy-paste error, is
+/// dropped with a warning at its line
+fn dedup_labels(labels: Vec<(String, usize)>, relative_path: &str) -> Vec<String> {
+    let mut first_lines: HashMap<String, usize> = HashMap::new();
+    let mut kept = Vec::with_capacity(labels.len());
+    for (label, line) in labels {
+        if let Some(first_line) = first_lines.get(&label) {
+            let lines = LineRange {
+                lines_start: line,
+                lines_end: line,
+            };
+            messages::warning(
+                Some(&Location::blueprint(relative_path, Some(&lines))),
+                &format!(
+                    "label '{}' repeats the one on line {} of the same environment",
+                    label, first_line
+                ),
+            );
+            continue;
+        }
+        first_lines.insert(label.clone(), line);
+        kept.push(label);
+    }
+    kept
+}
+
+/// Entries of a comma-separated list argument, trimmed, skipping empty ones
+///
+/// Lists are often wrapped over several lines with comments in between. After
+/// comment stripping this leaves blank lines and empty segments, and entries
+/// separated only by a line break when a comma was inside a comment; since a
+/// label never spans lines, line breaks separate entries as well.
+fn split_list(list: &str) -> Vec<String> {
+    split_list_lines(list)
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect()
+}
+
+/// `split_list` with the line of each entry, counted from 0 at the start of `list`
+fn split_list_lines(list: &str) -> Vec<(String, usize)> {
+    list.split('\n')
+        .enumerate()
+        .flat_map(|(line, text)| {
+            text.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(move |entry| (entry.to_string(), line))
+        })
+        .collect()
+}
+
+/// Extract lean declarations from \lean{...}
+/// Returns a list of declaration names (comma-separated in the macro)
+fn extract_lean(content: &str, macros: &Macros) -> Vec<String> {
+    macros
+        .lean
+        .captures(content)
+        .map(|caps| split_list(&caps[1]))
+        .unwrap_or_default()
+}
+
+/// Check for \leanok macro
+fn extract_leanok(content: &str, macros: &Macros) -> bool {
+    macros.leanok.is_match(content)
+}
+
+/// Check for \mathlibok macro
+fn extract_mathlibok(content: &str, macros: &Macros) -> bool {
+    macros.mathlibok.is_match(content)
+}
+
+/// Check for \notready macro
+fn extract_notready(content: &str, macros: &Macros) -> bool {
+    macros.notready.is_match(content)
+}
+
+static DISCUSSION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\discussion\{([^}]+)\}").unwrap());
+
+/// Extract discussion issue numbers from \discussion{...}
+/// Can appear multiple times, so returns a list
+fn extract_discussion(content: &str) -> Vec<String> {
+    DISCUSSION_RE
+        .captures_iter(content)
+        .map(|caps| caps[1].trim().to_string())
+        .collect()
+}
+
+/// `\ref{...}`, `\cref{...}` or `\Cref{...}` (also starred) around labels
+static REF_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\(?:ref|cref|Cref)\*?\s*\{([^{}]*)\}").unwrap());
+
+/// Labels in the argument of the first `\command{...}` matched by `command_re`
+/// (which ends at the opening brace), e.g. `\uses` or `\proves`, each with its
+/// file line; `content` is from `relative_path` and starts on file line
+/// `first_line`
+///
+/// One level of `\ref{...}`, `\cref{...}` or `\Cref{...}` around labels is
+/// unwrapped to the labels inside. Entries with any other macro are kept as
+/// written, with a warning at their line, since they cannot resolve to a label.
+fn extract_label_list(
+    content: &str,
+    relative_path: &str,
+    first_line: usize,
+    command_re: &Regex,
+    command: &str,
+) -> Vec<(String, usize)> {
+    let labels: Vec<(String, usize)> = label_list_lines(content, command_re)
+        .into_iter()
+        .map(|(label, line)| (label, first_line + line))
+        .collect();
+    for (label, line) in labels
+        .iter()
+        .filter(|(label, _)| label.contains(['\\', '{', '}']))
+    {
+        let lines = LineRange {
+            lines_start: *line,
+            lines_end: *line,
+        };
+        messages::warning(
+            Some(&Location::blueprint(relative_path, Some(&lines))),
+            &format!(
+                "\\{}{{...}} entry '{}' at {}:{} has a macro that cannot be unwrapped to a label",
+                command, label, relative_path, line
+            ),
+        );
+    }
+    labels
+}
+
+/// The entries of the first `command_re` list in `content`, `\ref`s
+/// unwrapped, with their lines counted from 0 at the start of `content`
+fn label_list_lines(content: &str, command_re: &Regex) -> Vec<(String, usize)> {
+    let Some(open) = command_re.find(content).map(|m| m.end() - 1) else {
+        return Vec::new();
+    };
+    let Some(end) = braced_group_end(content.as_bytes(), open) else {
+        return Vec::new();
+    };
+    // Keep the line breaks between the macro name and its labels, so that
+    // entries stay on their lines
+    let list = REF_RE.replace_all(&content[open + 1..end - 1], |caps: &regex::Captures| {
+        let prefix = caps.get(1).unwrap().start() - caps.get(0).unwrap().start();
+        let breaks = caps[0][..prefix].matches('\n').count();
+        format!("{}{}", "\n".repeat(breaks), &caps[1])
+    });
+    let first_line = content[..open].matches('\n').count();
+    split_list_lines(&list)
+        .into_iter()
+        .map(|(label, line)| (label, first_line + line))
+        .collect()
+}
+
+/// Extract labels from \proves{...} in `content`, which is from
+/// `relative_path` and starts on file line `first_line`
+/// Returns a list of labels that this proof proves
+fn extract_proves(
+    content: &str,
+    relative_path: &str,
+    first_line: usize,
+    macros: &Macros,
+) -> Vec<String> {
+    extract_label_list(content, relative_path, first_line, &macros.proves, "proves")
+        .into_iter()
+        .map(|(label, _)| label)
+        .collect()
+}
+
+static ENV_OPTIONS_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\[\s*([^\]]*?)\s*\]").unwrap());
+
+/// Extract the optional argument of an environment, e.g. `[Pythagorean Theorem]`
+/// in `\begin{theorem}[Pythagorean Theorem]`
+/// `content` is the environment body, starting right after `\begin{...}`;
+/// whitespace before the argument is skipped, as LaTeX does
+fn extract_env_options(content: &str) -> Option<String> {
+    ENV_OPTIONS_RE
+        .captures(content)
+        .map(|caps| caps[1].to_string())
+        .filter(|s| !s.is_empty())
+}
+
+static ENV_ARGS_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:\[[^\]]*\]\s*)?\{([^}]*)\}").unwrap());
+
+/// Extract the required argument of an environment, e.g. `{arg}` in
+/// `\begin{theorem}[Name]{arg}`, skipping the optional argument if present
+///
+/// Any leading `{...}` matches, so this is only an argument for environments
+/// declared with one, see `environments_with_arguments`.
+fn extract_env_args(content: &str) -> Option<String> {
+    ENV_ARGS_RE
+        .captures(content)
+        .map(|caps| caps[1].trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+static NEWENVIRONMENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\(?:re)?newenvironment\*?\s*\{\s*([^}\s]+)\s*\}\s*\[\s*(\d)\s*\](\s*\[)?")
+        .unwrap()
+});
+
+/// Environments declared with `\newenvironment{name}[n]` (or
+/// `\renewenvironment`) taking a required argument: `n` arguments, of which
+/// the first is optional when a default follows
+fn environments_with_arguments(files: &[(String, &str)]) -> HashSet<String> {
+    files
+        .iter()
+        .flat_map(|(_, content)| {
+            NEWENVIRONMENT_RE
+                .captures_iter(&strip_latex_comments(content))
+                .filter(|caps| {
+                    let count: usize = caps[2].parse().unwrap_or(0);
+                    count > usize::from(caps.get(3).is_some())
+                })
+                .map(|caps| caps[1].to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// A `\uses{...}` entry with the file line it is written on
+#[derive(Debug, Clone, PartialEq)]
+struct Dependency {
+    label: String,
+    line: usize,
+}
+
+/// Extract dependencies from \uses{...} in `content`, which is from
+/// `relative_path` and starts on file line `first_line`
+fn extract_uses(
+    content: &str,
+    relative_path: &str,
+    first_line: usize,
+    macros: &Macros,
+) -> Vec<Dependency> {
+    extract_label_list(content, relative_path, first_line, &macros.uses, "uses")
+        .into_iter()
+        .map(|(label, line)| Dependency { label, line })
+        .collect()
+}
+
+static HOME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\home\{([^}]+)\}").unwrap());
+
+/// Extract \home{url} from content
+fn extract_home(content: &str) -> Option<String> {
+    HOME_RE
+        .captures(content)
+        .map(|caps| caps[1].trim().to_string())
+}
+
+static GITHUB_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\github\{([^}]+)\}").unwrap());
+
+/// Extract \github{url} from content
+fn extract_github(content: &str) -> Option<String> {
+    GITHUB_RE
+        .captures(content)
+        .map(|caps| caps[1].trim().to_string())
+}
+
+static DOCHOME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\dochome\{([^}]+)\}").unwrap());
+
+/// Extract \dochome{url} from content
+fn extract_dochome(content: &str) -> Option<String> {
+    DOCHOME_RE
+        .captures(content)
+        .map(|caps| caps[1].trim().to_string())
+}
+
+/// Extract project config from content
+fn extract_config(content: &str) -> Config {
+    Config {
+        home: extract_home(content),
+        github: extract_github(content),
+        dochome: extract_dochome(content),
+        skip_files: Vec::new(),
+        macros: MacroNames::default(),
+        ignored_envs: Vec::new(),
+    }
+}
+
+/// Merge two configs, preferring values from `other` if present
+fn merge_config(base: Config, other: Config) -> Config {
+    Config {
+        home: other.home.or(base.home),
+        github: other.github.or(base.github),
+        dochome: other.dochome.or(base.dochome),
+        skip_files: [base.skip_files, other.skip_files].concat(),
+        macros: base.macros,
+        ignored_envs: [base.ignored_envs, other.ignored_envs].concat(),
+    }
+}
+
+/// Generate a fresh label in the form "a0000000000"
+fn generate_label(counter: u64) -> String {
+    format!("a{:010}", counter)
+}
+
+/// Hex digits of a stable ID
+const STABLE_ID_LEN: usize = 12;
+
+/// Content-based ID of a statement: the first hex digits of the SHA-256 of
+/// the full source lines it spans, so it does not depend on the file path
+fn stable_id(content: &str, statement: &Range<usize>) -> String {
+    let start = content[..statement.start]
+        .rfind('\n')
+        .map_or(0, |pos| pos + 1);
+    let end = content[statement.end..]
+        .find('\n')
+        .map_or(content.len(), |pos| statement.end + pos);
+    short_hash(&content.as_bytes()[start..end])
+}
+
+/// The first `STABLE_ID_LEN` hex digits of the SHA-256 of `bytes`
+fn short_hash(bytes: &[u8]) -> String {
+    let mut id = format!("{:x}", Sha256::digest(bytes));
+    id.truncate(STABLE_ID_LEN);
+    id
+}
+
+/// Convert a byte position to a 1-indexed line number
+fn byte_pos_to_line(content: &str, pos: usize) -> usize {
+    content[..pos].chars().filter(|&c| c == '\n').count() + 1
+}
+
+/// Parsed environment before label validation
+struct ParsedEnv {
+    env_type: String,
+    relative_path: String,
+    spec_lines: LineRange,
+    stable_id: String,
+    proof_lines: Option<LineRange>,
+    env_options: Option<String>,
+    env_args: Option<String>,
+    /// Position among the stub environments of the file, skipped ones
+    /// included, which `number_files` numbers in order
+    number_index: usize,
+    /// Number LaTeX gives the environment, see `number_files`
+    number: String,
+    /// Enclosing headings, see `section_paths`
+    section_path: Vec<String>,
+    /// Index of the environment this one is nested in, among the parsed ones
+    parent: Option<usize>,
+    labels: Vec<String>,
+    code_name: Option<String>,
+    lean_names: Option<Vec<String>>,
+    spec_ok: bool,
+    mathlib_ok: bool,
+    not_ready: bool,
+    discussion: Vec<String>,
+    spec_dependencies: Vec<Dependency>,
+    /// See `section_uses`
+    section_dependencies: Vec<Dependency>,
+    proof_ok: Option<bool>,
+    proof_mathlib_ok: Option<bool>,
+    proof_not_ready: Option<bool>,
+    proof_discussion: Option<Vec<String>>,
+    proof_dependencies: Option<Vec<Dependency>>,
+    proof_lean_names: Option<Vec<String>>,
+    /// See `macro_lines`
+    statement_macros: BTreeMap<String, usize>,
+    proof_macros: BTreeMap<String, usize>,
+}
+
+/// A standalone proof that uses \proves to reference its statement
+struct StandaloneProof {
+    proves_labels: Vec<String>,
+    lines: LineRange,
+    proof_ok: bool,
+    mathlib_ok: bool,
+    not_ready: bool,
+    discussion: Vec<String>,
+    dependencies: Vec<Dependency>,
+    lean_names: Vec<String>,
+    macros: BTreeMap<String, usize>,
+}
+
+/// Roles of the macros whose lines `--macro-positions` records
+const POSITIONED_MACROS: &[&str] = &["leanok", "lean", "mathlibok", "notready", "uses"];
+
+static MACRO_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\([A-Za-z]+)").unwrap());
+
+/// File line of the first occurrence of each `POSITIONED_MACROS` macro in
+/// `body`, comment-stripped text starting on line `first_line`, by role (so
+/// a renamed `\uses` is recorded as `uses`)
+///
+/// Comment stripping keeps every line break, so counting them gives the
+/// same lines as in the source.
+fn macro_lines(body: &str, first_line: usize, macros: &Macros) -> BTreeMap<String, usize> {
+    let mut lines = BTreeMap::new();
+    let mut line = first_line;
+    let mut counted_to = 0;
+    for caps in MACRO_RE.captures_iter(body) {
+        let name = caps.get(1).unwrap();
+        let Some(role) = macros
+            .roles
+            .get(name.as_str())
+            .filter(|role| POSITIONED_MACROS.contains(role))
+        else {
+            continue;
+        };
+        line += body[counted_to..name.start()].matches('\n').count();
+        counted_to = name.start();
+        lines.entry(role.to_string()).or_insert(line);
+    }
+    lines
+}
+
+/// Proof match result with content and line range
+struct ProofMatch {
+    content: String,
+    lines: LineRange,
+    /// Labels from \proves{...} - if present, this is a standalone proof
+    proves_labels: Vec<String>,
+    /// See `macro_lines`
+    macros: BTreeMap<String, usize>,
+}
+
+/// Build a `ProofMatch` from a proof environment
+fn proof_match(
+    content: &str,
+    relative_path: &str,
+    proof: &Environment,
+    macros: &Macros,
+) -> ProofMatch {
+    let proof_content = strip_latex_comments(&content[proof.body.clone()]);
+    let first_line = byte_pos_to_line(content, proof.body.start);
+
+    // Extract \proves{...} labels if present
+    let proves_labels = extract_proves(&proof_content, relative_path, first_line, macros);
+    let positions = macro_lines(&proof_content, first_line, macros);
+
+    ProofMatch {
+        content: proof_content,
+        lines: LineRange {
+            lines_start: byte_pos_to_line(content, proof.outer.start),
+            lines_end: byte_pos_to_line(content, proof.outer.end - 1), // -1 to get line of last char
+        },
+        proves_labels,
+        macros: positions,
+    }
+}
+
+/// Maximum number of bytes of whitespace allowed between the end of an
+/// environment and `\begin{proof}`, unless configured otherwise; a proof further
+/// away (e.g. after pages of blank lines) is not attached to the environment.
+/// Comments do not count, so a commented-out proof does not detach the real one
+pub const DEFAULT_PROOF_LOOKAHEAD_BYTES: usize = 4096;
+
+/// Find the proof environment that immediately follows the token at `after_token`
+///
+/// Only whitespace (at most `lookahead` bytes of it) and comments may come in
+/// between, and whole environments of the `skip_envs` types (e.g. a remark
+/// before the proof); the search stops at the first other token (in
+/// particular at the next `\begin{...}` of any other kind). A proof dropped
+/// only because of `lookahead` is reported with a warning.
+///
+/// Each step of the search is recorded in `trace`.
+fn find_following_proof<'a, 'b>(
+    content: &str,
+    relative_path: &str,
+    tokens: &[Token],
+    envs: &'b [Environment<'a>],
+    after_token: usize,
+    syntax: &FileSyntax,
+    trace: &mut Trace,
+) -> Option<&'b Environment<'a>> {
+    let lines = |env: &Environment| {
+        format!(
+            "lines {}-{}",
+            byte_pos_to_line(content, env.outer.start),
+            byte_pos_to_line(content, env.outer.end - 1)
+        )
+    };
+    let statement_end = tokens[after_token].end;
+    let mut after_token = after_token;
+    loop {
+        // The line break ending a comment belongs to the comment
+        let mut whitespace = 0;
+        let mut after_comment = false;
+        let Some(next) = tokens[after_token + 1..]
+            .iter()
+            .position(|token| match token.kind {
+                TokenKind::Comment => {
+                    after_comment = true;
+                    false
+                }
+                TokenKind::Text if content[token.start..token.end].trim().is_empty() => {
+                    let text = &content[token.start..token.end];
+                    let text = if after_comment {
+                        text.strip_prefix('\n').unwrap_or(text)
+                    } else {
+                        text
+                    };
+                    whitespace += text.len();
+                    after_comment = false;
+                    false
+                }
+                _ => true,
+            })
+            .map(|offset| after_token + 1 + offset)
+        else {
+            trace.line(2, || {
+                "no proof: only whitespace and comments until the end".to_string()
+            });
+            return None;
+        };
+        if whitespace > syntax.proof_lookahead {
+            let proof = envs
+                .iter()
+                .find(|env| env.begin_token == next && env.name == "proof");
+            if let Some(proof) = proof {
+                messages::warning(
+                    Some(&Location::blueprint(
+                        relative_path,
+                        Some(&LineRange {
+                            lines_start: byte_pos_to_line(content, proof.outer.start),
+                            lines_end: byte_pos_to_line(content, proof.outer.end - 1),
+                        }),
+                    )),
+                    &format!(
+                        "proof at {}:{} is more than {} bytes of whitespace after the statement ending on line {}, so it is not attached",
+                        relative_path,
+                        byte_pos_to_line(content, proof.outer.start),
+                        syntax.proof_lookahead,
+                        byte_pos_to_line(content, statement_end)
+                    ),
+                );
+            }
+            trace.line(2, || {
+                format!(
+                    "no proof: more than {} bytes of whitespace",
+                    syntax.proof_lookahead
+                )
+            });
+            return None;
+        }
+
+        // `envs` is sorted by start position
+        let index = envs.partition_point(|env| env.outer.start < tokens[next].start);
+        let Some(env) = envs.get(index).filter(|env| env.begin_token == next) else {
+            trace.line(2, || {
+                let text = &content[tokens[next].start..tokens[next].end];
+                let at = tokens[next].start + text.len() - text.trim_start().len();
+                format!(
+                    "no proof: '{}' on line {} comes first",
+                    snippet(text),
+                    byte_pos_to_line(content, at)
+                )
+            });
+            return None;
+        };
+        if env.name == "proof" {
+            trace.line(2, || format!("proof at {}", lines(env)));
+            return Some(env);
+        }
+        if !syntax.proof_after_envs.iter().any(|t| t == env.name) {
+            trace.line(2, || {
+                format!("no proof: {} at {} comes first", env.name, lines(env))
+            });
+            return None;
+        }
+        trace.line(2, || format!("passing over {} at {}", env.name, lines(env)));
+        after_token = env.end_token;
+    }
+}
+
+/// Labels with their lines for the trace, e.g. `lem1 (line 3), lem2 (line 4)`
+fn describe_lines(labels: &[(String, usize)]) -> String {
+    labels
+        .iter()
+        .map(|(label, line)| format!("{} (line {})", label, line))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Macros found in a statement or proof for the trace, e.g.
+/// `\lean{Foo.bar}, \leanok, \uses{lem1 (line 3)}`
+fn describe_macros(
+    lean: &[&str],
+    ok: bool,
+    mathlib_ok: bool,
+    not_ready: bool,
+    discussion: &[String],
+    uses: &[Dependency],
+) -> String {
+    let mut found = Vec::new();
+    if !lean.is_empty() {
+        found.push(format!("\\lean{{{}}}", lean.join(", ")));
+    }
+    for (name, present) in [
+        ("leanok", ok),
+        ("mathlibok", mathlib_ok),
+        ("notready", not_ready),
+    ] {
+        if present {
+            found.push(format!("\\{}", name));
+        }
+    }
+    if !discussion.is_empty() {
+        found.push(format!("\\discussion{{{}}}", discussion.join(", ")));
+    }
+    if !uses.is_empty() {
+        let uses: Vec<(String, usize)> = uses.iter().map(|d| (d.label.clone(), d.line)).collect();
+        found.push(format!("\\uses{{{}}}", describe_lines(&uses)));
+    }
+    if found.is_empty() {
+        "no macros".to_string()
+    } else {
+        found.join(", ")
+    }
+}
+
+/// Start of `text` for the trace: its first 40 characters, whitespace collapsed
+fn snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(40) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+/// Labels that the standalone proofs (`\proves`) in `content` prove,
+/// found without parsing the file (and so without its warnings)
+pub fn proved_labels(content: &str, macro_names: &MacroNames) -> Vec<String> {
+    let macros = Macros::new(macro_names);
+    let tokens = tokenize(content);
+    environments(&tokens)
+        .iter()
+        .filter(|env| env.name == "proof")
+        .flat_map(|env| {
+            let body = strip_latex_comments(&content[env.body.clone()]);
+            label_list_lines(&body, &macros.proves)
+        })
+        .map(|(label, _)| label)
+        .collect()
+}
+
+/// Every `\label{...}` of `content` outside comments, also those of
+/// environments that do not become stubs
+pub fn defined_labels(content: &str) -> Vec<String> {
+    LABEL_RE
+        .captures_iter(&strip_latex_comments(content))
+        .map(|caps| caps[1].to_string())
+        .collect()
+}
+
+/// Find all standalone proofs (those with \proves) in a file
+fn find_standalone_proofs(
+    content: &str,
+    relative_path: &str,
+    macros: &Macros,
+) -> Vec<StandaloneProof> {
+    let mut proofs = Vec::new();
+
+    let tokens = tokenize(content);
+
+    for env in environments(&tokens)
+        .iter()
+        .filter(|env| env.name == "proof")
+    {
+        let ProofMatch {
+            content: proof_content,
+            lines,
+            proves_labels,
+            macros: positions,
+        } = proof_match(content, relative_path, env, macros);
+
+        // Check if this proof has \proves
+        if proves_labels.is_empty() {
+            continue; // Not a standalone proof
+        }
+
+        proofs.push(StandaloneProof {
+            proves_labels,
+            lines,
+            proof_ok: extract_leanok(&proof_content, macros),
+            mathlib_ok: extract_mathlibok(&proof_content, macros),
+            not_ready: extract_notready(&proof_content, macros),
+            discussion: extract_discussion(&proof_content),
+            dependencies: extract_uses(&proof_content, relative_path, lines.lines_start, macros),
+            lean_names: extract_lean(&proof_content, macros),
+            macros: positions,
+        });
+    }
+
+    proofs
+}
+
+/// What to look for in content files, the same for all of them
+#[derive(Clone, Copy)]
+struct FileSyntax<'a> {
+    /// Environment types that become stubs
+    env_types: &'a [String],
+    /// See `ParseOptions::proof_after_envs`
+    proof_after_envs: &'a [String],
+    /// See `find_following_proof`
+    proof_lookahead: usize,
+    /// See `is_skipped`
+    skip_marker: &'a str,
+    macros: &'a Macros,
+}
+
+/// Parse a single .tex file and extract environments
+#[cfg(test)]
+fn parse_tex_file(content: &str, relative_path: &str, env_types: &[String]) -> Vec<ParsedEnv> {
+    let syntax = FileSyntax {
+        env_types,
+        proof_after_envs: &[],
+        proof_lookahead: DEFAULT_PROOF_LOOKAHEAD_BYTES,
+        skip_marker: DEFAULT_SKIP_MARKER,
+        macros: &DEFAULT_MACROS,
+    };
+    let FileEnvs {
+        mut envs,
+        numbering,
+        ..
+    } = parse_tex_file_profiled(
+        content,
+        relative_path,
+        &syntax,
+        &mut Profile::default(),
+        &mut Trace::default(),
+    );
+    let numbers = &number_files(&[(relative_path, &numbering)])[relative_path];
+    for env in &mut envs {
+        env.number = numbers[env.number_index].clone();
+    }
+    envs
+}
+
+/// Macro that drops the environment containing it, unless configured otherwise
+pub const DEFAULT_SKIP_MARKER: &str = "blueprintskip";
+
+static SKIP_COMMENT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*%\s*probe-blueprint:\s*skip\s*$").unwrap());
+
+/// Whether `env` is marked to be left out of the stubs: by the `\skip_marker`
+/// macro anywhere inside it but in the `nested` spans (statements nested in it
+/// and their proofs), or by a `% probe-blueprint: skip` comment on the line
+/// right before its `\begin` (which must start its own line)
+fn is_skipped(
+    content: &str,
+    tokens: &[Token],
+    env: &Environment,
+    nested: &[Range<usize>],
+    skip_marker: &str,
+) -> bool {
+    let has_marker = tokens[env.begin_token..env.end_token]
+        .iter()
+        .filter(|token| !nested.iter().any(|span| span.contains(&token.start)))
+        .any(|token| matches!(token.kind, TokenKind::Command { name, .. } if name == skip_marker));
+    if has_marker {
+        return true;
+    }
+
+    let before = &content[..env.outer.start];
+    let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+    if line_start == 0 || !before[line_start..].trim().is_empty() {
+        return false;
+    }
+    let previous = &before[..line_start - 1];
+    let previous = &previous[previous.rfind('\n').map_or(0, |pos| pos + 1)..];
+    SKIP_COMMENT_RE.is_match(previous.trim_end_matches('\r'))
+}
+
+/// What LaTeX numbering depends on in one file, in source order, see
+/// `number_files`
+#[derive(Debug, Clone, PartialEq)]
+enum NumberingEvent {
+    /// `\chapter`, but not `\chapter*`
+    Chapter,
+    /// `\setcounter{counter}{value}`
+    SetCounter(String, u64),
+    /// `\input` or `\include` of a file, relative to `blueprint/src`
+    Input(String),
+    /// A stub environment of the given type
+    Env(String),
+}
+
+/// Path of the file `\input{name}` reads, relative to `blueprint/src`
+fn input_path(name: &str) -> String {
+    let name = normalize_path(name.trim());
+    let name = name.trim_start_matches("./");
+    if name.ends_with(".tex") {
+        name.to_string()
+    } else {
+        format!("{}.tex", name)
+    }
+}
+
+/// The numbering events of one file: chapters, counter changes, inputs and
+/// the stub environments, one `Env` for each of `stub_envs`
+fn numbering_events(
+    content: &str,
+    tokens: &[Token],
+    stub_envs: &[&Environment],
+) -> Vec<NumberingEvent> {
+    let mut events = Vec::new();
+    let mut stub_envs = stub_envs.iter().peekable();
+    for (index, token) in tokens.iter().enumerate() {
+        if let Some(env) = stub_envs.next_if(|env| env.begin_token == index) {
+            events.push(NumberingEvent::Env(env.name.to_string()));
+            continue;
+        }
+        let TokenKind::Command { name, args } = &token.kind else {
+            continue;
+        };
+        match (*name, args.as_slice()) {
+            ("chapter", _) if !content[token.end..].starts_with('*') => {
+                events.push(NumberingEvent::Chapter);
+            }
+            ("setcounter", [counter, value]) => {
+                if let Ok(value) = content[value.clone()].trim().parse::<u64>() {
+                    let counter = content[counter.clone()].trim().to_string();
+                    events.push(NumberingEvent::SetCounter(counter, value));
+                }
+            }
+            ("input" | "include", [file, ..]) => {
+                events.push(NumberingEvent::Input(input_path(&content[file.clone()])));
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+/// LaTeX counters while numbering, see `number_files`
+#[derive(Default)]
+struct Counters {
+    chapter: Option<u64>,
+    envs: HashMap<String, u64>,
+}
+
+/// Number the stub environments of `path` and of the files it inputs, into
+/// `numbers`; a file is numbered once, where it is first read
+fn number_file<'a>(
+    path: &'a str,
+    events: &HashMap<&'a str, &'a [NumberingEvent]>,
+    counters: &mut Counters,
+    numbers: &mut HashMap<&'a str, Vec<String>>,
+) {
+    if numbers.contains_key(path) {
+        return;
+    }
+    numbers.insert(path, Vec::new());
+    for event in events[path] {
+        match event {
+            NumberingEvent::Chapter => {
+                counters.chapter = Some(counters.chapter.unwrap_or(0) + 1);
+                counters.envs.clear();
+            }
+            NumberingEvent::SetCounter(counter, value) => match counter.as_str() {
+                "chapter" => counters.chapter = Some(*value),
+                counter => {
+                    counters.envs.insert(counter.to_string(), *value);
+                }
+            },
+            NumberingEvent::Input(input) => {
+                if let Some((&input, _)) = events.get_key_value(input.as_str()) {
+                    number_file(input, events, counters, numbers);
+                }
+            }
+            NumberingEvent::Env(name) => {
+                let counter = counters.envs.entry(name.clone()).or_insert(0);
+                *counter += 1;
+                let number = match counters.chapter {
+                    Some(chapter) => format!("{}.{}", chapter, counter),
+                    None => counter.to_string(),
+                };
+                numbers.get_mut(path).unwrap().push(number);
+            }
+        }
+    }
+}
+
+/// Number stub environments the way LaTeX does, from the numbering events of
+/// each file (in processing order)
+///
+/// Each environment type has its own counter. `\chapter` (but not
+/// `\chapter*`) starts a new chapter and resets the counters, and
+/// `\setcounter{name}{n}` sets the chapter or an environment counter. Once
+/// there is a chapter, numbers are prefixed with it (`3.2`). The counters
+/// carry over from `content.tex` into the files it inputs, in the order they
+/// are read; a file that is not read from `content.tex` is numbered on its own.
+/// The numbers of each file follow its `Env` events.
+fn number_files<'a>(files: &[(&'a str, &'a [NumberingEvent])]) -> HashMap<&'a str, Vec<String>> {
+    let events: HashMap<&str, &[NumberingEvent]> = files.iter().copied().collect();
+    let mut numbers = HashMap::new();
+    if let Some((&root, _)) = events.get_key_value("content.tex") {
+        number_file(root, &events, &mut Counters::default(), &mut numbers);
+    }
+    for (path, _) in files {
+        number_file(path, &events, &mut Counters::default(), &mut numbers);
+    }
+    numbers
+}
+
+/// Sectioning commands tracked by `section_paths`, outermost first
+const HEADING_LEVELS: &[&str] = &["chapter", "section", "subsection", "subsubsection"];
+
+/// Title of the heading command `token`: its `{...}` argument, after an
+/// optional `*` and `[short title]`, with whitespace collapsed
+fn heading_title(content: &str, token: &Token, args: &[Range<usize>]) -> Option<String> {
+    let title = match args.first() {
+        Some(arg) => &content[arg.clone()],
+        None => {
+            let rest = content[token.end..]
+                .strip_prefix('*')
+                .unwrap_or(&content[token.end..]);
+            let mut rest = rest.trim_start();
+            if rest.starts_with('[') {
+                rest = rest[rest.find(']')? + 1..].trim_start();
+            }
+            if !rest.starts_with('{') {
+                return None;
+            }
+            let end = braced_group_end(rest.as_bytes(), 0)?;
+            &rest[1..end - 1]
+        }
+    };
+    Some(title.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Headings enclosing each stub environment, within one file
+///
+/// A stack of the current chapter, section, subsection and subsubsection
+/// titles is kept while scanning; a heading replaces the one at its level and
+/// closes the deeper ones.
+fn section_paths(content: &str, tokens: &[Token], stub_envs: &[&Environment]) -> Vec<Vec<String>> {
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut next_token = 0;
+    let mut paths = Vec::with_capacity(stub_envs.len());
+
+    for env in stub_envs {
+        for token in &tokens[next_token..env.begin_token] {
+            let TokenKind::Command { name, args } = &token.kind else {
+                continue;
+            };
+            let Some(level) = HEADING_LEVELS.iter().position(|heading| heading == name) else {
+                continue;
+            };
+            if let Some(title) = heading_title(content, token, args) {
+                while stack.last().is_some_and(|(open, _)| *open >= level) {
+                    stack.pop();
+                }
+                stack.push((level, title));
+            }
+        }
+        next_token = env.begin_token;
+
+        paths.push(stack.iter().map(|(_, title)| title.clone()).collect());
+    }
+
+    paths
+}
+
+/// Dependencies from `\uses{...}` at sectioning level, outside any
+/// environment but `document`, for each stub environment within one file
+///
+/// Such a `\uses` belongs to the most recent heading and applies to every stub
+/// under it, before or after it and in its subsections; one before the first
+/// heading applies to nothing.
+fn section_uses(
+    content: &str,
+    relative_path: &str,
+    tokens: &[Token],
+    all_envs: &[Environment],
+    stub_envs: &[&Environment],
+    macros: &Macros,
+) -> Vec<Vec<Dependency>> {
+    let enclosing: Vec<&Range<usize>> = all_envs
+        .iter()
+        .filter(|env| env.name != "document")
+        .map(|env| &env.outer)
+        .collect();
+    // Dependencies of each heading, and the headings open at each stub
+    let mut sections: Vec<Vec<Dependency>> = Vec::new();
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    let mut open_sections: Vec<Vec<usize>> = Vec::with_capacity(stub_envs.len());
+
+    for (index, token) in tokens.iter().enumerate() {
+        if stub_envs
+            .get(open_sections.len())
+            .is_some_and(|env| env.begin_token == index)
+        {
+            open_sections.push(stack.iter().map(|(_, section)| *section).collect());
+        }
+        let TokenKind::Command { name, args } = &token.kind else {
+            continue;
+        };
+        if let Some(level) = HEADING_LEVELS.iter().position(|heading| heading == name) {
+            if heading_title(content, token, args).is_some() {
+                while stack.last().is_some_and(|(open, _)| *open >= level) {
+                    stack.pop();
+                }
+                stack.push((level, sections.len()));
+                sections.push(Vec::new());
+            }
+        } else if macros.roles.get(*name) == Some(&"uses")
+            && !enclosing.iter().any(|outer| outer.contains(&token.start))
+        {
+            if let Some((_, section)) = stack.last() {
+                let text = strip_latex_comments(&content[token.start..token.end]);
+                sections[*section].extend(extract_uses(
+                    &text,
+                    relative_path,
+                    byte_pos_to_line(content, token.start),
+                    macros,
+                ));
+            }
+        }
+    }
+
+    open_sections
+        .into_iter()
+        .map(|open| {
+            let mut dependencies: Vec<Dependency> = Vec::new();
+            for dependency in open.into_iter().flat_map(|section| &sections[section]) {
+                if !dependencies.iter().any(|d| d.label == dependency.label) {
+                    dependencies.push(dependency.clone());
+                }
+            }
+            dependencies
+        })
+        .collect()
+}
+
+/// Parse a single .tex file and extract environments, recording stage timings
+/// and what was found and why in `trace`
+///
+/// Also returns the line ranges of the environments dropped by a skip marker,
+/// see `is_skipped`.
+fn parse_tex_file_profiled(
+    content: &str,
+    relative_path: &str,
+    syntax: &FileSyntax,
+    profile: &mut Profile,
+    trace: &mut Trace,
+) -> FileEnvs {
+    let FileSyntax {
+        env_types,
+        skip_marker,
+        macros,
+        ..
+    } = *syntax;
+    let mut envs = Vec::new();
+    let mut skipped = Vec::new();
+
+    // Comments and verbatim content are opaque to the tokenizer, and
+    // environments are matched by name with proper nesting
+    let start = profile.start();
+    let tokens = tokenize(content);
+    profile.record("tokenizing", start);
+
+    // All configured environments are found in the same pass, also those
+    // nested inside another one (e.g. a definition inside a theorem); each
+    // becomes a stub with the enclosing one as its parent.
+    let start = profile.start();
+    trace.line(0, || relative_path.to_string());
+    let describe = |env: &Environment| {
+        format!(
+            "{} at bytes {}..{}, lines {}-{}",
+            env.name,
+            env.outer.start,
+            env.outer.end,
+            byte_pos_to_line(content, env.outer.start),
+            byte_pos_to_line(content, env.outer.end - 1)
+        )
+    };
+    let all_envs = environments(&tokens);
+    let mut unconfigured: BTreeMap<String, usize> = BTreeMap::new();
+    for env in &all_envs {
+        if !env_types.iter().any(|t| t == env.name) && !NON_STATEMENT_ENVS.contains(&env.name) {
+            *unconfigured.entry(env.name.to_string()).or_default() += 1;
+            trace.line(1, || format!("{}: not a configured type", describe(env)));
+        }
+    }
+    let stub_envs: Vec<&Environment> = all_envs
+        .iter()
+        .filter(|env| env_types.iter().any(|t| t == env.name))
+        .collect();
+    // The proof search of each environment is traced with the environment
+    let (proofs, mut proof_traces): (Vec<Option<&Environment>>, Vec<Trace>) = stub_envs
+        .iter()
+        .map(|env| {
+            let mut proof_trace = trace.child();
+            let proof = find_following_proof(
+                content,
+                relative_path,
+                &tokens,
+                &all_envs,
+                env.end_token,
+                syntax,
+                &mut proof_trace,
+            );
+            (proof, proof_trace)
+        })
+        .unzip();
+    profile.record("environment matching", start);
+
+    let start = profile.start();
+    let numbering = numbering_events(content, &tokens, &stub_envs);
+    let section_paths = section_paths(content, &tokens, &stub_envs);
+    let section_uses = section_uses(
+        content,
+        relative_path,
+        &tokens,
+        &all_envs,
+        &stub_envs,
+        macros,
+    );
+    // Enclosing environments kept so far, as their end and index in `envs`
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    // End of the last skipped environment, whose nested ones are skipped too
+    let mut skipped_until = 0;
+    for (index, ((env_match, section_path), section_dependencies)) in stub_envs
+        .iter()
+        .zip(section_paths)
+        .zip(section_uses)
+        .enumerate()
+    {
+        // Calculate line numbers for the spec environment
+        let spec_lines = LineRange {
+            lines_start: byte_pos_to_line(content, env_match.outer.start),
+            lines_end: byte_pos_to_line(content, env_match.outer.end - 1),
+        };
+        trace.line(1, || describe(env_match));
+        if env_match.outer.start < skipped_until {
+            trace.line(2, || "skipped: inside a skipped environment".to_string());
+            continue;
+        }
+        while open
+            .last()
+            .is_some_and(|(end, _)| *end <= env_match.outer.start)
+        {
+            open.pop();
+        }
+        if let Some((_, parent)) = open.last() {
+            let parent: &ParsedEnv = &envs[*parent];
+            trace.line(2, || {
+                format!(
+                    "nested in {} at lines {}-{}",
+                    parent.env_type, parent.spec_lines.lines_start, parent.spec_lines.lines_end
+                )
+            });
+        }
+
+        // Nested statements and their proofs are left to their own stubs
+        let nested: Vec<Range<usize>> = stub_envs
+            .iter()
+            .zip(&proofs)
+            .skip(index + 1)
+            .take_while(|(env, _)| env.outer.start < env_match.outer.end)
+            .flat_map(|(env, proof)| [Some(*env), *proof])
+            .flatten()
+            .filter(|env| env.outer.end <= env_match.body.end)
+            .map(|env| env.outer.clone())
+            .collect();
+
+        // Numbered by LaTeX all the same, so only dropped here
+        if is_skipped(content, &tokens, env_match, &nested, skip_marker) {
+            trace.line(2, || "skipped: skip marker".to_string());
+            skipped.push(spec_lines);
+            skipped_until = env_match.outer.end;
+            continue;
+        }
+        trace.merge(std::mem::take(&mut proof_traces[index]));
+
+        // Strip LaTeX comments from the body (preserves line structure)
+        let body_start = env_match.body.start;
+        let env_content = &strip_latex_comments(&remove_spans(
+            &content[env_match.body.clone()],
+            nested
+                .iter()
+                .map(|span| span.start - body_start..span.end - body_start)
+                .collect(),
+        ));
+
+        // Extract the optional [...] and required {...} arguments of \begin{...}
+        let env_options = extract_env_options(env_content);
+        let env_args = extract_env_args(env_content);
+
+        // Extract all \label{...} in order from the statement
+        let mut labels: Vec<(String, usize)> = extract_all_labels_lines(env_content)
+            .into_iter()
+            .map(|(label, line)| (label, spec_lines.lines_start + line))
+            .collect();
+
+        // Extract \lean{...} - returns list of declarations with "probe:" prefix
+        let lean_names_list = extract_lean(env_content, macros);
+        let code_name = lean_names_list
+            .first()
+            .map(|name| format!("probe:{}", name));
+        let lean_names = if lean_names_list.len() > 1 {
+            Some(
+                lean_names_list
+                    .iter()
+                    .map(|name| format!("probe:{}", name))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        // Check for \leanok
+        let spec_ok = extract_leanok(env_content, macros);
+
+        // Check for \mathlibok
+        let mathlib_ok = extract_mathlibok(env_content, macros);
+
+        // Check for \notready
+        let not_ready = extract_notready(env_content, macros);
+
+        // Extract \discussion{...}
+        let discussion = extract_discussion(env_content);
+
+        // Extract \uses{...}
+        let spec_dependencies =
+            extract_uses(env_content, relative_path, spec_lines.lines_start, macros);
+
+        let statement_macros = macro_lines(
+            env_content,
+            byte_pos_to_line(content, env_match.body.start),
+            macros,
+        );
+
+        // Look for a following proof environment
+        let (
+            proof_lines,
+            proof_ok,
+            proof_mathlib_ok,
+            proof_not_ready,
+            proof_discussion,
+            proof_dependencies,
+            proof_lean_names,
+            proof_macros,
+        ) = if let Some(proof_match) =
+            proofs[index].map(|proof| proof_match(content, relative_path, proof, macros))
+        {
+            // Skip proofs that use \proves (they will be handled separately)
+            if !proof_match.proves_labels.is_empty() {
+                trace.line(2, || {
+                    format!(
+                        "proof has \\proves{{{}}}, left to the standalone proofs",
+                        proof_match.proves_labels.join(", ")
+                    )
+                });
+                (None, None, None, None, None, None, None, BTreeMap::new())
+            } else {
+                // Add proof labels to the labels list
+                let proof_labels = extract_all_labels_lines(&proof_match.content);
+                labels.extend(
+                    proof_labels
+                        .into_iter()
+                        .map(|(label, line)| (label, proof_match.lines.lines_start + line)),
+                );
+
+                // Check for \leanok in proof
+                let p_ok = if extract_leanok(&proof_match.content, macros) {
+                    Some(true)
+                } else {
+                    None
+                };
+
+                // Check for \mathlibok in proof
+                let p_mathlib = if extract_mathlibok(&proof_match.content, macros) {
+                    Some(true)
+                } else {
+                    None
+                };
+
+                // Check for \notready in proof
+                let p_not_ready = if extract_notready(&proof_match.content, macros) {
+                    Some(true)
+                } else {
+                    None
+                };
+
+                // Extract \discussion{...} from proof
+                let p_discussion = extract_discussion(&proof_match.content);
+                let p_discussion = if p_discussion.is_empty() {
+                    None
+                } else {
+                    Some(p_discussion)
+                };
+
+                // Extract \uses{...} from proof
+                let p_deps = extract_uses(
+                    &proof_match.content,
+                    relative_path,
+                    proof_match.lines.lines_start,
+                    macros,
+                );
+                let p_deps = if p_deps.is_empty() {
+                    None
+                } else {
+                    Some(p_deps)
+                };
+
+                // Extract \lean{...} from proof
+                let p_lean = extract_lean(&proof_match.content, macros);
+                let p_lean = if p_lean.is_empty() {
+                    None
+                } else {
+                    Some(p_lean)
+                };
+
+                (
+                    Some(proof_match.lines),
+                    p_ok,
+                    p_mathlib,
+                    p_not_ready,
+                    p_discussion,
+                    p_deps,
+                    p_lean,
+                    proof_match.macros,
+                )
+            }
+        } else {
+            (None, None, None, None, None, None, None, BTreeMap::new())
+        };
+
+        trace.line(2, || match labels.as_slice() {
+            [] => "labels: none, one is generated".to_string(),
+            labels => format!("labels: {}", describe_lines(labels)),
+        });
+        trace.line(2, || {
+            let lean: Vec<&str> = lean_names_list.iter().map(String::as_str).collect();
+            format!(
+                "statement: {}",
+                describe_macros(
+                    &lean,
+                    spec_ok,
+                    mathlib_ok,
+                    not_ready,
+                    &discussion,
+                    &spec_dependencies
+                )
+            )
+        });
+        if !section_dependencies.is_empty() {
+            trace.line(2, || {
+                let uses = section_dependencies
+                    .iter()
+                    .map(|d| (d.label.clone(), d.line))
+                    .collect::<Vec<_>>();
+                format!("section \\uses: {}", describe_lines(&uses))
+            });
+        }
+        if proof_lines.is_some() {
+            trace.line(2, || {
+                let lean: Vec<&str> = proof_lean_names
+                    .iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect();
+                format!(
+                    "proof: {}",
+                    describe_macros(
+                        &lean,
+                        proof_ok.is_some(),
+                        proof_mathlib_ok.is_some(),
+                        proof_not_ready.is_some(),
+                        proof_discussion.as_deref().unwrap_or_default(),
+                        proof_dependencies.as_deref().unwrap_or_default()
+                    )
+                )
+            });
+        }
+
+        envs.push(ParsedEnv {
+            env_type: env_match.name.to_string(),
+            relative_path: relative_path.to_string(),
+            spec_lines,
+            stable_id: stable_id(content, &env_match.outer),
+            proof_lines,
+            env_options,
+            env_args,
+            number_index: index,
+            number: String::new(),
+            section_path,
+            parent: open.last().map(|(_, parent)| *parent),
+            labels: dedup_labels(labels, relative_path),
+            code_name,
+            lean_names,
+            spec_ok,
+            mathlib_ok,
+            not_ready,
+            discussion,
+            spec_dependencies,
+            section_dependencies,
+            proof_ok,
+            proof_mathlib_ok,
+            proof_not_ready,
+            proof_discussion,
+            proof_dependencies,
+            proof_lean_names,
+            statement_macros,
+            proof_macros,
+        });
+        open.push((env_match.outer.end, envs.len() - 1));
+    }
+    profile.record("label extraction", start);
+
+    FileEnvs {
+        envs,
+        skipped,
+        unconfigured,
+        numbering,
+    }
+}
+
+/// Environments found in a file by `parse_tex_file_profiled`
+struct FileEnvs {
+    envs: Vec<ParsedEnv>,
+    /// Environments dropped by a skip marker
+    skipped: Vec<LineRange>,
+    /// Number of environments of each type that is neither configured nor
+    /// in `NON_STATEMENT_ENVS`
+    unconfigured: BTreeMap<String, usize>,
+    /// What the numbers of the environments depend on, see `number_files`
+    numbering: Vec<NumberingEvent>,
+}
+
+/// Everything extracted from a single content file
+struct ParsedFile {
+    envs: FileEnvs,
+    standalone_proofs: Vec<StandaloneProof>,
+    config: Config,
+}
+
+/// Parse one content file: environments, standalone proofs and config macros
+fn parse_file(
+    content: &str,
+    relative_path: &str,
+    syntax: &FileSyntax,
+    profile: &mut Profile,
+    trace: &mut Trace,
+) -> ParsedFile {
+    let config = extract_config(content);
+    let envs = parse_tex_file_profiled(content, relative_path, syntax, profile, trace);
+
+    // Find standalone proofs with \proves
+    let start = profile.start();
+    let standalone_proofs = find_standalone_proofs(content, relative_path, syntax.macros);
+    profile.record("standalone proofs", start);
+
+    ParsedFile {
+        envs,
+        standalone_proofs,
+        config,
+    }
+}
+
+/// Normalize a relative source path to use `/` as separator, so that
+/// stub paths are the same on every platform
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Stubs and project config parsed from a blueprint
+#[derive(Debug, Serialize)]
+pub struct ParsedBlueprint {
+    pub stubs: HashMap<String, Stub>,
+    pub config: Config,
+    /// Every label, including non-canonical aliases, mapped to its stub-name
+    #[serde(skip)]
+    pub labels: HashMap<String, String>,
+    /// Number of environments of each type that was not looked for (and is
+    /// not known to hold no statements, or in `ParseOptions::ignored_envs`)
+    #[serde(skip)]
+    pub unconfigured_envs: BTreeMap<String, usize>,
+}
+
+/// Options that change how parsed environments become stubs
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    /// Lean declaration names by label, applied as `code-name` (e.g. from
+    /// `stubify --lean-names-file`)
+    pub lean_names: HashMap<String, String>,
+    /// Keep the `\lean{...}` name from the source when a label is also in
+    /// `lean_names`
+    pub prefer_source: bool,
+    /// Lean declaration names by label from annotations in the Lean sources
+    /// (e.g. `stubify --lean-annotations`); only fill in environments without
+    /// `\lean{...}`
+    pub lean_annotations: HashMap<String, Vec<String>>,
+    /// Record the number LaTeX gives each environment as `theorem-number`
+    pub with_numbering: bool,
+    /// File names to skip besides `web.tex` and `print.tex` (e.g. from
+    /// `skip-files` in `.verilib/config.json`)
+    pub skip_files: Vec<String>,
+    /// Warn instead of failing when two standalone proofs `\proves` the
+    /// same label
+    pub warn_duplicate_proofs: bool,
+    /// Take several standalone proofs of the same label as parts of one
+    /// proof, without an error or warning
+    pub proof_parts: bool,
+    /// Environment types that may come between a statement and its proof
+    /// (e.g. `remark`); by default only whitespace and comments may
+    pub proof_after_envs: Vec<String>,
+    /// Bytes of whitespace allowed between a statement and its proof,
+    /// `DEFAULT_PROOF_LOOKAHEAD_BYTES` if unset
+    pub proof_lookahead: Option<usize>,
+    /// Record the lines of the status and dependency macros as `macro-positions`
+    pub macro_positions: bool,
+    /// Ignore `\uses{...}` at sectioning level, see `section_uses`
+    pub no_section_uses: bool,
+    /// Name of the macro that drops its environment, `DEFAULT_SKIP_MARKER` if unset
+    pub skip_marker: Option<String>,
+    /// Names of the blueprint macros (e.g. from `macros` in `.verilib/config.json`)
+    pub macros: MacroNames,
+    /// Environment types not to report as unconfigured (e.g. from
+    /// `ignored-envs` in `.verilib/config.json`)
+    pub ignored_envs: Vec<String>,
+    /// Labels of stubs outside the parsed files mapped to their stub-names
+    /// (e.g. from the stubs.json patched by `stubify --only`); dependencies
+    /// may name them, and the parsed files may not define them again
+    pub known_labels: HashMap<String, String>,
+    /// Leave `can-state` and `can-prove` unset, for callers that derive
+    /// readiness themselves once the stubs are merged with the rest of the
+    /// blueprint (e.g. `stubify --only`)
+    pub skip_readiness: bool,
+    /// Print every environment match, proof association, macro extraction
+    /// and label resolution to stderr (e.g. from `stubify --trace-parse`)
+    pub trace: bool,
+}
+
+/// Apply external Lean names to environments by label
+///
+/// A name from `options.lean_names` replaces the environment's `\lean{...}`
+/// names unless `prefer_source` is set and the source already has one.
+fn apply_lean_names(envs: &mut [ParsedEnv], options: &ParseOptions) {
+    if options.lean_names.is_empty() {
+        return;
+    }
+
+    let mut matched: HashSet<&str> = HashSet::new();
+    for env in envs.iter_mut() {
+        let Some((label, name)) = env
+            .labels
+            .iter()
+            .find_map(|label| options.lean_names.get_key_value(label))
+        else {
+            continue;
+        };
+        matched.insert(label);
+
+        if options.prefer_source && env.code_name.is_some() {
+            continue;
+        }
+        env.code_name = Some(format!("probe:{}", name));
+        env.lean_names = None;
+    }
+
+    let mut unmatched: Vec<&String> = options
+        .lean_names
+        .keys()
+        .filter(|label| !matched.contains(label.as_str()))
+        .collect();
+    unmatched.sort();
+    for label in unmatched {
+        messages::warning(
+            None,
+            &format!("label '{}' from Lean names matches no environment", label),
+        );
+    }
+}
+
+/// Fill in code-names from Lean-side annotations by label
+///
+/// Environments without `\lean{...}` take the annotated declarations (several
+/// declarations act like `\lean{a, b}`). Environments whose `\lean{...}`
+/// disagrees with the annotations keep their LaTeX names and are reported, as
+/// are annotated labels that match no environment.
+fn apply_lean_annotations(envs: &mut [ParsedEnv], annotations: &HashMap<String, Vec<String>>) {
+    if annotations.is_empty() {
+        return;
+    }
+
+    let mut matched: HashSet<&str> = HashSet::new();
+    for env in envs.iter_mut() {
+        let Some((label, names)) = env
+            .labels
+            .iter()
+            .find_map(|label| annotations.get_key_value(label))
+        else {
+            continue;
+        };
+        matched.insert(label);
+        let annotated: Vec<String> = names.iter().map(|name| format!("probe:{}", name)).collect();
+
+        match &env.code_name {
+            None => {
+                env.code_name = annotated.first().cloned();
+                env.lean_names = (annotated.len() > 1).then_some(annotated);
+            }
+            Some(code_name) => {
+                let source = env
+                    .lean_names
+                    .clone()
+                    .unwrap_or_else(|| vec![code_name.clone()]);
+                let source_set: HashSet<&String> = source.iter().collect();
+                let annotated_set: HashSet<&String> = annotated.iter().collect();
+                if source_set != annotated_set {
+                    messages::warning(
+                        Some(&Location::blueprint(&env.relative_path, Some(&env.spec_lines))),
+                        &format!(
+                            "label '{}' has \\lean{{{}}} in the blueprint but is annotated on {} in Lean",
+                            label,
+                            source
+                                .iter()
+                                .map(|name| name.trim_start_matches("probe:"))
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                            names.join(", ")
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut unmatched: Vec<(&String, &Vec<String>)> = annotations
+        .iter()
+        .filter(|(label, _)| !matched.contains(label.as_str()))
+        .collect();
+    unmatched.sort();
+    for (label, names) in unmatched {
+        messages::warning(
+            None,
+            &format!(
+                "Lean annotation for unknown label '{}' on {}",
+                label,
+                names.join(", ")
+            ),
+        );
+    }
+}
+
+/// Dependencies of a stub, each with the file it is written in (standalone
+/// proofs may be in other files than the statement)
+struct StubDependencies {
+    spec: Vec<(String, Dependency)>,
+    proof: Vec<(String, Dependency)>,
+}
+
+/// `dependencies` written in `relative_path`
+fn with_path(relative_path: &str, dependencies: Vec<Dependency>) -> Vec<(String, Dependency)> {
+    dependencies
+        .into_iter()
+        .map(|dependency| (relative_path.to_string(), dependency))
+        .collect()
+}
+
+/// Append the `entries` not in `list` yet, creating it if needed
+fn merge_unique(list: &mut Option<Vec<String>>, entries: &[String]) {
+    if entries.is_empty() {
+        return;
+    }
+    let list = list.get_or_insert_with(Vec::new);
+    for entry in entries {
+        if !list.contains(entry) {
+            list.push(entry.clone());
+        }
+    }
+}
+
+/// Stub-names of the `dependencies` in `field` of `stub_name`
+///
+/// An unknown label is an error; a dependency listed twice (also under two
+/// labels of the same stub) is kept, with a warning at its line.
+fn resolve_dependencies(
+    dependencies: &[(String, Dependency)],
+    field: &'static str,
+    stub_name: &str,
+    label_to_stub_name: &HashMap<String, String>,
+    trace: &mut Trace,
+) -> Result<Vec<String>, ProbeError> {
+    let mut first_lines: HashMap<&str, usize> = HashMap::new();
+    let mut resolved = Vec::with_capacity(dependencies.len());
+    for (relative_path, dependency) in dependencies {
+        trace.line(1, || {
+            format!(
+                "{} {} {} at {}:{} -> {}",
+                stub_name,
+                field,
+                dependency.label,
+                relative_path,
+                dependency.line,
+                label_to_stub_name
+                    .get(&dependency.label)
+                    .map_or("unknown label", String::as_str)
+            )
+        });
+        let Some(dep_stub_name) = label_to_stub_name.get(&dependency.label) else {
+            return Err(ProbeError::UnknownLabel {
+                label: dependency.label.clone(),
+                field,
+                stub: stub_name.to_string(),
+                path: relative_path.to_string(),
+                line: dependency.line,
+            });
+        };
+        if let Some(&first_line) = first_lines.get(dep_stub_name.as_str()) {
+            let line = LineRange {
+                lines_start: dependency.line,
+                lines_end: dependency.line,
+            };
+            messages::warning(
+                Some(&Location::blueprint(relative_path, Some(&line))),
+                &format!(
+                    "'{}' in {} of stub '{}' repeats the dependency on '{}' from line {}",
+                    dependency.label, field, stub_name, dep_stub_name, first_line
+                ),
+            );
+        } else {
+            first_lines.insert(dep_stub_name, dependency.line);
+        }
+        resolved.push(dep_stub_name.clone());
+    }
+    Ok(resolved)
+}
+
+/// Proof macros the statement's macros contradict, by name: `leanok` on the
+/// proof of a statement that is neither `\leanok` nor `\mathlibok` (a proof
+/// cannot be formalized before its statement), and `mathlibok` on the proof
+/// of a statement without `\mathlibok`
+pub fn inconsistent_proof_macros(
+    spec_ok: bool,
+    mathlib_ok: bool,
+    proof_ok: bool,
+    proof_mathlib_ok: bool,
+) -> Vec<&'static str> {
+    let mut inconsistent = Vec::new();
+    if proof_ok && !spec_ok && !mathlib_ok {
+        inconsistent.push("leanok");
+    }
+    if proof_mathlib_ok && !mathlib_ok {
+        inconsistent.push("mathlibok");
+    }
+    inconsistent
+}
+
+/// Statement formalized, in Mathlib or not
+fn is_stated(stub: &Stub) -> bool {
+    stub.spec_ok == Some(true) || stub.mathlib_ok == Some(true)
+}
+
+/// Nothing left to prove for the stub itself; a stub without a proof is
+/// proved once stated
+fn is_proved(stub: &Stub) -> bool {
+    stub.mathlib_ok == Some(true)
+        || match stub.stub_proof {
+            Some(_) => stub.proof_ok == Some(true),
+            None => stub.spec_ok == Some(true),
+        }
+}
+
+/// Dependencies that must be proved before the stub can be: the proof
+/// dependencies, or the spec-dependencies when there are none
+fn proof_prerequisites(stub: &Stub) -> &[String] {
+    match stub.proof_dependencies.as_deref() {
+        Some(dependencies) if !dependencies.is_empty() => dependencies,
+        _ => &stub.spec_dependencies,
+    }
+}
+
+/// Stub-names on a dependency cycle, over spec-dependencies and proof
+/// prerequisites
+///
+/// These are the strongly connected components with more than one stub (or
+/// a stub depending on itself), found with Tarjan's algorithm, iteratively
+/// so that long dependency chains cannot overflow the stack.
+fn cyclic_stubs(stubs: &HashMap<String, Stub>) -> BTreeSet<&str> {
+    let mut names: Vec<&str> = stubs.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    let position: HashMap<&str, usize> = names.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+    let edges: Vec<Vec<usize>> = names
+        .iter()
+        .map(|name| {
+            let stub = &stubs[*name];
+            stub.spec_dependencies
+                .iter()
+                .chain(proof_prerequisites(stub))
+                .filter_map(|dependency| position.get(dependency.as_str()).copied())
+                .collect()
+        })
+        .collect();
+
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; names.len()];
+    let mut low = vec![0; names.len()];
+    let mut on_stack = vec![false; names.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut next_index = 0;
+    let mut cyclic = BTreeSet::new();
+
+    for root in 0..names.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // Stubs being visited, with the position of the next edge to follow
+        let mut visiting: Vec<(usize, usize)> = Vec::new();
+        let mut pending = Some(root);
+
+        loop {
+            if let Some(node) = pending.take() {
+                index[node] = next_index;
+                low[node] = next_index;
+                next_index += 1;
+                stack.push(node);
+                on_stack[node] = true;
+                visiting.push((node, 0));
+            }
+            let Some(&(node, edge)) = visiting.last() else {
+                break;
+            };
+            if let Some(&dependency) = edges[node].get(edge) {
+                visiting.last_mut().unwrap().1 += 1;
+                if index[dependency] == UNVISITED {
+                    pending = Some(dependency);
+                } else if on_stack[dependency] {
+                    low[node] = low[node].min(index[dependency]);
+                }
+                continue;
+            }
+
+            visiting.pop();
+            if let Some(&(parent, _)) = visiting.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+            if low[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                if component.len() > 1 || edges[node].contains(&node) {
+                    cyclic.extend(component.into_iter().map(|member| names[member]));
+                }
+            }
+        }
+    }
+    cyclic
+}
+
+/// Set `can-state` and `can-prove` of every stub, as in LeanBlueprint's
+/// dependency graph
+///
+/// A stub can be stated when every spec-dependency is formalized (`\leanok`
+/// or `\mathlibok`), and proved when it is formalized itself and every proof
+/// dependency (or spec-dependency, without proof dependencies) is proved.
+/// Stubs on a dependency cycle get neither, with a warning.
+pub fn derive_readiness(stubs: &mut HashMap<String, Stub>) {
+    let cyclic: BTreeSet<String> = cyclic_stubs(stubs)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    for stub_name in &cyclic {
+        let stub = &stubs[stub_name];
+        let location = stub
+            .stub_path
+            .as_deref()
+            .map(|path| Location::blueprint(path, stub.stub_spec.as_ref()));
+        messages::warning(
+            location.as_ref(),
+            &format!(
+                "stub '{}' is on a dependency cycle, so it can neither be stated nor proved",
+                stub_name
+            ),
+        );
+    }
+
+    let readiness: Vec<(String, bool, bool)> = stubs
+        .iter()
+        .map(|(stub_name, stub)| {
+            if cyclic.contains(stub_name) {
+                return (stub_name.clone(), false, false);
+            }
+            let all = |dependencies: &[String], done: fn(&Stub) -> bool| {
+                dependencies
+                    .iter()
+                    .all(|dependency| stubs.get(dependency).is_some_and(done))
+            };
+            let can_state = all(&stub.spec_dependencies, is_stated);
+            let can_prove = is_stated(stub) && all(proof_prerequisites(stub), is_proved);
+            (stub_name.clone(), can_state, can_prove)
+        })
+        .collect();
+    for (stub_name, can_state, can_prove) in readiness {
+        let stub = stubs.get_mut(&stub_name).unwrap();
+        stub.can_state = can_state;
+        stub.can_prove = can_prove;
+    }
+}
+
+/// Make the `stable-id` of every stub unique
+///
+/// Statements spanning the same source lines, e.g. several unlabeled ones
+/// with the same text, hash to the same ID. Each of them gets the shared ID
+/// followed by `-` and a hash of its stub-path and its position among them
+/// in that file instead, with a warning. Split children follow their parent.
+/// Running this again on stubs it produced, with some stubs replaced (as
+/// `stubify --only` does), gives the IDs a single run over all of them would.
+pub fn disambiguate_stable_ids(stubs: &mut HashMap<String, Stub>) {
+    // Statements (not split children) by the hash of their source lines
+    let mut by_text: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (stub_name, stub) in stubs.iter() {
+        if stub.stub_path.is_some() {
+            let (text_id, _) = stub
+                .stable_id
+                .split_once('-')
+                .unwrap_or((&stub.stable_id, ""));
+            by_text
+                .entry(text_id.to_string())
+                .or_default()
+                .push(stub_name);
+        }
+    }
+
+    // (stub-name, new stable-id)
+    let mut updates: Vec<(String, String)> = Vec::new();
+    for (text_id, mut stub_names) in by_text {
+        if stub_names.len() < 2 {
+            updates.push((stub_names[0].to_string(), text_id));
+            continue;
+        }
+        let position = |stub_name: &str| {
+            let stub = &stubs[stub_name];
+            let start = stub.stub_spec.as_ref().map(|lines| lines.lines_start);
+            (stub.stub_path.clone(), start, stub_name.to_string())
+        };
+        stub_names.sort_by_cached_key(|stub_name| position(stub_name));
+
+        let first = &stubs[stub_names[0]];
+        let location = first
+            .stub_path
+            .as_deref()
+            .map(|path| Location::blueprint(path, first.stub_spec.as_ref()));
+        messages::warning(
+            location.as_ref(),
+            &format!(
+                "stable-id {} is shared by {} statements with the same source lines ({}); \
+                 each gets an ID from its file and position as well",
+                text_id,
+                stub_names.len(),
+                stub_names.join(", ")
+            ),
+        );
+
+        let mut ordinals: HashMap<&str, usize> = HashMap::new();
+        for stub_name in &stub_names {
+            let path = stubs[*stub_name].stub_path.as_deref().unwrap_or_default();
+            let ordinal = ordinals.entry(path).or_default();
+            *ordinal += 1;
+            let place = short_hash(format!("{}\n{}", path, ordinal).as_bytes());
+            updates.push((stub_name.to_string(), format!("{}-{}", text_id, place)));
+        }
+    }
+
+    for (stub_name, id) in updates {
+        let stub = stubs.get_mut(&stub_name).unwrap();
+        if stub.stable_id == id {
+            continue;
+        }
+        let old = std::mem::replace(&mut stub.stable_id, id.clone());
+        // Split children are named and identified `<parent>_<n>`
+        for i in 1.. {
+            let Some(child) = stubs.get_mut(&format!("{}_{}", stub_name, i)) else {
+                break;
+            };
+            if child.stable_id == format!("{}_{}", old, i) {
+                child.stable_id = format!("{}_{}", id, i);
+            }
+        }
+    }
+}
+
+/// Parse blueprint sources into stubs
+///
+/// `files` holds `(relative_path, content)` pairs for the `.tex` files under
+/// `blueprint/src`, in the order they should be processed. A top-level
+/// `web.tex` selects the environment types; `web.tex` and `print.tex` (and
+/// the files named in `ParseOptions::skip_files`) are not scanned for
+/// environments.
+pub fn parse_sources(files: &[(String, String)]) -> Result<ParsedBlueprint, ProbeError> {
+    parse_sources_with(files, &ParseOptions::default(), &mut Profile::default())
+}
+
+/// The environment types that become stubs: those of the `thms` option in
+/// web.tex, or the defaults without a web.tex
+pub fn env_types<P: AsRef<str>, C: AsRef<str>>(files: &[(P, C)]) -> Vec<String> {
+    match files.iter().find(|(path, _)| path.as_ref() == "web.tex") {
+        Some((_, content)) => parse_thms_option(content.as_ref()),
+        None => DEFAULT_ENVS.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+/// Parse blueprint sources into stubs with `options`, recording stage timings
+/// in `profile`
+pub fn parse_sources_with(
+    files: &[(String, String)],
+    options: &ParseOptions,
+    profile: &mut Profile,
+) -> Result<ParsedBlueprint, ProbeError> {
+    // Stub paths use `/` whatever platform the sources were read on
+    let files: Vec<(String, &str)> = files
+        .iter()
+        .map(|(path, content)| (normalize_path(path), content.as_str()))
+        .collect();
+
+    // Parse web.tex for environment types and config
+    let env_types = env_types(&files);
+    let mut project_config = files
+        .iter()
+        .find(|(path, _)| path == "web.tex")
+        .map(|(_, web_tex_content)| extract_config(web_tex_content))
+        .unwrap_or_default();
+
+    // Skip web.tex and print.tex (they're not content files) and the
+    // project's own exclusions
+    let content_files: Vec<&(String, &str)> = files
+        .iter()
+        .filter(|(relative_path, _)| {
+            let file_name = Path::new(relative_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            !SKIPPED_FILES.contains(&file_name)
+                && !options.skip_files.iter().any(|skip| skip == file_name)
+        })
+        .collect();
+
+    let macros = Macros::new(&options.macros);
+
+    // Parse files independently (in parallel when available); results keep
+    // the input order so the sequential phases below are deterministic
+    let syntax = FileSyntax {
+        env_types: &env_types,
+        proof_after_envs: &options.proof_after_envs,
+        proof_lookahead: options
+            .proof_lookahead
+            .unwrap_or(DEFAULT_PROOF_LOOKAHEAD_BYTES),
+        skip_marker: options
+            .skip_marker
+            .as_deref()
+            .unwrap_or(DEFAULT_SKIP_MARKER),
+        macros: &macros,
+    };
+    let mut trace = Trace::new(options.trace);
+    let parse_one = |(relative_path, content): &&(String, &str)| {
+        let mut file_profile = profile.child();
+        let mut file_trace = trace.child();
+        let parsed = parse_file(
+            content,
+            relative_path,
+            &syntax,
+            &mut file_profile,
+            &mut file_trace,
+        );
+        (parsed, file_profile, file_trace)
+    };
+    #[cfg(feature = "parallel")]
+    let parsed_files: Vec<(ParsedFile, Profile, Trace)> =
+        content_files.par_iter().map(parse_one).collect();
+    #[cfg(not(feature = "parallel"))]
+    let parsed_files: Vec<(ParsedFile, Profile, Trace)> =
+        content_files.iter().map(parse_one).collect();
+
+    // Collect all parsed environments and standalone proofs
+    let mut all_envs: Vec<ParsedEnv> = Vec::new();
+    let mut all_standalone_proofs: Vec<(String, StandaloneProof)> = Vec::new(); // (relative_path, proof)
+    let mut skipped_count = 0;
+    let mut unconfigured_envs: BTreeMap<String, usize> = BTreeMap::new();
+    let mut numbering: Vec<(&str, Vec<NumberingEvent>)> = Vec::new();
+
+    for ((relative_path, _), (parsed, file_profile, file_trace)) in
+        content_files.iter().zip(parsed_files)
+    {
+        profile.merge(file_profile);
+        trace.merge(file_trace);
+
+        for lines in &parsed.envs.skipped {
+            messages::notice(
+                Some(&Location::blueprint(relative_path, Some(lines))),
+                &format!(
+                    "Skipped environment at {}:{} (skip marker)",
+                    relative_path, lines.lines_start
+                ),
+            );
+        }
+        skipped_count += parsed.envs.skipped.len();
+        for (env_type, count) in parsed.envs.unconfigured {
+            if !options.ignored_envs.contains(&env_type) {
+                *unconfigured_envs.entry(env_type).or_default() += count;
+            }
+        }
+
+        // Extract config from content files as well (in case macros are there)
+        project_config = merge_config(project_config, parsed.config);
+
+        // Parents are indexed within the file
+        let offset = all_envs.len();
+        all_envs.extend(parsed.envs.envs.into_iter().map(|mut env| {
+            env.parent = env.parent.map(|index| offset + index);
+            env
+        }));
+        for proof in parsed.standalone_proofs {
+            all_standalone_proofs.push((relative_path.clone(), proof));
+        }
+        numbering.push((relative_path, parsed.envs.numbering));
+    }
+
+    let start = profile.start();
+
+    // Theorem-like environments take no required argument, so a `{...}`
+    // after `\begin` is usually part of the statement
+    if all_envs.iter().any(|env| env.env_args.is_some()) {
+        let with_arguments = environments_with_arguments(&files);
+        for env in &mut all_envs {
+            if !with_arguments.contains(&env.env_type) {
+                env.env_args = None;
+            }
+        }
+    }
+
+    // Counters carry over between files, so numbering waits for all of them
+    if options.with_numbering {
+        let files: Vec<(&str, &[NumberingEvent])> = numbering
+            .iter()
+            .map(|(path, events)| (*path, events.as_slice()))
+            .collect();
+        let numbers = number_files(&files);
+        for env in &mut all_envs {
+            env.number = numbers[env.relative_path.as_str()][env.number_index].clone();
+        }
+    }
+
+    apply_lean_names(&mut all_envs, options);
+    apply_lean_annotations(&mut all_envs, &options.lean_annotations);
+
+    // Track all seen labels for duplicate detection
+    let mut seen_labels: HashSet<String> = options.known_labels.keys().cloned().collect();
+    let mut label_counter: u64 = 0;
+    let mut all_stubs: HashMap<String, Stub> = HashMap::new();
+    // Build a map from label to stub name for quick lookup
+    let mut label_to_stub_name: HashMap<String, String> = options.known_labels.clone();
+    // Dependency labels of each stub, resolved to stub-names once all are known
+    let mut dependencies: HashMap<String, StubDependencies> = HashMap::new();
+    // Stub-name of each environment, for the `parent` of the ones after it
+    let mut stub_names: Vec<String> = Vec::with_capacity(all_envs.len());
+
+    // Process each environment
+    trace.line(0, || "label resolution".to_string());
+    for mut env in all_envs {
+        // Check existing labels for duplicates
+        for label in &env.labels {
+            if seen_labels.contains(label) {
+                return Err(ProbeError::DuplicateLabel {
+                    label: label.clone(),
+                });
+            }
+        }
+
+        // If no labels, generate one
+        let generated_label = env.labels.is_empty();
+        if generated_label {
+            loop {
+                let generated = generate_label(label_counter);
+                label_counter += 1;
+                if !seen_labels.contains(&generated) {
+                    env.labels.push(generated);
+                    break;
+                }
+            }
+        }
+
+        // Add all labels to seen set
+        for label in &env.labels {
+            seen_labels.insert(label.clone());
+        }
+
+        // Use the last label for stub-name
+        let primary_label = env.labels.iter().next_back().unwrap().clone();
+        let stub_name = format!("{}/{}", env.relative_path, primary_label);
+        stub_names.push(stub_name.clone());
+        trace.line(1, || {
+            let how = if generated_label {
+                "generated label"
+            } else {
+                "last label"
+            };
+            format!("{}: {} of {}", stub_name, how, env.labels.join(", "))
+        });
+
+        // Map all labels (including non-canonical ones) to this stub name
+        for label in &env.labels {
+            label_to_stub_name.insert(label.clone(), stub_name.clone());
+        }
+
+        let mut spec_dependencies = env.spec_dependencies;
+        if !options.no_section_uses {
+            for dependency in env.section_dependencies {
+                if !spec_dependencies
+                    .iter()
+                    .any(|d| d.label == dependency.label)
+                {
+                    spec_dependencies.push(dependency);
+                }
+            }
+        }
+        dependencies.insert(
+            stub_name.clone(),
+            StubDependencies {
+                spec: with_path(&env.relative_path, spec_dependencies),
+                proof: with_path(
+                    &env.relative_path,
+                    env.proof_dependencies.unwrap_or_default(),
+                ),
+            },
+        );
+
+        let is_axiom = (env.env_type == ASSUMPTION_ENV).then_some(true);
+        all_stubs.insert(
+            stub_name,
+            Stub {
+                label: primary_label,
+                generated_label,
+                label_aliases: env.labels[..env.labels.len() - 1].to_vec(),
+                stable_id: env.stable_id,
+                stub_type: Some(env.env_type),
+                stub_path: Some(env.relative_path),
+                stub_spec: Some(env.spec_lines),
+                proof_count: usize::from(env.proof_lines.is_some()),
+                stub_proof_parts: Vec::new(),
+                stub_proof: env.proof_lines,
+                env_options: env.env_options,
+                env_args: env.env_args,
+                theorem_number: options.with_numbering.then_some(env.number),
+                section_path: env.section_path,
+                parent: env.parent.map(|index| stub_names[index].clone()),
+                code_name: env.code_name,
+                lean_names: env.lean_names,
+                lean_path: None,
+                lean_lines: None,
+                lean_doc: None,
+                spec_ok: Some(env.spec_ok),
+                mathlib_ok: if env.mathlib_ok { Some(true) } else { None },
+                not_ready: if env.not_ready { Some(true) } else { None },
+                is_axiom,
+                discussion: env.discussion,
+                spec_dependencies: Vec::new(),
+                proof_ok: env.proof_ok,
+                proof_mathlib_ok: env.proof_mathlib_ok,
+                proof_not_ready: env.proof_not_ready,
+                proof_discussion: env.proof_discussion,
+                proof_dependencies: None,
+                proof_lean_names: env.proof_lean_names,
+                macro_positions: options.macro_positions.then_some(MacroPositions {
+                    statement: env.statement_macros,
+                    proof: env.proof_macros,
+                }),
+                can_state: false,
+                can_prove: false,
+            },
+        );
+    }
+
+    if skipped_count > 0 {
+        messages::notice(
+            None,
+            &format!("Skipped {} environments with a skip marker", skipped_count),
+        );
+    }
+
+    // Merge standalone proofs (those with \proves) into their corresponding
+    // stubs; where each stub got its standalone proof from, for duplicates
+    let mut standalone_proved: HashMap<String, String> = HashMap::new();
+    // File of the last standalone proof of each stub, for `stub-proof-parts`
+    let mut proof_paths: HashMap<String, String> = HashMap::new();
+    for (relative_path, proof) in all_standalone_proofs {
+        for proves_label in &proof.proves_labels {
+            trace.line(1, || {
+                format!(
+                    "\\proves{{{}}} at {}:{} -> {}",
+                    proves_label,
+                    relative_path,
+                    proof.lines.lines_start,
+                    label_to_stub_name
+                        .get(proves_label)
+                        .map_or("unknown label", String::as_str)
+                )
+            });
+            if let Some(stub_name) = label_to_stub_name.get(proves_label) {
+                let location = format!("{}:{}", relative_path, proof.lines.lines_start);
+                let previous = standalone_proved.insert(stub_name.clone(), location.clone());
+                // The same proof may name a statement by several labels
+                if previous.as_ref() == Some(&location) {
+                    continue;
+                }
+                if let Some(first) = previous.filter(|_| !options.proof_parts) {
+                    if !options.warn_duplicate_proofs {
+                        return Err(ProbeError::DuplicateProof {
+                            label: proves_label.clone(),
+                            first,
+                            second: location,
+                        });
+                    }
+                    messages::warning(
+                        Some(&Location::blueprint(&relative_path, Some(&proof.lines))),
+                        &format!(
+                            "\\proves{{{}}} at {} adds to the proof at {}",
+                            proves_label, location, first
+                        ),
+                    );
+                }
+                if let Some(stub) = all_stubs.get_mut(stub_name) {
+                    // Merge proof fields into the stub, accumulating over
+                    // the parts of a proof split into several environments
+                    let previous_path = proof_paths
+                        .insert(stub_name.clone(), relative_path.clone())
+                        .or_else(|| stub.stub_path.clone());
+                    if let (Some(previous), Some(previous_path)) = (stub.stub_proof, previous_path)
+                    {
+                        if stub.stub_proof_parts.is_empty() {
+                            stub.stub_proof_parts.push(ProofPart {
+                                stub_path: previous_path,
+                                lines: previous,
+                            });
+                        }
+                        stub.stub_proof_parts.push(ProofPart {
+                            stub_path: relative_path.clone(),
+                            lines: proof.lines,
+                        });
+                    }
+                    // Proved only when every part is
+                    let parts_ok = stub.proof_count == 0 || stub.proof_ok == Some(true);
+                    stub.proof_ok = (parts_ok && proof.proof_ok).then_some(true);
+                    stub.stub_proof = Some(proof.lines);
+                    stub.proof_count += 1;
+                    if proof.mathlib_ok {
+                        stub.proof_mathlib_ok = Some(true);
+                    }
+                    if proof.not_ready {
+                        stub.proof_not_ready = Some(true);
+                    }
+                    merge_unique(&mut stub.proof_discussion, &proof.discussion);
+                    merge_unique(&mut stub.proof_lean_names, &proof.lean_names);
+                    let proof_dependencies = &mut dependencies.get_mut(stub_name).unwrap().proof;
+                    for dependency in &proof.dependencies {
+                        if !proof_dependencies
+                            .iter()
+                            .any(|(_, d)| d.label == dependency.label)
+                        {
+                            proof_dependencies.push((relative_path.clone(), dependency.clone()));
+                        }
+                    }
+                    // Each macro keeps its first position over the parts
+                    if let Some(positions) = &mut stub.macro_positions {
+                        for (name, line) in &proof.macros {
+                            positions.proof.entry(name.clone()).or_insert(*line);
+                        }
+                    }
+                } else {
+                    messages::warning(
+                        Some(&Location::blueprint(&relative_path, Some(&proof.lines))),
+                        &format!(
+                            "\\proves{{{}}} in {} proves stub '{}' of a file that was not parsed; its proof is left as it was",
+                            proves_label, relative_path, stub_name
+                        ),
+                    );
+                }
+            } else {
+                messages::warning(
+                    Some(&Location::blueprint(&relative_path, Some(&proof.lines))),
+                    &format!(
+                        "\\proves{{{}}} in {} references unknown label",
+                        proves_label, relative_path
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut multiple_proofs: Vec<(&String, &Stub)> = all_stubs
+        .iter()
+        .filter(|(_, stub)| stub.proof_count > 1 && !options.proof_parts)
+        .collect();
+    multiple_proofs.sort_unstable_by_key(|(stub_name, _)| *stub_name);
+    for (stub_name, stub) in multiple_proofs {
+        let location = stub
+            .stub_path
+            .as_deref()
+            .map(|path| Location::blueprint(path, stub.stub_proof.as_ref()));
+        messages::warning(
+            location.as_ref(),
+            &format!("stub '{}' has multiple proofs", stub_name),
+        );
+    }
+
+    profile.record("stub assembly", start);
+
+    let start = profile.start();
+    // Resolve dependency labels to canonical stub-names
+    // Dependencies in .tex files are labels (possibly non-canonical), which we
+    // resolve to stub-names using the label_to_stub_name mapping
+    let mut dependencies: Vec<(String, StubDependencies)> = dependencies.into_iter().collect();
+    dependencies.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    for (stub_name, stub_dependencies) in dependencies {
+        let spec = resolve_dependencies(
+            &stub_dependencies.spec,
+            "spec-dependencies",
+            &stub_name,
+            &label_to_stub_name,
+            &mut trace,
+        )?;
+        let proof = resolve_dependencies(
+            &stub_dependencies.proof,
+            "proof-dependencies",
+            &stub_name,
+            &label_to_stub_name,
+            &mut trace,
+        )?;
+        let stub = all_stubs.get_mut(&stub_name).unwrap();
+        stub.spec_dependencies = spec;
+        stub.proof_dependencies = (!proof.is_empty()).then_some(proof);
+    }
+
+    disambiguate_stable_ids(&mut all_stubs);
+
+    // Split stubs with multiple code-names into separate child stubs
+    // Each child stub gets one code-name, and the parent stub references the children
+    let stub_names_to_split: Vec<String> = all_stubs
+        .iter()
+        .filter(|(_, stub)| {
+            stub.lean_names
+                .as_ref()
+                .is_some_and(|names| names.len() > 1)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for stub_name in stub_names_to_split {
+        let stub = all_stubs.get(&stub_name).unwrap().clone();
+        let code_names = stub.lean_names.as_ref().unwrap();
+        let base_label = &stub.label;
+
+        // Create child stubs, one for each code-name
+        let mut child_stub_names = Vec::new();
+        for (i, code_name) in code_names.iter().enumerate() {
+            let child_label = format!("{}_{}", base_label, i + 1);
+            // Child stub-name uses parent's stub-path
+            let child_stub_name = format!(
+                "{}/{}",
+                stub.stub_path.as_ref().unwrap_or(&String::new()),
+                child_label
+            );
+
+            let child_stub = Stub {
+                label: child_label.clone(),
+                generated_label: stub.generated_label,
+                label_aliases: Vec::new(),
+                stable_id: format!("{}_{}", stub.stable_id, i + 1),
+                stub_type: None,
+                stub_path: None,
+                stub_spec: None,
+                stub_proof: None,
+                proof_count: 0,
+                stub_proof_parts: Vec::new(),
+                env_options: None,
+                env_args: None,
+                theorem_number: None,
+                section_path: Vec::new(),
+                parent: None,
+                code_name: Some(code_name.clone()),
+                lean_names: None,
+                lean_path: None,
+                lean_lines: None,
+                lean_doc: None,
+                spec_ok: stub.spec_ok,
+                mathlib_ok: stub.mathlib_ok,
+                not_ready: stub.not_ready,
+                is_axiom: stub.is_axiom,
+                discussion: stub.discussion.clone(),
+                spec_dependencies: stub.spec_dependencies.clone(),
+                proof_ok: stub.proof_ok,
+                proof_mathlib_ok: stub.proof_mathlib_ok,
+                proof_not_ready: stub.proof_not_ready,
+                proof_discussion: stub.proof_discussion.clone(),
+                proof_dependencies: stub.proof_dependencies.clone(),
+                proof_lean_names: stub.proof_lean_names.clone(),
+                macro_positions: None,
+                can_state: false,
+                can_prove: false,
+            };
+
+            child_stub_names.push(child_stub_name.clone());
+
+            // Add child label to label_to_stub_name mapping
+            label_to_stub_name.insert(child_label, child_stub_name.clone());
+
+            all_stubs.insert(child_stub_name, child_stub);
+        }
+
+        // Update the parent stub: remove code-related and verification fields,
+        // set spec-dependencies to point to child stubs
+        let parent_stub = all_stubs.get_mut(&stub_name).unwrap();
+        parent_stub.code_name = None;
+        parent_stub.lean_names = None;
+        parent_stub.spec_ok = None;
+        parent_stub.mathlib_ok = None;
+        parent_stub.not_ready = None;
+        parent_stub.is_axiom = None;
+        parent_stub.discussion = Vec::new();
+        parent_stub.spec_dependencies = child_stub_names;
+        parent_stub.proof_ok = None;
+        parent_stub.proof_mathlib_ok = None;
+        parent_stub.proof_not_ready = None;
+        parent_stub.proof_discussion = None;
+        parent_stub.proof_dependencies = None;
+        parent_stub.proof_lean_names = None;
+    }
+
+    if !options.skip_readiness {
+        derive_readiness(&mut all_stubs);
+    }
+
+    profile.record("dependency resolution", start);
+
+    Ok(ParsedBlueprint {
+        stubs: all_stubs,
+        config: project_config,
+        labels: label_to_stub_name,
+        unconfigured_envs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Labels of `\uses` entries, without their lines
+    fn labels(dependencies: &[Dependency]) -> Vec<String> {
+        dependencies.iter().map(|d| d.label.clone()).collect()
+    }
+
+    fn uses(content: &str) -> Vec<String> {
+        labels(&extract_uses(content, "a.tex", 1, &DEFAULT_MACROS))
+    }
+
+    #[test]
+    fn test_parse_thms_option_default() {
+        let content = r"\usepackage[showmore, dep_graph]{blueprint}";
+        let envs = parse_thms_option(content);
+        assert_eq!(
+            envs,
+            DEFAULT_ENVS
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_thms_option_custom() {
+        let content = r"\usepackage[thms=dfn+lem+prop+thm+cor]{blueprint}";
+        let envs = parse_thms_option(content);
+        assert_eq!(envs, vec!["dfn", "lem", "prop", "thm", "cor"]);
+    }
+
+    #[test]
+    fn test_parse_thms_option_with_spaces() {
+        let content = r"\usepackage[showmore, thms = dfn + lem +thm, dep_graph]{blueprint}";
+        let envs = parse_thms_option(content);
+        assert_eq!(envs, vec!["dfn", "lem", "thm"]);
+
+        let content = "\\usepackage[\n  thms = dfn\n    + lem\n]{blueprint}";
+        let envs = parse_thms_option(content);
+        assert_eq!(envs, vec!["dfn", "lem"]);
+    }
+
+    #[test]
+    fn test_extract_all_labels_single() {
+        let labels = extract_all_labels(r"\label{foo}");
+        assert_eq!(labels, vec!["foo"]);
+    }
+
+    #[test]
+    fn test_extract_all_labels_multiple() {
+        let labels = extract_all_labels(r"\label{first}\label{second}\label{third}");
+        assert_eq!(labels, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_extract_all_labels_none() {
+        let labels = extract_all_labels(r"no labels here");
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn test_extract_lean() {
+        assert_eq!(
+            extract_lean(
+                r"\lean{Subgraph.Equation387_implies_Equation43}",
+                &DEFAULT_MACROS
+            ),
+            vec!["Subgraph.Equation387_implies_Equation43"]
+        );
+        assert_eq!(
+            extract_lean(r"no lean here", &DEFAULT_MACROS),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_extract_lean_multiple() {
+        assert_eq!(
+            extract_lean(r"\lean{Decl1, Decl2, Decl3}", &DEFAULT_MACROS),
+            vec!["Decl1", "Decl2", "Decl3"]
+        );
+    }
+
+    #[test]
+    fn test_extract_mathlibok() {
+        assert!(extract_mathlibok(r"\mathlibok", &DEFAULT_MACROS));
+        assert!(extract_mathlibok(
+            r"some text \mathlibok more text",
+            &DEFAULT_MACROS
+        ));
+        assert!(!extract_mathlibok(r"no mathlib here", &DEFAULT_MACROS));
+    }
+
+    #[test]
+    fn test_extract_notready() {
+        assert!(extract_notready(r"\notready", &DEFAULT_MACROS));
+        assert!(extract_notready(
+            r"some text \notready more text",
+            &DEFAULT_MACROS
+        ));
+        assert!(!extract_notready(
+            r"ready for formalization",
+            &DEFAULT_MACROS
+        ));
+    }
+
+    #[test]
+    fn test_extract_discussion() {
+        assert_eq!(extract_discussion(r"\discussion{123}"), vec!["123"]);
+        assert_eq!(
+            extract_discussion(r"\discussion{123}\discussion{456}"),
+            vec!["123", "456"]
+        );
+        assert_eq!(extract_discussion(r"no discussion"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_extract_proves() {
+        assert_eq!(
+            extract_proves(r"\proves{thm1}", "a.tex", 1, &DEFAULT_MACROS),
+            vec!["thm1"]
+        );
+        assert_eq!(
+            extract_proves(r"\proves{thm1, thm2}", "a.tex", 1, &DEFAULT_MACROS),
+            vec!["thm1", "thm2"]
+        );
+        assert_eq!(
+            extract_proves(r"no proves", "a.tex", 1, &DEFAULT_MACROS),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_extract_uses() {
+        assert_eq!(uses(r"\uses{eq387,eq43}"), vec!["eq387", "eq43"]);
+        assert_eq!(uses(r"\uses{r, s, t}"), vec!["r", "s", "t"]);
+        assert_eq!(uses(r"no uses"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unwrap_ref_macros() {
+        for content in [
+            r"\proves{\ref{thm:main}}",
+            r"\proves{ \ref{thm:main} }",
+            r"\proves{\ref{ thm:main }}",
+            r"\proves{\cref{thm:main}}",
+            r"\proves { \cref { thm:main } }",
+        ] {
+            assert_eq!(
+                extract_proves(content, "a.tex", 1, &DEFAULT_MACROS),
+                vec!["thm:main"],
+                "{content}"
+            );
+        }
+        assert_eq!(
+            extract_proves(
+                r"\proves{\cref{thm:a, thm:b}, thm:c}",
+                "a.tex",
+                1,
+                &DEFAULT_MACROS
+            ),
+            vec!["thm:a", "thm:b", "thm:c"]
+        );
+
+        assert_eq!(
+            uses(r"\uses{\ref{lem:a},lem:b, \cref{lem:c} ,\Cref*{lem:d}}"),
+            vec!["lem:a", "lem:b", "lem:c", "lem:d"]
+        );
+        assert_eq!(
+            uses(&strip_latex_comments(
+                "\\uses{\n  \\cref{lem:a}, % first\n  \\ref{lem:b}\n}"
+            )),
+            vec!["lem:a", "lem:b"]
+        );
+
+        // Other macros are kept as written (and warned about)
+        assert_eq!(
+            uses(r"\uses{\emph{lem:a}, lem:b}"),
+            vec![r"\emph{lem:a}", "lem:b"]
+        );
+        assert_eq!(uses(r"\uses{}"), Vec::<String>::new());
+
+        // A standalone proof naming its statement through \ref is merged into it
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{theorem}\label{thm:main}\end{theorem}
+\begin{lemma}\label{lem:a}\end{lemma}
+\begin{proof}\proves{\ref{thm:main}}\uses{\cref{lem:a}}\leanok\end{proof}"
+                .to_string(),
+        )];
+        let stubs = parse_sources(&files).unwrap().stubs;
+        assert_eq!(stubs["a.tex/thm:main"].proof_ok, Some(true));
+        assert_eq!(
+            stubs["a.tex/thm:main"].proof_dependencies,
+            Some(vec!["a.tex/lem:a".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_extract_uses_multiline_with_comments() {
+        let expected = vec!["lem:one", "lem:two", "lem:three"];
+
+        // Trailing comments on two lines, the last before the closing brace
+        let content = "\\uses{lem:one, % basic\n  lem:two,\n  lem:three % final, see below\n}";
+        assert_eq!(uses(&strip_latex_comments(content)), expected);
+
+        // A comma inside a comment, blank lines and a trailing comma
+        let content =
+            "\\uses{\n  lem:one % basic, lem:old\n\n  lem:two,,\r\n  lem:three, % done\n}";
+        assert_eq!(uses(&strip_latex_comments(content)), expected);
+
+        // The same through environment parsing
+        let content = "\\begin{theorem}\\label{thm}\n  \\uses{lem:one, % basic\n  lem:two, % more\n  lem:three}\n\\end{theorem}\n";
+        let envs = parse_tex_file(content, "a.tex", &["theorem".to_string()]);
+        assert_eq!(labels(&envs[0].spec_dependencies), expected);
+    }
+
+    #[test]
+    fn test_dependency_lines() {
+        let content = strip_latex_comments(
+            "\\begin{lemma}\n\\uses{lem:a, % first\n  lem:b,\n  \\cref{\n  lem:c}}\n\\end{lemma}",
+        );
+        let lines: Vec<(String, usize)> = extract_uses(&content, "a.tex", 10, &DEFAULT_MACROS)
+            .into_iter()
+            .map(|d| (d.label, d.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("lem:a".to_string(), 11),
+                ("lem:b".to_string(), 12),
+                ("lem:c".to_string(), 14),
+            ]
+        );
+
+        // Unknown labels are reported at their line, in the file of the proof
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{theorem}\label{thm}\uses{lem}\end{theorem}
+\begin{lemma}\label{lem}\end{lemma}"
+                    .to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{proof}\proves{thm}
+\uses{lem, thm,
+  lem:missing}
+\end{proof}"
+                    .to_string(),
+            ),
+        ];
+        let err = parse_sources(&files).unwrap_err();
+        match &err {
+            ProbeError::UnknownLabel {
+                label,
+                field,
+                path,
+                line,
+                ..
+            } => {
+                assert_eq!(
+                    (label.as_str(), *field, path.as_str(), *line),
+                    ("lem:missing", "proof-dependencies", "b.tex", 3)
+                );
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+
+        // A dependency listed twice is kept (with a warning)
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem}\label{lem:alias}\end{lemma}
+\begin{theorem}\label{thm}\uses{lem,
+  lem:alias}\end{theorem}"
+                .to_string(),
+        )];
+        let stubs = parse_sources(&files).unwrap().stubs;
+        assert_eq!(
+            stubs["a.tex/thm"].spec_dependencies,
+            vec!["a.tex/lem:alias", "a.tex/lem:alias"]
+        );
+    }
+
+    #[test]
+    fn test_extract_env_options() {
+        assert_eq!(
+            extract_env_options(r"[Pythagorean Theorem]\label{pyth}"),
+            Some("Pythagorean Theorem".to_string())
+        );
+        assert_eq!(
+            extract_env_options(r"[  Spaced  ]\label{x}"),
+            Some("Spaced".to_string())
+        );
+        assert_eq!(
+            extract_env_options(" [Spaced out]\\label{x}"),
+            Some("Spaced out".to_string())
+        );
+        assert_eq!(
+            extract_env_options("\n  [Next line]\\label{x}"),
+            Some("Next line".to_string())
+        );
+        assert_eq!(extract_env_options(r"\label{x} [not an option]"), None);
+        assert_eq!(extract_env_options(r"[]\label{x}"), None);
+    }
+
+    #[test]
+    fn test_extract_env_args() {
+        assert_eq!(
+            extract_env_args(r"[Name]{arg}\label{x}"),
+            Some("arg".to_string())
+        );
+        assert_eq!(extract_env_args(r"{arg}\label{x}"), Some("arg".to_string()));
+        assert_eq!(extract_env_args(r"[Name]\label{x}"), None);
+        assert_eq!(extract_env_args(r"\label{x}"), None);
+    }
+
+    #[test]
+    fn test_env_args_only_for_declared_arguments() {
+        let files = vec![
+            (
+                "macros.tex".to_string(),
+                r"\newenvironment{claim}[1]{\par}{}
+\newenvironment{remarkable}[1][Note]{\par}{}
+% \newenvironment{theorem}[1]{}{}"
+                    .to_string(),
+            ),
+            (
+                "web.tex".to_string(),
+                r"\usepackage[thms=theorem+claim+remarkable]{blueprint}".to_string(),
+            ),
+            (
+                "a.tex".to_string(),
+                r"\begin{theorem}{\em Every} group is a group.\label{thm}\end{theorem}
+\begin{claim}[Name] {the argument}\label{claim}\end{claim}
+\begin{remarkable}{not an argument}\label{rem}\end{remarkable}"
+                    .to_string(),
+            ),
+        ];
+
+        let stubs = parse_sources(&files).unwrap().stubs;
+        assert_eq!(stubs["a.tex/thm"].env_args, None);
+        assert_eq!(
+            stubs["a.tex/claim"].env_args.as_deref(),
+            Some("the argument")
+        );
+        assert_eq!(stubs["a.tex/claim"].env_options.as_deref(), Some("Name"));
+        assert_eq!(stubs["a.tex/rem"].env_args, None);
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_env_options() {
+        let content = r#"
+\begin{theorem}[Pythagorean Theorem]\label{pyth}\leanok
+  $a^2 + b^2 = c^2$.
+\end{theorem}
+
+\begin{lemma}\label{plain}
+  No title.
+\end{lemma}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string(), "lemma".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 2);
+        assert_eq!(envs[0].env_options, Some("Pythagorean Theorem".to_string()));
+        assert_eq!(envs[0].env_args, None);
+        assert_eq!(envs[0].labels, vec!["pyth"]);
+        assert_eq!(envs[1].env_options, None);
+    }
+
+    #[test]
+    fn test_generate_label() {
+        assert_eq!(generate_label(0), "a0000000000");
+        assert_eq!(generate_label(1), "a0000000001");
+        assert_eq!(generate_label(123), "a0000000123");
+        assert_eq!(generate_label(9999999999), "a9999999999");
+    }
+
+    #[test]
+    fn test_byte_pos_to_line() {
+        let content = "line1\nline2\nline3";
+        assert_eq!(byte_pos_to_line(content, 0), 1); // Start of line1
+        assert_eq!(byte_pos_to_line(content, 5), 1); // End of line1 (before \n)
+        assert_eq!(byte_pos_to_line(content, 6), 2); // Start of line2
+        assert_eq!(byte_pos_to_line(content, 12), 3); // Start of line3
+    }
+
+    #[test]
+    fn test_parse_tex_file_theorem_with_labels() {
+        let content = r#"
+\begin{theorem}[387 implies 43]\label{387_implies_43}\uses{eq387,eq43}\lean{Subgraph.Equation387_implies_Equation43}\leanok
+  Some content here.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "chapter/implications.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].env_type, "theorem");
+        assert_eq!(envs[0].labels, vec!["387_implies_43"]);
+        assert_eq!(
+            envs[0].code_name,
+            Some("probe:Subgraph.Equation387_implies_Equation43".to_string())
+        );
+        assert!(envs[0].spec_ok);
+        assert_eq!(labels(&envs[0].spec_dependencies), vec!["eq387", "eq43"]);
+        assert_eq!(envs[0].proof_ok, None);
+        assert_eq!(envs[0].proof_dependencies.as_deref().map(labels), None);
+        // Line numbers: starts on line 2, ends on line 4
+        assert_eq!(envs[0].spec_lines.lines_start, 2);
+        assert_eq!(envs[0].spec_lines.lines_end, 4);
+    }
+
+    #[test]
+    fn test_parse_tex_file_different_env_types() {
+        let content = r#"
+\begin{definition}\label{def1}
+  A definition.
+\end{definition}
+
+\begin{lemma}\label{lem1}
+  A lemma.
+\end{lemma}
+
+\begin{theorem}\label{thm1}
+  A theorem.
+\end{theorem}
+
+\begin{dfn}\label{dfn1}
+  A dfn (short form).
+\end{dfn}
+"#;
+        let env_types: Vec<String> = vec![
+            "definition".to_string(),
+            "lemma".to_string(),
+            "theorem".to_string(),
+            "dfn".to_string(),
+        ];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 4);
+        assert_eq!(envs[0].env_type, "definition");
+        assert_eq!(envs[0].labels, vec!["def1"]);
+        assert_eq!(envs[1].env_type, "lemma");
+        assert_eq!(envs[1].labels, vec!["lem1"]);
+        assert_eq!(envs[2].env_type, "theorem");
+        assert_eq!(envs[2].labels, vec!["thm1"]);
+        assert_eq!(envs[3].env_type, "dfn");
+        assert_eq!(envs[3].labels, vec!["dfn1"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_multiple_labels() {
+        let content = r#"
+\begin{theorem}\label{first_label}\label{second_label}\label{primary_label}
+  Content with multiple labels.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(
+            envs[0].labels,
+            vec!["first_label", "second_label", "primary_label"]
+        );
+    }
+
+    #[test]
+    fn test_parse_tex_file_no_label() {
+        let content = r#"
+\begin{lemma}
+  A lemma without any label.
+\end{lemma}
+"#;
+        let env_types: Vec<String> = vec!["lemma".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert!(envs[0].labels.is_empty());
+    }
+
+    #[test]
+    fn test_stub_uses_last_label() {
+        // Simulate the processing logic
+        let mut seen_labels: HashSet<String> = HashSet::new();
+        let labels = vec![
+            "first".to_string(),
+            "second".to_string(),
+            "primary".to_string(),
+        ];
+
+        for label in &labels {
+            seen_labels.insert(label.clone());
+        }
+
+        let primary_label = labels.last().unwrap();
+        let stub_name = format!("{}/{}", "file.tex", primary_label);
+
+        assert_eq!(stub_name, "file.tex/primary");
+    }
+
+    #[test]
+    fn test_strip_latex_comments_simple() {
+        let content = "hello % this is a comment\nworld";
+        let stripped = strip_latex_comments(content);
+        assert_eq!(stripped, "hello \nworld");
+    }
+
+    #[test]
+    fn test_strip_latex_comments_escaped_percent() {
+        let content = r"50\% discount";
+        let stripped = strip_latex_comments(content);
+        assert_eq!(stripped, r"50\% discount");
+    }
+
+    #[test]
+    fn test_strip_latex_comments_full_line() {
+        let content = "% full line comment\nactual content";
+        let stripped = strip_latex_comments(content);
+        assert_eq!(stripped, "\nactual content");
+    }
+
+    #[test]
+    fn test_strip_latex_comments_no_comments() {
+        let content = r"\begin{theorem}\label{foo}\end{theorem}";
+        let stripped = strip_latex_comments(content);
+        assert_eq!(stripped, content);
+    }
+
+    #[test]
+    fn test_parse_tex_file_commented_out_env() {
+        let content = r#"
+% \begin{theorem}\label{commented_out}
+%   This theorem is commented out.
+% \end{theorem}
+
+\begin{theorem}\label{active_theorem}
+  This theorem is active.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        // Only the active theorem should be found
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].labels, vec!["active_theorem"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_partially_commented() {
+        let content = r#"
+\begin{theorem}\label{my_theorem}
+  % \label{commented_label}
+  Active content here.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // Only the non-commented label should be found
+        assert_eq!(envs[0].labels, vec!["my_theorem"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_proof() {
+        let content = r#"
+\begin{theorem}\label{my_theorem}\lean{MyTheorem}\leanok
+  Statement of the theorem.
+\end{theorem}
+
+\begin{proof}\leanok\uses{lemma1,lemma2}
+  The proof goes here.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].labels, vec!["my_theorem"]);
+        assert!(envs[0].spec_ok);
+        assert_eq!(envs[0].proof_ok, Some(true));
+        assert_eq!(
+            envs[0].proof_dependencies.as_deref().map(labels),
+            Some(vec!["lemma1".to_string(), "lemma2".to_string()])
+        );
+        // Check proof lines are captured
+        assert!(envs[0].proof_lines.is_some());
+        let proof_lines = envs[0].proof_lines.as_ref().unwrap();
+        assert_eq!(proof_lines.lines_start, 6);
+        assert_eq!(proof_lines.lines_end, 8);
+    }
+
+    #[test]
+    fn test_parse_tex_file_proof_with_label() {
+        let content = r#"
+\begin{theorem}\label{thm_label}\leanok
+  Statement.
+\end{theorem}
+
+\begin{proof}\label{proof_label}\leanok
+  Proof content.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // Proof label should be added to the end
+        assert_eq!(envs[0].labels, vec!["thm_label", "proof_label"]);
+        // The stub name should use the last label (proof_label)
+    }
+
+    #[test]
+    fn test_parse_tex_file_proof_without_leanok() {
+        let content = r#"
+\begin{theorem}\label{my_theorem}\leanok
+  Statement.
+\end{theorem}
+
+\begin{proof}\uses{dep1}
+  Proof without leanok.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert!(envs[0].spec_ok);
+        // proof_ok should be None (not present) when \leanok is not in proof
+        assert_eq!(envs[0].proof_ok, None);
+        assert_eq!(
+            envs[0].proof_dependencies.as_deref().map(labels),
+            Some(vec!["dep1".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_tex_file_no_proof() {
+        let content = r#"
+\begin{definition}\label{my_def}\lean{MyDef}\leanok
+  A definition without proof.
+\end{definition}
+"#;
+        let env_types: Vec<String> = vec!["definition".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].labels, vec!["my_def"]);
+        assert_eq!(envs[0].proof_ok, None);
+        assert_eq!(envs[0].proof_dependencies.as_deref().map(labels), None);
+        assert!(envs[0].proof_lines.is_none());
+    }
+
+    #[test]
+    fn test_parse_tex_file_nested_same_name() {
+        let content = r#"
+\begin{theorem}\label{outer}
+  \begin{theorem}\label{inner}\leanok
+  \end{theorem}
+\end{theorem}
+\begin{proof}\leanok
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        // The nested environment is a stub of its own, and its \leanok is not
+        // the outer one's
+        assert_eq!(envs.len(), 2);
+        assert_eq!(envs[0].labels, vec!["outer"]);
+        assert_eq!(envs[0].spec_lines.lines_start, 2);
+        assert_eq!(envs[0].spec_lines.lines_end, 5);
+        assert!(!envs[0].spec_ok);
+        assert_eq!(envs[0].proof_ok, Some(true));
+        assert_eq!(envs[0].parent, None);
+        assert_eq!(envs[1].labels, vec!["inner"]);
+        assert!(envs[1].spec_ok);
+        assert!(envs[1].proof_lines.is_none());
+        assert_eq!(envs[1].parent, Some(0));
+    }
+
+    #[test]
+    fn test_parse_tex_file_nested_different_types() {
+        let content = r#"
+\begin{theorem}\label{thm}
+  \begin{lemma}\label{inner_lem}
+  \end{lemma}
+\end{theorem}
+\begin{lemma}\label{after}
+\end{lemma}
+"#;
+        let env_types: Vec<String> = vec!["lemma".to_string(), "theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 3);
+        assert_eq!(envs[0].env_type, "theorem");
+        assert_eq!(envs[0].labels, vec!["thm"]);
+        assert_eq!(envs[1].env_type, "lemma");
+        assert_eq!(envs[1].labels, vec!["inner_lem"]);
+        assert_eq!(envs[1].parent, Some(0));
+        assert_eq!(envs[2].labels, vec!["after"]);
+        assert_eq!(envs[2].parent, None);
+    }
+
+    #[test]
+    fn test_nested_definition_in_theorem() {
+        let content = r"\begin{lemma}\label{lem0}\end{lemma}
+\begin{theorem}\label{thm1}\lean{Foo.thm}\uses{lem0, def1}
+  \begin{definition}\label{def1}\lean{Foo.def}\uses{lem0}\leanok
+    A \emph{widget} is a gadget.
+  \end{definition}
+  \begin{proof}\uses{lem0}\leanok
+  \end{proof}
+  Every widget is fine.
+\end{theorem}
+\begin{proof}\leanok
+\end{proof}
+\begin{theorem}\label{thm2}
+  \blueprintskip
+  \begin{definition}\label{def2}\end{definition}
+\end{theorem}
+";
+        let stubs = parse_sources(&[("a.tex".to_string(), content.to_string())])
+            .unwrap()
+            .stubs;
+
+        assert_eq!(stubs.len(), 3);
+        let theorem = &stubs["a.tex/thm1"];
+        let definition = &stubs["a.tex/def1"];
+        assert_eq!(theorem.parent, None);
+        assert_eq!(definition.parent.as_deref(), Some("a.tex/thm1"));
+        assert_eq!(
+            (
+                theorem.stub_spec.unwrap().lines_start,
+                theorem.stub_spec.unwrap().lines_end
+            ),
+            (2, 9)
+        );
+        assert_eq!(
+            (
+                definition.stub_spec.unwrap().lines_start,
+                definition.stub_spec.unwrap().lines_end
+            ),
+            (3, 5)
+        );
+        assert_eq!(theorem.code_name.as_deref(), Some("probe:Foo.thm"));
+        assert_eq!(definition.code_name.as_deref(), Some("probe:Foo.def"));
+
+        // The definition's macros and proof are its own; the theorem depends on
+        // the definition as on any other stub
+        assert_eq!(theorem.spec_ok, Some(false));
+        assert_eq!(definition.spec_ok, Some(true));
+        assert_eq!(definition.stub_proof.unwrap().lines_start, 6);
+        assert_eq!(theorem.stub_proof.unwrap().lines_start, 10);
+        assert_eq!(
+            theorem.spec_dependencies,
+            vec!["a.tex/lem0".to_string(), "a.tex/def1".to_string()]
+        );
+        assert_eq!(definition.spec_dependencies, vec!["a.tex/lem0".to_string()]);
+        assert_eq!(
+            definition.proof_dependencies,
+            Some(vec!["a.tex/lem0".to_string()])
+        );
+        assert_eq!(theorem.proof_dependencies, None);
+        let json = serde_json::to_value(definition).unwrap();
+        assert_eq!(json["parent"], "a.tex/thm1");
+        assert!(serde_json::to_value(theorem)
+            .unwrap()
+            .get("parent")
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_tex_file_verbatim_is_opaque() {
+        let content = r#"
+\begin{verbatim}
+\begin{theorem}\label{hidden}\end{theorem}
+\end{verbatim}
+\begin{theorem}\label{shown}
+  Example: \verb|\leanok|
+\end{theorem}
+\iffalse
+\begin{theorem}\label{disabled}\end{theorem}
+\fi
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].labels, vec!["shown"]);
+        assert!(!envs[0].spec_ok);
+    }
+
+    #[test]
+    fn test_parse_tex_file_comment_before_proof() {
+        let content = r#"
+\begin{lemma}\label{lem}
+\end{lemma}
+% the proof follows
+\begin{proof}\uses{dep}
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["lemma".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(
+            envs[0].proof_dependencies.as_deref().map(labels),
+            Some(vec!["dep".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_tex_file_theorem_then_other_content_then_proof() {
+        // Proof should only be found if it immediately follows (with whitespace only)
+        let content = r#"
+\begin{theorem}\label{thm1}\leanok
+  First theorem.
+\end{theorem}
+
+Some intervening text here.
+
+\begin{proof}\leanok
+  This proof should NOT be associated with thm1.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // The proof should not be associated because there's intervening text
+        assert_eq!(envs[0].proof_ok, None);
+        assert!(envs[0].proof_lines.is_none());
+    }
+
+    #[test]
+    fn test_strip_nested_environments() {
+        let content =
+            r#"Top level content \begin{equation}\label{nested}\end{equation} more content"#;
+        let stripped = strip_nested_environments(content);
+        assert_eq!(stripped, "Top level content  more content");
+    }
+
+    #[test]
+    fn test_strip_nested_environments_multiple() {
+        let content =
+            r#"\begin{align}\label{a1}\end{align} text \begin{equation}\label{a2}\end{equation}"#;
+        let stripped = strip_nested_environments(content);
+        assert_eq!(stripped, " text ");
+    }
+
+    #[test]
+    fn test_strip_pathological_input() {
+        // Thousands of nested environments and long comment-heavy lines; both
+        // passes are linear, so this stays well under the time limit even in
+        // debug builds
+        let mut content = String::new();
+        for i in 0..2000 {
+            content.push_str(&format!(
+                "\\begin{{equation}}\\label{{e{i}}} x = y \\end{{equation}} text\n"
+            ));
+            content.push_str(&format!(
+                "{}% {}\n",
+                r"word \% ".repeat(20),
+                "comment ".repeat(20)
+            ));
+        }
+
+        let start = std::time::Instant::now();
+        let stripped = strip_nested_environments(&strip_latex_comments(&content));
+        let elapsed = start.elapsed();
+
+        assert!(!stripped.contains(r"\label"));
+        assert!(!stripped.contains("comment"));
+        assert_eq!(stripped.matches(r"\%").count(), 2000 * 20);
+        assert_eq!(stripped.lines().count(), 4000);
+        assert!(elapsed.as_secs() < 2, "stripping took {elapsed:?}");
+    }
+
+    #[test]
+    fn test_extract_labels_ignores_nested() {
+        // Labels inside nested environments should be ignored
+        let content = r#"\label{top_level}
+Some text here.
+\begin{equation}\label{nested_eq}
+  x = y
+\end{equation}
+More text.
+\begin{align}\label{nested_align}
+  a = b
+\end{align}"#;
+        let labels = extract_all_labels(content);
+        assert_eq!(labels, vec!["top_level"]);
+    }
+
+    #[test]
+    fn test_extract_labels_ignores_display_math() {
+        let content = r#"\label{thm:main}
+We have \[
+  x = y \label{eq:bracket}
+\] and $$ a = b \label{eq:dollars} $$
+and inline $c = d \label{eq:inline}$ with a price of \$5 \label{thm:alias}.
+"#;
+        assert_eq!(extract_all_labels(content), vec!["thm:main", "thm:alias"]);
+        // Line breaks of removed spans are kept
+        let stripped = strip_nested_environments(content);
+        assert_eq!(stripped.lines().count(), content.lines().count());
+        assert!(stripped.contains("We have \n\n and "));
+
+        // An unclosed `$` ends at a blank line and an unclosed `\[` is left
+        // alone, rather than swallowing the labels that follow
+        let unbalanced = "Costs $5.\n\n\\label{a} \\[ \\label{b}";
+        assert_eq!(extract_all_labels(unbalanced), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_duplicate_label_in_one_environment() {
+        let content = "\\begin{theorem}\\label{thm:a}\\label{thm:a}\nText.\n\\end{theorem}\n\\begin{proof}\n\\label{thm:b}\\label{thm:a}\n\\end{proof}\n";
+        assert_eq!(
+            extract_all_labels_lines(
+                "\\label{x}\n\\begin{equation}\\label{eq}\n\\end{equation}\n\\label{y}"
+            ),
+            vec![("x".to_string(), 0), ("y".to_string(), 3)]
+        );
+
+        let parsed = parse_sources(&[("a.tex".to_string(), content.to_string())]).unwrap();
+        assert_eq!(parsed.stubs.len(), 1);
+        assert_eq!(parsed.stubs["a.tex/thm:b"].label, "thm:b");
+        assert_eq!(parsed.labels["thm:a"], "a.tex/thm:b");
+
+        // The same label in two environments is still an error
+        let twice =
+            "\\begin{lemma}\\label{thm:a}\\end{lemma}\n\\begin{lemma}\\label{thm:a}\\end{lemma}\n";
+        let error = parse_sources(&[("a.tex".to_string(), twice.to_string())]).unwrap_err();
+        assert_eq!(error.to_string(), "Duplicate label found: thm:a");
+    }
+
+    #[test]
+    fn test_parse_tex_file_display_math_label() {
+        let content = r#"
+\begin{theorem}
+  For all $n$,
+  \[ f(n) = g(n) \label{eq:fg} \]
+  \label{thm:fg}
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].labels, vec!["thm:fg"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_proof_with_nested_equation() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\leanok
+  Statement of theorem.
+\end{theorem}
+
+\begin{proof}\label{my_proof}\leanok
+  We have
+  \begin{equation}\label{eq1}
+    x = y
+  \end{equation}
+  and therefore the result follows.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // Only top-level labels: my_thm from theorem, my_proof from proof
+        // eq1 from nested equation should be ignored
+        assert_eq!(envs[0].labels, vec!["my_thm", "my_proof"]);
+        assert_eq!(envs[0].proof_ok, Some(true));
+    }
+
+    #[test]
+    fn test_parse_tex_file_theorem_with_nested_env() {
+        let content = r#"
+\begin{theorem}\label{main_thm}
+  For all $x$, we have
+  \begin{equation}\label{internal_eq}
+    f(x) = g(x)
+  \end{equation}
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // Only main_thm should be captured, not internal_eq
+        assert_eq!(envs[0].labels, vec!["main_thm"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_line_numbers() {
+        let content = r#"\begin{theorem}\label{thm1}
+Line 2 content.
+Line 3 content.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].spec_lines.lines_start, 1);
+        assert_eq!(envs[0].spec_lines.lines_end, 4);
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_mathlibok() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\lean{MyThm}\mathlibok
+  A theorem in mathlib.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert!(envs[0].mathlib_ok);
+        // mathlibok should imply leanok for the spec
+        assert!(!envs[0].spec_ok); // leanok was not explicitly present
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_notready() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\notready
+  A theorem not ready for formalization.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert!(envs[0].not_ready);
+        assert!(!envs[0].spec_ok);
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_discussion() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\discussion{123}\discussion{456}
+  A theorem with discussions.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].discussion, vec!["123", "456"]);
+    }
+
+    #[test]
+    fn test_parse_tex_file_with_multiple_lean_names() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\lean{Thm1, Thm2, Thm3}\leanok
+  A theorem with multiple lean names.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].code_name, Some("probe:Thm1".to_string()));
+        assert_eq!(
+            envs[0].lean_names,
+            Some(vec![
+                "probe:Thm1".to_string(),
+                "probe:Thm2".to_string(),
+                "probe:Thm3".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_tex_file_proof_with_proves_not_associated() {
+        // When a proof has \proves, it should NOT be associated with the preceding theorem
+        let content = r#"
+\begin{theorem}\label{thm1}\leanok
+  First theorem.
+\end{theorem}
+
+\begin{proof}\proves{some_other_thm}\leanok
+  This proof is for a different theorem.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // The proof should NOT be associated since it has \proves
+        assert!(envs[0].proof_lines.is_none());
+        assert_eq!(envs[0].proof_ok, None);
+    }
+
+    #[test]
+    fn test_find_standalone_proofs() {
+        let content = r#"
+\begin{theorem}\label{thm1}
+  A theorem.
+\end{theorem}
+
+\begin{proof}\proves{thm1}\leanok\uses{lemma1}\lean{TheoremProof}
+  The proof.
+\end{proof}
+"#;
+        let proofs = find_standalone_proofs(content, "file.tex", &DEFAULT_MACROS);
+
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].proves_labels, vec!["thm1"]);
+        assert!(proofs[0].proof_ok);
+        assert_eq!(labels(&proofs[0].dependencies), vec!["lemma1"]);
+        assert_eq!(proofs[0].lean_names, vec!["TheoremProof"]);
+    }
+
+    #[test]
+    fn test_find_standalone_proofs_with_mathlibok() {
+        let content = r#"
+\begin{proof}\proves{thm1}\mathlibok
+  A mathlib proof.
+\end{proof}
+"#;
+        let proofs = find_standalone_proofs(content, "file.tex", &DEFAULT_MACROS);
+
+        assert_eq!(proofs.len(), 1);
+        assert!(proofs[0].mathlib_ok);
+    }
+
+    #[test]
+    fn test_find_standalone_proofs_with_notready() {
+        let content = r#"
+\begin{proof}\proves{thm1}\notready
+  A proof not ready.
+\end{proof}
+"#;
+        let proofs = find_standalone_proofs(content, "file.tex", &DEFAULT_MACROS);
+
+        assert_eq!(proofs.len(), 1);
+        assert!(proofs[0].not_ready);
+    }
+
+    #[test]
+    fn test_find_standalone_proofs_with_discussion() {
+        let content = r#"
+\begin{proof}\proves{thm1}\discussion{789}
+  A proof with discussion.
+\end{proof}
+"#;
+        let proofs = find_standalone_proofs(content, "file.tex", &DEFAULT_MACROS);
+
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].discussion, vec!["789"]);
+    }
+
+    #[test]
+    fn test_proof_with_all_macros() {
+        let content = r#"
+\begin{theorem}\label{my_thm}\lean{MyTheorem}\leanok\mathlibok\discussion{100}
+  Statement.
+\end{theorem}
+
+\begin{proof}\leanok\mathlibok\uses{dep1}\lean{MyProof}\discussion{200}
+  Proof.
+\end{proof}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "file.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        // Spec fields
+        assert!(envs[0].spec_ok);
+        assert!(envs[0].mathlib_ok);
+        assert_eq!(envs[0].discussion, vec!["100"]);
+        assert_eq!(envs[0].code_name, Some("probe:MyTheorem".to_string()));
+
+        // Proof fields
+        assert_eq!(envs[0].proof_ok, Some(true));
+        assert_eq!(envs[0].proof_mathlib_ok, Some(true));
+        assert_eq!(envs[0].proof_discussion, Some(vec!["200".to_string()]));
+        assert_eq!(
+            envs[0].proof_dependencies.as_deref().map(labels),
+            Some(vec!["dep1".to_string()])
+        );
+        assert_eq!(envs[0].proof_lean_names, Some(vec!["MyProof".to_string()]));
+    }
+
+    #[test]
+    fn test_extract_home() {
+        assert_eq!(
+            extract_home(r"\home{https://example.com/project}"),
+            Some("https://example.com/project".to_string())
+        );
+        assert_eq!(extract_home(r"no home here"), None);
+    }
+
+    #[test]
+    fn test_extract_github() {
+        assert_eq!(
+            extract_github(r"\github{https://github.com/user/repo}"),
+            Some("https://github.com/user/repo".to_string())
+        );
+        assert_eq!(extract_github(r"no github here"), None);
+    }
+
+    #[test]
+    fn test_extract_dochome() {
+        assert_eq!(
+            extract_dochome(r"\dochome{https://docs.example.com/}"),
+            Some("https://docs.example.com/".to_string())
+        );
+        assert_eq!(extract_dochome(r"no dochome here"), None);
+    }
+
+    #[test]
+    fn test_extract_config() {
+        let content = r#"
+\home{https://example.com}
+\github{https://github.com/user/repo}
+\dochome{https://docs.example.com}
+"#;
+        let config = extract_config(content);
+        assert_eq!(config.home, Some("https://example.com".to_string()));
+        assert_eq!(
+            config.github,
+            Some("https://github.com/user/repo".to_string())
+        );
+        assert_eq!(config.dochome, Some("https://docs.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_extract_config_partial() {
+        let content = r#"\github{https://github.com/user/repo}"#;
+        let config = extract_config(content);
+        assert_eq!(config.home, None);
+        assert_eq!(
+            config.github,
+            Some("https://github.com/user/repo".to_string())
+        );
+        assert_eq!(config.dochome, None);
+    }
+
+    #[test]
+    fn test_merge_config() {
+        let base = Config {
+            home: Some("base_home".to_string()),
+            github: Some("base_github".to_string()),
+            dochome: None,
+            ..Config::default()
+        };
+        let other = Config {
+            home: None,
+            github: Some("other_github".to_string()),
+            dochome: Some("other_dochome".to_string()),
+            ..Config::default()
+        };
+        let merged = merge_config(base, other);
+        assert_eq!(merged.home, Some("base_home".to_string())); // kept from base
+        assert_eq!(merged.github, Some("other_github".to_string())); // overridden by other
+        assert_eq!(merged.dochome, Some("other_dochome".to_string())); // added from other
+    }
+
+    #[test]
+    fn test_stub_splitting_multiple_code_names() {
+        // Test that stubs with multiple code-names get split into child stubs
+        let content = r#"
+\begin{theorem}\label{multi_thm}\lean{Thm1, Thm2, Thm3}\leanok
+  A theorem with multiple lean names.
+\end{theorem}
+"#;
+        let env_types: Vec<String> = vec!["theorem".to_string()];
+        let envs = parse_tex_file(content, "chapter/test.tex", &env_types);
+
+        assert_eq!(envs.len(), 1);
+        assert_eq!(
+            envs[0].lean_names,
+            Some(vec![
+                "probe:Thm1".to_string(),
+                "probe:Thm2".to_string(),
+                "probe:Thm3".to_string()
+            ])
+        );
+
+        // Build stubs from envs (simulating what run() does)
+        let mut all_stubs: HashMap<String, Stub> = HashMap::new();
+        let mut label_to_stub_name: HashMap<String, String> = HashMap::new();
+
+        for env in envs {
+            let label = env.labels.iter().next_back().unwrap().clone();
+            let stub_name = format!("{}/{}", env.relative_path, label);
+
+            label_to_stub_name.insert(label.clone(), stub_name.clone());
+            for lbl in &env.labels {
+                label_to_stub_name.insert(lbl.clone(), stub_name.clone());
+            }
+
+            all_stubs.insert(
+                stub_name,
+                Stub {
+                    label,
+                    generated_label: false,
+                    label_aliases: Vec::new(),
+                    stable_id: env.stable_id.clone(),
+                    stub_type: Some(env.env_type.clone()),
+                    stub_path: Some(env.relative_path.clone()),
+                    stub_spec: Some(env.spec_lines),
+                    stub_proof: None,
+                    proof_count: 0,
+                    stub_proof_parts: Vec::new(),
+                    env_options: env.env_options.clone(),
+                    env_args: env.env_args.clone(),
+                    theorem_number: None,
+                    section_path: Vec::new(),
+                    parent: None,
+                    code_name: env.code_name.clone(),
+                    lean_names: env.lean_names.clone(),
+                    lean_path: None,
+                    lean_lines: None,
+                    lean_doc: None,
+                    spec_ok: Some(env.spec_ok),
+                    mathlib_ok: Some(env.mathlib_ok),
+                    not_ready: Some(env.not_ready),
+                    is_axiom: None,
+                    discussion: env.discussion.clone(),
+                    spec_dependencies: vec![],
+                    proof_ok: None,
+                    proof_mathlib_ok: None,
+                    proof_not_ready: None,
+                    proof_discussion: None,
+                    proof_dependencies: None,
+                    proof_lean_names: None,
+                    macro_positions: None,
+                    can_state: false,
+                    can_prove: false,
+                },
+            );
+        }
+
+        // Simulate stub splitting
+        let stub_names_to_split: Vec<String> = all_stubs
+            .iter()
+            .filter(|(_, stub)| {
+                stub.lean_names
+                    .as_ref()
+                    .is_some_and(|names| names.len() > 1)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for stub_name in stub_names_to_split {
+            let stub = all_stubs.get(&stub_name).unwrap().clone();
+            let code_names = stub.lean_names.as_ref().unwrap();
+            let base_label = &stub.label;
+
+            let mut child_stub_names = Vec::new();
+            for (i, code_name) in code_names.iter().enumerate() {
+                let child_label = format!("{}_{}", base_label, i + 1);
+                let child_stub_name = format!(
+                    "{}/{}",
+                    stub.stub_path.as_ref().unwrap_or(&String::new()),
+                    child_label
+                );
+
+                let child_stub = Stub {
+                    label: child_label.clone(),
+                    generated_label: stub.generated_label,
+                    label_aliases: Vec::new(),
+                    stable_id: format!("{}_{}", stub.stable_id, i + 1),
+                    stub_type: None,
+                    stub_path: None,
+                    stub_spec: None,
+                    stub_proof: None,
+                    proof_count: 0,
+                    stub_proof_parts: Vec::new(),
+                    env_options: None,
+                    env_args: None,
+                    theorem_number: None,
+                    section_path: Vec::new(),
+                    parent: None,
+                    code_name: Some(code_name.clone()),
+                    lean_names: None,
+                    lean_path: None,
+                    lean_lines: None,
+                    lean_doc: None,
+                    spec_ok: stub.spec_ok,
+                    mathlib_ok: stub.mathlib_ok,
+                    not_ready: stub.not_ready,
+                    is_axiom: None,
+                    discussion: stub.discussion.clone(),
+                    spec_dependencies: stub.spec_dependencies.clone(),
+                    proof_ok: stub.proof_ok,
+                    proof_mathlib_ok: stub.proof_mathlib_ok,
+                    proof_not_ready: stub.proof_not_ready,
+                    proof_discussion: stub.proof_discussion.clone(),
+                    proof_dependencies: stub.proof_dependencies.clone(),
+                    proof_lean_names: stub.proof_lean_names.clone(),
+                    macro_positions: None,
+                    can_state: false,
+                    can_prove: false,
+                };
+
+                child_stub_names.push(child_stub_name.clone());
+                label_to_stub_name.insert(child_label, child_stub_name.clone());
+                all_stubs.insert(child_stub_name, child_stub);
+            }
+
+            let parent_stub = all_stubs.get_mut(&stub_name).unwrap();
+            parent_stub.code_name = None;
+            parent_stub.lean_names = None;
+            parent_stub.spec_ok = None;
+            parent_stub.mathlib_ok = None;
+            parent_stub.not_ready = None;
+            parent_stub.discussion = Vec::new();
+            parent_stub.spec_dependencies = child_stub_names;
+            parent_stub.proof_ok = None;
+            parent_stub.proof_mathlib_ok = None;
+            parent_stub.proof_not_ready = None;
+            parent_stub.proof_discussion = None;
+            parent_stub.proof_dependencies = None;
+            parent_stub.proof_lean_names = None;
+        }
+
+        // Verify: should have 4 stubs now (1 parent + 3 children)
+        assert_eq!(all_stubs.len(), 4);
+
+        // Check parent stub
+        let parent = all_stubs.get("chapter/test.tex/multi_thm").unwrap();
+        assert_eq!(parent.label, "multi_thm");
+        assert!(parent.stub_type.is_some()); // Parent keeps stub-type
+        assert!(parent.stub_path.is_some()); // Parent keeps stub-path
+        assert!(parent.stub_spec.is_some()); // Parent keeps stub-spec
+        assert!(parent.code_name.is_none()); // Parent loses code-name
+        assert!(parent.lean_names.is_none()); // Parent loses lean-names
+        assert!(parent.spec_ok.is_none()); // Parent loses spec-ok
+        assert_eq!(
+            parent.spec_dependencies,
+            vec![
+                "chapter/test.tex/multi_thm_1",
+                "chapter/test.tex/multi_thm_2",
+                "chapter/test.tex/multi_thm_3"
+            ]
+        );
+
+        // Check child stubs
+        let child1 = all_stubs.get("chapter/test.tex/multi_thm_1").unwrap();
+        assert_eq!(child1.label, "multi_thm_1");
+        assert!(child1.stub_type.is_none()); // Child has no stub-type
+        assert!(child1.stub_path.is_none()); // Child has no stub-path
+        assert!(child1.stub_spec.is_none()); // Child has no stub-spec
+        assert_eq!(child1.code_name, Some("probe:Thm1".to_string()));
+        assert!(child1.lean_names.is_none()); // Child has no lean-names
+        assert_eq!(child1.spec_ok, Some(true)); // Child inherits spec-ok
+
+        let child2 = all_stubs.get("chapter/test.tex/multi_thm_2").unwrap();
+        assert_eq!(child2.label, "multi_thm_2");
+        assert_eq!(child2.code_name, Some("probe:Thm2".to_string()));
+
+        let child3 = all_stubs.get("chapter/test.tex/multi_thm_3").unwrap();
+        assert_eq!(child3.label, "multi_thm_3");
+        assert_eq!(child3.code_name, Some("probe:Thm3".to_string()));
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let files = vec![
+            (
+                "web.tex".to_string(),
+                r"\usepackage[thms=lemma+theorem]{blueprint}\home{https://example.com}".to_string(),
+            ),
+            (
+                "print.tex".to_string(),
+                r"\begin{theorem}\label{ignored}\end{theorem}".to_string(),
+            ),
+            (
+                "chapter/main.tex".to_string(),
+                r"\begin{lemma}\label{lem1}\end{lemma}
+\begin{theorem}\label{thm1}\uses{lem1}\end{theorem}
+\begin{definition}\label{not_configured}\end{definition}"
+                    .to_string(),
+            ),
+        ];
+
+        let parsed = parse_sources(&files).unwrap();
+
+        assert_eq!(parsed.stubs.len(), 2);
+        assert_eq!(
+            parsed.stubs["chapter/main.tex/thm1"].spec_dependencies,
+            vec!["chapter/main.tex/lem1"]
+        );
+        assert_eq!(parsed.config.home, Some("https://example.com".to_string()));
+    }
+
+    #[test]
+    fn test_parse_sources_skip_files() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{lemma}\label{lem1}\end{lemma}".to_string(),
+            ),
+            (
+                "chapter/macros.tex".to_string(),
+                r"\begin{lemma}\label{skipped}\end{lemma}".to_string(),
+            ),
+            (
+                "chapter/print.tex".to_string(),
+                r"\begin{lemma}\label{ignored}\end{lemma}".to_string(),
+            ),
+        ];
+        let options = ParseOptions {
+            skip_files: vec!["macros.tex".to_string()],
+            ..ParseOptions::default()
+        };
+
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+
+        let names: Vec<&String> = parsed.stubs.keys().collect();
+        assert_eq!(names, vec!["a.tex/lem1"]);
+    }
+
+    #[test]
+    fn test_parse_sources_unknown_label() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{theorem}\label{thm1}\uses{missing}\end{theorem}".to_string(),
+        )];
+
+        let err = parse_sources(&files).unwrap_err();
+        assert!(err.to_string().contains("Unknown label 'missing'"));
+    }
+
+    #[test]
+    fn test_parse_sources_assumption_is_axiom() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{assumption}\label{grh}\end{assumption}
+\begin{theorem}\label{thm1}\uses{grh}\end{theorem}"
+                .to_string(),
+        )];
+
+        let parsed = parse_sources(&files).unwrap();
+
+        let grh = &parsed.stubs["a.tex/grh"];
+        assert_eq!(grh.stub_type.as_deref(), Some("assumption"));
+        assert_eq!(grh.is_axiom, Some(true));
+        assert_eq!(parsed.stubs["a.tex/thm1"].is_axiom, None);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(r"chapter\sub\main.tex"),
+            "chapter/sub/main.tex"
+        );
+        assert_eq!(normalize_path("chapter/main.tex"), "chapter/main.tex");
+    }
+
+    #[test]
+    fn test_parse_sources_windows_paths() {
+        // Sources read on Windows produce the same stubs as on Unix
+        let unix = vec![
+            (
+                "chapter/a.tex".to_string(),
+                r"\begin{lemma}\label{lem1}\end{lemma}".to_string(),
+            ),
+            (
+                "chapter/print.tex".to_string(),
+                r"\begin{lemma}\label{ignored}\end{lemma}".to_string(),
+            ),
+        ];
+        let windows: Vec<(String, String)> = unix
+            .iter()
+            .map(|(path, content)| (path.replace('/', "\\"), content.clone()))
+            .collect();
+
+        let parsed = parse_sources(&windows).unwrap();
+
+        assert_eq!(parsed.stubs.len(), 1);
+        let stub = &parsed.stubs["chapter/a.tex/lem1"];
+        assert_eq!(stub.stub_path.as_deref(), Some("chapter/a.tex"));
+        assert_eq!(
+            serde_json::to_value(&parsed.stubs).unwrap(),
+            serde_json::to_value(&parse_sources(&unix).unwrap().stubs).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_lean_names_override_source() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\lean{Src.one, Src.two}\end{lemma}
+\begin{lemma}\label{lem2}\end{lemma}
+\begin{lemma}\label{lem3}\lean{Src.three}\end{lemma}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            lean_names: HashMap::from([
+                ("lem1".to_string(), "File.one".to_string()),
+                ("lem2".to_string(), "File.two".to_string()),
+            ]),
+            prefer_source: false,
+            ..ParseOptions::default()
+        };
+
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+
+        // The file name replaces all \lean names, so lem1 is not split
+        assert_eq!(parsed.stubs.len(), 3);
+        let lem1 = &parsed.stubs["a.tex/lem1"];
+        assert_eq!(lem1.code_name.as_deref(), Some("probe:File.one"));
+        assert_eq!(lem1.lean_names, None);
+        assert_eq!(
+            parsed.stubs["a.tex/lem2"].code_name.as_deref(),
+            Some("probe:File.two")
+        );
+        assert_eq!(
+            parsed.stubs["a.tex/lem3"].code_name.as_deref(),
+            Some("probe:Src.three")
+        );
+    }
+
+    #[test]
+    fn test_lean_names_prefer_source() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\lean{Src.one}\end{lemma}
+\begin{lemma}\label{lem2}\end{lemma}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            lean_names: HashMap::from([
+                ("lem1".to_string(), "File.one".to_string()),
+                ("lem2".to_string(), "File.two".to_string()),
+            ]),
+            prefer_source: true,
+            ..ParseOptions::default()
+        };
+
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+
+        assert_eq!(
+            parsed.stubs["a.tex/lem1"].code_name.as_deref(),
+            Some("probe:Src.one")
+        );
+        assert_eq!(
+            parsed.stubs["a.tex/lem2"].code_name.as_deref(),
+            Some("probe:File.two")
+        );
+    }
+
+    #[test]
+    fn test_stable_id_survives_rename() {
+        let content = "intro\n\\begin{lemma}\\label{lem1}\nA\n\\end{lemma}\n\\begin{lemma}\nB\n\\end{lemma}\n";
+        let parse = |path: &str| {
+            parse_sources(&[(path.to_string(), content.to_string())])
+                .unwrap()
+                .stubs
+        };
+        let before = parse("old.tex");
+        let after = parse("chapter/new.tex");
+
+        let id = &before["old.tex/lem1"].stable_id;
+        assert_eq!(id.len(), STABLE_ID_LEN);
+        assert_eq!(&after["chapter/new.tex/lem1"].stable_id, id);
+        assert_eq!(
+            before["old.tex/a0000000000"].stable_id,
+            after["chapter/new.tex/a0000000000"].stable_id
+        );
+        assert_ne!(before["old.tex/a0000000000"].stable_id, *id);
+        assert!(before["old.tex/a0000000000"].generated_label);
+        assert!(!before["old.tex/lem1"].generated_label);
+    }
+
+    #[test]
+    fn test_stable_ids_are_unique() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{lemma}\leanok\end{lemma}
+\begin{lemma}\label{lem:b}\leanok\end{lemma}
+\begin{lemma}\leanok\end{lemma}"
+                    .to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{lemma}\leanok\end{lemma}
+\begin{lemma}\label{lem:f}Every $y$ is fine.\end{lemma}"
+                    .to_string(),
+            ),
+        ];
+        let stubs = parse_sources(&files).unwrap().stubs;
+
+        let ids: HashSet<&str> = stubs.values().map(|s| s.stable_id.as_str()).collect();
+        assert_eq!(ids.len(), stubs.len());
+        // Only the shared IDs are replaced, keeping the shared part
+        let shared = short_hash(br"\begin{lemma}\leanok\end{lemma}");
+        let replaced = ids.iter().filter(|id| id.contains('-')).count();
+        assert_eq!(replaced, 3);
+        assert!(ids
+            .iter()
+            .filter(|id| id.contains('-'))
+            .all(|id| id.starts_with(&format!("{shared}-"))));
+        let alone = parse_sources(&[(
+            "b.tex".to_string(),
+            r"\begin{lemma}\label{lem:f}Every $y$ is fine.\end{lemma}".to_string(),
+        )])
+        .unwrap()
+        .stubs;
+        assert_eq!(
+            stubs["b.tex/lem:f"].stable_id,
+            alone["b.tex/lem:f"].stable_id
+        );
+        // The replacement depends on file and position, not on other stubs
+        let reordered = parse_sources(&[files[1].clone(), files[0].clone()])
+            .unwrap()
+            .stubs;
+        let sorted = |stubs: &HashMap<String, Stub>| {
+            let mut ids: Vec<String> = stubs.values().map(|s| s.stable_id.clone()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(sorted(&reordered), sorted(&stubs));
+    }
+
+    #[test]
+    fn test_number_environments() {
+        let content = r"\begin{lemma}\label{l0}\end{lemma}
+\chapter{Intro}
+\begin{theorem}\label{t1}\end{theorem}
+\begin{lemma}\label{l1}\end{lemma}
+\begin{lemma}\label{l2}\end{lemma}
+\chapter*{Aside}
+% \chapter{Commented out}
+\setcounter{chapter}{4}
+\chapter{Main}
+\begin{lemma}\label{l3}\end{lemma}
+\setcounter{lemma}{6}
+\begin{lemma}\label{l4}\end{lemma}
+\begin{theorem}\label{t2}\end{theorem}";
+        let env_types: Vec<String> = vec!["lemma".to_string(), "theorem".to_string()];
+        let envs = parse_tex_file(content, "a.tex", &env_types);
+
+        let numbers: Vec<(&str, &str)> = envs
+            .iter()
+            .map(|env| (env.labels[0].as_str(), env.number.as_str()))
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![
+                ("l0", "1"),
+                ("t1", "1.1"),
+                ("l1", "1.1"),
+                ("l2", "1.2"),
+                ("l3", "5.1"),
+                ("l4", "5.7"),
+                ("t2", "5.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbering_across_chapter_files() {
+        let files: Vec<(String, String)> = [
+            (
+                "chapter/b.tex",
+                r"\chapter{B}\begin{lemma}\label{b1}\end{lemma}",
+            ),
+            (
+                "chapter/a.tex",
+                r"\chapter{A}\begin{lemma}\label{a1}\end{lemma}\begin{lemma}\label{a2}\end{lemma}",
+            ),
+            (
+                "chapter/c.tex",
+                r"\chapter{C}\begin{lemma}\label{c1}\end{lemma}",
+            ),
+            (
+                "unused.tex",
+                r"\chapter{D}\begin{lemma}\label{d1}\end{lemma}",
+            ),
+            (
+                "content.tex",
+                r"\input{chapter/a}
+% \input{chapter/c}
+\include{./chapter/b.tex}
+\setcounter{chapter}{6}
+\input{chapter/c}",
+            ),
+        ]
+        .into_iter()
+        .map(|(path, content)| (path.to_string(), content.to_string()))
+        .collect();
+        let options = ParseOptions {
+            with_numbering: true,
+            ..ParseOptions::default()
+        };
+
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+        let number = |stub_name: &str| stubs[stub_name].theorem_number.as_deref();
+        assert_eq!(number("chapter/a.tex/a1"), Some("1.1"));
+        assert_eq!(number("chapter/a.tex/a2"), Some("1.2"));
+        assert_eq!(number("chapter/b.tex/b1"), Some("2.1"));
+        assert_eq!(number("chapter/c.tex/c1"), Some("7.1"));
+        // Not read from content.tex
+        assert_eq!(number("unused.tex/d1"), Some("1.1"));
+    }
+
+    #[test]
+    fn test_section_paths() {
+        let content = r"\begin{lemma}\label{l0}\end{lemma}
+\chapter{Algebra}
+\section{Groups}
+\subsection[Subgroups]{Subgroups
+  and cosets}
+\begin{lemma}\label{l1}\end{lemma}
+% \section{Commented out}
+\subsubsection*{$G$-sets}
+\begin{lemma}\label{l2}\end{lemma}
+\section{Rings}
+\begin{lemma}\label{l3}\end{lemma}
+\chapter{Analysis}
+\begin{lemma}\label{l4}\end{lemma}";
+        let env_types: Vec<String> = vec!["lemma".to_string()];
+        let envs = parse_tex_file(content, "a.tex", &env_types);
+
+        let paths: Vec<(&str, Vec<&str>)> = envs
+            .iter()
+            .map(|env| {
+                (
+                    env.labels[0].as_str(),
+                    env.section_path.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("l0", vec![]),
+                ("l1", vec!["Algebra", "Groups", "Subgroups and cosets"]),
+                (
+                    "l2",
+                    vec!["Algebra", "Groups", "Subgroups and cosets", "$G$-sets"]
+                ),
+                ("l3", vec!["Algebra", "Rings"]),
+                ("l4", vec!["Analysis"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_section_paths_heading_without_argument() {
+        // `\section` as the operand of `\let` has no title; neither an
+        // unbalanced `}` nor multi-byte text after it may be taken as one
+        let env_types: Vec<String> = vec!["lemma".to_string()];
+        for content in [
+            "{\\let\\oldsection\\section}\n\\begin{lemma}\\label{l1}\\end{lemma}",
+            "\\let\\oldsection\\section\n\u{c9}tude {x}\n\\begin{lemma}\\label{l1}\\end{lemma}",
+        ] {
+            let envs = parse_tex_file(content, "a.tex", &env_types);
+            assert_eq!(envs.len(), 1);
+            assert!(envs[0].section_path.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_renamed_macros() {
+        // A blueprint migrating from its own macros: both names are recognized
+        let macros: MacroNames = serde_json::from_str(
+            r#"{"uses": ["uses", "dependson"], "lean": "leandecl", "leanok": ["leanok", "formalized"], "proves": "proofof"}"#,
+        )
+        .unwrap();
+        assert_eq!(macros.lean, vec!["leandecl"]);
+        assert_eq!(macros.mathlibok, vec!["mathlibok"]);
+
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{definition}\label{def:a}\leandecl{Foo.a}\formalized\end{definition}
+\begin{lemma}\label{lem:b}\dependson{def:a}\lean{Foo.b}\end{lemma}
+\begin{theorem}\label{thm:c}\uses{lem:b}\leanok\end{theorem}
+\section{Main}
+\dependson{def:a}
+\begin{theorem}\label{thm:d}\end{theorem}
+\begin{proof}\proofof{thm:c}\dependson{lem:b}\formalized\end{proof}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            macros,
+            macro_positions: true,
+            ..ParseOptions::default()
+        };
+
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+
+        let def = &stubs["a.tex/def:a"];
+        assert_eq!(def.code_name.as_deref(), Some("probe:Foo.a"));
+        assert_eq!(def.spec_ok, Some(true));
+        // \lean is no longer a name for the lean role
+        let lem = &stubs["a.tex/lem:b"];
+        assert_eq!(lem.spec_dependencies, vec!["a.tex/def:a"]);
+        assert_eq!(lem.code_name, None);
+        assert_eq!(stubs["a.tex/thm:c"].spec_dependencies, vec!["a.tex/lem:b"]);
+        assert_eq!(stubs["a.tex/thm:c"].spec_ok, Some(true));
+        assert_eq!(stubs["a.tex/thm:d"].spec_dependencies, vec!["a.tex/def:a"]);
+
+        // The standalone proof
+        let thm = &stubs["a.tex/thm:c"];
+        assert_eq!(thm.proof_ok, Some(true));
+        assert_eq!(
+            thm.proof_dependencies,
+            Some(vec!["a.tex/lem:b".to_string()])
+        );
+        // Positions are keyed by role
+        let positions = &stubs["a.tex/lem:b"].macro_positions.as_ref().unwrap();
+        assert_eq!(positions.statement.get("uses"), Some(&2));
+
+        // The defaults are left out of the config
+        let config = serde_json::to_value(Config::default()).unwrap();
+        assert!(config.get("macros").is_none());
+    }
+
+    /// `(can-state, can-prove)` of every stub, by label
+    fn readiness(content: &str) -> BTreeMap<String, (bool, bool)> {
+        parse_sources(&[("a.tex".to_string(), content.to_string())])
+            .unwrap()
+            .stubs
+            .into_values()
+            .map(|stub| (stub.label, (stub.can_state, stub.can_prove)))
+            .collect()
+    }
+
+    #[test]
+    fn test_readiness_chain() {
+        let readiness = readiness(
+            r"\begin{definition}\label{a}\leanok\end{definition}
+\begin{lemma}\label{b}\uses{a}\leanok\end{lemma}
+\begin{proof}\end{proof}
+\begin{theorem}\label{c}\uses{b}\leanok\end{theorem}
+\begin{proof}\leanok\end{proof}
+\begin{theorem}\label{d}\uses{c}\end{theorem}",
+        );
+
+        assert_eq!(readiness["a"], (true, true));
+        // A definition without a proof is proved once stated
+        assert_eq!(readiness["b"], (true, true));
+        // b is stated but its proof is not done
+        assert_eq!(readiness["c"], (true, false));
+        assert_eq!(readiness["d"], (true, false));
+    }
+
+    #[test]
+    fn test_readiness_diamond() {
+        let readiness = readiness(
+            r"\begin{definition}\label{base}\end{definition}
+\begin{definition}\label{lib}\mathlibok\end{definition}
+\begin{lemma}\label{left}\uses{base}\leanok\end{lemma}
+\begin{lemma}\label{right}\uses{base, lib}\end{lemma}
+\begin{theorem}\label{top}\leanok\end{theorem}
+\begin{proof}\uses{left, right}\end{proof}
+\begin{theorem}\label{top2}\uses{lib}\leanok\end{theorem}
+\begin{proof}\uses{left}\end{proof}",
+        );
+
+        assert_eq!(readiness["base"], (true, false));
+        assert_eq!(readiness["left"], (false, false));
+        assert_eq!(readiness["right"], (false, false));
+        // Proof dependencies replace the spec-dependencies for can-prove
+        assert_eq!(readiness["top"], (true, false));
+        assert_eq!(readiness["top2"], (true, true));
+    }
+
+    #[test]
+    fn test_readiness_cycle() {
+        let readiness = readiness(
+            r"\begin{lemma}\label{x}\uses{y}\leanok\end{lemma}
+\begin{lemma}\label{y}\leanok\end{lemma}
+\begin{proof}\uses{x}\end{proof}
+\begin{lemma}\label{self}\uses{self}\leanok\end{lemma}
+\begin{theorem}\label{z}\uses{x}\leanok\end{theorem}",
+        );
+
+        assert_eq!(readiness["x"], (false, false));
+        assert_eq!(readiness["y"], (false, false));
+        assert_eq!(readiness["self"], (false, false));
+        // Only the stubs on the cycle are affected
+        assert_eq!(readiness["z"], (true, true));
+    }
+
+    #[test]
+    fn test_skip_readiness() {
+        let files = [(
+            "a.tex".to_string(),
+            r"\begin{definition}\label{a}\leanok\end{definition}".to_string(),
+        )];
+        let options = ParseOptions {
+            skip_readiness: true,
+            ..ParseOptions::default()
+        };
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+        assert!(!stubs["a.tex/a"].can_state);
+        assert_eq!(readiness(&files[0].1)["a"], (true, true));
+    }
+
+    #[test]
+    fn test_skip_markers() {
+        let content = r"\begin{theorem}\label{thm:classical}
+  \blueprintskip
+  A quoted classical result.
+\end{theorem}
+% probe-blueprint: skip
+\begin{theorem}A restatement.\end{theorem}
+%probe-blueprint:skip
+  \begin{lemma}\begin{theorem}\end{theorem}\end{lemma}
+% probe-blueprint: skip
+
+\begin{lemma}\label{lem:kept}% \blueprintskip
+\end{lemma}
+% probe-blueprint: skip
+Text. \begin{lemma}\label{lem:inline}\end{lemma}
+\begin{lemma}\label{lem:custom}\omit\end{lemma}";
+        let env_types: Vec<String> = vec!["theorem".to_string(), "lemma".to_string()];
+
+        let mut syntax = FileSyntax {
+            env_types: &env_types,
+            proof_after_envs: &[],
+            proof_lookahead: DEFAULT_PROOF_LOOKAHEAD_BYTES,
+            skip_marker: DEFAULT_SKIP_MARKER,
+            macros: &DEFAULT_MACROS,
+        };
+        let FileEnvs {
+            envs,
+            skipped,
+            numbering,
+            ..
+        } = parse_tex_file_profiled(
+            content,
+            "a.tex",
+            &syntax,
+            &mut Profile::default(),
+            &mut Trace::default(),
+        );
+        let labels: Vec<&str> = envs.iter().map(|env| env.labels[0].as_str()).collect();
+        // The comment must be on the line right before \begin, which must
+        // start its line; commented-out markers do not count
+        assert_eq!(labels, vec!["lem:kept", "lem:inline", "lem:custom"]);
+        let skipped: Vec<(usize, usize)> = skipped
+            .iter()
+            .map(|lines| (lines.lines_start, lines.lines_end))
+            .collect();
+        assert_eq!(skipped, vec![(1, 4), (6, 6), (8, 8)]);
+
+        // Skipped environments keep their numbers
+        let numbers = &number_files(&[("a.tex", &numbering)])["a.tex"];
+        assert_eq!(numbers[envs[0].number_index], "2");
+
+        // A configured marker replaces \blueprintskip
+        syntax.skip_marker = "omit";
+        let envs = parse_tex_file_profiled(
+            content,
+            "a.tex",
+            &syntax,
+            &mut Profile::default(),
+            &mut Trace::default(),
+        )
+        .envs;
+        let labels: Vec<&str> = envs.iter().map(|env| env.labels[0].as_str()).collect();
+        assert_eq!(labels, vec!["thm:classical", "lem:kept", "lem:inline"]);
+    }
+
+    #[test]
+    fn test_trace_parse() {
+        let content = r"\begin{lemma}\label{lem1}\leanok\end{lemma}
+See below.
+\begin{proof}\end{proof}
+\begin{theorem}\label{thm1}\uses{lem1}
+  \begin{definition}\label{def1}\end{definition}
+\end{theorem}
+% Proof
+\begin{proof}\leanok\end{proof}
+\begin{thm}\end{thm}";
+        let env_types: Vec<String> = vec![
+            "lemma".to_string(),
+            "theorem".to_string(),
+            "definition".to_string(),
+        ];
+        let syntax = FileSyntax {
+            env_types: &env_types,
+            proof_after_envs: &[],
+            proof_lookahead: DEFAULT_PROOF_LOOKAHEAD_BYTES,
+            skip_marker: DEFAULT_SKIP_MARKER,
+            macros: &DEFAULT_MACROS,
+        };
+        let mut trace = Trace::new(true).child();
+        parse_tex_file_profiled(
+            content,
+            "a.tex",
+            &syntax,
+            &mut Profile::default(),
+            &mut trace,
+        );
+
+        assert_eq!(
+            trace.lines(),
+            [
+                "a.tex",
+                "  thm at bytes 222..242, lines 9-9: not a configured type",
+                "  lemma at bytes 0..43, lines 1-1",
+                "    no proof: 'See below.' on line 2 comes first",
+                "    labels: lem1 (line 1)",
+                r"    statement: \leanok",
+                "  theorem at bytes 80..181, lines 4-6",
+                "    proof at lines 8-8",
+                "    labels: thm1 (line 4)",
+                r"    statement: \uses{lem1 (line 4)}",
+                r"    proof: \leanok",
+                "  definition at bytes 121..167, lines 5-5",
+                "    nested in theorem at lines 4-6",
+                r"    no proof: '\end{theorem}' on line 6 comes first",
+                "    labels: def1 (line 5)",
+                "    statement: no macros",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_section_uses() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{definition}\label{def:a}\end{definition}
+\begin{definition}\label{def:b}\end{definition}
+\uses{def:b}
+\section{Groups}
+\begin{lemma}\label{lem1}\uses{def:b}\end{lemma}
+\begin{proof}\uses{lem0}\end{proof}
+\uses{def:a, % shared
+  def:b}
+\subsection{Subgroups}
+\begin{lemma}\label{lem2}\end{lemma}
+\section{Rings}
+\begin{lemma}\label{lem3}\uses{lem1}\end{lemma}
+\begin{lemma}\label{lem0}\end{lemma}"
+                .to_string(),
+        )];
+
+        let stubs = parse_sources(&files).unwrap().stubs;
+        let uses = |label: &str| stubs[&format!("a.tex/{label}")].spec_dependencies.clone();
+        // The section's \uses is added to the statement's own, deduplicated,
+        // also in subsections
+        assert_eq!(uses("lem1"), vec!["a.tex/def:b", "a.tex/def:a"]);
+        assert_eq!(uses("lem2"), vec!["a.tex/def:a", "a.tex/def:b"]);
+        // Not in the next section, nor before the first heading; a \uses in
+        // a proof is the proof's
+        assert_eq!(uses("lem3"), vec!["a.tex/lem1"]);
+        assert!(uses("def:a").is_empty());
+        assert_eq!(
+            stubs["a.tex/lem1"].proof_dependencies,
+            Some(vec!["a.tex/lem0".to_string()])
+        );
+
+        let options = ParseOptions {
+            no_section_uses: true,
+            ..ParseOptions::default()
+        };
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+        assert_eq!(stubs["a.tex/lem1"].spec_dependencies, vec!["a.tex/def:b"]);
+        assert!(stubs["a.tex/lem2"].spec_dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_theorem_number_only_with_numbering() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\chapter{A}\begin{lemma}\label{lem1}\end{lemma}".to_string(),
+        )];
+
+        let parsed = parse_sources(&files).unwrap();
+        assert!(parsed.stubs["a.tex/lem1"].theorem_number.is_none());
+
+        let options = ParseOptions {
+            with_numbering: true,
+            ..ParseOptions::default()
+        };
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        assert_eq!(
+            parsed.stubs["a.tex/lem1"].theorem_number.as_deref(),
+            Some("1.1")
+        );
+    }
+
+    #[test]
+    fn test_proof_count() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{lemma}\label{lem1}\end{lemma}
+\begin{proof}First.\end{proof}
+\begin{lemma}\label{lem2}\end{lemma}
+\begin{proof}Only.\end{proof}
+\begin{lemma}\label{lem3}\end{lemma}"
+                    .to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{proof}\proves{lem1}\leanok Second.\end{proof}".to_string(),
+            ),
+        ];
+
+        let parsed = parse_sources(&files).unwrap();
+        let lem1 = &parsed.stubs["a.tex/lem1"];
+        assert_eq!(lem1.proof_count, 2);
+        // The first proof lacks \leanok
+        assert_eq!(lem1.proof_ok, None);
+        assert_eq!(parsed.stubs["a.tex/lem2"].proof_count, 1);
+        assert_eq!(parsed.stubs["a.tex/lem3"].proof_count, 0);
+
+        let json = serde_json::to_string(lem1).unwrap();
+        assert!(json.contains(r#""proof-count":2"#), "{}", json);
+        let json = serde_json::to_string(&parsed.stubs["a.tex/lem2"]).unwrap();
+        assert!(!json.contains("proof-count"), "{}", json);
+    }
+
+    #[test]
+    fn test_duplicate_standalone_proofs() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{theorem}\label{thm}\label{thm-alias}\end{theorem}
+\begin{proof}\proves{thm, thm-alias}First.\end{proof}"
+                    .to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"
+
+\begin{proof}\proves{thm}\leanok Second.\end{proof}"
+                    .to_string(),
+            ),
+        ];
+
+        let err = parse_sources(&files).unwrap_err();
+        match &err {
+            ProbeError::DuplicateProof {
+                label,
+                first,
+                second,
+            } => {
+                assert_eq!(label, "thm");
+                assert_eq!(first, "a.tex:2");
+                assert_eq!(second, "b.tex:3");
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+
+        // Naming a statement by two labels in one proof is not a duplicate
+        assert!(parse_sources_with(
+            &files[..1],
+            &ParseOptions::default(),
+            &mut Profile::default()
+        )
+        .is_ok());
+
+        // With the warning, the proofs are merged and the last is stub-proof
+        let options = ParseOptions {
+            warn_duplicate_proofs: true,
+            ..ParseOptions::default()
+        };
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        let thm = &parsed.stubs["a.tex/thm-alias"];
+        assert_eq!(thm.stub_proof.as_ref().unwrap().lines_start, 3);
+        assert_eq!(thm.proof_ok, None);
+        assert_eq!(thm.proof_count, 2);
+    }
+
+    #[test]
+    fn test_proof_parts() {
+        let files = vec![
+            (
+                "a.tex".to_string(),
+                r"\begin{theorem}\label{thm:main}\end{theorem}
+\begin{lemma}\label{lem:a}\end{lemma}
+\begin{lemma}\label{lem:b}\end{lemma}
+\begin{proof}\proves{thm:main}Part 1.\uses{lem:a}\lean{Main.part1}\leanok\end{proof}"
+                    .to_string(),
+            ),
+            (
+                "b.tex".to_string(),
+                r"\begin{proof}\proves{thm:main}Part 2.
+\uses{lem:a, lem:b}\lean{Main.part2}
+\end{proof}"
+                    .to_string(),
+            ),
+        ];
+        let options = ParseOptions {
+            proof_parts: true,
+            macro_positions: true,
+            ..ParseOptions::default()
+        };
+
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        let thm = &parsed.stubs["a.tex/thm:main"];
+        assert_eq!(thm.proof_count, 2);
+        // Part 1 has the first of each macro, and the only \leanok
+        assert_eq!(
+            thm.macro_positions.as_ref().unwrap().proof,
+            BTreeMap::from([
+                ("lean".to_string(), 4),
+                ("leanok".to_string(), 4),
+                ("uses".to_string(), 4)
+            ])
+        );
+        // Part 2 lacks \leanok
+        assert_eq!(thm.proof_ok, None);
+        assert_eq!(
+            thm.proof_dependencies,
+            Some(vec!["a.tex/lem:a".to_string(), "a.tex/lem:b".to_string()])
+        );
+        assert_eq!(
+            thm.proof_lean_names,
+            Some(vec!["Main.part1".to_string(), "Main.part2".to_string()])
+        );
+        let parts: Vec<(&str, usize, usize)> = thm
+            .stub_proof_parts
+            .iter()
+            .map(|part| {
+                (
+                    part.stub_path.as_str(),
+                    part.lines.lines_start,
+                    part.lines.lines_end,
+                )
+            })
+            .collect();
+        assert_eq!(parts, vec![("a.tex", 4, 4), ("b.tex", 1, 3)]);
+        let json = serde_json::to_value(thm).unwrap();
+        assert_eq!(
+            json["stub-proof-parts"][1],
+            serde_json::json!({"stub-path": "b.tex", "lines-start": 1, "lines-end": 3})
+        );
+
+        // With \leanok in every part, the proof is done
+        let mut files = files;
+        files[1].1 = files[1].1.replace("Part 2.", r"Part 2.\leanok");
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        assert_eq!(parsed.stubs["a.tex/thm:main"].proof_ok, Some(true));
+
+        // A single proof has no parts
+        let json = serde_json::to_value(&parsed.stubs["a.tex/lem:a"]).unwrap();
+        assert!(json.get("stub-proof-parts").is_none());
+    }
+
+    #[test]
+    fn test_proof_lookahead_boundary() {
+        let statement = r"\begin{lemma}\label{lem1}\end{lemma}";
+        let proof = r"\begin{proof}\uses{lem0}\end{proof}";
+        let env_types: Vec<String> = vec!["lemma".to_string()];
+
+        let at_limit = format!(
+            "{statement}{}{proof}",
+            "\n".repeat(DEFAULT_PROOF_LOOKAHEAD_BYTES)
+        );
+        let envs = parse_tex_file(&at_limit, "a.tex", &env_types);
+        assert!(envs[0].proof_lines.is_some());
+
+        let past_limit = format!(
+            "{statement}{}{proof}",
+            "\n".repeat(DEFAULT_PROOF_LOOKAHEAD_BYTES + 1)
+        );
+        let envs = parse_tex_file(&past_limit, "a.tex", &env_types);
+        assert!(envs[0].proof_lines.is_none());
+
+        // Comments do not count, e.g. a commented-out old proof
+        let old_proof = "% An old proof.\n".repeat(DEFAULT_PROOF_LOOKAHEAD_BYTES);
+        let commented = format!("{statement}\n{old_proof}{proof}");
+        let envs = parse_tex_file(&commented, "a.tex", &env_types);
+        assert!(envs[0].proof_lines.is_some());
+
+        // Any other environment in between ends the search
+        let interrupted = format!("{statement}\n\\begin{{remark}}\\end{{remark}}\n{proof}");
+        let envs = parse_tex_file(&interrupted, "a.tex", &env_types);
+        assert!(envs[0].proof_lines.is_none());
+    }
+
+    #[test]
+    fn test_configured_proof_lookahead() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\end{lemma}
+
+
+\begin{proof}\leanok\end{proof}"
+                .to_string(),
+        )];
+
+        let parsed = parse_sources(&files).unwrap();
+        assert_eq!(parsed.stubs["a.tex/lem1"].proof_ok, Some(true));
+
+        let options = ParseOptions {
+            proof_lookahead: Some(2),
+            ..ParseOptions::default()
+        };
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+        assert!(parsed.stubs["a.tex/lem1"].stub_proof.is_none());
+        assert_eq!(parsed.stubs["a.tex/lem1"].proof_ok, None);
+    }
+
+    #[test]
+    fn test_proof_after_envs() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\end{lemma}
+\begin{remark}A remark.\end{remark}
+% comment
+\begin{remark}\begin{proof}\end{proof}\end{remark}
+\begin{proof}\leanok\end{proof}
+
+\begin{lemma}\label{lem2}\end{lemma}
+\begin{example}An example.\end{example}
+\begin{proof}\end{proof}
+
+\begin{lemma}\label{lem3}\end{lemma}
+\begin{remark}A remark.\end{remark}
+Some text.
+\begin{proof}\end{proof}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            proof_after_envs: vec!["remark".to_string()],
+            ..ParseOptions::default()
+        };
+
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+
+        // The proof after the remarks, not the one nested in the second remark
+        let proof = stubs["a.tex/lem1"].stub_proof.unwrap();
+        assert_eq!((proof.lines_start, proof.lines_end), (5, 5));
+        assert_eq!(stubs["a.tex/lem1"].proof_ok, Some(true));
+        // Unlisted environments and text still end the search
+        assert!(stubs["a.tex/lem2"].stub_proof.is_none());
+        assert!(stubs["a.tex/lem3"].stub_proof.is_none());
+
+        // Without the option, the remark separates the lemma from its proof
+        let stubs = parse_sources(&files).unwrap().stubs;
+        assert!(stubs["a.tex/lem1"].stub_proof.is_none());
+    }
+
+    #[test]
+    fn test_macro_positions() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}
+% \leanok in a comment
+\uses{def1}
+\lean{A.one}\leanok
+\end{lemma}
+\begin{proof}
+\uses{def1}
+
+\leanok
+\end{proof}
+
+\begin{definition}\label{def1}\notready\end{definition}
+
+\begin{proof}\proves{lem2}
+  \leanok
+\end{proof}
+\begin{lemma}\label{lem2}\mathlibok\end{lemma}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            macro_positions: true,
+            ..ParseOptions::default()
+        };
+
+        let stubs = parse_sources_with(&files, &options, &mut Profile::default())
+            .unwrap()
+            .stubs;
+
+        let lines = |pairs: &[(&str, usize)]| {
+            pairs
+                .iter()
+                .map(|(name, line)| (name.to_string(), *line))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let lem1 = stubs["a.tex/lem1"].macro_positions.as_ref().unwrap();
+        assert_eq!(
+            lem1.statement,
+            lines(&[("uses", 3), ("lean", 4), ("leanok", 4)])
+        );
+        assert_eq!(lem1.proof, lines(&[("uses", 7), ("leanok", 9)]));
+        let def1 = stubs["a.tex/def1"].macro_positions.as_ref().unwrap();
+        assert_eq!(def1.statement, lines(&[("notready", 12)]));
+        assert!(def1.proof.is_empty());
+        // A standalone proof gives the proof positions of the stub it proves
+        let lem2 = stubs["a.tex/lem2"].macro_positions.as_ref().unwrap();
+        assert_eq!(lem2.statement, lines(&[("mathlibok", 17)]));
+        assert_eq!(lem2.proof, lines(&[("leanok", 15)]));
+
+        // Off by default
+        let stubs = parse_sources(&files).unwrap().stubs;
+        assert!(stubs["a.tex/lem1"].macro_positions.is_none());
+    }
+
+    #[test]
+    fn test_labels_map_includes_aliases() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{alias}\label{lem1}\end{lemma}
+\begin{theorem}\label{thm1}\lean{A, B}\end{theorem}"
+                .to_string(),
+        )];
+
+        let labels = parse_sources(&files).unwrap().labels;
+
+        assert_eq!(labels["alias"], "a.tex/lem1");
+        assert_eq!(labels["lem1"], "a.tex/lem1");
+        assert_eq!(labels["thm1"], "a.tex/thm1");
+        // Split stubs are reachable by their generated child labels
+        assert_eq!(labels.len(), 5);
+    }
+
+    #[test]
+    fn test_lean_annotations_fill_missing_names() {
+        let files = vec![(
+            "a.tex".to_string(),
+            r"\begin{lemma}\label{lem1}\end{lemma}
+\begin{lemma}\label{lem2}\lean{Src.two}\end{lemma}
+\begin{lemma}\label{lem3}\end{lemma}"
+                .to_string(),
+        )];
+        let options = ParseOptions {
+            lean_annotations: HashMap::from([
+                ("lem1".to_string(), vec!["Lean.one".to_string()]),
+                ("lem2".to_string(), vec!["Lean.two".to_string()]),
+                (
+                    "lem3".to_string(),
+                    vec!["Lean.a".to_string(), "Lean.b".to_string()],
+                ),
+                ("unknown".to_string(), vec!["Lean.x".to_string()]),
+            ]),
+            ..ParseOptions::default()
+        };
+
+        let parsed = parse_sources_with(&files, &options, &mut Profile::default()).unwrap();
+
+        assert_eq!(
+            parsed.stubs["a.tex/lem1"].code_name.as_deref(),
+            Some("probe:Lean.one")
+        );
+        // Conflicting LaTeX names win
+        assert_eq!(
+            parsed.stubs["a.tex/lem2"].code_name.as_deref(),
+            Some("probe:Src.two")
+        );
+        // Several annotated declarations split the stub like \lean{a, b}
+        assert_eq!(parsed.stubs["a.tex/lem3"].spec_dependencies.len(), 2);
+    }
+}
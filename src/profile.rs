@@ -0,0 +1,117 @@
+//! Per-stage timing for `stubify --profile`
+//!
+//! A disabled profile never reads the clock, so the parsing core can be
+//! instrumented unconditionally and still run where `Instant` is unavailable
+//! (e.g. wasm32-unknown-unknown).
+
+use std::time::{Duration, Instant};
+
+/// Accumulated wall-clock time per processing stage, in first-seen order
+///
+/// Stages run in parallel are summed across threads.
+#[derive(Debug, Default)]
+pub struct Profile {
+    enabled: bool,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Profile {
+    pub fn new(enabled: bool) -> Self {
+        Profile {
+            enabled,
+            stages: Vec::new(),
+        }
+    }
+
+    /// A fresh profile with the same enabled state, for work done elsewhere
+    /// (e.g. on another thread) and later combined with [`Profile::merge`]
+    pub fn child(&self) -> Self {
+        Profile::new(self.enabled)
+    }
+
+    /// Add the stage times of `other` to this profile
+    pub fn merge(&mut self, other: Profile) {
+        for (stage, duration) in other.stages {
+            self.add(stage, duration);
+        }
+    }
+
+    /// Start timing a stage; returns `None` when profiling is disabled
+    pub fn start(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    /// Add the time elapsed since `start` to `stage`
+    pub fn record(&mut self, stage: &'static str, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.add(stage, start.elapsed());
+        }
+    }
+
+    fn add(&mut self, stage: &'static str, duration: Duration) {
+        match self.stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, total)) => *total += duration,
+            None => self.stages.push((stage, duration)),
+        }
+    }
+
+    /// Format the recorded stages as a table
+    pub fn report(&self) -> String {
+        let total: Duration = self.stages.iter().map(|(_, d)| *d).sum();
+        let width = self
+            .stages
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+
+        let mut out = format!("{:<width$}  {:>10}  {:>6}\n", "stage", "ms", "%");
+        for (name, duration) in self.stages.iter().chain([&("total", total)]) {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * duration.as_secs_f64() / total.as_secs_f64()
+            };
+            out.push_str(&format!(
+                "{:<width$}  {:>10.3}  {:>6.1}\n",
+                name,
+                duration.as_secs_f64() * 1000.0,
+                percent
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_profile_records_nothing() {
+        let mut profile = Profile::default();
+        let start = profile.start();
+        assert!(start.is_none());
+        profile.record("stage", start);
+        assert!(profile.stages.is_empty());
+    }
+
+    #[test]
+    fn test_record_accumulates_per_stage() {
+        let mut profile = Profile::new(true);
+        for _ in 0..2 {
+            let start = profile.start();
+            profile.record("a", start);
+        }
+        let start = profile.start();
+        profile.record("b", start);
+
+        let names: Vec<_> = profile.stages.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        let report = profile.report();
+        assert!(report.lines().next().unwrap().starts_with("stage"));
+        assert!(report.lines().last().unwrap().starts_with("total"));
+    }
+}
@@ -0,0 +1,174 @@
+//! pyo3 bindings exposing the parser and the atomize/verify transformations
+//!
+//! All functions exchange plain Python dicts shaped exactly like the
+//! corresponding JSON files (stubs.json, atoms.json, proofs.json).
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::commands::stubify::{PathBase, StubifyOptions};
+use crate::commands::{atomize, stubify, verify};
+use crate::error;
+
+create_exception!(
+    probe_blueprint,
+    BlueprintError,
+    PyException,
+    "Error raised by probe-blueprint, with `kind`, `message`, `error_type`, \
+     `context` and `diagnostic` attributes."
+);
+
+/// Classify an error for the `kind` attribute of `BlueprintError`
+fn error_kind(err: &(dyn Error + 'static)) -> &'static str {
+    if err.is::<std::io::Error>() {
+        "io"
+    } else if err.is::<serde_json::Error>() {
+        "json"
+    } else {
+        "blueprint"
+    }
+}
+
+/// Convert an error into a `BlueprintError` carrying its kind and message,
+/// and the diagnostic `--emit-machine-readable-errors` prints for it
+fn to_py_err(py: Python<'_>, err: Box<dyn Error>) -> PyErr {
+    let kind = error_kind(err.as_ref());
+    let message = err.to_string();
+    let diagnostic = error::to_machine_readable(err.as_ref());
+    let py_err = BlueprintError::new_err(message.clone());
+    let value = py_err.value(py);
+    // Attribute assignment on a fresh exception instance cannot fail in practice
+    let _ = value.setattr("kind", kind);
+    let _ = value.setattr("message", message);
+    let _ = value.setattr("error_type", diagnostic["error_type"].as_str());
+    if let Ok(context) = to_py(py, &diagnostic["context"]) {
+        let _ = value.setattr("context", context);
+    }
+    if let Ok(diagnostic) = to_py(py, &diagnostic) {
+        let _ = value.setattr("diagnostic", diagnostic);
+    }
+    py_err
+}
+
+/// Convert a Python object to a Rust value by round-tripping through `json`
+fn from_py<T: DeserializeOwned>(obj: &Bound<'_, PyAny>) -> PyResult<T> {
+    let py = obj.py();
+    let json: String = py
+        .import("json")?
+        .call_method1("dumps", (obj,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(|e| to_py_err(py, e.into()))
+}
+
+/// Convert a Rust value to a plain Python object by round-tripping through `json`
+fn to_py<'py, T: Serialize>(py: Python<'py>, value: &T) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_string(value).map_err(|e| to_py_err(py, e.into()))?;
+    py.import("json")?.call_method1("loads", (json,))
+}
+
+/// A Lean source root option: `True` for the roots from the Lake
+/// configuration, a path for that root, `False` or `None` for off
+fn lean_root(value: &Bound<'_, PyAny>) -> PyResult<Option<Option<String>>> {
+    if value.is_none() {
+        return Ok(None);
+    }
+    if let Ok(enabled) = value.extract::<bool>() {
+        return Ok(enabled.then_some(None));
+    }
+    Ok(Some(Some(value.extract()?)))
+}
+
+/// The `StubifyOptions` for the keyword arguments of `parse_blueprint`, named
+/// like the stubify options that shape the stubs (`--with-numbering` is
+/// `with_numbering`)
+fn stubify_options(options: Option<&Bound<'_, PyDict>>) -> PyResult<StubifyOptions> {
+    let mut stubify = StubifyOptions::default();
+    let Some(options) = options else {
+        return Ok(stubify);
+    };
+    for (key, value) in options.iter() {
+        let key: String = key.extract()?;
+        match key.as_str() {
+            "jobs" => stubify.jobs = value.extract()?,
+            "lean_names_file" => stubify.lean_names_file = value.extract()?,
+            "prefer_source" => stubify.prefer_source = value.extract()?,
+            "locate_lean" => stubify.locate_lean = lean_root(&value)?,
+            "lean_annotations" => stubify.lean_annotations = lean_root(&value)?,
+            "lean_annotation_marker" => stubify.lean_annotation_marker = value.extract()?,
+            "with_numbering" => stubify.with_numbering = value.extract()?,
+            "qualify_names" => stubify.qualify_names = value.extract()?,
+            "env_type_map" => stubify.env_type_map = value.extract()?,
+            "warn_duplicate_proofs" => stubify.warn_duplicate_proofs = value.extract()?,
+            "strict" => stubify.strict = value.extract()?,
+            "proof_parts" => stubify.proof_parts = value.extract()?,
+            "proof_after_envs" => stubify.proof_after_envs = value.extract()?,
+            "proof_lookahead" => stubify.proof_lookahead = value.extract()?,
+            "macro_positions" => stubify.macro_positions = value.extract()?,
+            "no_section_uses" => stubify.no_section_uses = value.extract()?,
+            "skip_marker" => stubify.skip_marker = value.extract()?,
+            "path_base" => {
+                let base: String = value.extract()?;
+                stubify.path_base = serde_json::from_value::<PathBase>(base.clone().into())
+                    .map_err(|_| {
+                        PyValueError::new_err(format!(
+                            "path_base must be 'src' or 'project', not '{base}'"
+                        ))
+                    })?;
+            }
+            _ => {
+                return Err(PyTypeError::new_err(format!(
+                    "parse_blueprint() got an unexpected keyword argument '{key}'"
+                )))
+            }
+        }
+    }
+    Ok(stubify)
+}
+
+/// Parse the blueprint of a project and return the stubs as a dict, as
+/// `stubify` writes them with the same options
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn parse_blueprint<'py>(
+    py: Python<'py>,
+    path: &str,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let options = stubify_options(options)?;
+    let parsed = stubify::build_project(Path::new(path), &options).map_err(|e| to_py_err(py, e))?;
+    to_py(py, &parsed.stubs)
+}
+
+/// Transform a stubs dict into an atoms dict
+#[pyfunction]
+#[pyo3(name = "atomize")]
+fn py_atomize<'py>(py: Python<'py>, stubs: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    let stubs: HashMap<String, atomize::Stub> = from_py(stubs)?;
+    let atoms = atomize::build_atoms(&stubs, atomize::DependencyKind::All);
+    to_py(py, &atoms)
+}
+
+/// Transform a stubs dict into a proofs dict
+#[pyfunction]
+#[pyo3(name = "verify")]
+fn py_verify<'py>(py: Python<'py>, stubs: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    let stubs: HashMap<String, verify::Stub> = from_py(stubs)?;
+    let proofs = verify::build_proofs(&stubs);
+    to_py(py, &proofs)
+}
+
+#[pymodule]
+fn probe_blueprint(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("BlueprintError", m.py().get_type::<BlueprintError>())?;
+    m.add_function(wrap_pyfunction!(parse_blueprint, m)?)?;
+    m.add_function(wrap_pyfunction!(py_atomize, m)?)?;
+    m.add_function(wrap_pyfunction!(py_verify, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,506 @@
+//! Single-pass LaTeX tokenizer
+//!
+//! Walks the source once and produces a flat stream of tokens with byte
+//! offsets: commands (with the braced arguments that immediately follow them),
+//! environment begin/end events with their nesting depth, comments, verbatim
+//! spans and plain text. Comments and verbatim content are opaque, so commands
+//! and environments inside them never reach the parser.
+
+use std::ops::Range;
+
+/// Environments whose body is taken literally
+const VERBATIM_ENVS: &[&str] = &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind<'a> {
+    /// `\name` or a control symbol such as `\%`, with the byte ranges of the
+    /// braced arguments directly following it (contents, without the braces)
+    Command {
+        name: &'a str,
+        args: Vec<Range<usize>>,
+    },
+    /// `\begin{name}`; `depth` is the number of environments already open
+    Begin { name: &'a str, depth: usize },
+    /// `\end{name}`; `depth` is the depth of the matching `\begin`
+    End { name: &'a str, depth: usize },
+    /// `%` up to (not including) the newline, or an `\iffalse ... \fi` block
+    Comment,
+    /// Literal content: a verbatim environment body or `\verb|...|`
+    Verbatim,
+    /// Anything else
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A matched `\begin{name} ... \end{name}` pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment<'a> {
+    pub name: &'a str,
+    pub depth: usize,
+    /// From the start of `\begin` to the end of `\end{name}`
+    pub outer: Range<usize>,
+    /// Between `\begin{name}` and `\end{name}`
+    pub body: Range<usize>,
+    /// Index of the `Begin` token
+    pub begin_token: usize,
+    /// Index of the `End` token
+    pub end_token: usize,
+}
+
+/// Find the end of a `{...}` group starting at `pos` (which must be `{`),
+/// skipping escaped characters and comments. Returns the position after `}`.
+pub(crate) fn braced_group_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut i = pos;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'%' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'{' => depth += 1,
+            b'}' => {
+                // A `}` before any `{` closes an enclosing group, not this one
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Read a command name starting right after a backslash at `pos`.
+/// Returns the end of the name (letters, or a single control symbol).
+fn command_name_end(src: &str, pos: usize) -> usize {
+    let bytes = src.as_bytes();
+    if pos >= bytes.len() {
+        return pos;
+    }
+    if bytes[pos].is_ascii_alphabetic() {
+        let mut end = pos;
+        while end < bytes.len() && bytes[end].is_ascii_alphabetic() {
+            end += 1;
+        }
+        end
+    } else {
+        // Control symbol: exactly one (possibly multi-byte) character
+        pos + src[pos..].chars().next().map_or(0, char::len_utf8)
+    }
+}
+
+/// Find the end of an `\iffalse` block starting after the `\iffalse` command,
+/// honouring nested conditionals. Returns the position after the matching `\fi`.
+fn iffalse_end(src: &str, mut pos: usize) -> usize {
+    let bytes = src.as_bytes();
+    let mut depth = 1usize;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'%' => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            b'\\' => {
+                let name_end = command_name_end(src, pos + 1);
+                let name = &src[pos + 1..name_end];
+                if name == "fi" {
+                    depth -= 1;
+                    if depth == 0 {
+                        return name_end;
+                    }
+                } else if name.starts_with("if") && name != "iff" {
+                    depth += 1;
+                }
+                pos = name_end.max(pos + 1);
+            }
+            _ => pos += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Tokenize LaTeX source in a single forward pass
+pub fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    // Names of currently open environments
+    let mut open: Vec<&str> = Vec::new();
+    let mut pos = 0;
+    let mut text_start: Option<usize> = None;
+
+    macro_rules! flush_text {
+        () => {
+            if let Some(start) = text_start.take() {
+                tokens.push(Token {
+                    kind: TokenKind::Text,
+                    start,
+                    end: pos,
+                });
+            }
+        };
+    }
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'%' => {
+                flush_text!();
+                let start = pos;
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Comment,
+                    start,
+                    end: pos,
+                });
+            }
+            b'\\' => {
+                flush_text!();
+                let start = pos;
+                let name_end = command_name_end(src, pos + 1);
+                let name = &src[pos + 1..name_end];
+
+                match name {
+                    "begin" | "end" if bytes.get(name_end) == Some(&b'{') => {
+                        let Some(group_end) = braced_group_end(bytes, name_end) else {
+                            pos = name_end;
+                            tokens.push(Token {
+                                kind: TokenKind::Command { name, args: vec![] },
+                                start,
+                                end: pos,
+                            });
+                            continue;
+                        };
+                        let env_name = src[name_end + 1..group_end - 1].trim();
+                        pos = group_end;
+
+                        if name == "begin" {
+                            tokens.push(Token {
+                                kind: TokenKind::Begin {
+                                    name: env_name,
+                                    depth: open.len(),
+                                },
+                                start,
+                                end: pos,
+                            });
+
+                            if VERBATIM_ENVS.contains(&env_name) {
+                                // Body is literal up to the matching \end{name}
+                                let closing = format!("\\end{{{env_name}}}");
+                                let body_end = src[pos..]
+                                    .find(&closing)
+                                    .map_or(bytes.len(), |offset| pos + offset);
+                                if body_end > pos {
+                                    tokens.push(Token {
+                                        kind: TokenKind::Verbatim,
+                                        start: pos,
+                                        end: body_end,
+                                    });
+                                }
+                                pos = body_end;
+                                if body_end < bytes.len() {
+                                    let end_start = pos;
+                                    pos += closing.len();
+                                    tokens.push(Token {
+                                        kind: TokenKind::End {
+                                            name: env_name,
+                                            depth: open.len(),
+                                        },
+                                        start: end_start,
+                                        end: pos,
+                                    });
+                                }
+                            } else {
+                                open.push(env_name);
+                            }
+                        } else {
+                            // Close the innermost open environment with this
+                            // name, implicitly closing any unterminated ones inside
+                            let depth = match open.iter().rposition(|&n| n == env_name) {
+                                Some(index) => {
+                                    open.truncate(index);
+                                    index
+                                }
+                                None => open.len(),
+                            };
+                            tokens.push(Token {
+                                kind: TokenKind::End {
+                                    name: env_name,
+                                    depth,
+                                },
+                                start,
+                                end: pos,
+                            });
+                        }
+                    }
+                    "verb" => {
+                        // \verb|...| or \verb*|...|: delimiter is the next character
+                        let mut delim_pos = name_end;
+                        if bytes.get(delim_pos) == Some(&b'*') {
+                            delim_pos += 1;
+                        }
+                        let closing = src[delim_pos..].chars().next().and_then(|delim| {
+                            let body_start = delim_pos + delim.len_utf8();
+                            src[body_start..]
+                                .find([delim, '\n'])
+                                .map(|offset| body_start + offset)
+                                .filter(|&end| src[end..].starts_with(delim))
+                                .map(|end| end + delim.len_utf8())
+                        });
+                        match closing {
+                            Some(end) => {
+                                pos = end;
+                                tokens.push(Token {
+                                    kind: TokenKind::Verbatim,
+                                    start,
+                                    end,
+                                });
+                            }
+                            None => {
+                                pos = name_end;
+                                tokens.push(Token {
+                                    kind: TokenKind::Command { name, args: vec![] },
+                                    start,
+                                    end: pos,
+                                });
+                            }
+                        }
+                    }
+                    "iffalse" => {
+                        pos = iffalse_end(src, name_end);
+                        tokens.push(Token {
+                            kind: TokenKind::Comment,
+                            start,
+                            end: pos,
+                        });
+                    }
+                    _ => {
+                        pos = name_end;
+                        // Collect directly following braced arguments
+                        let mut args = Vec::new();
+                        if name.bytes().all(|b| b.is_ascii_alphabetic()) {
+                            while bytes.get(pos) == Some(&b'{') {
+                                match braced_group_end(bytes, pos) {
+                                    Some(group_end) => {
+                                        args.push(pos + 1..group_end - 1);
+                                        pos = group_end;
+                                    }
+                                    None => break,
+                                }
+                            }
+                        }
+                        tokens.push(Token {
+                            kind: TokenKind::Command { name, args },
+                            start,
+                            end: pos,
+                        });
+                    }
+                }
+            }
+            _ => {
+                if text_start.is_none() {
+                    text_start = Some(pos);
+                }
+                pos += 1;
+            }
+        }
+    }
+    flush_text!();
+
+    tokens
+}
+
+/// Pair `Begin`/`End` tokens into environments, ordered by start position.
+/// Unterminated environments are dropped.
+pub fn environments<'a>(tokens: &[Token<'a>]) -> Vec<Environment<'a>> {
+    let mut envs = Vec::new();
+    // Indices of the Begin tokens of currently open environments
+    let mut open: Vec<usize> = Vec::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Begin { .. } => open.push(index),
+            TokenKind::End { name, .. } => {
+                let matching = open.iter().rposition(|&begin| {
+                    matches!(tokens[begin].kind, TokenKind::Begin { name: n, .. } if n == name)
+                });
+                if let Some(position) = matching {
+                    let begin_index = open[position];
+                    open.truncate(position);
+                    let begin = &tokens[begin_index];
+                    let TokenKind::Begin { depth, .. } = begin.kind else {
+                        unreachable!()
+                    };
+                    envs.push(Environment {
+                        name,
+                        depth,
+                        outer: begin.start..token.end,
+                        body: begin.end..token.start,
+                        begin_token: begin_index,
+                        end_token: index,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    envs.sort_by_key(|env| env.outer.start);
+    envs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds<'a>(tokens: &'a [Token<'a>]) -> Vec<&'a TokenKind<'a>> {
+        tokens.iter().map(|t| &t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_commands_and_text() {
+        let src = r"a \label{x}\leanok b";
+        let tokens = tokenize(src);
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                &TokenKind::Text,
+                &TokenKind::Command {
+                    name: "label",
+                    args: vec![Range { start: 9, end: 10 }]
+                },
+                &TokenKind::Command {
+                    name: "leanok",
+                    args: vec![]
+                },
+                &TokenKind::Text,
+            ]
+        );
+        assert_eq!(&src[tokens[1].start..tokens[1].end], r"\label{x}");
+    }
+
+    #[test]
+    fn test_tokenize_control_symbol_is_not_comment() {
+        let tokens = tokenize(r"50\% off % comment");
+        assert!(matches!(
+            tokens[1].kind,
+            TokenKind::Command { name: "%", .. }
+        ));
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::Comment);
+    }
+
+    #[test]
+    fn test_tokenize_begin_end_depth() {
+        let tokens = tokenize(r"\begin{a}\begin{b}\end{b}\end{a}");
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                &TokenKind::Begin {
+                    name: "a",
+                    depth: 0
+                },
+                &TokenKind::Begin {
+                    name: "b",
+                    depth: 1
+                },
+                &TokenKind::End {
+                    name: "b",
+                    depth: 1
+                },
+                &TokenKind::End {
+                    name: "a",
+                    depth: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_verbatim_is_opaque() {
+        let src = "\\begin{verbatim}\\begin{theorem} % x\\end{verbatim}";
+        let tokens = tokenize(src);
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                &TokenKind::Begin {
+                    name: "verbatim",
+                    depth: 0
+                },
+                &TokenKind::Verbatim,
+                &TokenKind::End {
+                    name: "verbatim",
+                    depth: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_verb() {
+        let tokens = tokenize(r"\verb|\begin{x}| \verb+%+");
+        assert_eq!(tokens[0].kind, TokenKind::Verbatim);
+        assert_eq!(tokens[2].kind, TokenKind::Verbatim);
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_tokenize_iffalse() {
+        let src = r"a\iffalse \ifx x \fi \begin{theorem} \fi b";
+        let tokens = tokenize(src);
+        assert_eq!(
+            kinds(&tokens),
+            vec![&TokenKind::Text, &TokenKind::Comment, &TokenKind::Text]
+        );
+        assert_eq!(&src[tokens[2].start..], " b");
+    }
+
+    #[test]
+    fn test_tokenize_argument_skips_comment_braces() {
+        let src = "\\uses{a, % }\n b}";
+        let tokens = tokenize(src);
+        assert_eq!(tokens.len(), 1);
+        let TokenKind::Command { args, .. } = &tokens[0].kind else {
+            panic!("expected command");
+        };
+        assert_eq!(&src[args[0].clone()], "a, % }\n b");
+    }
+
+    #[test]
+    fn test_braced_group_end_unbalanced() {
+        assert_eq!(braced_group_end(b"{a{b}}c", 0), Some(6));
+        assert_eq!(braced_group_end(b"a}{b}", 0), None);
+    }
+
+    #[test]
+    fn test_environments_nested_same_name() {
+        let src = r"\begin{t}A\begin{t}B\end{t}C\end{t}";
+        let tokens = tokenize(src);
+        let envs = environments(&tokens);
+        assert_eq!(envs.len(), 2);
+        assert_eq!(&src[envs[0].body.clone()], r"A\begin{t}B\end{t}C");
+        assert_eq!(envs[0].depth, 0);
+        assert_eq!(&src[envs[1].body.clone()], "B");
+        assert_eq!(envs[1].depth, 1);
+    }
+
+    #[test]
+    fn test_environments_unterminated_dropped() {
+        let src = r"\begin{a}\begin{b}\end{a}";
+        let envs = environments(&tokenize(src));
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].name, "a");
+    }
+}
@@ -0,0 +1,90 @@
+//! Parse trace for `stubify --trace-parse`
+//!
+//! Like a [`Profile`](crate::profile::Profile), a trace is passed through the
+//! parsing code itself, so it shows what that code did rather than what a
+//! reimplementation thinks it would do. A disabled trace builds no messages.
+//! Files parsed in parallel record into their own child trace, which the
+//! sequential phase prints in file order.
+
+/// Indented lines describing how sources were parsed, on stderr
+#[derive(Debug, Default)]
+pub struct Trace {
+    enabled: bool,
+    /// Lines held back until merged into the parent, for a child trace;
+    /// `None` prints each line right away
+    buffer: Option<Vec<String>>,
+}
+
+impl Trace {
+    pub fn new(enabled: bool) -> Self {
+        Trace {
+            enabled,
+            buffer: None,
+        }
+    }
+
+    /// A trace with the same enabled state that keeps its lines until
+    /// [`Trace::merge`]d, for work done elsewhere (e.g. on another thread)
+    pub fn child(&self) -> Self {
+        Trace {
+            enabled: self.enabled,
+            buffer: Some(Vec::new()),
+        }
+    }
+
+    /// Record the line built by `message`, indented by `depth` levels
+    pub fn line(&mut self, depth: usize, message: impl FnOnce() -> String) {
+        if !self.enabled {
+            return;
+        }
+        self.push(format!("{}{}", "  ".repeat(depth), message()));
+    }
+
+    fn push(&mut self, line: String) {
+        match &mut self.buffer {
+            Some(buffer) => buffer.push(line),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Record the lines of a child trace after those recorded so far
+    pub fn merge(&mut self, other: Trace) {
+        for line in other.buffer.unwrap_or_default() {
+            self.push(line);
+        }
+    }
+
+    /// Lines held back by a child trace
+    pub fn lines(&self) -> &[String] {
+        self.buffer.as_deref().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_trace_builds_nothing() {
+        let mut trace = Trace::default().child();
+        trace.line(0, || unreachable!());
+        assert!(trace.lines().is_empty());
+    }
+
+    #[test]
+    fn test_merge_keeps_order() {
+        let root = Trace::new(true);
+        let mut parent = root.child();
+        let mut child = parent.child();
+        parent.line(0, || "a.tex".to_string());
+        child.line(1, || "lemma".to_string());
+        child.line(2, || "labels: lem1".to_string());
+        parent.merge(child);
+        parent.line(0, || "b.tex".to_string());
+
+        assert_eq!(
+            parent.lines(),
+            ["a.tex", "  lemma", "    labels: lem1", "b.tex"]
+        );
+    }
+}
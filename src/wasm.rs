@@ -0,0 +1,15 @@
+//! wasm-bindgen bindings for running stub extraction on in-memory sources
+
+use wasm_bindgen::prelude::*;
+
+use crate::parse;
+
+/// Parse blueprint sources given as a JSON array of `[relative_path, content]`
+/// pairs (paths relative to `blueprint/src`) and return the parsed stubs and
+/// project config as JSON: `{"stubs": {...}, "config": {...}}`
+#[wasm_bindgen]
+pub fn parse_files(files_json: &str) -> Result<String, JsError> {
+    let files: Vec<(String, String)> = serde_json::from_str(files_json)?;
+    let parsed = parse::parse_sources(&files).map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(serde_json::to_string(&parsed)?)
+}
@@ -0,0 +1,154 @@
+"""Tests for the pyo3 bindings, checked against the CLI on the fixture project.
+
+Build the extension into the active environment first, e.g.
+`maturin develop` (features come from pyproject.toml), then run
+`pytest tests/python`. The CLI used for comparison is taken from
+`PROBE_BLUEPRINT_BIN`, falling back to `cargo run`.
+"""
+
+import json
+import os
+import shutil
+import subprocess
+from pathlib import Path
+
+import pytest
+
+import probe_blueprint
+
+REPO = Path(__file__).resolve().parents[2]
+FIXTURE = REPO / "tests" / "fixtures" / "project"
+
+
+def run_cli(*args):
+    binary = os.environ.get("PROBE_BLUEPRINT_BIN")
+    if binary:
+        cmd = [binary]
+    else:
+        cmd = ["cargo", "run", "--quiet", "--manifest-path", str(REPO / "Cargo.toml"), "--"]
+    subprocess.run(cmd + [str(arg) for arg in args], check=True, capture_output=True)
+
+
+def load(path):
+    return json.loads(Path(path).read_text())
+
+
+@pytest.fixture
+def project(tmp_path):
+    """The fixture project, with a .verilib/config.json that changes the stubs"""
+    dest = tmp_path / "project"
+    shutil.copytree(FIXTURE, dest)
+    chapter = dest / "blueprint" / "src" / "chapter"
+    (chapter / "extra.tex").write_text(
+        "\\begin{lemma}\\label{lem:extra}\\dependson{def:magma}\\end{lemma}\n"
+        "\\begin{remark}Not a statement.\\end{remark}\n"
+    )
+    (chapter / "generated.tex").write_text("\\begin{lemma}\\label{lem:gen}\\end{lemma}\n")
+    (dest / ".verilib").mkdir()
+    (dest / ".verilib" / "config.json").write_text(
+        json.dumps(
+            {
+                "skip-files": ["generated.tex"],
+                "macros": {"uses": ["uses", "dependson"]},
+                "ignored-envs": ["remark"],
+            }
+        )
+    )
+    return dest
+
+
+def test_parse_blueprint_matches_cli(project):
+    run_cli("stubify", project, "-o", project / ".verilib" / "stubs.json")
+    expected = load(project / ".verilib" / "stubs.json")
+
+    assert probe_blueprint.parse_blueprint(str(project)) == expected
+    # The config of the project applies to both
+    assert "chapter/generated.tex/lem:gen" not in expected
+    assert expected["chapter/extra.tex/lem:extra"]["spec-dependencies"] == [
+        "chapter/basics.tex/def:magma"
+    ]
+
+
+def test_parse_blueprint_options_match_cli(project):
+    output = project / ".verilib" / "stubs.json"
+    run_cli(
+        "stubify", project, "-o", output,
+        "--with-numbering", "--macro-positions", "--path-base", "project", "--jobs", "2",
+    )
+    expected = load(output)
+
+    stubs = probe_blueprint.parse_blueprint(
+        str(project), with_numbering=True, macro_positions=True, path_base="project", jobs=2
+    )
+
+    assert stubs == expected
+    assert stubs["chapter/basics.tex/lem:assoc"]["stub-path"] == "blueprint/src/chapter/basics.tex"
+
+
+def test_parse_blueprint_shape(project):
+    stubs = probe_blueprint.parse_blueprint(str(project))
+
+    main = stubs["chapter/main.tex/thm:main"]
+    assert main["env-options"] == "Main theorem"
+    assert main["spec-dependencies"] == [
+        "chapter/main.tex/thm:main_1",
+        "chapter/main.tex/thm:main_2",
+    ]
+    assert stubs["chapter/main.tex/thm:main_1"]["code-name"] == "probe:Magma.main"
+    assert stubs["chapter/basics.tex/lem:assoc"]["proof-ok"] is True
+
+
+def test_atomize_matches_cli(project):
+    run_cli("atomize", project, "-o", project / ".verilib" / "atoms.json")
+    stubs = load(project / ".verilib" / "stubs.json")
+    expected = load(project / ".verilib" / "atoms.json")
+
+    assert probe_blueprint.atomize(stubs) == expected
+
+
+def test_verify_matches_cli(project):
+    run_cli("verify", project, "-o", project / ".verilib" / "proofs.json")
+    stubs = load(project / ".verilib" / "stubs.json")
+    expected = load(project / ".verilib" / "proofs.json")
+
+    assert probe_blueprint.verify(stubs) == expected
+
+
+def test_missing_blueprint_raises(tmp_path):
+    with pytest.raises(probe_blueprint.BlueprintError) as excinfo:
+        probe_blueprint.parse_blueprint(str(tmp_path))
+
+    assert excinfo.value.kind == "blueprint"
+    assert "blueprint/src directory not found" in excinfo.value.message
+    assert excinfo.value.error_type == "MissingBlueprintSrc"
+    assert excinfo.value.diagnostic["error_type"] == "MissingBlueprintSrc"
+
+
+def test_parse_error_carries_diagnostic(project):
+    (project / "blueprint" / "src" / "chapter" / "dup.tex").write_text(
+        "\\begin{lemma}\\label{def:magma}\\end{lemma}\n"
+    )
+
+    with pytest.raises(probe_blueprint.BlueprintError) as excinfo:
+        probe_blueprint.parse_blueprint(str(project))
+
+    assert excinfo.value.error_type == "DuplicateLabel"
+    assert excinfo.value.context["label"] == "def:magma"
+    assert excinfo.value.diagnostic["context"] == excinfo.value.context
+
+
+def test_malformed_stubs_raise():
+    with pytest.raises(probe_blueprint.BlueprintError) as excinfo:
+        probe_blueprint.atomize({"a.tex/x": {"code-name": "probe:X"}})
+
+    assert excinfo.value.kind == "json"
+
+
+def test_unknown_option_raises(project):
+    with pytest.raises(TypeError):
+        probe_blueprint.parse_blueprint(str(project), no_such_option=True)
+
+
+def test_invalid_option_value_raises(project):
+    with pytest.raises(ValueError):
+        probe_blueprint.parse_blueprint(str(project), path_base="elsewhere")
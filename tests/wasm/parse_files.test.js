@@ -0,0 +1,54 @@
+// Node test for the wasm-bindgen `parse_files` binding.
+//
+// Build the package first:
+//   cargo build --release --target wasm32-unknown-unknown --no-default-features --features wasm
+//   wasm-bindgen --target nodejs --out-dir target/wasm-pkg \
+//     target/wasm32-unknown-unknown/release/probe_blueprint.wasm
+// Then run:
+//   node --test tests/wasm/
+
+const assert = require("node:assert");
+const path = require("node:path");
+const test = require("node:test");
+
+const pkgDir =
+  process.env.PROBE_BLUEPRINT_WASM_PKG ||
+  path.join(__dirname, "..", "..", "target", "wasm-pkg");
+const { parse_files } = require(path.join(pkgDir, "probe_blueprint.js"));
+
+test("parses stubs and config from in-memory sources", () => {
+  const files = [
+    ["web.tex", "\\usepackage[thms=theorem+lemma]{blueprint}\n\\github{https://github.com/user/repo}"],
+    [
+      "chapter/main.tex",
+      [
+        "\\begin{lemma}\\label{lem1}\\lean{Lem1}\\leanok",
+        "  A lemma.",
+        "\\end{lemma}",
+        "",
+        "\\begin{theorem}[Main]\\label{thm1}\\uses{lem1}\\lean{Thm1}",
+        "  A theorem.",
+        "\\end{theorem}",
+        "",
+      ].join("\n"),
+    ],
+  ];
+
+  const result = JSON.parse(parse_files(JSON.stringify(files)));
+
+  assert.strictEqual(result.config.github, "https://github.com/user/repo");
+  const thm = result.stubs["chapter/main.tex/thm1"];
+  assert.strictEqual(thm["code-name"], "probe:Thm1");
+  assert.strictEqual(thm["env-options"], "Main");
+  assert.deepStrictEqual(thm["spec-dependencies"], ["chapter/main.tex/lem1"]);
+  assert.strictEqual(result.stubs["chapter/main.tex/lem1"]["spec-ok"], true);
+});
+
+test("reports parse errors as exceptions", () => {
+  const files = [["a.tex", "\\begin{theorem}\\uses{missing}\\end{theorem}"]];
+  assert.throws(() => parse_files(JSON.stringify(files)), /Unknown label 'missing'/);
+});
+
+test("rejects malformed input", () => {
+  assert.throws(() => parse_files("not json"));
+});